@@ -4,3 +4,4 @@ export 'src/finalizable_handle.dart';
 export 'src/message_channel.dart';
 export 'src/method_channel.dart';
 export 'src/mock_message_channel_context.dart';
+export 'src/shared_buffer.dart';
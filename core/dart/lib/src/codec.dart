@@ -31,6 +31,11 @@ const int _valueFloat64List = 255 - 15;
 const int _valueAttachment = _valueString;
 const int _valueFinalizableHandle = _valueAttachment - 1;
 
+// Only in values encoded by `Value::encode_deduplicated`. Followed by size;
+// 0 means the next value is shared subtree definition, n + 1 is reference to
+// n-th definition (numbered in order in which definitions end).
+const int _valueShared = _valueString;
+
 const int _valueList = 255 - 16;
 const int _valueMap = 255 - 17;
 const int _valueLast = _valueMap;
@@ -195,13 +200,43 @@ class Deserializer {
     return _readValue(buffer, attachments, finalizableHandleProvider);
   }
 
+  /// Deserializes value encoded by `Value::encode` on Rust side. Encoded values
+  /// have no attachments; strings and typed lists are stored inline.
+  ///
+  /// Repeated subtrees in values encoded by `Value::encode_deduplicated` are
+  /// decoded as the same object.
+  Object? deserializeEncoded(ByteData data) {
+    final buffer = ReadBuffer(data);
+    return _readValue(buffer, const [], null, <Object?>[]);
+  }
+
   Object? _readValue(ReadBuffer buffer, List attachments,
-      FinalizableHandleProvider finalizableHandleProvider) {
+      FinalizableHandleProvider? finalizableHandleProvider,
+      [List<Object?>? shared]) {
     if (!buffer.hasRemaining) throw const FormatException('Message corrupted');
     final int type = buffer.getUint8();
     if (type < _valueLast) {
       return type; //small integer
     }
+    // Encoded values (no finalizable handle provider) contain inline typed lists
+    if (finalizableHandleProvider == null &&
+        type >= _valueFloat64List &&
+        type <= _valueInt8List) {
+      return _readTypedList(buffer, type);
+    }
+    if (shared != null && type == _valueShared) {
+      final index = _readSize(buffer);
+      if (index == 0) {
+        final value = _readValue(
+            buffer, attachments, finalizableHandleProvider, shared);
+        shared.add(value);
+        return value;
+      }
+      if (index > shared.length) {
+        throw const FormatException('Message corrupted');
+      }
+      return shared[index - 1];
+    }
     switch (type) {
       case _valueNull:
         return null;
@@ -221,13 +256,13 @@ class Deserializer {
         return attachments[index];
       case _valueFinalizableHandle:
         final id = _readSize(buffer);
-        return finalizableHandleProvider.getFinalizableHandle(id);
+        return finalizableHandleProvider!.getFinalizableHandle(id);
       case _valueList:
         final int length = _readSize(buffer);
         final List<Object?> result = List<Object?>.filled(length, null);
         for (int i = 0; i < length; i++) {
-          result[i] =
-              _readValue(buffer, attachments, finalizableHandleProvider);
+          result[i] = _readValue(
+              buffer, attachments, finalizableHandleProvider, shared);
         }
         return result;
       case _valueMap:
@@ -236,10 +271,10 @@ class Deserializer {
         // Allow deserializing with JSON if keys are all Strings
         bool allStrings = true;
         for (int i = 0; i < length; i++) {
-          final key =
-              _readValue(buffer, attachments, finalizableHandleProvider);
-          result[key] =
-              _readValue(buffer, attachments, finalizableHandleProvider);
+          final key = _readValue(
+              buffer, attachments, finalizableHandleProvider, shared);
+          result[key] = _readValue(
+              buffer, attachments, finalizableHandleProvider, shared);
           if (key is! String) {
             allStrings = false;
           }
@@ -250,6 +285,32 @@ class Deserializer {
     }
   }
 
+  Object _readTypedList(ReadBuffer buffer, int type) {
+    final length = _readSize(buffer);
+    switch (type) {
+      case _valueInt8List:
+        return buffer.getInt8List(length);
+      case _valueUint8List:
+        return buffer.getUint8List(length);
+      case _valueInt16List:
+        return buffer.getInt16List(length);
+      case _valueUint16List:
+        return buffer.getUint16List(length);
+      case _valueInt32List:
+        return buffer.getInt32List(length);
+      case _valueUint32List:
+        return buffer.getUint32List(length);
+      case _valueInt64List:
+        return buffer.getInt64List(length);
+      case _valueFloat32List:
+        return buffer.getFloat32List(length);
+      case _valueFloat64List:
+        return buffer.getFloat64List(length);
+      default:
+        throw const FormatException('Message corrupted');
+    }
+  }
+
   ///
   /// This method is intended for use by subclasses overriding
   /// [readValueOfType].
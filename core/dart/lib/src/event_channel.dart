@@ -1,7 +1,9 @@
 import 'dart:async';
+import 'dart:typed_data';
 
 import 'package:flutter/foundation.dart';
 
+import 'codec.dart';
 import 'message_channel.dart';
 import 'method_channel.dart';
 
@@ -17,8 +19,16 @@ class NativeEventChannel {
         NativeMethodChannel.withMessageChannel(messageChannel);
     late StreamController<dynamic> controller;
     controller = StreamController.broadcast(onListen: () async {
+      final chunks = _ChunkAssembler();
       messageChannel.setHandler((message) {
-        controller.add(message);
+        if (_ChunkAssembler.isChunk(message)) {
+          final value = chunks.add(message);
+          if (value != _ChunkAssembler.incomplete) {
+            controller.add(value);
+          }
+        } else {
+          controller.add(message);
+        }
       });
       try {
         await methodChannel.invokeMethod<void>('listen', arguments);
@@ -51,3 +61,36 @@ class NativeEventChannel {
   final String name;
   final MessageChannelContext context;
 }
+
+// Reassembles messages posted through Rust `EventSink::post_message_chunked`.
+// Each chunk is [marker, transferId, index, isLast, isEncoded, bytes].
+class _ChunkAssembler {
+  static const _marker = 'nativeshell_event_chunk';
+  static final incomplete = Object();
+
+  static bool isChunk(dynamic message) =>
+      message is List && message.length == 6 && message[0] == _marker;
+
+  final _transfers = <int, BytesBuilder>{};
+
+  Object? add(List chunk) {
+    final transferId = chunk[1] as int;
+    final isLast = chunk[3] as bool;
+    final isEncoded = chunk[4] as bool;
+    final bytes = chunk[5] as Uint8List;
+    final builder =
+        _transfers.putIfAbsent(transferId, () => BytesBuilder(copy: false));
+    builder.add(bytes);
+    if (!isLast) {
+      return incomplete;
+    }
+    _transfers.remove(transferId);
+    final data = builder.takeBytes();
+    if (isEncoded) {
+      return const Deserializer().deserializeEncoded(
+          ByteData.view(data.buffer, data.offsetInBytes, data.length));
+    } else {
+      return data;
+    }
+  }
+}
@@ -14,6 +14,19 @@ class NoSuchChannelException implements Exception {
   final String channel;
 }
 
+/// Thrown when native side fails to decode message sent to [channel], i.e.
+/// because it contains string that is not valid UTF-8.
+class MessageDecodeException implements Exception {
+  MessageDecodeException({required this.channel, required this.message});
+
+  @override
+  String toString() =>
+      'Native MessageChannel "$channel" failed to decode message: $message';
+
+  final String channel;
+  final String message;
+}
+
 typedef MessageChannelHandler = FutureOr<dynamic> Function(dynamic message);
 
 /// [MessageChannel] can be used to communicate with its counterpart (handler)
@@ -30,12 +43,23 @@ class MessageChannel {
 
   void setHandler(MessageChannelHandler? handler) {
     this.handler = handler;
+    if (handler != null) {
+      _context.handlerRegistered(name);
+    }
   }
 
   Future<dynamic> sendMessage(dynamic message) {
     return _messageSender(message);
   }
 
+  /// Lets native side know that this channel has been discovered. Resolves
+  /// `ready()` future of the channel registration in Rust.
+  void ping() {
+    _context.ping(name);
+  }
+
+  MessageChannelContext get context => _context;
+
   late MessageSender _messageSender;
   MessageChannelHandler? handler;
   final String name;
@@ -65,6 +89,13 @@ abstract class MessageChannelContext {
   /// used to send messages for this channel.
   MessageSender registerChannel(String name, MessageChannel channel);
 
+  /// Called when handler is set for a registered channel. Lets native side
+  /// deliver messages that were waiting for the handler.
+  void handlerRegistered(String name) {}
+
+  /// Called when channel is pinged.
+  void ping(String name) {}
+
   /// Returns default message context for this executable. Only ever use this
   /// if using native_shell core as part of the main application
   /// (i.e. not a plugin).
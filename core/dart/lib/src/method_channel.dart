@@ -1,7 +1,9 @@
 import 'dart:async';
+import 'dart:typed_data';
 
 import 'package:flutter/services.dart';
 
+import 'codec.dart';
 import 'message_channel.dart';
 
 typedef MethodCallHandler = FutureOr<dynamic> Function(MethodCall call);
@@ -21,6 +23,8 @@ class NativeMethodChannel {
         try {
           final res = await handler(MethodCall(value[0], value[1]));
           return ['ok', res];
+        } on MissingPluginException catch (e) {
+          return ['err', _notImplementedCode, e.message, null];
         } on PlatformException catch (e) {
           return ['err', e.code, e.message, e.details];
         } catch (error) {
@@ -37,14 +41,66 @@ class NativeMethodChannel {
     }
   }
 
-  Future<T> invokeMethod<T>(String method, [dynamic arguments]) async {
-    final res = await _messageChannel.sendMessage([method, arguments]);
+  Future<T> invokeMethod<T>(String method, [dynamic arguments]) {
+    return _invoke<T>([method, arguments]);
+  }
+
+  /// Invokes method and calls [onProgress] for every progress update reported
+  /// through Rust `ProgressReply`. Progress updates are delivered in order and
+  /// before the returned future completes.
+  Future<T> invokeMethodWithProgress<T>(
+    String method,
+    dynamic arguments, {
+    required void Function(dynamic progress) onProgress,
+  }) async {
+    _progressChannel ??= MessageChannel(
+      '${_messageChannel.name}/progress',
+      context: _messageChannel.context,
+    )..setHandler(_onProgress);
+    final progressId = _nextProgressId++;
+    _progressListeners[progressId] = onProgress;
+    try {
+      return await _invoke<T>([method, arguments, progressId]);
+    } finally {
+      _progressListeners.remove(progressId);
+    }
+  }
+
+  // Progress updates are posted as [progressId, value].
+  void _onProgress(dynamic message) {
+    final listener = _progressListeners[message[0] as int];
+    if (listener != null) {
+      listener(message[1]);
+    }
+  }
+
+  Future<T> _invoke<T>(List message) async {
+    final res = await _messageChannel.sendMessage(message);
     if (res[0] == 'ok') {
       return res[1];
+    } else if (res[0] == 'ok_raw') {
+      return _decodeRaw(res[1]);
+    } else if (res[0] == 'err_raw') {
+      final err = _decodeRaw(res[1]) as List;
+      throw PlatformException(code: err[0], message: err[1], details: err[2]);
+    } else if (res[1] == _notImplementedCode) {
+      throw MissingPluginException(res[2]);
     } else {
       throw PlatformException(code: res[1], message: res[2], details: res[3]);
     }
   }
 
+  // Must match Rust `NOT_IMPLEMENTED_ERROR_CODE`.
+  static const _notImplementedCode = 'not_implemented';
+
+  // Raw replies contain value encoded by Rust `Value::encode`.
+  Object? _decodeRaw(Uint8List data) {
+    return const Deserializer().deserializeEncoded(
+        ByteData.view(data.buffer, data.offsetInBytes, data.length));
+  }
+
   final MessageChannel _messageChannel;
+  MessageChannel? _progressChannel;
+  final _progressListeners = <int, void Function(dynamic)>{};
+  int _nextProgressId = 0;
 }
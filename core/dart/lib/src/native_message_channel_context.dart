@@ -6,6 +6,7 @@ import 'dart:typed_data';
 import 'codec.dart';
 import 'finalizable_handle.dart';
 import 'message_channel.dart';
+import 'mock_message_channel_context.dart';
 import 'native_functions.dart';
 
 abstract class NativeMessageChannelDelegate {
@@ -95,6 +96,16 @@ class NativeMessageChannelContext
     return (msg) => _sendMessage(name, msg);
   }
 
+  @override
+  void handlerRegistered(String name) {
+    _postMessage(["handler_registered", name]);
+  }
+
+  @override
+  void ping(String name) {
+    _postMessage(["ping", name]);
+  }
+
   Future<dynamic> _sendMessage(String channel, dynamic message) async {
     final replyId = _nextReplyId++;
     _postMessage(["message", replyId, channel, message]);
@@ -119,6 +130,18 @@ class NativeMessageChannelContext
       final channel = data[2] as String;
       final completer = _pendingReplies.remove(replyId)!;
       completer.completeError(NoSuchChannelException(channel: channel));
+    } else if (message == "reply_no_handler") {
+      final replyId = data[1] as int;
+      final channel = data[2] as String;
+      final completer = _pendingReplies.remove(replyId)!;
+      completer.completeError(NoRegisteredHandlerException(channel: channel));
+    } else if (message == "reply_decode_error") {
+      final replyId = data[1] as int;
+      final channel = data[2] as String;
+      final error = data[3] as String;
+      final completer = _pendingReplies.remove(replyId)!;
+      completer.completeError(
+          MessageDecodeException(channel: channel, message: error));
     } else if (message == "send_message") {
       final channelName = data[1] as String;
       final replyId = data[2] as int;
@@ -65,6 +65,77 @@ class ReadBuffer {
     return list;
   }
 
+  /// Reads the given number of Int8s from the buffer.
+  Int8List getInt8List(int length) {
+    final Int8List list =
+        data.buffer.asInt8List(data.offsetInBytes + _position, length);
+    _position += 1 * length;
+    return list;
+  }
+
+  /// Reads the given number of Int16s from the buffer.
+  Int16List getInt16List(int length) {
+    _alignTo(2);
+    final Int16List list =
+        data.buffer.asInt16List(data.offsetInBytes + _position, length);
+    _position += 2 * length;
+    return list;
+  }
+
+  /// Reads the given number of Uint16s from the buffer.
+  Uint16List getUint16List(int length) {
+    _alignTo(2);
+    final Uint16List list =
+        data.buffer.asUint16List(data.offsetInBytes + _position, length);
+    _position += 2 * length;
+    return list;
+  }
+
+  /// Reads the given number of Int32s from the buffer.
+  Int32List getInt32List(int length) {
+    _alignTo(4);
+    final Int32List list =
+        data.buffer.asInt32List(data.offsetInBytes + _position, length);
+    _position += 4 * length;
+    return list;
+  }
+
+  /// Reads the given number of Uint32s from the buffer.
+  Uint32List getUint32List(int length) {
+    _alignTo(4);
+    final Uint32List list =
+        data.buffer.asUint32List(data.offsetInBytes + _position, length);
+    _position += 4 * length;
+    return list;
+  }
+
+  /// Reads the given number of Int64s from the buffer.
+  Int64List getInt64List(int length) {
+    _alignTo(8);
+    final Int64List list =
+        data.buffer.asInt64List(data.offsetInBytes + _position, length);
+    _position += 8 * length;
+    return list;
+  }
+
+  /// Reads the given number of Float32s from the buffer.
+  Float32List getFloat32List(int length) {
+    _alignTo(4);
+    final Float32List list =
+        data.buffer.asFloat32List(data.offsetInBytes + _position, length);
+    _position += 4 * length;
+    return list;
+  }
+
+  /// Reads the given number of Float64s from the buffer.
+  Float64List getFloat64List(int length) {
+    _alignTo(8);
+    final Float64List list =
+        data.buffer.asFloat64List(data.offsetInBytes + _position, length);
+    _position += 8 * length;
+    return list;
+  }
+
   void _alignTo(int alignment) {
     final int mod = _position % alignment;
     if (mod != 0) _position += alignment - mod;
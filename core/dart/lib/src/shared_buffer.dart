@@ -0,0 +1,102 @@
+import 'dart:ffi';
+import 'dart:io';
+import 'dart:typed_data';
+
+import 'finalizable_handle.dart';
+
+/// First item of the list that Rust `SharedBuffer` is sent as.
+const sharedBufferMarker = 'nativeshell_shared_buffer';
+
+/// Shared memory region sent from Rust as `Value::SharedBuffer`.
+///
+/// The operating system handle is owned by native side and stays open as long
+/// as this object (or rather its [FinalizableHandle]) is reachable. Memory
+/// mapped with [map] remains valid until [close] is called.
+class SharedBuffer {
+  SharedBuffer._(this.handle, this.length, this._owner);
+
+  /// Converts value received from Rust into [SharedBuffer]. Returns `null` if
+  /// the value does not represent shared buffer (i.e. the handle could not be
+  /// duplicated on native side).
+  static SharedBuffer? fromValue(dynamic value) {
+    if (value is List &&
+        value.length == 4 &&
+        value[0] == sharedBufferMarker &&
+        value[1] is int &&
+        value[2] is int &&
+        value[3] is FinalizableHandle) {
+      return SharedBuffer._(value[1], value[2], value[3]);
+    }
+    return null;
+  }
+
+  /// File descriptor on Unix, `HANDLE` on Windows.
+  final int handle;
+
+  /// Size of the region in bytes.
+  final int length;
+
+  FinalizableHandle? _owner;
+  final _mappings = <Pointer<Uint8>>[];
+
+  bool get isClosed => _owner == null;
+
+  /// Maps the memory (read and write) and returns view of it.
+  Uint8List map() {
+    if (isClosed) {
+      throw StateError('SharedBuffer is closed');
+    }
+    final data = Platform.isWindows
+        ? _mapViewOfFile(Pointer.fromAddress(handle), _fileMapAllAccess, 0, 0,
+            length)
+        : _mmap(nullptr, length, _protRead | _protWrite, _mapShared, handle, 0);
+    if (data.address == 0 || data.address == -1) {
+      throw StateError('Failed to map SharedBuffer');
+    }
+    final res = data.cast<Uint8>();
+    _mappings.add(res);
+    return res.asTypedList(length);
+  }
+
+  /// Unmaps all views returned by [map] and releases the handle, which native
+  /// side closes once this object is garbage collected. Views must not be
+  /// accessed after calling this method.
+  void close() {
+    for (final mapping in _mappings) {
+      if (Platform.isWindows) {
+        _unmapViewOfFile(mapping.cast());
+      } else {
+        _munmap(mapping.cast(), length);
+      }
+    }
+    _mappings.clear();
+    _owner = null;
+  }
+}
+
+const _protRead = 1;
+const _protWrite = 2;
+const _mapShared = 1;
+const _fileMapAllAccess = 0xF001F;
+
+final _libc = DynamicLibrary.process();
+
+final _mmap = _libc.lookupFunction<
+    Pointer<Void> Function(
+        Pointer<Void>, IntPtr, Int32, Int32, Int32, IntPtr),
+    Pointer<Void> Function(
+        Pointer<Void>, int, int, int, int, int)>('mmap');
+
+final _munmap = _libc.lookupFunction<Int32 Function(Pointer<Void>, IntPtr),
+    int Function(Pointer<Void>, int)>('munmap');
+
+final _kernel32 = DynamicLibrary.open('kernel32.dll');
+
+final _mapViewOfFile = _kernel32.lookupFunction<
+    Pointer<Void> Function(Pointer<Void>, Uint32, Uint32, Uint32, IntPtr),
+    Pointer<Void> Function(
+        Pointer<Void>, int, int, int, int)>('MapViewOfFile');
+
+final _unmapViewOfFile = _kernel32.lookupFunction<
+    Int32 Function(Pointer<Void>),
+    int Function(Pointer<Void>)>('UnmapViewOfFile');
@@ -0,0 +1,1550 @@
+use core::fmt::Display;
+
+use alloc::{vec, vec::Vec};
+
+#[cfg(feature = "std")]
+use futures::io::{AsyncWrite, AsyncWriteExt};
+#[cfg(feature = "std")]
+use std::collections::HashMap;
+
+use crate::value::Value;
+
+pub(crate) const VALUE_NULL: u8 = 255 - 0;
+pub(crate) const VALUE_TRUE: u8 = 255 - 1;
+pub(crate) const VALUE_FALSE: u8 = 255 - 2;
+pub(crate) const VALUE_INT64: u8 = 255 - 3;
+pub(crate) const VALUE_FLOAT64: u8 = 255 - 4;
+pub(crate) const VALUE_SMALL_STRING: u8 = 255 - 5;
+
+// Deserialization
+#[cfg_attr(any(feature = "mock", not(feature = "std")), allow(unused))]
+pub(crate) const VALUE_STRING: u8 = 255 - 6;
+pub(crate) const VALUE_INT8LIST: u8 = 255 - 7;
+pub(crate) const VALUE_UINT8LIST: u8 = 255 - 8;
+pub(crate) const VALUE_INT16LIST: u8 = 255 - 9;
+pub(crate) const VALUE_UINT16LIST: u8 = 255 - 10;
+pub(crate) const VALUE_INT32LIST: u8 = 255 - 11;
+pub(crate) const VALUE_UINT32LIST: u8 = 255 - 12;
+pub(crate) const VALUE_INT64LIST: u8 = 255 - 13;
+pub(crate) const VALUE_FLOAT32LIST: u8 = 255 - 14;
+pub(crate) const VALUE_FLOAT64LIST: u8 = 255 - 15;
+
+// Serialization
+#[cfg_attr(any(feature = "mock", not(feature = "std")), allow(unused))]
+pub(crate) const VALUE_ATTACHMENT: u8 = VALUE_STRING; // this will be passed directly as Dart_CObject
+#[cfg_attr(any(feature = "mock", not(feature = "std")), allow(unused))]
+pub(crate) const VALUE_FINALIZABLE_HANDLE: u8 = VALUE_ATTACHMENT - 1;
+
+// Only used in values encoded by `Value::encode_deduplicated`; attachments never
+// appear in encoded values so the tag is reused. Followed by size, `0` means
+// that the next value is definition of shared subtree, `n + 1` is back-reference
+// to n-th definition. Definitions are numbered in order in which they end.
+const VALUE_SHARED: u8 = VALUE_STRING;
+
+pub(crate) const VALUE_LIST: u8 = 255 - 16;
+pub(crate) const VALUE_MAP: u8 = 255 - 17;
+pub(crate) const VALUE_LAST: u8 = VALUE_MAP;
+
+// Every byte is either a tag or a small integer, so values added later are
+// written as `VALUE_SHARED` with index that `Writer::write_size` never produces,
+// followed by extended tag. Data encoded by earlier versions decodes unchanged
+// and earlier versions reject extended values as malformed.
+const VALUE_EXTENDED: usize = u32::MAX as usize;
+const VALUE_EXTENDED_INT32: u8 = 0;
+const VALUE_EXTENDED_UINT32: u8 = 1;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum DecodeError {
+    InvalidUtf8,
+    Malformed,
+    /// Input exceeds one of the limits configured in [`DecodeLimits`].
+    LimitExceeded,
+}
+
+impl Display for DecodeError {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        match self {
+            Self::InvalidUtf8 => write!(f, "string is not valid UTF-8"),
+            Self::Malformed => write!(f, "malformed input"),
+            Self::LimitExceeded => write!(f, "decoding limit exceeded"),
+        }
+    }
+}
+
+#[cfg(feature = "std")]
+impl std::error::Error for DecodeError {}
+
+/// Determines how strings with invalid UTF-8 sequences are decoded.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum StringDecoding {
+    /// Invalid UTF-8 results in [`DecodeError::InvalidUtf8`].
+    #[default]
+    Strict,
+    /// Invalid UTF-8 sequences are replaced with `U+FFFD REPLACEMENT CHARACTER`.
+    Lossy,
+}
+
+/// Options used when decoding messages received from Dart.
+#[derive(Debug, Clone, Default)]
+pub struct DecodeOptions {
+    pub string_decoding: StringDecoding,
+    pub limits: DecodeLimits,
+}
+
+/// Limits enforced while decoding to guard against malformed or malicious
+/// input. Exceeding any of them results in [`DecodeError::LimitExceeded`].
+/// `None` means no limit (default).
+#[derive(Debug, Clone, Default)]
+pub struct DecodeLimits {
+    /// Maximum size of the encoded message in bytes, including typed data
+    /// that is passed out of line. Back-references in values encoded with
+    /// [`Value::encode_deduplicated`] count as the size of the referenced
+    /// subtree (including its nested definition headers), so expanded value
+    /// is limited rather than the input.
+    pub max_total_bytes: Option<usize>,
+    /// Maximum number of items in a list, map or typed list.
+    pub max_collection_length: Option<usize>,
+    /// Maximum nesting depth of lists and maps. Top level list or map has
+    /// depth of 1. Depth of subtrees inserted through back-references counts
+    /// as well.
+    pub max_depth: Option<usize>,
+}
+
+impl DecodeLimits {
+    fn check(limit: Option<usize>, value: usize) -> Result<(), DecodeError> {
+        match limit {
+            Some(limit) if value > limit => Err(DecodeError::LimitExceeded),
+            _ => Ok(()),
+        }
+    }
+
+    pub(crate) fn check_total_bytes(&self, bytes: usize) -> Result<(), DecodeError> {
+        Self::check(self.max_total_bytes, bytes)
+    }
+
+    pub(crate) fn check_collection_length(&self, len: usize) -> Result<(), DecodeError> {
+        Self::check(self.max_collection_length, len)
+    }
+
+    pub(crate) fn check_depth(&self, depth: usize) -> Result<(), DecodeError> {
+        Self::check(self.max_depth, depth)
+    }
+}
+
+pub(crate) struct Reader<'a> {
+    buf: &'a [u8],
+    pos: usize,
+}
+
+#[allow(unused)]
+impl<'a> Reader<'a> {
+    pub fn new(buf: &'a [u8]) -> Self {
+        Reader { buf, pos: 0 }
+    }
+    pub(crate) fn read_u8(&mut self) -> u8 {
+        let n = self.buf[self.pos];
+        self.pos += 1;
+        n
+    }
+    pub(crate) fn read_u16(&mut self) -> u16 {
+        self.pos += 2;
+        let s = &self.buf[self.pos - 2..self.pos];
+        u16::from_ne_bytes(clone_into_array(s))
+    }
+    pub(crate) fn read_u32(&mut self) -> u32 {
+        self.pos += 4;
+        let s = &self.buf[self.pos - 4..self.pos];
+        u32::from_ne_bytes(clone_into_array(s))
+    }
+    pub(crate) fn read_u64(&mut self) -> u64 {
+        self.pos += 8;
+        let s = &self.buf[self.pos - 8..self.pos];
+        u64::from_ne_bytes(clone_into_array(s))
+    }
+    pub(crate) fn read_i64(&mut self) -> i64 {
+        self.pos += 8;
+        let s = &self.buf[self.pos - 8..self.pos];
+        i64::from_ne_bytes(clone_into_array(s))
+    }
+    pub(crate) fn read_f64(&mut self) -> f64 {
+        let n = self.read_u64();
+        f64::from_bits(n)
+    }
+    pub(crate) fn read_size(&mut self) -> usize {
+        let n = self.read_u8();
+        match n {
+            254 => self.read_u16() as usize,
+            255 => self.read_u32() as usize,
+            _ => n as usize,
+        }
+    }
+    pub(crate) fn read_bytes(&mut self, len: usize) -> &'a [u8] {
+        let v = &self.buf[self.pos..self.pos + len];
+        self.pos += len;
+        v
+    }
+    pub(crate) fn align_to(&mut self, align: usize) {
+        let m = self.pos % align;
+        if m > 0 {
+            self.pos += align - m;
+        }
+    }
+    pub(crate) fn ended(&self) -> bool {
+        self.pos >= self.buf.len()
+    }
+    pub(crate) fn remaining(&self) -> usize {
+        self.buf.len().saturating_sub(self.pos)
+    }
+    // Ensures that there are at least `len` bytes remaining.
+    pub(crate) fn checked(&mut self, len: usize) -> Result<&mut Self, DecodeError> {
+        match self.pos.checked_add(len) {
+            Some(end) if end <= self.buf.len() => Ok(self),
+            _ => Err(DecodeError::Malformed),
+        }
+    }
+    pub(crate) fn read_checked_size(&mut self) -> Result<usize, DecodeError> {
+        let n = self.checked(1)?.read_u8();
+        match n {
+            254 => Ok(self.checked(2)?.read_u16() as usize),
+            255 => Ok(self.checked(4)?.read_u32() as usize),
+            _ => Ok(n as usize),
+        }
+    }
+}
+
+// Value waiting to be written by `Encoder::write_value`, possibly with its
+// encoding already known.
+enum Pending<'a> {
+    Value(&'a Value),
+    Encoded(&'a Value, Vec<u8>),
+}
+
+// Self-contained encoding produced by `Value::encode`. Unlike messages sent to
+// Dart it has no attachments; strings are always stored inline (as
+// VALUE_SMALL_STRING) and typed lists are stored inline after their
+// VALUE_*LIST tag, aligned to element size.
+struct Encoder {}
+
+impl Encoder {
+    // When `canonical` is set map entries are written ordered by encoding of
+    // their keys (and values for entries with same key encoding).
+    // Lists and maps are traversed using explicit stack of values that are yet
+    // to be written, so that deeply nested values don't overflow native stack.
+    fn write_value(writer: &mut Writer, value: &Value, canonical: bool) {
+        let mut stack = vec![Pending::Value(value)];
+        while let Some(item) = stack.pop() {
+            match item {
+                Pending::Value(value) => {
+                    Self::write_single_value(writer, value, canonical, &mut stack);
+                }
+                Pending::Encoded(value, encoded) => {
+                    // Encoding was produced at offset 0, so it can only be
+                    // reused if padding would be the same.
+                    if writer.0.len() & 7 == 0 || Self::is_position_independent(value) {
+                        writer.0.extend_from_slice(&encoded);
+                    } else {
+                        Self::write_single_value(writer, value, canonical, &mut stack);
+                    }
+                }
+            }
+        }
+    }
+
+    // Whether encoding of value contains no alignment padding.
+    fn is_position_independent(value: &Value) -> bool {
+        matches!(
+            value,
+            Value::Null
+                | Value::Bool(_)
+                | Value::I64(_)
+                | Value::I32(_)
+                | Value::U32(_)
+                | Value::String(_)
+                | Value::I8List(_)
+                | Value::U8List(_)
+                | Value::BoolList(_)
+        )
+    }
+
+    fn write_extended_tag(writer: &mut Writer, tag: u8) {
+        writer.write_u8(VALUE_SHARED);
+        writer.write_u8(255);
+        writer.write_u32(VALUE_EXTENDED as u32);
+        writer.write_u8(tag);
+    }
+
+    // Writes scalar value or header of list or map. Items of list or map are
+    // pushed to `stack` in reverse order.
+    fn write_single_value<'a>(
+        writer: &mut Writer,
+        value: &'a Value,
+        canonical: bool,
+        stack: &mut Vec<Pending<'a>>,
+    ) {
+        match value {
+            Value::Null => {
+                writer.write_u8(VALUE_NULL);
+            }
+            Value::Bool(v) => {
+                writer.write_u8(if *v { VALUE_TRUE } else { VALUE_FALSE });
+            }
+            Value::I64(n) => {
+                if (0..VALUE_LAST as i64).contains(n) {
+                    writer.write_u8(*n as u8);
+                } else {
+                    writer.write_u8(VALUE_INT64);
+                    writer.write_i64(*n);
+                }
+            }
+            Value::I32(n) => {
+                Self::write_extended_tag(writer, VALUE_EXTENDED_INT32);
+                writer.write_i32(*n);
+            }
+            Value::U32(n) => {
+                Self::write_extended_tag(writer, VALUE_EXTENDED_UINT32);
+                writer.write_u32(*n);
+            }
+            Value::F64(n) => {
+                writer.write_u8(VALUE_FLOAT64);
+                writer.align_to(8);
+                writer.write_f64(*n);
+            }
+            Value::String(v) => {
+                writer.write_u8(VALUE_SMALL_STRING);
+                writer.write_size(v.len());
+                writer.write_string(v);
+            }
+            Value::I8List(v) => Self::write_list(writer, VALUE_INT8LIST, v),
+            Value::U8List(v) => Self::write_list(writer, VALUE_UINT8LIST, v),
+            Value::I16List(v) => Self::write_list(writer, VALUE_INT16LIST, v),
+            Value::U16List(v) => Self::write_list(writer, VALUE_UINT16LIST, v),
+            Value::I32List(v) => Self::write_list(writer, VALUE_INT32LIST, v),
+            Value::U32List(v) => Self::write_list(writer, VALUE_UINT32LIST, v),
+            Value::I64List(v) => Self::write_list(writer, VALUE_INT64LIST, v),
+            Value::F32List(v) => Self::write_list(writer, VALUE_FLOAT32LIST, v),
+            Value::F64List(v) => Self::write_list(writer, VALUE_FLOAT64LIST, v),
+            Value::BoolList(v) => {
+                writer.write_u8(VALUE_UINT8LIST);
+                writer.write_size(v.len());
+                v.iter().for_each(|b| writer.write_u8(*b as u8));
+            }
+            Value::List(list) => {
+                writer.write_u8(VALUE_LIST);
+                writer.write_size(list.len());
+                stack.extend(list.iter().rev().map(Pending::Value));
+            }
+            Value::Map(map) => {
+                writer.write_u8(VALUE_MAP);
+                writer.write_size(map.len());
+                if canonical {
+                    // Every key is encoded once; values are only encoded to
+                    // order entries with same key encoding, encoding all of
+                    // them upfront would encode nested maps repeatedly.
+                    let mut entries: Vec<_> = map
+                        .iter()
+                        .map(|e| (e.0.canonical_encode(), None, e))
+                        .collect();
+                    entries.sort_by(|a, b| a.0.cmp(&b.0));
+                    let mut start = 0;
+                    while start < entries.len() {
+                        let key = &entries[start].0;
+                        let end =
+                            start + entries[start..].iter().take_while(|e| &e.0 == key).count();
+                        if end - start > 1 {
+                            let same_key = &mut entries[start..end];
+                            for e in same_key.iter_mut() {
+                                e.1 = Some(e.2 .1.canonical_encode());
+                            }
+                            same_key.sort_by(|a, b| a.1.cmp(&b.1));
+                        }
+                        start = end;
+                    }
+                    for (key, value, e) in entries.into_iter().rev() {
+                        stack.push(match value {
+                            Some(value) => Pending::Encoded(&e.1, value),
+                            None => Pending::Value(&e.1),
+                        });
+                        stack.push(Pending::Encoded(&e.0, key));
+                    }
+                } else {
+                    map.iter().rev().for_each(|e| {
+                        stack.push(Pending::Value(&e.1));
+                        stack.push(Pending::Value(&e.0));
+                    });
+                }
+            }
+            #[cfg(feature = "std")]
+            Value::Dart(_) | Value::FinalizableHandle(_) => {
+                panic!("Dart objects and finalizable handles can not be encoded");
+            }
+            #[cfg(feature = "shared_buffer")]
+            Value::SharedBuffer(_) => {
+                panic!("Shared buffers can not be encoded");
+            }
+        }
+    }
+
+    // Values that are worth replacing with back-reference when repeated.
+    #[cfg(feature = "std")]
+    fn is_shareable(value: &Value) -> bool {
+        match value {
+            Value::String(s) => s.len() > 2,
+            Value::List(list) => !list.is_empty(),
+            Value::Map(map) => !map.is_empty(),
+            Value::I8List(_)
+            | Value::U8List(_)
+            | Value::I16List(_)
+            | Value::U16List(_)
+            | Value::I32List(_)
+            | Value::U32List(_)
+            | Value::I64List(_)
+            | Value::F32List(_)
+            | Value::F64List(_)
+            | Value::BoolList(_) => true,
+            _ => false,
+        }
+    }
+
+    // Assigns ids to subtrees so that equal subtrees get the same id. Ids are
+    // assigned bottom-up and children are represented by their ids, so each
+    // node is hashed and compared only once regardless of depth.
+    #[cfg(feature = "std")]
+    fn subtree_ids(value: &Value) -> HashMap<*const Value, usize> {
+        #[derive(PartialEq, Eq, Hash)]
+        enum Node<'a> {
+            Leaf(&'a Value),
+            List(Vec<usize>),
+            Map(Vec<usize>),
+        }
+        let mut nodes = HashMap::<Node, usize>::new();
+        let mut ids = HashMap::<*const Value, usize>::new();
+        // Values are pushed twice, second time once their children have ids.
+        let mut stack = vec![(value, false)];
+        while let Some((value, children_done)) = stack.pop() {
+            let children = || match value {
+                Value::List(list) => list.iter().collect(),
+                Value::Map(map) => map.iter().flat_map(|e| [&e.0, &e.1]).collect(),
+                _ => Vec::new(),
+            };
+            if !children_done {
+                stack.push((value, true));
+                stack.extend(children().into_iter().map(|child| (child, false)));
+                continue;
+            }
+            let child_ids = || {
+                children()
+                    .into_iter()
+                    .map(|c| ids[&(c as *const _)])
+                    .collect()
+            };
+            let node = match value {
+                Value::List(_) => Node::List(child_ids()),
+                Value::Map(_) => Node::Map(child_ids()),
+                value => Node::Leaf(value),
+            };
+            let next_id = nodes.len();
+            let id = *nodes.entry(node).or_insert(next_id);
+            ids.insert(value, id);
+        }
+        ids
+    }
+
+    // Counts occurrences of shareable subtrees. Repeated subtrees are only
+    // written once so their children are not counted again.
+    #[cfg(feature = "std")]
+    fn count_shared(value: &Value, ids: &HashMap<*const Value, usize>) -> HashMap<usize, usize> {
+        let mut counts = HashMap::new();
+        let mut stack = vec![value];
+        while let Some(value) = stack.pop() {
+            if !Self::is_shareable(value) {
+                continue;
+            }
+            let count = counts.entry(ids[&(value as *const _)]).or_default();
+            *count += 1;
+            if *count > 1 {
+                continue;
+            }
+            match value {
+                Value::List(list) => stack.extend(list.iter()),
+                Value::Map(map) => map.iter().for_each(|e| {
+                    stack.push(&e.0);
+                    stack.push(&e.1);
+                }),
+                _ => {}
+            }
+        }
+        counts
+    }
+
+    // Repeated subtrees are assigned index once they are completely written,
+    // same as when they are read.
+    #[cfg(feature = "std")]
+    fn write_value_deduplicated(
+        writer: &mut Writer,
+        value: &Value,
+        ids: &HashMap<*const Value, usize>,
+        counts: &HashMap<usize, usize>,
+    ) {
+        enum Item<'a> {
+            Value(&'a Value),
+            Defined(usize),
+        }
+        // Subtree id to index of its definition.
+        let mut defined = HashMap::<usize, usize>::new();
+        let mut stack = vec![Item::Value(value)];
+        while let Some(item) = stack.pop() {
+            let value = match item {
+                Item::Value(value) => value,
+                Item::Defined(id) => {
+                    let index = defined.len();
+                    defined.insert(id, index);
+                    continue;
+                }
+            };
+            let id = ids[&(value as *const _)];
+            let repeated = counts.get(&id).map(|c| *c > 1).unwrap_or(false);
+            if repeated {
+                writer.write_u8(VALUE_SHARED);
+                match defined.get(&id) {
+                    Some(index) => {
+                        writer.write_size(index + 1);
+                        continue;
+                    }
+                    None => writer.write_size(0),
+                }
+                stack.push(Item::Defined(id));
+            }
+            match value {
+                Value::List(list) => {
+                    writer.write_u8(VALUE_LIST);
+                    writer.write_size(list.len());
+                    stack.extend(list.iter().rev().map(Item::Value));
+                }
+                Value::Map(map) => {
+                    writer.write_u8(VALUE_MAP);
+                    writer.write_size(map.len());
+                    map.iter().rev().for_each(|e| {
+                        stack.push(Item::Value(&e.1));
+                        stack.push(Item::Value(&e.0));
+                    });
+                }
+                value => Self::write_value(writer, value, false),
+            }
+        }
+    }
+
+    fn write_list<T: Element>(writer: &mut Writer, tag: u8, list: &[T]) {
+        writer.write_u8(tag);
+        writer.write_size(list.len());
+        writer.align_to(T::SIZE);
+        list.iter().for_each(|v| v.write(writer.0));
+    }
+
+    // Lists and maps are read using explicit stack of partially read values,
+    // so that deeply nested input doesn't overflow native stack.
+    //
+    // Back-references to shared subtrees are counted against the limits as if
+    // the subtree was written in place, otherwise small input could expand
+    // into arbitrarily large value.
+    fn read_value<'a, T: DecodedValue<'a>>(
+        reader: &mut Reader<'a>,
+        options: &DecodeOptions,
+    ) -> Result<T, DecodeError> {
+        let mut stack = Vec::<ReadFrame<T>>::new();
+        let mut shared = Vec::<SharedValue<T>>::new();
+        // Number of lists and maps on stack.
+        let mut depth = 0;
+        // Deepest nesting reached since the innermost shared definition started.
+        let mut peak_depth = 0;
+        // Bytes added by expanding back-references.
+        let mut expanded_bytes = 0usize;
+        loop {
+            let start = reader.pos;
+            let t = reader.checked(1)?.read_u8();
+            let mut value = if t < VALUE_LAST {
+                T::from_value(Value::I64(t as i64))
+            } else {
+                match t {
+                    VALUE_NULL => T::from_value(Value::Null),
+                    VALUE_FALSE => T::from_value(Value::Bool(false)),
+                    VALUE_TRUE => T::from_value(Value::Bool(true)),
+                    VALUE_INT64 => T::from_value(Value::I64(reader.checked(8)?.read_i64())),
+                    VALUE_FLOAT64 => {
+                        reader.align_to(8);
+                        T::from_value(Value::F64(reader.checked(8)?.read_f64()))
+                    }
+                    VALUE_SMALL_STRING => {
+                        let len = reader.read_checked_size()?;
+                        let bytes = reader.checked(len)?.read_bytes(len);
+                        T::from_string(bytes, options.string_decoding)?
+                    }
+                    VALUE_INT8LIST => T::from_bytes(Self::read_bytes(reader, options)?, true),
+                    VALUE_UINT8LIST => T::from_bytes(Self::read_bytes(reader, options)?, false),
+                    VALUE_INT16LIST => {
+                        T::from_value(Value::I16List(Self::read_list(reader, options)?))
+                    }
+                    VALUE_UINT16LIST => {
+                        T::from_value(Value::U16List(Self::read_list(reader, options)?))
+                    }
+                    VALUE_INT32LIST => {
+                        T::from_value(Value::I32List(Self::read_list(reader, options)?))
+                    }
+                    VALUE_UINT32LIST => {
+                        T::from_value(Value::U32List(Self::read_list(reader, options)?))
+                    }
+                    VALUE_INT64LIST => {
+                        T::from_value(Value::I64List(Self::read_list(reader, options)?))
+                    }
+                    VALUE_FLOAT32LIST => {
+                        T::from_value(Value::F32List(Self::read_list(reader, options)?))
+                    }
+                    VALUE_FLOAT64LIST => {
+                        T::from_value(Value::F64List(Self::read_list(reader, options)?))
+                    }
+                    VALUE_LIST => {
+                        let len = reader.read_checked_size()?;
+                        options.limits.check_collection_length(len)?;
+                        options.limits.check_depth(depth + 1)?;
+                        peak_depth = peak_depth.max(depth + 1);
+                        if len > 0 {
+                            depth += 1;
+                            stack.push(ReadFrame::List(Vec::new(), len));
+                            continue;
+                        }
+                        T::from_list(Vec::new())
+                    }
+                    VALUE_MAP => {
+                        let len = reader.read_checked_size()?;
+                        options.limits.check_collection_length(len)?;
+                        options.limits.check_depth(depth + 1)?;
+                        peak_depth = peak_depth.max(depth + 1);
+                        if len > 0 {
+                            depth += 1;
+                            stack.push(ReadFrame::Map(Vec::new(), len, None));
+                            continue;
+                        }
+                        T::from_map(Vec::new())
+                    }
+                    VALUE_SHARED => match reader.read_checked_size()? {
+                        0 => {
+                            stack.push(ReadFrame::Shared {
+                                start: reader.pos,
+                                expanded_bytes,
+                                depth,
+                                outer_peak_depth: peak_depth,
+                            });
+                            peak_depth = depth;
+                            continue;
+                        }
+                        VALUE_EXTENDED => match reader.checked(1)?.read_u8() {
+                            VALUE_EXTENDED_INT32 => {
+                                T::from_value(Value::I32(reader.checked(4)?.read_u32() as i32))
+                            }
+                            VALUE_EXTENDED_UINT32 => {
+                                T::from_value(Value::U32(reader.checked(4)?.read_u32()))
+                            }
+                            _ => return Err(DecodeError::Malformed),
+                        },
+                        index => {
+                            let value = shared.get(index - 1).ok_or(DecodeError::Malformed)?;
+                            let reference_len = reader.pos - start;
+                            expanded_bytes = expanded_bytes
+                                .saturating_add(value.encoded_len.saturating_sub(reference_len));
+                            options
+                                .limits
+                                .check_total_bytes(reader.pos.saturating_add(expanded_bytes))?;
+                            options.limits.check_depth(depth + value.depth)?;
+                            peak_depth = peak_depth.max(depth + value.depth);
+                            value.value.clone()
+                        }
+                    },
+                    _ => return Err(DecodeError::Malformed),
+                }
+            };
+            // Store the value in its parent; repeat for parents that are complete.
+            loop {
+                match stack.last_mut() {
+                    None => return Ok(value),
+                    Some(ReadFrame::List(list, len)) => {
+                        list.push(value);
+                        if list.len() < *len {
+                            break;
+                        }
+                        value = match stack.pop() {
+                            Some(ReadFrame::List(list, _)) => T::from_list(list),
+                            _ => unreachable!(),
+                        };
+                        depth -= 1;
+                    }
+                    Some(ReadFrame::Map(map, len, key)) => match key.take() {
+                        None => {
+                            key.replace(value);
+                            break;
+                        }
+                        Some(key) => {
+                            map.push((key, value));
+                            if map.len() < *len {
+                                break;
+                            }
+                            value = match stack.pop() {
+                                Some(ReadFrame::Map(map, _, _)) => T::from_map(map),
+                                _ => unreachable!(),
+                            };
+                            depth -= 1;
+                        }
+                    },
+                    Some(ReadFrame::Shared {
+                        start,
+                        expanded_bytes: outer_expanded_bytes,
+                        depth: shared_depth,
+                        outer_peak_depth,
+                    }) => {
+                        let (start, outer_expanded_bytes, shared_depth, outer_peak_depth) = (
+                            *start,
+                            *outer_expanded_bytes,
+                            *shared_depth,
+                            *outer_peak_depth,
+                        );
+                        stack.pop();
+                        shared.push(SharedValue {
+                            value: value.clone(),
+                            encoded_len: (reader.pos - start)
+                                .saturating_add(expanded_bytes - outer_expanded_bytes),
+                            depth: peak_depth - shared_depth,
+                        });
+                        peak_depth = peak_depth.max(outer_peak_depth);
+                    }
+                }
+            }
+        }
+    }
+
+    fn read_list<T: Element>(
+        reader: &mut Reader,
+        options: &DecodeOptions,
+    ) -> Result<Vec<T>, DecodeError> {
+        let len = reader.read_checked_size()?;
+        options.limits.check_collection_length(len)?;
+        let size = len.checked_mul(T::SIZE).ok_or(DecodeError::Malformed)?;
+        reader.align_to(T::SIZE);
+        let bytes = reader.checked(size)?.read_bytes(size);
+        Ok(bytes.chunks_exact(T::SIZE).map(T::read).collect())
+    }
+
+    fn read_bytes<'a>(
+        reader: &mut Reader<'a>,
+        options: &DecodeOptions,
+    ) -> Result<&'a [u8], DecodeError> {
+        let len = reader.read_checked_size()?;
+        options.limits.check_collection_length(len)?;
+        Ok(reader.checked(len)?.read_bytes(len))
+    }
+}
+
+// Value produced by `Encoder::read_value`. Strings and byte lists are passed
+// as slices of the input so that they can be borrowed instead of copied.
+pub(crate) trait DecodedValue<'a>: Clone {
+    fn from_value(value: Value) -> Self;
+    fn from_string(bytes: &'a [u8], decoding: StringDecoding) -> Result<Self, DecodeError>;
+    fn from_bytes(bytes: &'a [u8], signed: bool) -> Self;
+    fn from_list(list: Vec<Self>) -> Self;
+    fn from_map(map: Vec<(Self, Self)>) -> Self;
+}
+
+impl DecodedValue<'_> for Value {
+    fn from_value(value: Value) -> Self {
+        value
+    }
+
+    fn from_string(bytes: &[u8], decoding: StringDecoding) -> Result<Self, DecodeError> {
+        Ok(Value::String(Value::validate_utf8(
+            bytes.to_vec(),
+            decoding,
+        )?))
+    }
+
+    fn from_bytes(bytes: &[u8], signed: bool) -> Self {
+        if signed {
+            Value::I8List(bytes.iter().map(|b| *b as i8).collect())
+        } else {
+            Value::U8List(bytes.to_vec())
+        }
+    }
+
+    fn from_list(list: Vec<Self>) -> Self {
+        Value::List(list)
+    }
+
+    fn from_map(map: Vec<(Self, Self)>) -> Self {
+        Value::Map(map.into())
+    }
+}
+
+// Partially read value in `Encoder::read_value`.
+enum ReadFrame<T> {
+    // Items read so far and expected length.
+    List(Vec<T>, usize),
+    // Entries read so far, expected length and key of entry whose value is
+    // being read.
+    Map(Vec<(T, T)>, usize, Option<T>),
+    // Definition of shared value; the value is stored once read. Keeps
+    // position where the definition started, expanded bytes and depth at that
+    // point and deepest nesting reached before the definition.
+    Shared {
+        start: usize,
+        expanded_bytes: usize,
+        depth: usize,
+        outer_peak_depth: usize,
+    },
+}
+
+// Shared subtree together with its size when written in place and its
+// nesting depth, used to enforce decode limits on back-references.
+struct SharedValue<T> {
+    value: T,
+    encoded_len: usize,
+    depth: usize,
+}
+
+// Typed list element stored in native byte order.
+pub(crate) trait Element: Sized {
+    const SIZE: usize;
+    fn write(&self, buf: &mut Vec<u8>);
+    fn read(bytes: &[u8]) -> Self;
+}
+
+macro_rules! impl_element {
+    ($t:ty) => {
+        impl Element for $t {
+            const SIZE: usize = core::mem::size_of::<$t>();
+            fn write(&self, buf: &mut Vec<u8>) {
+                buf.extend_from_slice(&self.to_ne_bytes());
+            }
+            fn read(bytes: &[u8]) -> Self {
+                <$t>::from_ne_bytes(clone_into_array(bytes))
+            }
+        }
+    };
+}
+
+impl_element!(i8);
+impl_element!(u8);
+impl_element!(i16);
+impl_element!(u16);
+impl_element!(i32);
+impl_element!(u32);
+impl_element!(i64);
+impl_element!(f32);
+impl_element!(f64);
+
+impl Value {
+    /// Encodes the value into self-contained byte buffer that can be stored or
+    /// passed around and later turned back into value using [`Value::decode`].
+    ///
+    /// Panics if the value contains Dart objects, finalizable handles or shared
+    /// buffers.
+    pub fn encode(&self) -> Vec<u8> {
+        let mut buf = Vec::new();
+        Encoder::write_value(&mut Writer::new(&mut buf), self, false);
+        buf
+    }
+
+    /// Same as [`Value::encode`] except that map entries are ordered by their
+    /// encoded keys. Maps with same entries produce identical bytes regardless
+    /// of the order in which the entries were inserted, which makes the result
+    /// suitable for hashing or signing. Result can be decoded using
+    /// [`Value::decode`].
+    ///
+    /// Panics if the value contains Dart objects, finalizable handles or shared
+    /// buffers.
+    pub fn canonical_encode(&self) -> Vec<u8> {
+        let mut buf = Vec::new();
+        Encoder::write_value(&mut Writer::new(&mut buf), self, true);
+        buf
+    }
+
+    /// Same as [`Value::encode`] except that subtrees (lists, maps, strings and
+    /// typed lists) that occur more than once are only written once; every
+    /// further occurrence is replaced with back-reference to the first one.
+    /// Result can be decoded using [`Value::decode`], decoded value contains
+    /// copies of the repeated subtree. On Dart side (i.e. when sent through
+    /// [`MethodCallReply::send_raw_ok`](crate::MethodCallReply::send_raw_ok))
+    /// all occurrences decode to the same object.
+    ///
+    /// Since back-references are expanded, decoded value may be much larger
+    /// than the encoded buffer. Keep that in mind when decoding untrusted data.
+    ///
+    /// Panics if the value contains Dart objects, finalizable handles or shared
+    /// buffers.
+    #[cfg(feature = "std")]
+    pub fn encode_deduplicated(&self) -> Vec<u8> {
+        let ids = Encoder::subtree_ids(self);
+        let counts = Encoder::count_shared(self, &ids);
+        let mut buf = Vec::new();
+        Encoder::write_value_deduplicated(&mut Writer::new(&mut buf), self, &ids, &counts);
+        buf
+    }
+
+    /// Writes the same bytes as [`Value::encode`] into `writer`. Output is
+    /// written in chunks as the value is traversed, so the whole encoded value
+    /// is never held in memory. Strings and typed lists are copied piecewise.
+    ///
+    /// Panics if the value contains Dart objects, finalizable handles or shared
+    /// buffers.
+    #[cfg(feature = "std")]
+    pub async fn encode_async<W: AsyncWrite + Unpin>(&self, writer: W) -> std::io::Result<()> {
+        AsyncEncoder {
+            writer,
+            buf: Vec::new(),
+        }
+        .encode(self)
+        .await
+    }
+
+    /// Decodes value previously encoded with [`Value::encode`]. Unlike messages
+    /// received from Dart the buffer is not trusted; malformed input results in
+    /// [`DecodeError::Malformed`].
+    pub fn decode(buf: &[u8]) -> Result<Value, DecodeError> {
+        Self::decode_with_options(buf, &DecodeOptions::default())
+    }
+
+    /// Same as [`Value::decode`] but allows specifying how strings are decoded.
+    pub fn decode_with_options(buf: &[u8], options: &DecodeOptions) -> Result<Value, DecodeError> {
+        options.limits.check_total_bytes(buf.len())?;
+        let mut reader = Reader::new(buf);
+        let value = Encoder::read_value(&mut reader, options)?;
+        if reader.ended() {
+            Ok(value)
+        } else {
+            Err(DecodeError::Malformed)
+        }
+    }
+}
+
+// Preferred size of writes done by `Value::encode_async`.
+#[cfg(feature = "std")]
+const ASYNC_CHUNK_SIZE: usize = 64 * 1024;
+
+#[cfg(feature = "std")]
+struct AsyncEncoder<W> {
+    writer: W,
+    buf: Vec<u8>,
+}
+
+#[cfg(feature = "std")]
+enum EncodeFrame<'a> {
+    List(core::slice::Iter<'a, Value>),
+    // Holds value of the entry whose key has just been written.
+    Map(core::slice::Iter<'a, (Value, Value)>, Option<&'a Value>),
+}
+
+#[cfg(feature = "std")]
+impl<W: AsyncWrite + Unpin> AsyncEncoder<W> {
+    // Lists and maps are traversed using explicit stack so that the future
+    // doesn't need to be boxed for nested values.
+    async fn encode(mut self, value: &Value) -> std::io::Result<()> {
+        let mut stack = Vec::<EncodeFrame>::new();
+        let mut next = Some(value);
+        loop {
+            let value = match next.take() {
+                Some(value) => value,
+                None => match stack.last_mut() {
+                    None => break,
+                    Some(EncodeFrame::List(iter)) => match iter.next() {
+                        Some(value) => value,
+                        None => {
+                            stack.pop();
+                            continue;
+                        }
+                    },
+                    Some(EncodeFrame::Map(iter, pending)) => match pending.take() {
+                        Some(value) => value,
+                        None => match iter.next() {
+                            Some((key, value)) => {
+                                *pending = Some(value);
+                                key
+                            }
+                            None => {
+                                stack.pop();
+                                continue;
+                            }
+                        },
+                    },
+                },
+            };
+            match value {
+                Value::List(list) => {
+                    self.write_header(VALUE_LIST, list.len());
+                    stack.push(EncodeFrame::List(list.iter()));
+                }
+                Value::Map(map) => {
+                    self.write_header(VALUE_MAP, map.len());
+                    stack.push(EncodeFrame::Map(map.iter(), None));
+                }
+                Value::String(s) => {
+                    self.write_header(VALUE_SMALL_STRING, s.len());
+                    self.write_list(s.as_bytes()).await?;
+                }
+                Value::I8List(v) => self.write_typed_list(VALUE_INT8LIST, v).await?,
+                Value::U8List(v) => self.write_typed_list(VALUE_UINT8LIST, v).await?,
+                Value::I16List(v) => self.write_typed_list(VALUE_INT16LIST, v).await?,
+                Value::U16List(v) => self.write_typed_list(VALUE_UINT16LIST, v).await?,
+                Value::I32List(v) => self.write_typed_list(VALUE_INT32LIST, v).await?,
+                Value::U32List(v) => self.write_typed_list(VALUE_UINT32LIST, v).await?,
+                Value::I64List(v) => self.write_typed_list(VALUE_INT64LIST, v).await?,
+                Value::F32List(v) => self.write_typed_list(VALUE_FLOAT32LIST, v).await?,
+                Value::F64List(v) => self.write_typed_list(VALUE_FLOAT64LIST, v).await?,
+                Value::BoolList(v) => {
+                    self.write_header(VALUE_UINT8LIST, v.len());
+                    for chunk in v.chunks(ASYNC_CHUNK_SIZE) {
+                        self.buf.extend(chunk.iter().map(|b| *b as u8));
+                        self.flush_aligned().await?;
+                    }
+                }
+                value => Encoder::write_value(&mut Writer::new(&mut self.buf), value, false),
+            }
+            if self.buf.len() >= ASYNC_CHUNK_SIZE {
+                self.flush_aligned().await?;
+            }
+        }
+        self.writer.write_all(&self.buf).await?;
+        self.writer.flush().await
+    }
+
+    fn write_header(&mut self, tag: u8, len: usize) {
+        let mut writer = Writer::new(&mut self.buf);
+        writer.write_u8(tag);
+        writer.write_size(len);
+    }
+
+    async fn write_typed_list<T: Element>(&mut self, tag: u8, list: &[T]) -> std::io::Result<()> {
+        self.write_header(tag, list.len());
+        Writer::new(&mut self.buf).align_to(T::SIZE);
+        self.write_list(list).await
+    }
+
+    async fn write_list<T: Element>(&mut self, list: &[T]) -> std::io::Result<()> {
+        for chunk in list.chunks(ASYNC_CHUNK_SIZE / T::SIZE) {
+            chunk.iter().for_each(|v| v.write(&mut self.buf));
+            self.flush_aligned().await?;
+        }
+        Ok(())
+    }
+
+    // Alignment is computed from buffer length; writing only multiples of 8
+    // keeps buffer length congruent with position in the output.
+    async fn flush_aligned(&mut self) -> std::io::Result<()> {
+        let len = self.buf.len() - self.buf.len() % 8;
+        if len > 0 {
+            self.writer.write_all(&self.buf[..len]).await?;
+            self.buf.drain(..len);
+        }
+        Ok(())
+    }
+}
+
+// Decodes value from the beginning of `buf`, which may contain more data.
+// Returns the value and number of bytes consumed.
+#[cfg(feature = "mmap")]
+pub(crate) fn decode_prefix<'a, T: DecodedValue<'a>>(
+    buf: &'a [u8],
+    options: &DecodeOptions,
+) -> Result<(T, usize), DecodeError> {
+    let mut reader = Reader::new(buf);
+    let value = Encoder::read_value(&mut reader, options)?;
+    options.limits.check_total_bytes(reader.pos)?;
+    Ok((value, reader.pos))
+}
+
+pub(crate) struct Writer<'a>(pub(crate) &'a mut Vec<u8>);
+
+#[allow(unused)]
+impl<'a> Writer<'a> {
+    pub(crate) fn new(v: &'a mut Vec<u8>) -> Self {
+        Writer(v)
+    }
+    pub(crate) fn write_u8(&mut self, n: u8) {
+        self.0.push(n);
+    }
+    pub(crate) fn write_u16(&mut self, n: u16) {
+        self.0.extend_from_slice(&n.to_ne_bytes());
+    }
+    pub(crate) fn write_u32(&mut self, n: u32) {
+        self.0.extend_from_slice(&n.to_ne_bytes());
+    }
+    pub(crate) fn write_i32(&mut self, n: i32) {
+        self.0.extend_from_slice(&n.to_ne_bytes());
+    }
+    pub(crate) fn write_u64(&mut self, n: u64) {
+        self.0.extend_from_slice(&n.to_ne_bytes());
+    }
+    pub(crate) fn write_i64(&mut self, n: i64) {
+        self.0.extend_from_slice(&n.to_ne_bytes());
+    }
+    pub(crate) fn write_f64(&mut self, n: f64) {
+        self.write_u64(n.to_bits());
+    }
+    pub(crate) fn write_size(&mut self, n: usize) {
+        if n < 254 {
+            self.write_u8(n as u8);
+        } else if n <= u16::MAX as usize {
+            self.write_u8(254);
+            self.write_u16(n as u16);
+        } else if n < u32::MAX as usize {
+            self.write_u8(255);
+            self.write_u32(n as u32);
+        } else {
+            // flutter only support 32 bit value
+            panic!("Not implemented");
+        }
+    }
+    pub(crate) fn write_string(&mut self, s: &str) {
+        self.0.extend_from_slice(s.as_bytes());
+    }
+    pub(crate) fn align_to(&mut self, align: usize) {
+        let m = self.0.len() % align;
+        if m == 0 {
+            return;
+        }
+        let m = align - m;
+        for _ in 0..m {
+            self.write_u8(0);
+        }
+    }
+}
+
+pub(crate) fn clone_into_array<A, T>(slice: &[T]) -> A
+where
+    A: Default + AsMut<[T]>,
+    T: Clone,
+{
+    let mut a = A::default();
+    a.as_mut().clone_from_slice(slice);
+    a
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{DecodeError, DecodeLimits, DecodeOptions, StringDecoding, Value};
+
+    use super::{VALUE_LIST, VALUE_SMALL_STRING, VALUE_STRING, VALUE_UINT32LIST};
+
+    #[test]
+    fn test_encode() {
+        let value = Value::List(vec![
+            Value::Null,
+            true.into(),
+            10.into(),
+            (-1).into(),
+            1000.into(),
+            Value::I32(10),
+            Value::U32(u32::MAX),
+            1.5.into(),
+            "Hello".into(),
+            "x".repeat(100).into(),
+            Value::U8List(vec![1, 2, 3]),
+            Value::I32List(vec![-1, 2]),
+            Value::F64List(vec![1.0, 2.5]),
+            Value::Map(vec![("a".into(), Value::I16List(vec![5]))].into()),
+        ]);
+        let encoded = value.encode();
+        assert_eq!(Value::decode(&encoded), Ok(value));
+
+        // typed list data is aligned to element size
+        let encoded = Value::List(vec![1.into(), Value::F32List(vec![1.0])]).encode();
+        assert_eq!(encoded.len(), 12);
+
+        assert_eq!(
+            Value::decode(&encoded[..encoded.len() - 1]),
+            Err(DecodeError::Malformed)
+        );
+        assert_eq!(Value::decode(&[VALUE_STRING]), Err(DecodeError::Malformed));
+
+        // 32-bit integers use extended tags; all small integers are still inline.
+        assert_eq!(Value::decode(&[236]), Ok(Value::I64(236)));
+        assert_eq!(Value::I64(237).encode(), vec![237]);
+        let encoded = Value::I32(5).encode();
+        assert_eq!(encoded[..6], [VALUE_STRING, 255, 0xFF, 0xFF, 0xFF, 0xFF]);
+        assert_eq!(Value::decode(&encoded), Ok(Value::I32(5)));
+        for value in [Value::i32(i32::MIN), Value::i32(-1), Value::u32(u32::MAX)] {
+            assert_eq!(Value::decode(&value.encode()).as_ref(), Ok(&value));
+        }
+        assert_eq!(Value::from(5i32), Value::I64(5));
+        let mut unknown = encoded.clone();
+        unknown[6] = 0xFF;
+        assert_eq!(Value::decode(&unknown), Err(DecodeError::Malformed));
+        assert_eq!(
+            Value::decode(&encoded[..encoded.len() - 1]),
+            Err(DecodeError::Malformed)
+        );
+        assert_eq!(
+            Value::decode(&[VALUE_SMALL_STRING, 2, b'a', 0xFF]),
+            Err(DecodeError::InvalidUtf8)
+        );
+        let options = DecodeOptions {
+            string_decoding: StringDecoding::Lossy,
+            ..Default::default()
+        };
+        assert_eq!(
+            Value::decode_with_options(&[VALUE_SMALL_STRING, 2, b'a', 0xFF], &options),
+            Ok("a\u{FFFD}".into())
+        );
+    }
+
+    #[test]
+    fn test_encode_deduplicated() {
+        let config = Value::Map(
+            vec![
+                ("name".into(), "shared configuration".into()),
+                ("values".into(), Value::F64List(vec![1.0, 2.0, 3.0])),
+            ]
+            .into(),
+        );
+        let value = Value::List(vec![
+            Value::Map(vec![("config".into(), config.clone())].into()),
+            Value::Map(vec![("config".into(), config.clone())].into()),
+            config.clone(),
+            Value::F64List(vec![1.0, 2.0, 3.0]),
+            Value::F32List(vec![1.0]),
+        ]);
+        let encoded = value.encode_deduplicated();
+        assert!(encoded.len() < value.encode().len());
+        assert_eq!(Value::decode(&encoded), Ok(value));
+
+        // Nothing repeated, encoding is same as without deduplication.
+        let value = Value::List(vec!["abc".into(), Value::I32List(vec![1])]);
+        assert_eq!(value.encode_deduplicated(), value.encode());
+
+        // Reference to undefined subtree.
+        assert_eq!(
+            Value::decode(&[VALUE_LIST, 1, VALUE_STRING, 1]),
+            Err(DecodeError::Malformed)
+        );
+    }
+
+    #[test]
+    fn test_encode_deduplicated_deep() {
+        const DEPTH: usize = 100_000;
+        let mut shared = Value::String("leaf".into());
+        for _ in 0..DEPTH {
+            shared = Value::List(vec![shared, 1.into()]);
+        }
+        let value = Value::List(vec![
+            shared.clone(),
+            Value::Map(vec![("shared".into(), shared.clone())].into()),
+            shared,
+        ]);
+        let encoded = value.encode_deduplicated();
+        // Shared subtree is written once, followed by two back-references.
+        assert!(encoded.len() < value.encode().len() / 2);
+        assert_eq!(Value::decode(&encoded), Ok(value));
+    }
+
+    #[test]
+    fn test_encode_bool_list() {
+        let bools: Vec<bool> = (0..10000).map(|i| i % 3 == 0).collect();
+        let list: Value = bools.clone().into();
+        assert!(matches!(list, Value::List(_)));
+        let value = Value::bool_list(bools.clone());
+        assert!(matches!(value, Value::BoolList(_)));
+
+        // Received as U8List, but still convertible to Vec<bool>.
+        let encoded = value.encode();
+        let decoded = Value::decode(&encoded).unwrap();
+        assert!(matches!(decoded, Value::U8List(_)));
+        let decoded: Vec<bool> = decoded.try_into().unwrap();
+        assert_eq!(decoded, bools);
+
+        // One byte per element, values are not bit-packed.
+        let bytes: Vec<u8> = bools.iter().map(|b| *b as u8).collect();
+        assert_eq!(encoded, Value::U8List(bytes).encode());
+        let header_len = encoded.len() - bools.len();
+        assert!(header_len < 8);
+        // Never larger than list of Bool on the wire, while in memory each
+        // element takes a byte instead of a whole Value.
+        assert!(encoded.len() <= list.encode().len());
+        assert!(
+            bools.len() * core::mem::size_of::<bool>() * 8
+                <= bools.len() * core::mem::size_of::<Value>()
+        );
+    }
+
+    #[test]
+    fn test_decode_limits() {
+        let options = DecodeOptions {
+            limits: DecodeLimits {
+                max_total_bytes: Some(64),
+                max_collection_length: Some(1000),
+                max_depth: Some(2),
+            },
+            ..Default::default()
+        };
+        // Header claiming a billion elements.
+        let mut buf = vec![VALUE_LIST, 255];
+        buf.extend_from_slice(&1_000_000_000u32.to_ne_bytes());
+        assert_eq!(
+            Value::decode_with_options(&buf, &options),
+            Err(DecodeError::LimitExceeded)
+        );
+        buf[0] = VALUE_UINT32LIST;
+        assert_eq!(
+            Value::decode_with_options(&buf, &options),
+            Err(DecodeError::LimitExceeded)
+        );
+        // Without limits this is caught by the size check before allocating.
+        assert_eq!(Value::decode(&buf), Err(DecodeError::Malformed));
+
+        let nested = Value::List(vec![Value::List(vec![1.into()])]);
+        assert_eq!(
+            Value::decode_with_options(&nested.encode(), &options),
+            Ok(nested.clone())
+        );
+        let nested = Value::List(vec![nested]);
+        assert_eq!(
+            Value::decode_with_options(&nested.encode(), &options),
+            Err(DecodeError::LimitExceeded)
+        );
+
+        let large = Value::U8List(vec![0; 100]);
+        assert_eq!(
+            Value::decode_with_options(&large.encode(), &options),
+            Err(DecodeError::LimitExceeded)
+        );
+    }
+
+    #[test]
+    fn test_decode_limits_shared() {
+        // Every level references the previous one twice, so the decoded value
+        // doubles in size with each level while the input grows linearly.
+        let chain = |levels: usize| {
+            let mut value = Value::List(vec!["shared".into()]);
+            for _ in 0..levels {
+                value = Value::List(vec![value.clone(), value]);
+            }
+            value
+        };
+        let value = chain(20);
+        let encoded = value.encode_deduplicated();
+        assert!(encoded.len() < 300);
+
+        let options = |limits: DecodeLimits| DecodeOptions {
+            limits,
+            ..Default::default()
+        };
+        let bytes_limited = options(DecodeLimits {
+            max_total_bytes: Some(1024),
+            ..Default::default()
+        });
+        assert_eq!(
+            Value::decode_with_options(&encoded, &bytes_limited),
+            Err(DecodeError::LimitExceeded)
+        );
+        let depth_limited = options(DecodeLimits {
+            max_depth: Some(3),
+            ..Default::default()
+        });
+        assert_eq!(
+            Value::decode_with_options(&encoded, &depth_limited),
+            Err(DecodeError::LimitExceeded)
+        );
+
+        // Expanded value within limits decodes.
+        let value = chain(3);
+        let encoded = value.encode_deduplicated();
+        assert!(encoded.len() < value.encode().len());
+        let limits = DecodeLimits {
+            max_total_bytes: Some(2 * value.encode().len()),
+            max_collection_length: Some(2),
+            max_depth: Some(4),
+        };
+        assert_eq!(
+            Value::decode_with_options(&encoded, &options(limits.clone())),
+            Ok(value.clone())
+        );
+        // Limit that fits the input but not the expanded value.
+        let limits = DecodeLimits {
+            max_total_bytes: Some(encoded.len()),
+            ..limits
+        };
+        assert_eq!(
+            Value::decode_with_options(&encoded, &options(limits.clone())),
+            Err(DecodeError::LimitExceeded)
+        );
+        let limits = DecodeLimits {
+            max_total_bytes: None,
+            max_depth: Some(3),
+            ..limits
+        };
+        assert_eq!(
+            Value::decode_with_options(&encoded, &options(limits)),
+            Err(DecodeError::LimitExceeded)
+        );
+    }
+
+    #[test]
+    fn test_deeply_nested() {
+        const DEPTH: usize = 100_000;
+        let mut value = Value::Null;
+        for i in 0..DEPTH {
+            value = if i % 2 == 0 {
+                Value::List(vec![value])
+            } else {
+                Value::Map(vec![("key".into(), value)].into())
+            };
+        }
+        assert_eq!(value.max_depth(), DEPTH);
+
+        let encoded = value.encode();
+        assert_eq!(value.canonical_encode(), encoded);
+        let decoded = Value::decode(&encoded).unwrap();
+        assert_eq!(decoded.max_depth(), DEPTH);
+        assert_eq!(decoded, value);
+        assert_eq!(decoded.encode(), encoded);
+
+        let cloned = value.clone();
+        assert_eq!(cloned, value);
+        let mut other = Value::Null;
+        for i in 0..DEPTH {
+            other = if i % 2 == 0 {
+                Value::List(vec![other])
+            } else {
+                Value::Map(vec![("other".into(), other)].into())
+            };
+        }
+        assert_ne!(other, value);
+        assert_eq!(format!("{:?}", value).matches("Map(").count(), DEPTH / 2);
+
+        let options = DecodeOptions {
+            limits: DecodeLimits {
+                max_depth: Some(DEPTH - 1),
+                ..Default::default()
+            },
+            ..Default::default()
+        };
+        assert_eq!(
+            Value::decode_with_options(&encoded, &options),
+            Err(DecodeError::LimitExceeded)
+        );
+    }
+
+    #[test]
+    fn test_canonical_encode() {
+        // Keys that compare as equal keep their insertion order in map, so
+        // regular encoding depends on it.
+        let entries = vec![
+            (Value::F64(0.0), Value::from("a")),
+            (Value::F64(-0.0), Value::from("b")),
+        ];
+        let mut reversed = entries.clone();
+        reversed.reverse();
+        let v1 = Value::List(vec!["x".into(), Value::Map(entries.into())]);
+        let v2 = Value::List(vec!["x".into(), Value::Map(reversed.into())]);
+        assert_ne!(v1.encode(), v2.encode());
+        assert_eq!(v1.canonical_encode(), v2.canonical_encode());
+
+        let nested = |entries: Vec<(Value, Value)>| {
+            Value::Map(
+                vec![
+                    ("b".into(), Value::Map(entries.into())),
+                    ("a".into(), Value::F64List(vec![1.0, 2.0])),
+                ]
+                .into(),
+            )
+        };
+        let m1 = nested(vec![("y".into(), 1.into()), ("x".into(), 2.into())]);
+        let m2 = nested(vec![("x".into(), 2.into()), ("y".into(), 1.into())]);
+        assert_eq!(m1.canonical_encode(), m2.canonical_encode());
+        assert_eq!(Value::decode(&m1.canonical_encode()).unwrap(), m1);
+
+        // Keys and values that need alignment are written at other offsets
+        // than they were encoded at for sorting.
+        let aligned = |entries: Vec<(Value, Value)>| {
+            Value::List(vec![
+                "abc".into(),
+                Value::Map(entries.into()),
+                Value::F64(3.0),
+            ])
+        };
+        let entries = vec![
+            (Value::F64(1.0), Value::F64List(vec![1.0])),
+            (Value::F64(1.0), Value::F64List(vec![0.5])),
+            (Value::F64List(vec![2.0]), Value::F32List(vec![2.0])),
+            ("key".into(), Value::F64(4.0)),
+        ];
+        let mut reversed = entries.clone();
+        reversed.reverse();
+        let a1 = aligned(entries);
+        let a2 = aligned(reversed);
+        let encoded = a1.canonical_encode();
+        assert_eq!(a2.canonical_encode(), encoded);
+        let decoded = Value::decode(&encoded).unwrap();
+        assert_eq!(decoded.canonical_encode(), encoded);
+    }
+
+    #[test]
+    fn test_encode_async() {
+        use std::{
+            cell::RefCell,
+            io,
+            pin::Pin,
+            rc::Rc,
+            task::{Context, Poll},
+        };
+
+        use futures::{io::AsyncWrite, FutureExt};
+
+        use super::ASYNC_CHUNK_SIZE;
+
+        // Accepts at most 1000 bytes per write; records largest write requested.
+        #[derive(Default)]
+        struct Output {
+            data: Vec<u8>,
+            max_write: usize,
+        }
+
+        struct TestWriter(Rc<RefCell<Output>>);
+
+        impl AsyncWrite for TestWriter {
+            fn poll_write(
+                self: Pin<&mut Self>,
+                _cx: &mut Context<'_>,
+                buf: &[u8],
+            ) -> Poll<io::Result<usize>> {
+                let mut output = self.0.borrow_mut();
+                output.max_write = output.max_write.max(buf.len());
+                let len = buf.len().min(1000);
+                output.data.extend_from_slice(&buf[..len]);
+                Poll::Ready(Ok(len))
+            }
+
+            fn poll_flush(self: Pin<&mut Self>, _cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+                Poll::Ready(Ok(()))
+            }
+
+            fn poll_close(self: Pin<&mut Self>, _cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+                Poll::Ready(Ok(()))
+            }
+        }
+
+        let value = Value::List(vec![
+            "a".into(),
+            1.5f64.into(),
+            Value::U8List((0..200_000).map(|i| i as u8).collect()),
+            vec![("key".into(), Value::F64List(vec![1.0; 50_000]))].into(),
+            "x".repeat(100_000).into(),
+            Value::BoolList(vec![true; 3]),
+            Value::I16List(vec![1, 2, 3]),
+            Value::List(vec![]),
+            Value::Null,
+        ]);
+        let expected = value.encode();
+
+        let output = Rc::new(RefCell::new(Output::default()));
+        let writer = TestWriter(output.clone());
+        // Writer never returns pending so the future completes on first poll.
+        value.encode_async(writer).now_or_never().unwrap().unwrap();
+        let output = output.borrow();
+        assert_eq!(output.data, expected);
+        assert!(output.max_write <= ASYNC_CHUNK_SIZE + 16);
+    }
+}
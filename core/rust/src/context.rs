@@ -2,14 +2,17 @@ use std::{
     any::{Any, TypeId},
     cell::{Ref, RefCell},
     collections::HashMap,
+    future::Future,
     rc::Rc,
+    time::Duration,
 };
 
 use crate::{
+    finalizable_handle::FinalizableHandleState,
     message_channel::nativeshell_init_message_channel_context, util::black_box, GetMessageChannel,
 };
 
-use super::RunLoop;
+use super::{Elapsed, Handle, RunLoop, RunLoopSender};
 
 pub struct Context {
     internal: Rc<ContextInternal>,
@@ -39,14 +42,22 @@ impl Context {
             internal,
             outermost: true,
         };
-        ffi_methods();
         let result = res.clone();
-        let prev_context = CURRENT_CONTEXT.with(|c| c.replace(Some(res)));
-        if prev_context.is_some() {
+        if CURRENT_CONTEXT.with(|c| c.borrow().is_some()) {
             panic!("another context is already associated with current thread.");
         }
+        CURRENT_CONTEXT.with(|c| c.replace(Some(res)));
         CURRENT_CONTEXT_FALLBACK.with(|c| c.replace(Some(res_fallback)));
+        // If initialization panics the thread locals must not be left behind,
+        // otherwise no other context could be created on this thread.
+        let guard = InitGuard {};
+        ffi_methods();
         result.message_channel();
+        #[cfg(feature = "mock")]
+        if POISON_NEXT_INIT.with(|p| p.replace(false)) {
+            panic!("context initialization poisoned");
+        }
+        std::mem::forget(guard);
         result
     }
 
@@ -54,6 +65,61 @@ impl Context {
         &self.internal.run_loop
     }
 
+    /// Shortcut for `context.run_loop().new_sender()`.
+    pub fn run_loop_sender(&self) -> RunLoopSender {
+        self.run_loop().new_sender()
+    }
+
+    /// Shortcut for `context.run_loop().on_idle_for(duration, callback)`. See
+    /// [`RunLoop::on_idle_for`].
+    #[must_use]
+    pub fn on_idle_for<F>(&self, duration: Duration, callback: F) -> Handle
+    where
+        F: FnMut() + 'static,
+    {
+        self.run_loop().on_idle_for(duration, callback)
+    }
+
+    /// Returns attachment of type `T`, creating it with `on_init` if this is the
+    /// first time it is requested. There is at most one attachment per type and
+    /// it lives until the context is dropped.
+    ///
+    /// This is the intended way for plugins to keep per-context singletons.
+    /// The plugin should expose the attachment through an extension trait,
+    /// same as [`GetMessageChannel`] does for [`MessageChannel`](crate::MessageChannel):
+    ///
+    /// ```
+    /// use std::cell::Ref;
+    /// use nativeshell_core::{Context, GetMessageChannel};
+    ///
+    /// pub struct MyPlugin {}
+    ///
+    /// impl MyPlugin {
+    ///     fn new() -> Self {
+    ///         // Other attachments may be used here.
+    ///         let context = Context::get();
+    ///         let _channel = context.message_channel();
+    ///         Self {}
+    ///     }
+    /// }
+    ///
+    /// pub trait GetMyPlugin {
+    ///     fn my_plugin(&self) -> Ref<MyPlugin>;
+    /// }
+    ///
+    /// impl GetMyPlugin for Context {
+    ///     fn my_plugin(&self) -> Ref<MyPlugin> {
+    ///         self.get_attachment(MyPlugin::new)
+    ///     }
+    /// }
+    /// ```
+    ///
+    /// Attachments are dropped in reverse order of creation. Attachments
+    /// requested from `on_init` are created first, so they are still available
+    /// when the attachment that requested them is dropped. The message channel
+    /// is created together with the context and is dropped last.
+    ///
+    /// `on_init` must not request attachment of the same type.
     pub fn get_attachment<T: Any, F: FnOnce() -> T>(&self, on_init: F) -> Ref<T> {
         let id = TypeId::of::<T>();
         // Do a separate check here, make sure attachments is not borrowed while
@@ -108,6 +174,44 @@ impl Context {
         });
         context.run_loop().run();
     }
+
+    #[cfg(feature = "mock")]
+    /// Helper function for unit tests. Makes next [`Context::new`] on current
+    /// thread panic after attachments have been initialized.
+    pub fn poison_next_init() {
+        POISON_NEXT_INIT.with(|p| p.set(true));
+    }
+}
+
+struct InitGuard {}
+
+impl Drop for InitGuard {
+    fn drop(&mut self) {
+        // Take both before dropping; dropping the outermost context removes
+        // attachments and may access the thread locals.
+        let context = CURRENT_CONTEXT.try_with(|c| c.take()).ok().flatten();
+        let fallback = CURRENT_CONTEXT_FALLBACK
+            .try_with(|c| c.take())
+            .ok()
+            .flatten();
+        drop(context);
+        drop(fallback);
+    }
+}
+
+/// Returns sender for run loop of context associated with current thread.
+/// Panics if there is no such context (see [`Context::get()`]).
+pub fn current_sender() -> RunLoopSender {
+    Context::get().run_loop_sender()
+}
+
+/// Shortcut for [`RunLoop::with_timeout`] on run loop of context associated
+/// with current thread. Panics if there is no such context.
+pub fn with_timeout<F: Future>(
+    future: F,
+    duration: Duration,
+) -> impl Future<Output = Result<F::Output, Elapsed>> {
+    Context::get().run_loop().with_timeout(future, duration)
 }
 
 thread_local! {
@@ -115,9 +219,18 @@ thread_local! {
     static CURRENT_CONTEXT_FALLBACK: RefCell<Option<Context>> = RefCell::new(None);
 }
 
+#[cfg(feature = "mock")]
+thread_local! {
+    static POISON_NEXT_INIT: std::cell::Cell<bool> = const { std::cell::Cell::new(false) };
+}
+
 impl Drop for Context {
     fn drop(&mut self) {
         if self.outermost {
+            // Finalize outstanding handles while message channel and run loop
+            // are still available.
+            FinalizableHandleState::finalize_on_shutdown();
+
             // Remove attachment in reverse order in which they were inserted
             while self.internal.attachments.borrow().len() > 0 {
                 let to_remove_index = self.internal.attachments.borrow().len() - 1;
@@ -2,17 +2,46 @@
 /// We need these traits to be specialized for <T> and Option<T>, see
 /// https://lukaskalbertodt.github.io/2019/12/05/generalized-autoref-based-specialization.html
 /// for details on how this works.
-use std::{
+use alloc::{format, vec::Vec};
+use core::{
     convert::{TryFrom, TryInto},
+    marker::PhantomData,
+    mem,
     result::Result,
 };
 
-use crate::{TryFromError, Value};
+use alloc::string::String;
+
+use crate::{TryFromError, Value, ValueTupleList};
 
 pub struct WrapMut<'a, T>(pub &'a mut T);
 
 pub struct Wrap<'a, T>(pub &'a T);
 
+// Value implements Drop so generated code can't move payload out of it by
+// pattern matching. Other values are returned back as error.
+
+pub fn into_map(mut value: Value) -> Result<ValueTupleList, Value> {
+    match &mut value {
+        Value::Map(map) => Ok(mem::take(map)),
+        _ => Err(value),
+    }
+}
+
+pub fn into_list(mut value: Value) -> Result<Vec<Value>, Value> {
+    match &mut value {
+        Value::List(list) => Ok(mem::take(list)),
+        _ => Err(value),
+    }
+}
+
+pub fn into_string(mut value: Value) -> Result<String, Value> {
+    match &mut value {
+        Value::String(string) => Ok(mem::take(string)),
+        _ => Err(value),
+    }
+}
+
 pub trait Assign {
     fn assign(&mut self, value: Value, skip_if_empty: bool) -> Result<(), TryFromError>;
     fn set_optional_to_none(&mut self);
@@ -81,3 +110,84 @@ impl<'a, T> IsNone for &Wrap<'a, Option<T>> {
         self.0.is_none()
     }
 }
+
+/// Appends entries of flattened field to the map of parent struct. Panics if
+/// the value is not a map or if any of its keys is already present in parent
+/// map (regular fields and earlier flattened fields).
+pub fn flatten_into(target: &mut Vec<(Value, Value)>, mut value: Value) {
+    match &mut value {
+        Value::Map(entries) => {
+            for (key, value) in mem::take(entries) {
+                if target.iter().any(|e| e.0 == key) {
+                    panic!("duplicate key {:?} in flattened field.", key);
+                }
+                target.push((key, value));
+            }
+        }
+        Value::Null => {}
+        _ => panic!(
+            "flattened field must convert to Value::Map, got {:?}",
+            value
+        ),
+    }
+}
+
+/// Implemented by `TryFromValue` for structs; returns keys consumed by the
+/// struct when used as flattened field or `None` if the struct accepts
+/// arbitrary entries (i.e. it contains flattened map itself).
+pub trait FlattenKeys {
+    fn flatten_keys() -> Option<Vec<&'static str>>;
+}
+
+pub struct Keys<T>(pub PhantomData<T>);
+
+pub trait KnownKeys {
+    fn known_keys(&self) -> Option<Vec<&'static str>>;
+}
+
+impl<T: FlattenKeys> KnownKeys for Keys<T> {
+    fn known_keys(&self) -> Option<Vec<&'static str>> {
+        T::flatten_keys()
+    }
+}
+
+impl<T> KnownKeys for &Keys<T> {
+    fn known_keys(&self) -> Option<Vec<&'static str>> {
+        None
+    }
+}
+
+/// Returns error if keys of flattened fields conflict with each other or with
+/// keys of regular fields.
+pub fn check_flattened_keys(
+    fields: &[&str],
+    flattened: &[Option<Vec<&'static str>>],
+) -> Result<(), TryFromError> {
+    let mut seen = fields.to_vec();
+    for key in flattened.iter().flatten().flatten() {
+        if seen.contains(key) {
+            return Err(TryFromError::OtherError(format!(
+                "duplicate key \"{}\" in flattened field.",
+                key
+            )));
+        }
+        seen.push(key);
+    }
+    Ok(())
+}
+
+/// Removes entries for flattened field from `entries`. Field with known keys
+/// only takes its own entries, otherwise all remaining entries are taken.
+pub fn take_flattened(entries: &mut Vec<(Value, Value)>, keys: Option<&[&str]>) -> Value {
+    let taken = match keys {
+        Some(keys) => {
+            let (taken, rest) = core::mem::take(entries)
+                .into_iter()
+                .partition(|e| matches!(&e.0, Value::String(key) if keys.contains(&key.as_str())));
+            *entries = rest;
+            taken
+        }
+        None => core::mem::take(entries),
+    };
+    Value::Map(taken.into())
+}
@@ -4,13 +4,15 @@ use std::{
         atomic::{AtomicIsize, Ordering},
         Mutex, MutexGuard,
     },
+    task::{Poll, Waker},
 };
 
+use futures::{future::poll_fn, Future};
 use once_cell::sync::OnceCell;
 
 use crate::{
-    ffi::DartWeakPersistentHandle, util::Capsule, Context, GetMessageChannel, IsolateId,
-    RUN_LOOP_SENDER,
+    ffi::DartWeakPersistentHandle, isolate_run_loop_sender, util::Capsule, Context,
+    GetMessageChannel, IsolateId,
 };
 
 ///
@@ -57,6 +59,7 @@ impl FinalizableHandle {
                 handle: None,
                 isolate_id,
                 external_size,
+                attach_wakers: Vec::new(),
                 finalizer: Some(Capsule::new_with_sender(
                     Box::new(finalizer),
                     Context::get().run_loop().new_sender(),
@@ -66,6 +69,12 @@ impl FinalizableHandle {
         Self { id }
     }
 
+    /// Identifier of this handle. Same identifier is used by the Dart
+    /// `FinalizableHandle` counterpart.
+    pub fn id(&self) -> isize {
+        self.id
+    }
+
     /// Whether this handle is attached to a Dart object. This will be `false`
     /// initially and becomes `true` once the Finalizable handle is send to Dart.
     /// `false` after the Dart counterpart gets garbage collected.
@@ -78,6 +87,29 @@ impl FinalizableHandle {
             .unwrap_or(false)
     }
 
+    /// Returns future that resolves with target isolate once this handle gets
+    /// attached to a Dart object (i.e. after it has been sent to Dart). Resolves
+    /// immediately if the handle is already attached.
+    ///
+    /// The future never resolves if the handle is finalized without being
+    /// attached, for example when the target isolate is destroyed.
+    pub fn attached(&self) -> impl Future<Output = IsolateId> {
+        let id = self.id;
+        poll_fn(move |cx| {
+            let mut state = FinalizableHandleState::get();
+            match state.objects.get_mut(&id) {
+                Some(object) if object.handle.is_some() => Poll::Ready(object.isolate_id),
+                Some(object) => {
+                    if !object.attach_wakers.iter().any(|w| w.will_wake(cx.waker())) {
+                        object.attach_wakers.push(cx.waker().clone());
+                    }
+                    Poll::Pending
+                }
+                None => Poll::Pending,
+            }
+        })
+    }
+
     /// Whether the Dart object was already garbage collected finalized.
     pub fn is_finalized(&self) -> bool {
         let state = FinalizableHandleState::get();
@@ -96,28 +128,28 @@ impl FinalizableHandle {
                 // The actual dart method to update isolate size must be called from
                 // Dart thread, so we ask message channel to relay the request,
                 // which should result in a call to 'update_persistent_handle_size'.
-                RUN_LOOP_SENDER
-                    .get()
-                    .expect("MessageChannel was not initialized!")
-                    .send(move || {
-                        Context::get()
-                            .message_channel()
-                            .request_update_external_size(isolate_id, handle);
-                    });
+                isolate_run_loop_sender(isolate_id).send(move || {
+                    Context::get()
+                        .message_channel()
+                        .request_update_external_size(isolate_id, handle);
+                });
             }
         }
     }
 
     #[cfg(feature = "mock")]
-    /// Allows simulating object finalizers
+    /// Allows simulating object finalizers. The finalizer is scheduled on run
+    /// loop, unless immediate finalization is enabled for current thread.
     pub fn finalize(&self) {
-        let mut state = FinalizableHandleState::get();
-        let mut object = state.objects.remove(&self.id);
-        if let Some(mut object) = object.take() {
+        let object = FinalizableHandleState::get().objects.remove(&self.id);
+        if let Some(mut object) = object {
+            object.handle.take();
             if let Some(mut finalizer) = object.finalizer.take() {
-                let sender = RUN_LOOP_SENDER
-                    .get()
-                    .expect("MessageChannel was not initialized!");
+                if IMMEDIATE_FINALIZATION.with(|f| f.get()) {
+                    finalizer.take().unwrap()();
+                    return;
+                }
+                let sender = isolate_run_loop_sender(object.isolate_id);
                 sender.send(move || {
                     let finalizer = finalizer.take().unwrap();
                     finalizer();
@@ -125,6 +157,15 @@ impl FinalizableHandle {
             }
         }
     }
+
+    #[cfg(feature = "mock")]
+    /// When enabled, [`FinalizableHandle::finalize`] invokes the finalizer
+    /// synchronously instead of scheduling it on run loop, so tests can assert
+    /// that the finalizer ran without pumping the run loop. Only affects
+    /// current thread.
+    pub fn set_immediate_finalization(enabled: bool) {
+        IMMEDIATE_FINALIZATION.with(|f| f.set(enabled));
+    }
 }
 
 //
@@ -152,6 +193,11 @@ impl Drop for FinalizableHandle {
     }
 }
 
+#[cfg(feature = "mock")]
+thread_local! {
+    static IMMEDIATE_FINALIZATION: std::cell::Cell<bool> = const { std::cell::Cell::new(false) };
+}
+
 pub(crate) struct FinalizableHandleState {
     objects: HashMap<isize, FinalizableObjectState>,
 }
@@ -166,7 +212,9 @@ impl FinalizableHandleState {
     pub(crate) fn get() -> MutexGuard<'static, Self> {
         static FUNCTIONS: OnceCell<Mutex<FinalizableHandleState>> = OnceCell::new();
         let state = FUNCTIONS.get_or_init(|| Mutex::new(FinalizableHandleState::new()));
-        state.lock().unwrap()
+        // Panic while holding the lock (i.e. in method handler) must not make
+        // the state unusable.
+        state.lock().unwrap_or_else(|e| e.into_inner())
     }
 
     /// Executes all finalizers that were not registered with the isolates.
@@ -191,18 +239,111 @@ impl FinalizableHandleState {
             .collect();
 
         if !finalizers.is_empty() {
-            RUN_LOOP_SENDER
-                .get()
-                .expect("MessageChannel was not initialized!")
-                .send(move || {
-                    for mut f in finalizers {
-                        f.take().unwrap()();
+            isolate_run_loop_sender(isolate).send(move || {
+                for mut f in finalizers {
+                    f.take().unwrap()();
+                }
+            });
+        }
+    }
+
+    /// Invokes finalizers of all outstanding handles created on current thread.
+    /// Called when outermost [`Context`] is being dropped, before the message
+    /// channel and run loop sender are torn down, so the finalizers can still
+    /// access the context.
+    pub(crate) fn finalize_on_shutdown() {
+        let finalizers: Vec<_> = {
+            let mut state = Self::get();
+            let to_remove: Vec<_> = state
+                .objects
+                .iter()
+                .filter_map(|(id, object)| match &object.finalizer {
+                    Some(finalizer) if finalizer.get_ref().is_ok() => Some(*id),
+                    _ => None,
+                })
+                .collect();
+            to_remove
+                .iter()
+                .filter_map(|id| state.objects.remove(id))
+                .filter_map(|mut object| {
+                    // Weak persistent handle can only be deleted on isolate thread,
+                    // which is not necessarily this one, and the isolate may already
+                    // be gone. It is left to Dart; the Dart finalizer will find
+                    // nothing to do.
+                    object.handle.take();
+                    object.finalizer.take()
+                })
+                .collect()
+        };
+        // Invoke finalizers outside of the lock; they may access other handles.
+        for mut finalizer in finalizers {
+            finalizer.take().unwrap()();
+        }
+    }
+
+    /// Marks all finalizable handles within value as attached, simulating what
+    /// Dart does when receiving the value.
+    #[cfg(feature = "mock")]
+    pub(crate) fn attach_mock_handles(value: &crate::Value, isolate_id: IsolateId) {
+        use crate::Value;
+        match value {
+            Value::FinalizableHandle(handle) => {
+                let wakers = {
+                    let mut state = Self::get();
+                    match state.objects.get_mut(&handle.id) {
+                        Some(object) if object.handle.is_none() => {
+                            assert_eq!(object.isolate_id, isolate_id);
+                            object.handle = Some(Movable(std::ptr::null_mut()));
+                            std::mem::take(&mut object.attach_wakers)
+                        }
+                        _ => Vec::new(),
                     }
-                });
+                };
+                wakers.into_iter().for_each(|w| w.wake());
+            }
+            Value::List(list) => {
+                for v in list {
+                    Self::attach_mock_handles(v, isolate_id);
+                }
+            }
+            Value::Map(map) => {
+                for (k, v) in map.iter() {
+                    Self::attach_mock_handles(k, isolate_id);
+                    Self::attach_mock_handles(v, isolate_id);
+                }
+            }
+            _ => {}
         }
     }
 }
 
+#[cfg(feature = "mock")]
+impl FinalizableHandleState {
+    /// Simulates Dart garbage collecting object attached to handle with given id.
+    /// Same as native finalizer, the handle is detached immediately and the
+    /// finalizer is scheduled on run loop. Returns `false` if the handle is not
+    /// attached to given isolate.
+    pub(crate) fn simulate_gc(handle: isize, isolate_id: IsolateId) -> bool {
+        {
+            let mut state = Self::get();
+            match state.objects.get_mut(&handle) {
+                Some(object) if object.isolate_id == isolate_id && object.handle.is_some() => {
+                    object.handle.take();
+                }
+                _ => return false,
+            }
+        }
+        isolate_run_loop_sender(isolate_id).send(move || {
+            let object = Self::get().objects.remove(&handle);
+            // Finalizer may have been removed in FinalizableHandle::drop
+            if let Some(mut finalizer) = object.and_then(|mut o| o.finalizer.take()) {
+                finalizer.take().unwrap()();
+            }
+        });
+        true
+    }
+}
+
 // We can't use Capsule for WeakPersistentHandle because it might be accessed
 // from GC thread.
 struct Movable<T>(T);
@@ -214,6 +355,7 @@ struct FinalizableObjectState {
     isolate_id: IsolateId,
     external_size: isize,
     finalizer: Option<Capsule<Box<dyn FnOnce()>>>,
+    attach_wakers: Vec<Waker>,
 }
 
 impl Drop for FinalizableObjectState {
@@ -260,6 +402,9 @@ pub(crate) mod finalizable_handle_native {
             if let Some(handle) = object.handle.take() {
                 (DartFunctions::get().delete_weak_persistent_handle)(handle.0);
             }
+        } else {
+            // Already finalized during shutdown.
+            return;
         }
         let sender = RUN_LOOP_SENDER
             .get()
@@ -293,6 +438,10 @@ pub(crate) mod finalizable_handle_native {
             );
             object.handle = Some(Movable(weak_handle));
             assert_eq!(object.isolate_id, isolate_id);
+            // Wake outside of the lock; waking may poll the future synchronously.
+            let wakers = std::mem::take(&mut object.attach_wakers);
+            drop(state);
+            wakers.into_iter().for_each(|w| w.wake());
             return handle;
         }
         null_handle
@@ -24,6 +24,14 @@ impl Handle {
     }
 }
 
+impl std::fmt::Debug for Handle {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("Handle")
+            .field("active", &self.on_cancel.is_some())
+            .finish()
+    }
+}
+
 impl Drop for Handle {
     fn drop(&mut self) {
         self.cancel();
@@ -3,26 +3,69 @@
 #![allow(clippy::identity_op)]
 #![allow(clippy::module_inception)]
 #![allow(clippy::bool_assert_comparison)]
+// Without `std` feature only `Value` and its encoding are available.
+#![cfg_attr(not(feature = "std"), no_std)]
 
+extern crate alloc;
+
+mod codec;
+#[cfg(feature = "std")]
 mod context;
+#[cfg(feature = "std")]
 pub mod ffi;
+#[cfg(feature = "std")]
 mod finalizable_handle;
+#[cfg(feature = "std")]
 mod handle;
+#[cfg(feature = "std")]
 mod message_channel;
+#[cfg(feature = "mmap")]
+mod mmap;
 
+#[cfg(feature = "std")]
 pub mod platform;
+#[cfg(feature = "std")]
 mod run_loop;
+#[cfg(feature = "shared_buffer")]
+mod shared_buffer;
+#[cfg(feature = "std")]
+mod standard_codec;
 mod value;
+#[cfg(feature = "std")]
+mod value_interner;
+
+#[cfg(all(
+    feature = "shared_buffer",
+    any(target_os = "android", target_os = "ios")
+))]
+compile_error!("shared_buffer feature is only supported on desktop platforms");
 
 // Note: Util is public but there are no API stability guarantees
+#[cfg(feature = "std")]
 pub mod util;
 
+pub use codec::{DecodeError, DecodeLimits, DecodeOptions, StringDecoding};
+#[cfg(feature = "std")]
 pub use context::*;
+#[cfg(feature = "std")]
 pub use finalizable_handle::*;
+#[cfg(feature = "std")]
 pub use handle::*;
+#[cfg(feature = "std")]
 pub use message_channel::*;
+#[cfg(feature = "mmap")]
+pub use mmap::*;
+#[cfg(feature = "std")]
 pub use run_loop::*;
+#[cfg(feature = "shared_buffer")]
+pub use shared_buffer::*;
+#[cfg(feature = "std")]
+pub use standard_codec::*;
+#[cfg(feature = "std")]
+pub use util::{select2, Either};
 pub use value::*;
+#[cfg(feature = "std")]
+pub use value_interner::*;
 
 #[cfg(feature = "nativeshell_derive")]
 pub mod derive_internal;
@@ -1,13 +1,23 @@
-use std::rc::{Rc, Weak};
+use std::{
+    cell::Cell,
+    rc::{Rc, Weak},
+};
 
 use async_trait::async_trait;
 
 use crate::{
-    unpack_method_call, unpack_result, util::FutureCompleter, Context, GetMessageChannel,
-    IsolateId, MessageChannelDelegate, MethodCall, MethodCallError, MethodCallReply, PlatformError,
-    TryFromError, Value,
+    unpack_method_call, unpack_result,
+    util::{CompletableFuture, FutureCompleter},
+    Context, GetMessageChannel, HandlerKind, IsolateId, MessageChannelDelegate, MethodCall,
+    MethodCallError, MethodCallReply, PlatformError, PostMessageError, TryFromError, Value,
 };
 
+use super::method_handler::ThreadAffinity;
+
+/// Suffix appended to method channel name to get the channel on which
+/// [`ProgressReply`] posts progress updates.
+pub const PROGRESS_CHANNEL_SUFFIX: &str = "/progress";
+
 pub type PlatformResult = Result<Value, PlatformError>;
 
 pub trait IntoPlatformResult {
@@ -24,6 +34,18 @@ impl<T: Into<Value>, E: Into<PlatformError>> IntoPlatformResult for Result<T, E>
 pub trait AsyncMethodHandler: Sized + 'static {
     async fn on_method_call(&self, call: MethodCall) -> PlatformResult;
 
+    /// Called instead of [`AsyncMethodHandler::on_method_call`] for every method
+    /// call. Override this if the handler needs to report progress of long
+    /// running calls. Default implementation ignores `progress` and calls
+    /// [`AsyncMethodHandler::on_method_call`].
+    async fn on_method_call_with_progress(
+        &self,
+        call: MethodCall,
+        _progress: ProgressReply,
+    ) -> PlatformResult {
+        self.on_method_call(call).await
+    }
+
     /// Implementation can store weak reference if it needs to pass it around.
     /// Guaranteed to call before any other methods.
     ///
@@ -83,7 +105,8 @@ impl AsyncMethodInvoker {
 
         let args: Value = args.into();
         let call: Value = vec![Value::String(method.into()), args].into();
-        Context::get().message_channel().send_message(
+        // Reply is discarded if this future is dropped before completing.
+        let _reply = Context::get().message_channel().send_message(
             target_isolate,
             &self.channel_name,
             call,
@@ -92,7 +115,7 @@ impl AsyncMethodInvoker {
                     let result = unpack_result(value).expect("Malformed message");
                     completer.complete(result);
                 }
-                Err(err) => completer.complete(Err(MethodCallError::SendError(err))),
+                Err(err) => completer.complete(Err(MethodCallError::from_send_error(err))),
             },
         );
 
@@ -140,18 +163,69 @@ impl AsyncMethodInvoker {
     {
         let args: Value = args.into();
         let call: Value = vec![Value::String(method.into()), args].into();
-        Context::get().message_channel().send_message(
-            target_isolate,
-            &self.channel_name,
-            call,
-            move |res| match res {
-                Ok(value) => {
-                    let result = unpack_result(value).expect("Malformed message");
-                    reply(result);
-                }
-                Err(err) => reply(Err(MethodCallError::SendError(err))),
-            },
-        );
+        Context::get()
+            .message_channel()
+            .send_message(
+                target_isolate,
+                &self.channel_name,
+                call,
+                move |res| match res {
+                    Ok(value) => {
+                        let result = unpack_result(value).expect("Malformed message");
+                        reply(result);
+                    }
+                    Err(err) => reply(Err(MethodCallError::from_send_error(err))),
+                },
+            )
+            .detach();
+    }
+}
+
+/// Reports intermediate results of a method call before the final reply.
+///
+/// Caller that wants to receive progress appends a progress id to the method
+/// call message (`[method, args, progress_id]`). Every report is then posted as
+/// `[progress_id, value]` on channel `<channel>/progress` (see
+/// [`PROGRESS_CHANNEL_SUFFIX`]). Reports are delivered in order and before the
+/// reply; once the method call returns, further reports are ignored. On Dart side
+/// this is handled by `NativeMethodChannel.invokeMethodWithProgress`.
+///
+/// If the caller did not ask for progress, reports are silently dropped.
+#[derive(Clone)]
+pub struct ProgressReply {
+    channel: String,
+    isolate: IsolateId,
+    progress_id: Option<i64>,
+    closed: Rc<Cell<bool>>,
+}
+
+impl ProgressReply {
+    /// Whether the caller asked for progress updates.
+    pub fn is_requested(&self) -> bool {
+        self.progress_id.is_some() && !self.closed.get()
+    }
+
+    pub fn report<V: Into<Value>>(&self, value: V) -> Result<(), PostMessageError> {
+        match self.progress_id {
+            Some(progress_id) if !self.closed.get() => {
+                Context::get().message_channel().post_message(
+                    self.isolate,
+                    &self.channel,
+                    Value::List(vec![progress_id.into(), value.into()]),
+                )
+            }
+            _ => Ok(()),
+        }
+    }
+}
+
+fn unpack_progress_id(message: &Value) -> Option<i64> {
+    match message {
+        Value::List(items) => match items.get(2) {
+            Some(Value::I64(progress_id)) => Some(*progress_id),
+            _ => None,
+        },
+        _ => None,
     }
 }
 
@@ -169,22 +243,44 @@ impl<T: AsyncMethodHandler> RegisteredAsyncMethodHandler<T> {
             inner: Rc::new(RegisteredAsyncMethodHandlerInner {
                 channel: channel.into(),
                 handler,
+                affinity: ThreadAffinity::new(),
             }),
         };
         res.inner.init();
         Context::get()
             .message_channel()
-            .register_delegate(&res.inner.channel, res.inner.clone());
+            .register_delegate_with_kind(
+                &res.inner.channel,
+                res.inner.clone(),
+                HandlerKind::AsyncMethod,
+            );
         res
     }
 
     pub fn handler(&self) -> Rc<T> {
+        self.check_thread();
         self.inner.handler.clone()
     }
+
+    /// Returns future that resolves once Dart has contacted this channel. See
+    /// [`MessageChannel::channel_ready`](crate::MessageChannel::channel_ready).
+    pub fn ready(&self) -> CompletableFuture<IsolateId> {
+        self.check_thread();
+        Context::get()
+            .message_channel()
+            .channel_ready(&self.inner.channel)
+    }
+
+    fn check_thread(&self) {
+        self.inner
+            .affinity
+            .check("RegisteredAsyncMethodHandler", &self.inner.channel);
+    }
 }
 
 impl<T: AsyncMethodHandler> Drop for RegisteredAsyncMethodHandler<T> {
     fn drop(&mut self) {
+        self.check_thread();
         Context::get()
             .message_channel()
             .unregister_delegate(&self.inner.channel);
@@ -194,6 +290,7 @@ impl<T: AsyncMethodHandler> Drop for RegisteredAsyncMethodHandler<T> {
 struct RegisteredAsyncMethodHandlerInner<T: AsyncMethodHandler> {
     channel: String,
     handler: Rc<T>,
+    affinity: ThreadAffinity,
 }
 
 impl<T: AsyncMethodHandler> RegisteredAsyncMethodHandlerInner<T> {
@@ -215,10 +312,18 @@ impl<T: AsyncMethodHandler> MessageChannelDelegate for RegisteredAsyncMethodHand
         message: Value,
         reply: Box<dyn FnOnce(Value) -> bool>,
     ) {
+        let progress = ProgressReply {
+            channel: format!("{}{}", self.channel, PROGRESS_CHANNEL_SUFFIX),
+            isolate,
+            progress_id: unpack_progress_id(&message),
+            closed: Rc::new(Cell::new(false)),
+        };
         if let Some(call) = unpack_method_call(message, isolate) {
             let handler = self.handler.clone();
             Context::get().run_loop().spawn(async move {
-                let result = handler.on_method_call(call).await;
+                let closed = progress.closed.clone();
+                let result = handler.on_method_call_with_progress(call, progress).await;
+                closed.set(true);
                 MethodCallReply { reply }.send(result);
             });
         } else {
@@ -1,49 +1,65 @@
-use crate::{ffi::DartValue, value::Value};
+use crate::{codec::*, ffi::DartValue, value::Value, DecodeError, DecodeOptions, IsolateId};
 
-const VALUE_NULL: u8 = 255 - 0;
-const VALUE_TRUE: u8 = 255 - 1;
-const VALUE_FALSE: u8 = 255 - 2;
-const VALUE_INT64: u8 = 255 - 3;
-const VALUE_FLOAT64: u8 = 255 - 4;
-const VALUE_SMALL_STRING: u8 = 255 - 5;
-
-// Deserialization
-const VALUE_STRING: u8 = 255 - 6;
-const VALUE_INT8LIST: u8 = 255 - 7;
-const VALUE_UINT8LIST: u8 = 255 - 8;
-const VALUE_INT16LIST: u8 = 255 - 9;
-const VALUE_UINT16LIST: u8 = 255 - 10;
-const VALUE_INT32LIST: u8 = 255 - 11;
-const VALUE_UINT32LIST: u8 = 255 - 12;
-const VALUE_INT64LIST: u8 = 255 - 13;
-const VALUE_FLOAT32LIST: u8 = 255 - 14;
-const VALUE_FLOAT64LIST: u8 = 255 - 15;
-
-// Serialization
-const VALUE_ATTACHMENT: u8 = VALUE_STRING; // this will be passed directly as Dart_CObject
-const VALUE_FINALIZABLE_HANDLE: u8 = VALUE_ATTACHMENT - 1;
-
-const VALUE_LIST: u8 = 255 - 16;
-const VALUE_MAP: u8 = 255 - 17;
-const VALUE_LAST: u8 = VALUE_MAP;
+use super::native_vector::take_vec;
 
 pub(super) struct Deserializer {}
 
+/// Message that failed to decode. `header` contains leading items of top level
+/// list that were decoded before the failure, so that the message can still be
+/// replied to.
+pub(super) struct DeserializeError {
+    pub error: DecodeError,
+    pub header: Vec<Value>,
+}
+
 impl Deserializer {
-    pub unsafe fn deserialize(buf: &[u8]) -> Value {
+    #[cfg(test)]
+    pub unsafe fn deserialize(buf: &[u8], options: &DecodeOptions) -> Result<Value, DecodeError> {
+        Self::deserialize_message(buf, options).map_err(|e| e.error)
+    }
+
+    // Note that if decoding fails, attachments that have not been read yet are leaked.
+    pub unsafe fn deserialize_message(
+        buf: &[u8],
+        options: &DecodeOptions,
+    ) -> Result<Value, DeserializeError> {
         let mut reader = Reader::new(buf);
-        Self::read_value(&mut reader)
+        let mut total_bytes = buf.len();
+        let mut header = Vec::new();
+        let res = options
+            .limits
+            .check_total_bytes(total_bytes)
+            .and_then(|_| match buf.first() {
+                Some(&VALUE_LIST) => {
+                    reader.read_u8();
+                    let len = reader.read_size();
+                    options.limits.check_collection_length(len)?;
+                    options.limits.check_depth(1)?;
+                    for _ in 0..len {
+                        let value = Self::read_value(&mut reader, options, 1, &mut total_bytes)?;
+                        header.push(value);
+                    }
+                    Ok(Value::List(std::mem::take(&mut header)))
+                }
+                _ => Self::read_value(&mut reader, options, 0, &mut total_bytes),
+            });
+        res.map_err(|error| DeserializeError { error, header })
     }
 
-    unsafe fn read_value(reader: &mut Reader) -> Value {
+    unsafe fn read_value(
+        reader: &mut Reader,
+        options: &DecodeOptions,
+        depth: usize,
+        total_bytes: &mut usize,
+    ) -> Result<Value, DecodeError> {
         if reader.ended() {
             panic!("Malformed stream");
         }
         let t = reader.read_u8();
         if t < VALUE_LAST {
-            return Value::I64(t as i64);
+            return Ok(Value::I64(t as i64));
         }
-        match t {
+        let value = match t {
             VALUE_NULL => Value::Null,
             VALUE_FALSE => Value::Bool(false),
             VALUE_TRUE => Value::Bool(true),
@@ -54,37 +70,58 @@ impl Deserializer {
             }
             VALUE_SMALL_STRING => {
                 let len = reader.read_size();
-                Value::String(reader.read_string(len))
+                let bytes = reader.read_bytes(len).to_vec();
+                Value::String(Value::validate_utf8(bytes, options.string_decoding)?)
             }
             VALUE_STRING => {
-                let vec = Self::read_vec::<u8>(reader);
-                Value::String(String::from_utf8_unchecked(vec))
+                let vec = Self::read_vec::<u8>(reader, options, total_bytes)?;
+                Value::String(Value::validate_utf8(vec, options.string_decoding)?)
+            }
+            VALUE_INT8LIST => Value::I8List(Self::read_list::<i8>(reader, options, total_bytes)?),
+            VALUE_UINT8LIST => Value::U8List(Self::read_list::<u8>(reader, options, total_bytes)?),
+            VALUE_INT16LIST => {
+                Value::I16List(Self::read_list::<i16>(reader, options, total_bytes)?)
+            }
+            VALUE_UINT16LIST => {
+                Value::U16List(Self::read_list::<u16>(reader, options, total_bytes)?)
+            }
+            VALUE_INT32LIST => {
+                Value::I32List(Self::read_list::<i32>(reader, options, total_bytes)?)
+            }
+            VALUE_UINT32LIST => {
+                Value::U32List(Self::read_list::<u32>(reader, options, total_bytes)?)
+            }
+            VALUE_INT64LIST => {
+                Value::I64List(Self::read_list::<i64>(reader, options, total_bytes)?)
+            }
+            VALUE_FLOAT32LIST => {
+                Value::F32List(Self::read_list::<f32>(reader, options, total_bytes)?)
+            }
+            VALUE_FLOAT64LIST => {
+                Value::F64List(Self::read_list::<f64>(reader, options, total_bytes)?)
             }
-            VALUE_INT8LIST => Value::I8List(Self::read_vec::<i8>(reader)),
-            VALUE_UINT8LIST => Value::U8List(Self::read_vec::<u8>(reader)),
-            VALUE_INT16LIST => Value::I16List(Self::read_vec::<i16>(reader)),
-            VALUE_UINT16LIST => Value::U16List(Self::read_vec::<u16>(reader)),
-            VALUE_INT32LIST => Value::I32List(Self::read_vec::<i32>(reader)),
-            VALUE_UINT32LIST => Value::U32List(Self::read_vec::<u32>(reader)),
-            VALUE_INT64LIST => Value::I64List(Self::read_vec::<i64>(reader)),
-            VALUE_FLOAT32LIST => Value::F32List(Self::read_vec::<f32>(reader)),
-            VALUE_FLOAT64LIST => Value::F64List(Self::read_vec::<f64>(reader)),
             VALUE_LIST => {
                 let len = reader.read_size();
+                options.limits.check_collection_length(len)?;
+                options.limits.check_depth(depth + 1)?;
                 let mut list = Vec::new();
-                list.reserve(len);
+                // Every item takes at least one byte; don't trust the length for
+                // reserving more than that.
+                list.reserve(len.min(reader.remaining()));
                 for _ in 0..len {
-                    let value = Self::read_value(reader);
+                    let value = Self::read_value(reader, options, depth + 1, total_bytes)?;
                     list.push(value);
                 }
                 Value::List(list)
             }
             VALUE_MAP => {
                 let len = reader.read_size();
+                options.limits.check_collection_length(len)?;
+                options.limits.check_depth(depth + 1)?;
                 let mut map = Vec::<(Value, Value)>::new();
                 for _ in 0..len {
-                    let k = Self::read_value(reader);
-                    let v = Self::read_value(reader);
+                    let k = Self::read_value(reader, options, depth + 1, total_bytes)?;
+                    let v = Self::read_value(reader, options, depth + 1, total_bytes)?;
                     map.push((k, v));
                 }
                 Value::Map(map.into())
@@ -92,172 +129,151 @@ impl Deserializer {
             _ => {
                 panic!("Unsupported value type: {}", t);
             }
-        }
+        };
+        Ok(value)
     }
 
-    unsafe fn read_vec<T>(reader: &mut Reader) -> Vec<T> {
+    unsafe fn read_vec<T: Copy>(
+        reader: &mut Reader,
+        options: &DecodeOptions,
+        total_bytes: &mut usize,
+    ) -> Result<Vec<T>, DecodeError> {
         let ptr = reader.read_u64();
         let size = reader.read_size() as u64;
-        Vec::<T>::from_raw_parts(ptr as *mut T, size as usize, size as usize)
+        // Take ownership first so that the data is released if a limit is exceeded.
+        let vec = take_vec(ptr as *mut T, size as usize);
+        *total_bytes = total_bytes.saturating_add(vec.len() * std::mem::size_of::<T>());
+        options.limits.check_total_bytes(*total_bytes)?;
+        Ok(vec)
     }
-}
-
-struct Reader<'a> {
-    buf: &'a [u8],
-    pos: usize,
-}
 
-impl<'a> Reader<'a> {
-    pub fn new(buf: &'a [u8]) -> Self {
-        Reader { buf, pos: 0 }
-    }
-    fn read_u8(&mut self) -> u8 {
-        let n = self.buf[self.pos];
-        self.pos += 1;
-        n
-    }
-    fn read_u16(&mut self) -> u16 {
-        self.pos += 2;
-        let s = &self.buf[self.pos - 2..self.pos];
-        u16::from_ne_bytes(clone_into_array(s))
-    }
-    fn read_u32(&mut self) -> u32 {
-        self.pos += 4;
-        let s = &self.buf[self.pos - 4..self.pos];
-        u32::from_ne_bytes(clone_into_array(s))
-    }
-    fn read_u64(&mut self) -> u64 {
-        self.pos += 8;
-        let s = &self.buf[self.pos - 8..self.pos];
-        u64::from_ne_bytes(clone_into_array(s))
-    }
-    fn read_i64(&mut self) -> i64 {
-        self.pos += 8;
-        let s = &self.buf[self.pos - 8..self.pos];
-        i64::from_ne_bytes(clone_into_array(s))
-    }
-    fn read_f64(&mut self) -> f64 {
-        let n = self.read_u64();
-        f64::from_bits(n)
-    }
-    fn read_size(&mut self) -> usize {
-        let n = self.read_u8();
-        match n {
-            254 => self.read_u16() as usize,
-            255 => self.read_u32() as usize,
-            _ => n as usize,
-        }
-    }
-    fn read_string(&mut self, len: usize) -> String {
-        if len == 0 {
-            String::from("")
-        } else {
-            let v = &self.buf[self.pos..self.pos + len];
-            self.pos += len;
-            String::from_utf8_lossy(v).to_owned().to_string()
-        }
-    }
-    fn align_to(&mut self, align: usize) {
-        let m = self.pos % align;
-        if m > 0 {
-            self.pos += align - m;
-        }
-    }
-    fn ended(&self) -> bool {
-        self.pos >= self.buf.len()
+    unsafe fn read_list<T: Copy>(
+        reader: &mut Reader,
+        options: &DecodeOptions,
+        total_bytes: &mut usize,
+    ) -> Result<Vec<T>, DecodeError> {
+        let vec = Self::read_vec::<T>(reader, options, total_bytes)?;
+        options.limits.check_collection_length(vec.len())?;
+        Ok(vec)
     }
 }
 
 pub(super) struct Serializer {}
 
 impl Serializer {
-    pub fn serialize(value: Value) -> Vec<DartValue> {
+    pub fn serialize(value: Value, target_isolate: IsolateId) -> Vec<DartValue> {
         let mut res = Vec::new();
         let mut buf = Vec::new();
         let mut writer = Writer::new(&mut buf);
-        Self::write_value(&mut writer, value, &mut res);
+        Self::write_value(&mut writer, value, &mut res, target_isolate);
         res.push(DartValue::U8List(buf));
         res
     }
 
-    fn write_value(writer: &mut Writer, value: Value, attachments: &mut Vec<DartValue>) {
-        match value {
-            Value::Null => {
-                writer.write_u8(VALUE_NULL);
-            }
-            Value::Bool(v) => {
-                writer.write_u8(if v { VALUE_TRUE } else { VALUE_FALSE });
-            }
-            Value::I64(n) => {
-                if n < VALUE_LAST as i64 {
-                    writer.write_u8(n as u8);
-                } else {
-                    writer.write_u8(VALUE_INT64);
-                    writer.write_i64(n);
+    // Lists and maps are traversed using explicit stack of values that are yet
+    // to be written, so that deeply nested values don't overflow native stack.
+    #[cfg_attr(not(feature = "shared_buffer"), allow(unused_variables))]
+    fn write_value(
+        writer: &mut Writer,
+        value: Value,
+        attachments: &mut Vec<DartValue>,
+        target_isolate: IsolateId,
+    ) {
+        let mut stack = vec![value];
+        while let Some(mut value) = stack.pop() {
+            // Value implements Drop, so payloads are taken out of it rather
+            // than moved.
+            match &mut value {
+                Value::Null => {
+                    writer.write_u8(VALUE_NULL);
                 }
-            }
-            Value::F64(n) => {
-                writer.write_u8(VALUE_FLOAT64);
-                writer.align_to(8);
-                writer.write_f64(n);
-            }
-            Value::String(v) => {
-                if v.len() < 50 {
-                    writer.write_u8(VALUE_SMALL_STRING);
-                    writer.write_size(v.len());
-                    writer.write_string(&v);
-                } else {
+                Value::Bool(v) => {
+                    writer.write_u8(if *v { VALUE_TRUE } else { VALUE_FALSE });
+                }
+                Value::I64(n) => {
+                    if *n < VALUE_LAST as i64 {
+                        writer.write_u8(*n as u8);
+                    } else {
+                        writer.write_u8(VALUE_INT64);
+                        writer.write_i64(*n);
+                    }
+                }
+                // Dart doesn't distinguish integer widths.
+                Value::I32(n) => {
+                    stack.push(Value::I64((*n).into()));
+                }
+                Value::U32(n) => {
+                    stack.push(Value::I64((*n).into()));
+                }
+                Value::F64(n) => {
+                    writer.write_u8(VALUE_FLOAT64);
+                    writer.align_to(8);
+                    writer.write_f64(*n);
+                }
+                Value::String(v) => {
+                    if v.len() < 50 {
+                        writer.write_u8(VALUE_SMALL_STRING);
+                        writer.write_size(v.len());
+                        writer.write_string(v);
+                    } else {
+                        Self::write_attachment(writer, std::mem::take(v), attachments);
+                    }
+                }
+                Value::I8List(v) => {
+                    Self::write_attachment(writer, std::mem::take(v), attachments);
+                }
+                Value::U8List(v) => {
+                    Self::write_attachment(writer, std::mem::take(v), attachments);
+                }
+                Value::I16List(v) => {
+                    Self::write_attachment(writer, std::mem::take(v), attachments);
+                }
+                Value::U16List(v) => {
+                    Self::write_attachment(writer, std::mem::take(v), attachments);
+                }
+                Value::I32List(v) => {
+                    Self::write_attachment(writer, std::mem::take(v), attachments);
+                }
+                Value::U32List(v) => {
+                    Self::write_attachment(writer, std::mem::take(v), attachments);
+                }
+                Value::I64List(v) => {
+                    Self::write_attachment(writer, std::mem::take(v), attachments);
+                }
+                Value::F32List(v) => {
+                    Self::write_attachment(writer, std::mem::take(v), attachments);
+                }
+                Value::F64List(v) => {
+                    Self::write_attachment(writer, std::mem::take(v), attachments);
+                }
+                Value::BoolList(v) => {
+                    let v: Vec<u8> = v.iter().map(|b| *b as u8).collect();
                     Self::write_attachment(writer, v, attachments);
                 }
-            }
-            Value::I8List(v) => {
-                Self::write_attachment(writer, v, attachments);
-            }
-            Value::U8List(v) => {
-                Self::write_attachment(writer, v, attachments);
-            }
-            Value::I16List(v) => {
-                Self::write_attachment(writer, v, attachments);
-            }
-            Value::U16List(v) => {
-                Self::write_attachment(writer, v, attachments);
-            }
-            Value::I32List(v) => {
-                Self::write_attachment(writer, v, attachments);
-            }
-            Value::U32List(v) => {
-                Self::write_attachment(writer, v, attachments);
-            }
-            Value::I64List(v) => {
-                Self::write_attachment(writer, v, attachments);
-            }
-            Value::F32List(v) => {
-                Self::write_attachment(writer, v, attachments);
-            }
-            Value::F64List(v) => {
-                Self::write_attachment(writer, v, attachments);
-            }
-            Value::List(list) => {
-                writer.write_u8(VALUE_LIST);
-                writer.write_size(list.len());
-                list.into_iter().for_each(|v| {
-                    Self::write_value(writer, v, attachments);
-                });
-            }
-            Value::Map(map) => {
-                writer.write_u8(VALUE_MAP);
-                writer.write_size(map.len());
-                map.into_iter().for_each(|v| {
-                    Self::write_value(writer, v.0, attachments);
-                    Self::write_value(writer, v.1, attachments);
-                });
-            }
-            Value::Dart(v) => {
-                Self::write_attachment(writer, v, attachments);
-            }
-            Value::FinalizableHandle(handle) => {
-                writer.write_u8(VALUE_FINALIZABLE_HANDLE);
-                writer.write_size(handle.id as usize);
+                Value::List(list) => {
+                    writer.write_u8(VALUE_LIST);
+                    writer.write_size(list.len());
+                    stack.extend(list.drain(..).rev());
+                }
+                Value::Map(map) => {
+                    writer.write_u8(VALUE_MAP);
+                    writer.write_size(map.len());
+                    std::mem::take(map).into_iter().rev().for_each(|v| {
+                        stack.push(v.1);
+                        stack.push(v.0);
+                    });
+                }
+                Value::Dart(v) => {
+                    Self::write_attachment(writer, v.clone(), attachments);
+                }
+                Value::FinalizableHandle(handle) => {
+                    writer.write_u8(VALUE_FINALIZABLE_HANDLE);
+                    writer.write_size(handle.id as usize);
+                }
+                #[cfg(feature = "shared_buffer")]
+                Value::SharedBuffer(buffer) => {
+                    stack.push(buffer.to_token(target_isolate));
+                }
             }
         }
     }
@@ -273,69 +289,183 @@ impl Serializer {
     }
 }
 
-struct Writer<'a>(&'a mut Vec<u8>);
+#[cfg(test)]
+mod tests {
+    use crate::{DecodeError, DecodeLimits, DecodeOptions, StringDecoding, Value};
 
-#[allow(unused)]
-impl<'a> Writer<'a> {
-    fn new(v: &'a mut Vec<u8>) -> Self {
-        Writer(v)
-    }
-    fn write_u8(&mut self, n: u8) {
-        self.0.push(n);
-    }
-    fn write_u16(&mut self, n: u16) {
-        self.0.extend_from_slice(&n.to_ne_bytes());
-    }
-    fn write_u32(&mut self, n: u32) {
-        self.0.extend_from_slice(&n.to_ne_bytes());
-    }
-    fn write_i32(&mut self, n: i32) {
-        self.0.extend_from_slice(&n.to_ne_bytes());
-    }
-    fn write_u64(&mut self, n: u64) {
-        self.0.extend_from_slice(&n.to_ne_bytes());
+    use crate::codec::{VALUE_LIST, VALUE_MAP, VALUE_SMALL_STRING, VALUE_STRING};
+
+    fn decode(buf: &[u8], string_decoding: StringDecoding) -> Result<Value, DecodeError> {
+        let options = DecodeOptions {
+            string_decoding,
+            ..Default::default()
+        };
+        unsafe { super::Deserializer::deserialize(buf, &options) }
     }
-    fn write_i64(&mut self, n: i64) {
-        self.0.extend_from_slice(&n.to_ne_bytes());
+
+    // Large strings are passed as pointer to vector allocated by Rust.
+    fn large_string_message(bytes: &[u8]) -> Vec<u8> {
+        let mut vec = std::mem::ManuallyDrop::new(bytes.to_vec().into_boxed_slice());
+        let mut buf = vec![VALUE_STRING];
+        buf.extend_from_slice(&(vec.as_mut_ptr() as u64).to_ne_bytes());
+        buf.push(bytes.len() as u8);
+        buf
     }
-    fn write_f64(&mut self, n: f64) {
-        self.write_u64(n.to_bits());
+
+    #[test]
+    fn test_invalid_utf8() {
+        let buf = [VALUE_SMALL_STRING, 3, b'a', 0xFF, b'b'];
+        assert_eq!(
+            decode(&buf, StringDecoding::Strict),
+            Err(DecodeError::InvalidUtf8)
+        );
+        assert_eq!(
+            decode(&buf, StringDecoding::Lossy),
+            Ok(Value::String("a\u{FFFD}b".into()))
+        );
+
+        let buf = large_string_message(&[b'a', 0xFF, b'b']);
+        assert_eq!(
+            decode(&buf, StringDecoding::Strict),
+            Err(DecodeError::InvalidUtf8)
+        );
+        let buf = large_string_message(&[b'a', 0xFF, b'b']);
+        assert_eq!(
+            decode(&buf, StringDecoding::Lossy),
+            Ok(Value::String("a\u{FFFD}b".into()))
+        );
+
+        let buf = large_string_message("Hello".as_bytes());
+        assert_eq!(
+            decode(&buf, StringDecoding::Strict),
+            Ok(Value::String("Hello".into()))
+        );
     }
-    fn write_size(&mut self, n: usize) {
-        if n < 254 {
-            self.write_u8(n as u8);
-        } else if n <= u16::max_value() as usize {
-            self.write_u8(254);
-            self.write_u16(n as u16);
-        } else if n < u32::max_value() as usize {
-            self.write_u8(255);
-            self.write_u32(n as u32);
-        } else {
-            // flutter only support 32 bit value
-            panic!("Not implemented");
-        }
+
+    #[test]
+    fn test_decode_error_header() {
+        // ["message", 7, "ch", <invalid string>]
+        let buf = [
+            VALUE_LIST,
+            4,
+            VALUE_SMALL_STRING,
+            7,
+            b'm',
+            b'e',
+            b's',
+            b's',
+            b'a',
+            b'g',
+            b'e',
+            7,
+            VALUE_SMALL_STRING,
+            2,
+            b'c',
+            b'h',
+            VALUE_SMALL_STRING,
+            1,
+            0xFF,
+        ];
+        let options = DecodeOptions::default();
+        let err = match unsafe { super::Deserializer::deserialize_message(&buf, &options) } {
+            Ok(_) => panic!("expected error"),
+            Err(err) => err,
+        };
+        assert_eq!(err.error, DecodeError::InvalidUtf8);
+        assert_eq!(
+            err.header,
+            vec![Value::from("message"), Value::I64(7), Value::from("ch")]
+        );
     }
-    fn write_string(&mut self, s: &str) {
-        self.0.extend_from_slice(s.as_bytes());
+
+    #[test]
+    fn test_decode_limits() {
+        let options = DecodeOptions {
+            limits: DecodeLimits {
+                max_total_bytes: Some(16),
+                max_collection_length: Some(1000),
+                max_depth: Some(2),
+            },
+            ..Default::default()
+        };
+        let decode = |buf: &[u8]| unsafe { super::Deserializer::deserialize(buf, &options) };
+
+        // Header claiming a billion elements.
+        let mut buf = vec![VALUE_LIST, 255];
+        buf.extend_from_slice(&1_000_000_000u32.to_ne_bytes());
+        assert_eq!(decode(&buf), Err(DecodeError::LimitExceeded));
+        buf[0] = VALUE_MAP;
+        assert_eq!(decode(&buf), Err(DecodeError::LimitExceeded));
+
+        assert_eq!(
+            decode(&[VALUE_LIST, 1, VALUE_LIST, 1, 10]),
+            Ok(Value::List(vec![Value::List(vec![10.into()])]))
+        );
+        assert_eq!(
+            decode(&[VALUE_LIST, 1, VALUE_LIST, 1, VALUE_LIST, 0]),
+            Err(DecodeError::LimitExceeded)
+        );
+
+        // Out of line data counts towards total bytes.
+        let buf = large_string_message(&[b'a'; 20]);
+        assert_eq!(decode(&buf), Err(DecodeError::LimitExceeded));
     }
-    fn align_to(&mut self, align: usize) {
-        let m = self.0.len() % align;
-        if m == 0 {
-            return;
+
+    #[cfg(all(feature = "shared_buffer", target_os = "linux"))]
+    #[test]
+    fn test_shared_buffer() {
+        use std::{
+            fs::File,
+            io::{Read, Seek, Write},
+            mem::ManuallyDrop,
+            os::fd::{AsFd, AsRawFd, FromRawFd},
+        };
+
+        use crate::{Context, SharedBuffer, SHARED_BUFFER_MARKER};
+
+        extern "C" {
+            fn memfd_create(
+                name: *const std::os::raw::c_char,
+                flags: std::os::raw::c_uint,
+            ) -> std::os::raw::c_int;
         }
-        let m = align - m;
-        for _ in 0..m {
-            self.write_u8(0);
+
+        let _context = Context::new();
+        let fd = unsafe { memfd_create(b"test\0".as_ptr() as *const _, 0) };
+        assert!(fd >= 0);
+        let mut file = unsafe { File::from_raw_fd(fd) };
+        file.write_all(b"shared").unwrap();
+        let buffer = SharedBuffer::new(file.into(), 6);
+        let original_fd = buffer.as_fd().as_raw_fd();
+
+        let token = buffer.to_token(1);
+        let fd = match &token {
+            Value::List(list) => match list.as_slice() {
+                [Value::String(marker), Value::I64(fd), Value::I64(6), Value::FinalizableHandle(_)] =>
+                {
+                    assert_eq!(marker, SHARED_BUFFER_MARKER);
+                    *fd as i32
+                }
+                _ => panic!("unexpected list {:?}", list),
+            },
+            other => panic!("unexpected value {:?}", other),
+        };
+        // Receiver gets a duplicate while the original is closed with the buffer.
+        assert_ne!(fd, original_fd);
+
+        let path = format!("/proc/self/fd/{}", fd);
+        let target = std::fs::read_link(&path).unwrap();
+        {
+            // Duplicate is owned by the finalizable handle.
+            let mut file = ManuallyDrop::new(unsafe { File::from_raw_fd(fd) });
+            let mut contents = String::new();
+            file.rewind().unwrap();
+            file.read_to_string(&mut contents).unwrap();
+            assert_eq!(contents, "shared");
         }
-    }
-}
 
-fn clone_into_array<A, T>(slice: &[T]) -> A
-where
-    A: Default + AsMut<[T]>,
-    T: Clone,
-{
-    let mut a = A::default();
-    a.as_mut().clone_from_slice(slice);
-    a
+        // Token that is never delivered closes the duplicate.
+        drop(token);
+        assert_ne!(std::fs::read_link(&path).ok(), Some(target));
+    }
 }
@@ -1,29 +1,200 @@
 use std::{
-    cell::{Ref, RefCell, RefMut},
+    cell::{Cell, Ref, RefCell, RefMut},
     collections::HashMap,
+    mem,
     rc::{Rc, Weak},
+    sync::{
+        atomic::{AtomicI64, Ordering},
+        Arc, Mutex,
+    },
+    time::{Duration, Instant},
 };
 
 use crate::{
-    Context, GetMessageChannel, IsolateId, MethodHandler, PostMessageError,
-    RegisteredMethodHandler, Value,
+    Context, GetMessageChannel, Handle, HandlerKind, IsolateId, MethodHandler, PostMessageError,
+    RegisteredMethodHandler, RunLoopSender, Value,
 };
 
+/// First item of every chunk posted by [`EventSink::post_message_chunked`].
+pub const EVENT_CHUNK_MARKER: &str = "nativeshell_event_chunk";
+
+/// Channel name shared between event channel and its sinks, so that sinks
+/// follow the channel when it is moved with [`RegisteredEventChannel::rechannel`].
+type SharedChannelName = Arc<Mutex<String>>;
+
 pub struct EventSink {
     id: i64,
-    channel_name: String,
+    channel_name: SharedChannelName,
     isolate_id: IsolateId,
+    next_transfer_id: Cell<i64>,
+    sampling: Rc<RefCell<SamplingState>>,
+}
+
+#[derive(Default)]
+struct SamplingState {
+    last_post: Option<Instant>,
+    pending: Option<Value>,
+    flush: Option<Handle>,
 }
 
 impl EventSink {
+    /// Sink identifier. Unique across all event channels, so handler registered
+    /// for multiple channels can tell the sinks apart.
     pub fn id(&self) -> i64 {
         self.id
     }
 
+    /// Name of the event channel this sink belongs to.
+    pub fn channel_name(&self) -> String {
+        self.channel_name.lock().unwrap().clone()
+    }
+
     pub fn post_message<V: Into<Value>>(&self, message: V) -> Result<(), PostMessageError> {
         let context = Context::get();
         let channel = context.message_channel();
-        channel.post_message(self.isolate_id, &self.channel_name, message.into())
+        channel.post_message(self.isolate_id, &self.channel_name(), message.into())
+    }
+
+    /// Posts message unless the previous sampled message was posted less than
+    /// `min_interval` ago. In that case the message is kept and posted once
+    /// the interval elapses, unless replaced by a newer message in the meanwhile.
+    /// This limits the rate of messages while making sure that the latest
+    /// message is always delivered.
+    ///
+    /// Pending message is dropped together with the sink. Errors when posting
+    /// pending message are ignored.
+    pub fn post_message_sampled<V: Into<Value>>(
+        &self,
+        message: V,
+        min_interval: Duration,
+    ) -> Result<(), PostMessageError> {
+        let now = Instant::now();
+        let mut state = self.sampling.borrow_mut();
+        let next_post = state.last_post.map(|last| last + min_interval);
+        match next_post {
+            Some(next_post) if next_post > now => {
+                state.pending = Some(message.into());
+                if state.flush.is_none() {
+                    let sampling = Rc::downgrade(&self.sampling);
+                    let channel_name = self.channel_name.clone();
+                    let isolate_id = self.isolate_id;
+                    let flush = Context::get()
+                        .run_loop()
+                        .schedule(next_post - now, move || {
+                            if let Some(sampling) = sampling.upgrade() {
+                                let message = {
+                                    let mut state = sampling.borrow_mut();
+                                    if let Some(mut flush) = state.flush.take() {
+                                        flush.detach();
+                                    }
+                                    state.last_post = Some(Instant::now());
+                                    state.pending.take()
+                                };
+                                if let Some(message) = message {
+                                    let context = Context::get();
+                                    let channel = context.message_channel();
+                                    let channel_name = channel_name.lock().unwrap().clone();
+                                    channel
+                                        .post_message(isolate_id, &channel_name, message)
+                                        .ok();
+                                }
+                            }
+                        });
+                    state.flush = Some(flush);
+                }
+                Ok(())
+            }
+            _ => {
+                state.last_post = Some(now);
+                state.pending = None;
+                state.flush = None;
+                drop(state);
+                self.post_message(message)
+            }
+        }
+    }
+
+    /// Returns sink that can be moved to other threads. Must be called on
+    /// run loop thread.
+    pub fn sendable(&self) -> SendableEventSink {
+        SendableEventSink {
+            id: self.id,
+            channel_name: self.channel_name.clone(),
+            isolate_id: self.isolate_id,
+            sender: Context::get().run_loop_sender(),
+        }
+    }
+
+    /// Posts message split into chunks of at most `chunk_size` bytes. `U8List`
+    /// messages are split as they are, other values are first encoded using
+    /// [`Value::encode`].
+    ///
+    /// Each chunk is posted as
+    /// `[EVENT_CHUNK_MARKER, transfer_id, index, is_last, is_encoded, bytes]`.
+    /// Chunks of single transfer are posted in order and the last chunk has
+    /// `is_last` set. Receiver concatenates the bytes and, if `is_encoded` is
+    /// set, decodes the result. Dart `NativeEventChannel` does this
+    /// automatically.
+    pub fn post_message_chunked<V: Into<Value>>(
+        &self,
+        message: V,
+        chunk_size: usize,
+    ) -> Result<(), PostMessageError> {
+        assert!(chunk_size > 0, "chunk_size must not be zero");
+        let mut value = message.into();
+        let (bytes, encoded) = match &mut value {
+            Value::U8List(bytes) => (mem::take(bytes), false),
+            _ => (value.encode(), true),
+        };
+        let transfer_id = self.next_transfer_id.get();
+        self.next_transfer_id.set(transfer_id + 1);
+        let chunk_count = bytes.len().max(1).div_ceil(chunk_size);
+        for index in 0..chunk_count {
+            let start = (index * chunk_size).min(bytes.len());
+            let end = (start + chunk_size).min(bytes.len());
+            self.post_message(vec![
+                EVENT_CHUNK_MARKER.into(),
+                transfer_id.into(),
+                (index as i64).into(),
+                (index == chunk_count - 1).into(),
+                encoded.into(),
+                Value::U8List(bytes[start..end].to_vec()),
+            ])?;
+        }
+        Ok(())
+    }
+}
+
+/// Event sink that can be used from any thread. Messages are posted from
+/// the run loop thread that created the sink (see [`EventSink::sendable`]).
+#[derive(Clone)]
+pub struct SendableEventSink {
+    id: i64,
+    channel_name: SharedChannelName,
+    isolate_id: IsolateId,
+    sender: RunLoopSender,
+}
+
+impl SendableEventSink {
+    pub fn id(&self) -> i64 {
+        self.id
+    }
+
+    /// Schedules the message to be posted on run loop thread and returns
+    /// immediately. Messages are posted in order. Errors (i.e. isolate has
+    /// already exited) are ignored.
+    pub fn post<V: Into<Value>>(&self, message: V) {
+        let message = message.into();
+        let channel_name = self.channel_name.clone();
+        let isolate_id = self.isolate_id;
+        self.sender.send(move || {
+            let context = Context::get();
+            let channel = context.message_channel();
+            let channel_name = channel_name.lock().unwrap().clone();
+            channel
+                .post_message(isolate_id, &channel_name, message)
+                .ok();
+        });
     }
 }
 
@@ -45,7 +216,7 @@ pub trait EventHandler: Sized + 'static {
 }
 
 pub struct RegisteredEventChannel<T: EventHandler> {
-    _internal: RegisteredMethodHandler<EventChannelInternal<T>>,
+    internal: RegisteredMethodHandler<EventChannelInternal<T>>,
     handler: Rc<RefCell<T>>,
 }
 
@@ -54,41 +225,65 @@ impl<T: EventHandler> RegisteredEventChannel<T> {
         Self::new_ref(channel, Rc::new(RefCell::new(handler)))
     }
 
+    /// Registers shared handler for the channel. Same handler can be registered
+    /// for multiple channels; [`EventHandler::assign_weak_self`] is called for
+    /// every registration. Sink identifiers are unique across channels and
+    /// [`EventSink::channel_name`] tells which channel the sink belongs to.
     pub fn new_ref(channel: &str, handler: Rc<RefCell<T>>) -> Self {
         handler
             .borrow_mut()
             .assign_weak_self(Rc::downgrade(&handler));
 
+        let internal = EventChannelInternal {
+            handler: handler.clone(),
+            channel_name: Arc::new(Mutex::new(channel.into())),
+            inner: RefCell::new(Inner {
+                isolate_to_sink: HashMap::new(),
+            }),
+        };
         Self {
-            _internal: EventChannelInternal {
-                handler: handler.clone(),
-                channel_name: channel.into(),
-                inner: RefCell::new(Inner {
-                    next_sink_id: 1,
-                    isolate_to_sink: HashMap::new(),
-                }),
-            }
-            .register(channel),
+            internal: RegisteredMethodHandler::new_with_kind(
+                channel,
+                internal,
+                HandlerKind::EventChannel,
+            ),
             handler,
         }
     }
 
+    /// Name of the channel the event handler is currently registered for.
+    pub fn channel(&self) -> String {
+        self.internal.channel()
+    }
+
+    /// Moves the event channel to `new_name`. Active subscriptions are kept;
+    /// existing sinks (including sendable sinks) post subsequent events on the
+    /// new channel and `cancel` calls are expected on the new channel as well.
+    /// See [`RegisteredMethodHandler::rechannel`].
+    pub fn rechannel(&self, new_name: &str) {
+        self.internal.rechannel(new_name);
+        *self.internal.handler().channel_name.lock().unwrap() = new_name.into();
+    }
+
     pub fn borrow(&self) -> Ref<T> {
+        self.internal.check_thread("RegisteredEventChannel");
         self.handler.borrow()
     }
 
     pub fn borrow_mut(&self) -> RefMut<T> {
+        self.internal.check_thread("RegisteredEventChannel");
         self.handler.borrow_mut()
     }
 }
 
+static NEXT_SINK_ID: AtomicI64 = AtomicI64::new(1);
+
 struct Inner {
-    next_sink_id: i64,
     isolate_to_sink: HashMap<IsolateId, i64>,
 }
 
 struct EventChannelInternal<T: EventHandler> {
-    channel_name: String,
+    channel_name: SharedChannelName,
     pub handler: Rc<RefCell<T>>,
     inner: RefCell<Inner>,
 }
@@ -98,12 +293,13 @@ impl<T: EventHandler> MethodHandler for EventChannelInternal<T> {
         match call.method.as_str() {
             "listen" => {
                 let mut inner = self.inner.borrow_mut();
-                let sink_id = inner.next_sink_id;
-                inner.next_sink_id += 1;
+                let sink_id = NEXT_SINK_ID.fetch_add(1, Ordering::Relaxed);
                 let sink = EventSink {
                     id: sink_id,
                     channel_name: self.channel_name.clone(),
                     isolate_id: call.isolate,
+                    next_transfer_id: Cell::new(0),
+                    sampling: Default::default(),
                 };
                 inner.isolate_to_sink.insert(call.isolate, sink_id);
                 self.handler
@@ -3,20 +3,39 @@ use std::{
     collections::HashMap,
     ffi::c_void,
     rc::Rc,
+    sync::{
+        atomic::{AtomicUsize, Ordering},
+        Arc,
+    },
 };
 
+use once_cell::sync::OnceCell;
+
 use crate::{
     ffi::{raw, DartPort, DartValue, NativePort},
     message_channel::codec::Serializer,
-    Context, FinalizableHandleState, IsolateId, Value,
+    Context, DecodeOptions, FinalizableHandleState, Handle, IsolateId, RunLoopSender,
+    StringDecoding, TraceEvent, Value,
 };
 
-use super::codec::Deserializer;
+use super::{
+    codec::{DeserializeError, Deserializer},
+    native_vector::take_vec,
+};
 
 #[path = "message_channel_common.rs"]
 mod common;
 pub use common::*;
 
+/// Returns sender for run loop of the thread that isolate is attached on.
+/// All isolates are attached on the main thread.
+pub(crate) fn isolate_run_loop_sender(_isolate_id: IsolateId) -> RunLoopSender {
+    RUN_LOOP_SENDER
+        .get()
+        .expect("MessageChannel was not initialized!")
+        .clone()
+}
+
 pub struct MessageChannel {
     // used to get isolate exit notification
     native_port: RefCell<Option<NativePort>>,
@@ -24,11 +43,21 @@ pub struct MessageChannel {
     delegates: RefCell<HashMap<String, Rc<dyn MessageChannelDelegate>>>,
     pending_replies: RefCell<HashMap<i64, PendingReply>>,
     next_message_id: Cell<i64>,
+    decode_options: RefCell<DecodeOptions>,
+    queued_messages: QueuedMessages,
+    outbound_queue: OutboundQueue,
+    inbound_backlog: InboundBacklog,
+    isolate_tasks: IsolateTasks,
+    channel_readiness: ChannelReadiness,
+    handler_kinds: HandlerKinds,
+    reply_watchdog: ReplyWatchdog,
 }
 
 struct PendingReply {
     reply: Box<dyn FnOnce(Result<Value, SendMessageError>)>,
     isolate_id: IsolateId,
+    // Dropping the reply disarms the watchdog.
+    watchdog: Option<Handle>,
 }
 
 impl MessageChannel {
@@ -37,32 +66,93 @@ impl MessageChannel {
             .set(Context::get().run_loop().new_sender())
             .map_err(|_| ())
             .expect("Message channel already initialized");
+        let inbound_backlog = InboundBacklog::default();
+        INBOUND_PENDING
+            .set(inbound_backlog.pending())
+            .map_err(|_| ())
+            .expect("Message channel already initialized");
         Self {
             native_port: RefCell::new(None),
             isolates: RefCell::new(HashMap::new()),
             delegates: RefCell::new(HashMap::new()),
             pending_replies: RefCell::new(HashMap::new()),
             next_message_id: Cell::new(0),
+            decode_options: RefCell::new(DecodeOptions {
+                string_decoding: StringDecoding::Lossy,
+                ..Default::default()
+            }),
+            queued_messages: QueuedMessages::default(),
+            outbound_queue: OutboundQueue::default(),
+            inbound_backlog,
+            isolate_tasks: IsolateTasks::default(),
+            channel_readiness: ChannelReadiness::default(),
+            handler_kinds: HandlerKinds::default(),
+            reply_watchdog: ReplyWatchdog::default(),
         }
     }
 
+    /// Sets options used to decode messages received from Dart. Unlike
+    /// [`DecodeOptions::default`], strings are decoded with
+    /// [`StringDecoding::Lossy`] unless set otherwise.
+    ///
+    /// Messages that fail to decode are reported as
+    /// [`TraceEvent::MessageDecodeFailed`] and dropped. If the message expects
+    /// a reply, Dart side receives `MessageDecodeException`.
+    pub fn set_decode_options(&self, options: DecodeOptions) {
+        self.decode_options.replace(options);
+    }
+
+    /// Sends message to the handler registered for `channel` in target isolate
+    /// and calls `reply` with the result.
+    ///
+    /// Messages sent to an isolate, either through `send_message` or
+    /// [`MessageChannel::post_message`], are delivered in the order in which
+    /// these methods were called.
+    ///
+    /// Dropping or cancelling the returned handle discards the reply; `reply`
+    /// will not be called. The message itself is still delivered. Call
+    /// [`Handle::detach`] to keep the reply without holding the handle.
+    #[must_use]
     pub fn send_message<F>(
         &self,
         target_isolate: IsolateId,
         channel: &str,
         message: Value,
         reply: F,
-    ) where
+    ) -> Handle
+    where
         F: FnOnce(Result<Value, SendMessageError>) + 'static,
     {
         let isolate = self.isolates.borrow().get(&target_isolate).cloned();
         if let Some(isolate) = isolate {
             let id = self.next_message_id.replace(self.next_message_id.get() + 1);
+            let watchdog = self.reply_watchdog.arm(channel, move || {
+                let reply = Context::current().and_then(|context| {
+                    let reply = context
+                        .message_channel()
+                        .pending_replies
+                        .borrow_mut()
+                        .remove(&id);
+                    reply
+                });
+                match reply {
+                    Some(mut reply) => {
+                        // Timer has already fired.
+                        if let Some(mut watchdog) = reply.watchdog.take() {
+                            watchdog.detach();
+                        }
+                        (reply.reply)(Err(SendMessageError::Timeout));
+                        true
+                    }
+                    None => false,
+                }
+            });
             self.pending_replies.borrow_mut().insert(
                 id,
                 PendingReply {
                     reply: Box::new(reply),
                     isolate_id: target_isolate,
+                    watchdog,
                 },
             );
             let v = Serializer::serialize(
@@ -73,6 +163,7 @@ impl MessageChannel {
                     message,
                 ]
                 .into(),
+                target_isolate,
             );
 
             if !isolate.send(DartValue::Array(v)) {
@@ -81,11 +172,25 @@ impl MessageChannel {
                     (reply.reply)(Err(SendMessageError::MessageRefused));
                 }
             }
+            Handle::new(move || {
+                if let Some(context) = Context::current() {
+                    let reply = context
+                        .message_channel()
+                        .pending_replies
+                        .borrow_mut()
+                        .remove(&id);
+                    drop(reply);
+                }
+            })
         } else {
             reply(Err(SendMessageError::InvalidIsolate));
+            Handle::new(|| {})
         }
     }
 
+    /// Posts message to the handler registered for `channel` in target isolate
+    /// without waiting for reply. Ordering relative to other messages is same
+    /// as for [`MessageChannel::send_message`].
     pub fn post_message(
         &self,
         target_isolate: IsolateId,
@@ -101,6 +206,7 @@ impl MessageChannel {
                     message,
                 ]
                 .into(),
+                target_isolate,
             );
             if !isolate.send(DartValue::Array(v)) {
                 Err(PostMessageError::MessageRefused)
@@ -116,11 +222,66 @@ impl MessageChannel {
     where
         F: MessageChannelDelegate + 'static,
     {
-        self.delegates.borrow_mut().insert(channel.into(), delegate);
+        self.register_delegate_with_options(channel, delegate, Default::default());
+    }
+
+    pub fn register_delegate_with_options<F>(
+        &self,
+        channel: &str,
+        delegate: Rc<F>,
+        options: RegisterDelegateOptions,
+    ) where
+        F: MessageChannelDelegate + 'static,
+    {
+        self.delegates
+            .borrow_mut()
+            .insert(channel.into(), delegate.clone());
+        self.handler_kinds.insert(channel, HandlerKind::Delegate);
+        if options.replay_joined_isolates {
+            let mut isolates: Vec<IsolateId> = self.isolates.borrow().keys().cloned().collect();
+            isolates.sort_unstable();
+            for isolate_id in isolates {
+                delegate.on_isolate_joined(isolate_id);
+            }
+        }
     }
 
     pub fn unregister_delegate(&self, channel: &str) {
         self.delegates.borrow_mut().remove(channel);
+        self.channel_readiness.reset_channel(channel);
+        self.handler_kinds.remove(channel);
+    }
+
+    fn queued_messages(&self) -> &QueuedMessages {
+        &self.queued_messages
+    }
+
+    fn outbound_queue(&self) -> &OutboundQueue {
+        &self.outbound_queue
+    }
+
+    fn inbound_backlog(&self) -> &InboundBacklog {
+        &self.inbound_backlog
+    }
+
+    fn isolate_tasks(&self) -> &IsolateTasks {
+        &self.isolate_tasks
+    }
+
+    fn channel_readiness(&self) -> &ChannelReadiness {
+        &self.channel_readiness
+    }
+
+    fn handler_kinds(&self) -> &HandlerKinds {
+        &self.handler_kinds
+    }
+
+    fn reply_watchdog(&self) -> &ReplyWatchdog {
+        &self.reply_watchdog
+    }
+
+    fn has_isolate(&self, isolate_id: IsolateId) -> bool {
+        self.isolates.borrow().contains_key(&isolate_id)
     }
 
     fn all_delegates(&self) -> Vec<Rc<dyn MessageChannelDelegate>> {
@@ -154,6 +315,48 @@ impl MessageChannel {
         }
     }
 
+    fn on_message_received(&self, isolate_id: IsolateId, data: Vec<u8>) {
+        let value =
+            unsafe { Deserializer::deserialize_message(&data, &self.decode_options.borrow()) };
+        match value {
+            Ok(value) => self.on_value_received(isolate_id, value),
+            Err(err) => self.handle_decode_error(isolate_id, err),
+        }
+    }
+
+    fn handle_decode_error(&self, isolate_id: IsolateId, err: DeserializeError) {
+        let mut header = err.header.into_iter();
+        let message = header.next().and_then(|v| String::try_from(v).ok());
+        let reply_id = header.next().and_then(|v| i64::try_from(v).ok());
+        let channel = header.next().and_then(|v| String::try_from(v).ok());
+        let channel = match (message.as_deref(), reply_id, channel) {
+            (Some("message"), Some(reply_id), Some(channel)) => {
+                let v = Serializer::serialize(
+                    vec![
+                        Value::String("reply_decode_error".into()),
+                        reply_id.into(),
+                        channel.clone().into(),
+                        err.error.to_string().into(),
+                    ]
+                    .into(),
+                    isolate_id,
+                );
+                let isolate = self.isolates.borrow().get(&isolate_id).cloned();
+                if let Some(isolate) = isolate {
+                    isolate.send(DartValue::Array(v));
+                }
+                Some(channel)
+            }
+            _ => None,
+        };
+        Context::get()
+            .run_loop()
+            .emit_trace_event(TraceEvent::MessageDecodeFailed {
+                channel,
+                error: err.error,
+            });
+    }
+
     fn on_value_received(&self, isolate_id: IsolateId, value: Value) {
         if self.handle_message(isolate_id, value).is_none() {
             panic!("MessageChannel: Malformed message");
@@ -167,6 +370,7 @@ impl MessageChannel {
                 (handle as i64).into(),
             ]
             .into(),
+            target_isolate,
         );
         let isolate = self.isolates.borrow().get(&target_isolate).cloned();
         if let Some(isolate) = isolate {
@@ -185,6 +389,14 @@ impl MessageChannel {
             "no_handler" => {
                 self.handle_no_handler(iter.next()?.try_into().ok()?, iter.next()?.try_into().ok()?)
             }
+            "ping" => {
+                let channel: String = iter.next()?.try_into().ok()?;
+                self.channel_readiness.contact(isolate_id, &channel);
+            }
+            "handler_registered" => {
+                let channel: String = iter.next()?.try_into().ok()?;
+                self.deliver_queued_messages(isolate_id, &channel);
+            }
             "reply" => {
                 self.handle_reply(iter.next()?.try_into().ok()?, iter.next()?);
             }
@@ -192,6 +404,7 @@ impl MessageChannel {
                 let reply_id: i64 = iter.next()?.try_into().ok()?;
                 let channel: String = iter.next()?.try_into().ok()?;
                 let message = iter.next()?;
+                self.channel_readiness.contact(isolate_id, &channel);
                 self.handle_send_message(isolate_id, channel, reply_id, message);
             }
             _ => {}
@@ -200,19 +413,22 @@ impl MessageChannel {
     }
 
     fn handle_no_channel(&self, reply_id: i64, channel: String) {
-        if let Some(reply) = self.pending_replies.borrow_mut().remove(&reply_id) {
+        let reply = self.pending_replies.borrow_mut().remove(&reply_id);
+        if let Some(reply) = reply {
             (reply.reply)(Err(SendMessageError::ChannelNotFound { channel }));
         }
     }
 
     fn handle_no_handler(&self, reply_id: i64, channel: String) {
-        if let Some(reply) = self.pending_replies.borrow_mut().remove(&reply_id) {
+        let reply = self.pending_replies.borrow_mut().remove(&reply_id);
+        if let Some(reply) = reply {
             (reply.reply)(Err(SendMessageError::HandlerNotRegistered { channel }));
         }
     }
 
     fn handle_reply(&self, reply_id: i64, value: Value) {
-        if let Some(reply) = self.pending_replies.borrow_mut().remove(&reply_id) {
+        let reply = self.pending_replies.borrow_mut().remove(&reply_id);
+        if let Some(reply) = reply {
             (reply.reply)(Ok(value));
         }
     }
@@ -232,15 +448,28 @@ impl MessageChannel {
             .cloned()
             .expect("received message from unknown isolate");
         match delegate {
-            Some(delegate) => {
+            Some(delegate) if delegate.has_handler() => {
                 let reply = Box::new(move |value: Value| {
                     let v = Serializer::serialize(
                         vec![Value::String("reply".into()), reply_id.into(), value].into(),
+                        isolate_id,
                     );
                     port.send(DartValue::Array(v))
                 });
                 delegate.on_message(isolate_id, message, reply);
             }
+            Some(_) => {
+                let v = Serializer::serialize(
+                    vec![
+                        Value::String("reply_no_handler".into()),
+                        reply_id.into(),
+                        channel.into(),
+                    ]
+                    .into(),
+                    isolate_id,
+                );
+                port.send(DartValue::Array(v));
+            }
             None => {
                 let v = Serializer::serialize(
                     vec![
@@ -249,6 +478,7 @@ impl MessageChannel {
                         channel.into(),
                     ]
                     .into(),
+                    isolate_id,
                 );
                 port.send(DartValue::Array(v));
             }
@@ -274,10 +504,15 @@ impl MessageChannel {
             })
             .collect();
         for reply in replies_to_remove {
-            if let Some(reply) = self.pending_replies.borrow_mut().remove(&reply) {
+            let reply = self.pending_replies.borrow_mut().remove(&reply);
+            if let Some(reply) = reply {
                 (reply.reply)(Err(SendMessageError::IsolateShutDown));
             }
         }
+        self.queued_messages.fail(isolate_id);
+        self.outbound_queue.fail(isolate_id);
+        self.isolate_tasks.abort(isolate_id);
+        self.channel_readiness.reset_isolate(isolate_id);
         // Make sure to execute all finalizers that didn't have chance to register
         // with the isolate.
         FinalizableHandleState::get().finalize_all(isolate_id);
@@ -318,13 +553,20 @@ pub(super) extern "C" fn register_isolate(port: i64, isolate_id: *mut c_void) ->
     isolate_id
 }
 
+// Backlog counter of the message channel, see `InboundBacklog::pending`.
+static INBOUND_PENDING: OnceCell<Arc<AtomicUsize>> = OnceCell::new();
+
 pub(super) extern "C" fn post_message(isolate_id: IsolateId, message: *mut u8, len: u64) {
     let sender = RUN_LOOP_SENDER.get().unwrap();
-    let vec = unsafe { Vec::from_raw_parts(message, len as usize, len as usize) };
+    let vec = unsafe { take_vec(message, len as usize) };
+    INBOUND_PENDING
+        .get()
+        .unwrap()
+        .fetch_add(1, Ordering::SeqCst);
     sender.send(move || {
-        let value = unsafe { Deserializer::deserialize(&vec) };
-        Context::get()
-            .message_channel()
-            .on_value_received(isolate_id, value);
+        let context = Context::get();
+        let channel = context.message_channel();
+        channel.inbound_backlog.message_processed();
+        channel.on_message_received(isolate_id, vec);
     });
 }
@@ -1,16 +1,40 @@
-use std::{cell::Ref, fmt::Display};
+use std::{
+    cell::{Cell, Ref, RefCell},
+    collections::HashMap,
+    fmt::Display,
+    rc::{Rc, Weak},
+    sync::{
+        atomic::{AtomicUsize, Ordering},
+        Arc,
+    },
+    time::{Duration, Instant},
+};
 
+use futures::{
+    future::{abortable, AbortHandle},
+    Future,
+};
 use once_cell::sync::OnceCell;
 
-use crate::{Context, IsolateId, MessageChannel, RunLoopSender, Value};
+use crate::{
+    util::{CompletableFuture, FutureCompleter},
+    Context, Handle, IsolateId, JoinHandle, MessageChannel, RunLoop, RunLoopSender, TraceEvent,
+    Value,
+};
 
 #[derive(Debug)]
 pub enum SendMessageError {
     InvalidIsolate,
     MessageRefused,
     IsolateShutDown,
-    ChannelNotFound { channel: String },
-    HandlerNotRegistered { channel: String },
+    ChannelNotFound {
+        channel: String,
+    },
+    HandlerNotRegistered {
+        channel: String,
+    },
+    /// Reply did not arrive in time, see [`MessageChannel::set_reply_watchdog`].
+    Timeout,
 }
 
 #[derive(Debug)]
@@ -37,6 +61,7 @@ impl Display for SendMessageError {
                     channel
                 )
             }
+            Self::Timeout => write!(f, "timed out waiting for response"),
         }
     }
 }
@@ -53,10 +78,52 @@ impl Display for PostMessageError {
 impl std::error::Error for SendMessageError {}
 impl std::error::Error for PostMessageError {}
 
+/// Priority of message sent to Dart through
+/// [`MessageChannel::send_message_with_priority`] or
+/// [`MessageChannel::post_message_with_priority`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash, Default)]
+pub enum MessagePriority {
+    /// Message is queued and dispatched at the end of current run loop turn,
+    /// after all normal priority messages sent during the turn.
+    Low,
+    /// Message is dispatched immediately.
+    #[default]
+    Normal,
+}
+
+/// Options for [`MessageChannel::register_delegate_with_options`].
+#[derive(Debug, Clone, Default)]
+pub struct RegisterDelegateOptions {
+    /// Call [`MessageChannelDelegate::on_isolate_joined`] during registration
+    /// for every isolate that joined before the delegate was registered.
+    pub replay_joined_isolates: bool,
+}
+
+/// Kind of handler registered for channel, see
+/// [`MessageChannel::registered_channels`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum HandlerKind {
+    /// [`MethodHandler`](crate::MethodHandler) or
+    /// [`SimpleMethodHandler`](crate::SimpleMethodHandler).
+    Method,
+    /// [`AsyncMethodHandler`](crate::AsyncMethodHandler).
+    AsyncMethod,
+    /// [`EventHandler`](crate::EventHandler) registered as event channel.
+    EventChannel,
+    /// Delegate registered directly through [`MessageChannel::register_delegate`].
+    Delegate,
+}
+
 pub trait MessageChannelDelegate {
     fn on_isolate_joined(&self, isolate: IsolateId);
     fn on_message(&self, isolate: IsolateId, message: Value, reply: Box<dyn FnOnce(Value) -> bool>);
     fn on_isolate_exited(&self, isolate: IsolateId);
+
+    /// Whether the delegate is able to handle messages. Messages sent to delegate
+    /// without handler are replied with [`SendMessageError::HandlerNotRegistered`].
+    fn has_handler(&self) -> bool {
+        true
+    }
 }
 
 pub trait GetMessageChannel {
@@ -69,4 +136,638 @@ impl GetMessageChannel for Context {
     }
 }
 
+impl MessageChannel {
+    /// Like [`MessageChannel::send_message`], but if the target isolate has no
+    /// handler registered for the channel yet, the message is kept and delivered
+    /// once the handler gets registered. If that doesn't happen within `timeout`
+    /// reply is called with the original [`SendMessageError::ChannelNotFound`] or
+    /// [`SendMessageError::HandlerNotRegistered`] error.
+    ///
+    /// Useful during isolate startup, when native code may send a message
+    /// before Dart code had a chance to set up the handler.
+    ///
+    /// Messages that had to wait for the handler are not covered by the
+    /// ordering guarantee of [`MessageChannel::send_message`]; they may be
+    /// delivered after messages sent later.
+    pub fn send_message_when_ready<F>(
+        &self,
+        target_isolate: IsolateId,
+        channel: &str,
+        message: Value,
+        timeout: Duration,
+        reply: F,
+    ) where
+        F: FnOnce(Result<Value, SendMessageError>) + 'static,
+    {
+        self.send_message_until(
+            target_isolate,
+            channel.into(),
+            message,
+            Instant::now() + timeout,
+            Box::new(reply),
+        );
+    }
+
+    fn send_message_until(
+        &self,
+        target_isolate: IsolateId,
+        channel: String,
+        message: Value,
+        deadline: Instant,
+        reply: Box<dyn FnOnce(Result<Value, SendMessageError>)>,
+    ) {
+        let retry_message = message.clone();
+        let retry_channel = channel.clone();
+        self.send_message(target_isolate, &channel, message, move |res| match res {
+            Err(
+                err @ (SendMessageError::ChannelNotFound { .. }
+                | SendMessageError::HandlerNotRegistered { .. }),
+            ) if Instant::now() < deadline => {
+                Context::get().message_channel().queued_messages().queue(
+                    target_isolate,
+                    retry_channel,
+                    retry_message,
+                    deadline,
+                    err,
+                    reply,
+                );
+            }
+            res => reply(res),
+        })
+        .detach();
+    }
+
+    /// Like [`MessageChannel::send_message`] but with given priority. Low
+    /// priority messages are dispatched after normal priority messages sent
+    /// during the same run loop turn. Messages with same priority keep their
+    /// order.
+    #[must_use]
+    pub fn send_message_with_priority<F>(
+        &self,
+        target_isolate: IsolateId,
+        channel: &str,
+        message: Value,
+        priority: MessagePriority,
+        reply: F,
+    ) -> Handle
+    where
+        F: FnOnce(Result<Value, SendMessageError>) + 'static,
+    {
+        match priority {
+            MessagePriority::Normal => self.send_message(target_isolate, channel, message, reply),
+            MessagePriority::Low => {
+                if !self.has_isolate(target_isolate) {
+                    reply(Err(SendMessageError::InvalidIsolate));
+                    return Handle::new(|| {});
+                }
+                let slot = Rc::new(RefCell::new(Some(reply)));
+                let slot_clone = slot.clone();
+                self.outbound_queue().push(
+                    target_isolate,
+                    OutboundMessage {
+                        channel: channel.into(),
+                        message,
+                        reply: Some(Box::new(move |res| {
+                            let reply = slot_clone.borrow_mut().take();
+                            if let Some(reply) = reply {
+                                reply(res);
+                            }
+                        })),
+                    },
+                );
+                Handle::new(move || {
+                    let reply = slot.borrow_mut().take();
+                    drop(reply);
+                })
+            }
+        }
+    }
+
+    /// Like [`MessageChannel::post_message`] but with given priority. See
+    /// [`MessageChannel::send_message_with_priority`] for ordering. Low
+    /// priority message refused by the isolate when dispatched is dropped.
+    pub fn post_message_with_priority(
+        &self,
+        target_isolate: IsolateId,
+        channel: &str,
+        message: Value,
+        priority: MessagePriority,
+    ) -> Result<(), PostMessageError> {
+        match priority {
+            MessagePriority::Normal => self.post_message(target_isolate, channel, message),
+            MessagePriority::Low => {
+                if !self.has_isolate(target_isolate) {
+                    return Err(PostMessageError::InvalidIsolate);
+                }
+                self.outbound_queue().push(
+                    target_isolate,
+                    OutboundMessage {
+                        channel: channel.into(),
+                        message,
+                        reply: None,
+                    },
+                );
+                Ok(())
+            }
+        }
+    }
+
+    /// Sets observer notified when backlog of messages received from Dart that
+    /// are waiting to be processed crosses one of the saturation thresholds
+    /// (see [`MessageChannel::set_saturation_thresholds`]). The observer is
+    /// called with number of pending messages, both when backlog rises above
+    /// a threshold and when it falls back below it.
+    ///
+    /// Backlog is sampled on the run loop thread whenever a received message
+    /// is about to be processed.
+    pub fn set_saturation_observer(&self, observer: Box<dyn Fn(usize /* pending */)>) {
+        self.inbound_backlog()
+            .observer
+            .replace(Some(observer.into()));
+    }
+
+    /// Sets backlog thresholds for the saturation observer. Defaults to
+    /// [`DEFAULT_SATURATION_THRESHOLDS`].
+    pub fn set_saturation_thresholds(&self, thresholds: &[usize]) {
+        let backlog = self.inbound_backlog();
+        let mut thresholds = thresholds.to_vec();
+        thresholds.sort_unstable();
+        thresholds.dedup();
+        backlog.thresholds.replace(thresholds);
+        backlog.level.set(0);
+    }
+
+    /// Sets global timeout for replies to messages sent through
+    /// [`MessageChannel::send_message`] and methods built on top of it. Reply
+    /// that doesn't arrive within `timeout` is resolved with
+    /// [`SendMessageError::Timeout`] and a late reply from Dart is ignored.
+    /// This prevents pending replies from accumulating when Dart never replies.
+    ///
+    /// Only affects messages sent after this call. `None` disables the
+    /// watchdog (default).
+    pub fn set_reply_watchdog(&self, timeout: Option<Duration>) {
+        self.reply_watchdog().timeout.set(timeout);
+    }
+
+    /// Returns future that resolves once Dart has contacted the channel, either
+    /// by pinging it (`MessageChannel.ping()` in Dart) or by sending message to
+    /// it. Resolves with the isolate that made the first contact. If that
+    /// already happened the future resolves immediately.
+    ///
+    /// Contact is forgotten when the delegate for channel is unregistered or
+    /// when the isolate that made the contact exits. Futures that are still
+    /// pending stay armed and resolve once the channel is contacted again;
+    /// when registered handler is moved to other channel (see
+    /// [`RegisteredMethodHandler::rechannel`](crate::RegisteredMethodHandler::rechannel))
+    /// they are moved along with it.
+    pub fn channel_ready(&self, channel: &str) -> CompletableFuture<IsolateId> {
+        self.channel_readiness().wait(channel)
+    }
+
+    pub(crate) fn move_channel_ready(&self, old_channel: &str, new_channel: &str) {
+        self.channel_readiness()
+            .move_waiting(old_channel, new_channel);
+    }
+
+    /// Returns channels that currently have delegate registered together with
+    /// kind of the handler, sorted by channel name.
+    pub fn registered_channels(&self) -> Vec<(String, HandlerKind)> {
+        let mut channels: Vec<_> = self
+            .handler_kinds()
+            .kinds
+            .borrow()
+            .iter()
+            .map(|(channel, kind)| (channel.clone(), *kind))
+            .collect();
+        channels.sort_by(|a, b| a.0.cmp(&b.0));
+        channels
+    }
+
+    /// Registers delegate and records kind of the handler behind it.
+    pub(crate) fn register_delegate_with_kind<F>(
+        &self,
+        channel: &str,
+        delegate: Rc<F>,
+        kind: HandlerKind,
+    ) where
+        F: MessageChannelDelegate + 'static,
+    {
+        self.register_delegate(channel, delegate);
+        self.handler_kinds().insert(channel, kind);
+    }
+
+    /// Delivers messages queued for channel after handler has been registered.
+    pub(super) fn deliver_queued_messages(&self, isolate: IsolateId, channel: &str) {
+        for message in self
+            .queued_messages()
+            .take(|m| m.isolate == isolate && m.channel == channel)
+        {
+            self.send_message_until(
+                message.isolate,
+                message.channel,
+                message.message,
+                message.deadline,
+                message.reply,
+            );
+        }
+    }
+}
+
+/// Messages waiting for handler to be registered on target isolate.
+#[derive(Default)]
+pub(super) struct QueuedMessages {
+    next_id: Cell<usize>,
+    messages: RefCell<Vec<QueuedMessage>>,
+}
+
+struct QueuedMessage {
+    id: usize,
+    isolate: IsolateId,
+    channel: String,
+    message: Value,
+    deadline: Instant,
+    reply: Box<dyn FnOnce(Result<Value, SendMessageError>)>,
+    timeout: Handle,
+}
+
+impl QueuedMessages {
+    fn queue(
+        &self,
+        isolate: IsolateId,
+        channel: String,
+        message: Value,
+        deadline: Instant,
+        error: SendMessageError,
+        reply: Box<dyn FnOnce(Result<Value, SendMessageError>)>,
+    ) {
+        let id = self.next_id.replace(self.next_id.get() + 1);
+        let timeout = Context::get().run_loop().schedule(
+            deadline.saturating_duration_since(Instant::now()),
+            move || {
+                let message = Context::get()
+                    .message_channel()
+                    .queued_messages()
+                    .take(|m| m.id == id)
+                    .pop();
+                if let Some(mut message) = message {
+                    message.timeout.detach();
+                    (message.reply)(Err(error));
+                }
+            },
+        );
+        self.messages.borrow_mut().push(QueuedMessage {
+            id,
+            isolate,
+            channel,
+            message,
+            deadline,
+            reply,
+            timeout,
+        });
+    }
+
+    /// Fails messages queued for isolate that has exited.
+    pub(super) fn fail(&self, isolate: IsolateId) {
+        for message in self.take(|m| m.isolate == isolate) {
+            (message.reply)(Err(SendMessageError::IsolateShutDown));
+        }
+    }
+
+    fn take<F: Fn(&QueuedMessage) -> bool>(&self, predicate: F) -> Vec<QueuedMessage> {
+        let mut messages = self.messages.borrow_mut();
+        let (taken, kept) = messages.drain(..).partition(predicate);
+        *messages = kept;
+        taken
+    }
+}
+
+/// Default thresholds for [`MessageChannel::set_saturation_observer`].
+pub const DEFAULT_SATURATION_THRESHOLDS: [usize; 3] = [100, 1000, 10000];
+
+/// Tracks backlog of messages received from Dart, see
+/// [`MessageChannel::set_saturation_observer`].
+pub(super) struct InboundBacklog {
+    // Messages received but not processed yet. Incremented on the thread that
+    // receives the message, which may be other than the run loop thread.
+    pending: Arc<AtomicUsize>,
+    thresholds: RefCell<Vec<usize>>,
+    // Number of thresholds reached by last sample
+    level: Cell<usize>,
+    observer: RefCell<Option<Rc<dyn Fn(usize)>>>,
+}
+
+impl Default for InboundBacklog {
+    fn default() -> Self {
+        Self {
+            pending: Arc::new(AtomicUsize::new(0)),
+            thresholds: RefCell::new(DEFAULT_SATURATION_THRESHOLDS.to_vec()),
+            level: Cell::new(0),
+            observer: RefCell::new(None),
+        }
+    }
+}
+
+impl InboundBacklog {
+    /// Counter of pending messages for threads other than the run loop thread;
+    /// increment it when message is received.
+    #[cfg_attr(feature = "mock", allow(dead_code))]
+    pub(super) fn pending(&self) -> Arc<AtomicUsize> {
+        self.pending.clone()
+    }
+
+    /// Called when message is received on the run loop thread.
+    #[cfg_attr(not(feature = "mock"), allow(dead_code))]
+    pub(super) fn message_received(&self) {
+        self.pending.fetch_add(1, Ordering::SeqCst);
+    }
+
+    /// Called before processing received message. Samples the backlog
+    /// including the message being processed.
+    pub(super) fn message_processed(&self) {
+        let pending = self.pending.fetch_sub(1, Ordering::SeqCst);
+        let level = self
+            .thresholds
+            .borrow()
+            .iter()
+            .take_while(|t| pending >= **t)
+            .count();
+        if level != self.level.replace(level) {
+            let observer = self.observer.borrow().clone();
+            if let Some(observer) = observer {
+                observer(pending);
+            }
+        }
+    }
+}
+
+/// Low priority messages waiting to be dispatched at the end of current run
+/// loop turn.
+#[derive(Default)]
+pub(super) struct OutboundQueue {
+    messages: RefCell<HashMap<IsolateId, Vec<OutboundMessage>>>,
+    flush: RefCell<Option<Handle>>,
+}
+
+struct OutboundMessage {
+    channel: String,
+    message: Value,
+    reply: Option<Box<dyn FnOnce(Result<Value, SendMessageError>)>>,
+}
+
+impl OutboundQueue {
+    fn push(&self, isolate: IsolateId, message: OutboundMessage) {
+        self.messages
+            .borrow_mut()
+            .entry(isolate)
+            .or_default()
+            .push(message);
+        let mut flush = self.flush.borrow_mut();
+        if flush.is_none() {
+            *flush = Some(Context::get().run_loop().schedule_next(|| {
+                Context::get().message_channel().flush_outbound_queue();
+            }));
+        }
+    }
+
+    fn take(&self) -> Vec<(IsolateId, Vec<OutboundMessage>)> {
+        if let Some(mut flush) = self.flush.borrow_mut().take() {
+            flush.detach();
+        }
+        let mut messages: Vec<_> = self.messages.borrow_mut().drain().collect();
+        messages.sort_by_key(|(isolate, _)| *isolate);
+        messages
+    }
+
+    /// Fails messages queued for isolate that has exited.
+    pub(super) fn fail(&self, isolate: IsolateId) {
+        let messages = self.messages.borrow_mut().remove(&isolate);
+        for message in messages.unwrap_or_default() {
+            if let Some(reply) = message.reply {
+                reply(Err(SendMessageError::IsolateShutDown));
+            }
+        }
+    }
+}
+
+impl MessageChannel {
+    fn flush_outbound_queue(&self) {
+        for (isolate, messages) in self.outbound_queue().take() {
+            for message in messages {
+                match message.reply {
+                    Some(reply) => self
+                        .send_message(isolate, &message.channel, message.message, reply)
+                        .detach(),
+                    None => {
+                        self.post_message(isolate, &message.channel, message.message)
+                            .ok();
+                    }
+                }
+            }
+        }
+    }
+}
+
+impl RunLoop {
+    /// Spawns the future on this run loop and ties it to the lifetime of given
+    /// isolate. When the isolate exits the future is dropped without being
+    /// polled again and the [`JoinHandle`] resolves to `None`. Same happens
+    /// immediately if the isolate is not registered with the message channel.
+    ///
+    /// Must be called on the thread where the [`Context`] is current.
+    pub fn spawn_for_isolate<T: 'static>(
+        &self,
+        isolate: IsolateId,
+        future: impl Future<Output = T> + 'static,
+    ) -> JoinHandle<Option<T>> {
+        let (future, abort_handle) = abortable(future);
+        let registration = {
+            let context = Context::get();
+            let channel = context.message_channel();
+            if channel.has_isolate(isolate) {
+                Some(channel.isolate_tasks().register(isolate, abort_handle))
+            } else {
+                abort_handle.abort();
+                None
+            }
+        };
+        self.spawn(async move {
+            // Dropped when the task completes or is dropped.
+            let _registration = registration;
+            future.await.ok()
+        })
+    }
+}
+
+/// Tasks spawned through [`RunLoop::spawn_for_isolate`] that are still running.
+#[derive(Default)]
+pub(super) struct IsolateTasks {
+    next_id: Cell<usize>,
+    tasks: Rc<RefCell<Vec<IsolateTask>>>,
+}
+
+struct IsolateTask {
+    id: usize,
+    isolate: IsolateId,
+    abort_handle: AbortHandle,
+}
+
+// Removes the task from `IsolateTasks` when dropped.
+struct IsolateTaskRegistration {
+    id: usize,
+    tasks: Weak<RefCell<Vec<IsolateTask>>>,
+}
+
+impl Drop for IsolateTaskRegistration {
+    fn drop(&mut self) {
+        if let Some(tasks) = self.tasks.upgrade() {
+            // Dropped after the borrow ends, abort handle may hold the last
+            // reference to other task.
+            let _task = {
+                let mut tasks = tasks.borrow_mut();
+                let index = tasks.iter().position(|t| t.id == self.id);
+                index.map(|index| tasks.remove(index))
+            };
+        }
+    }
+}
+
+impl IsolateTasks {
+    fn register(&self, isolate: IsolateId, abort_handle: AbortHandle) -> IsolateTaskRegistration {
+        let id = self.next_id.replace(self.next_id.get() + 1);
+        self.tasks.borrow_mut().push(IsolateTask {
+            id,
+            isolate,
+            abort_handle,
+        });
+        IsolateTaskRegistration {
+            id,
+            tasks: Rc::downgrade(&self.tasks),
+        }
+    }
+
+    /// Number of tasks tied to the isolate that are still running.
+    #[cfg_attr(not(feature = "mock"), allow(dead_code))]
+    pub(super) fn count(&self, isolate: IsolateId) -> usize {
+        self.tasks
+            .borrow()
+            .iter()
+            .filter(|t| t.isolate == isolate)
+            .count()
+    }
+
+    /// Aborts tasks belonging to isolate that has exited.
+    pub(super) fn abort(&self, isolate: IsolateId) {
+        let tasks: Vec<_> = {
+            let mut tasks = self.tasks.borrow_mut();
+            let (taken, kept) = tasks.drain(..).partition(|t| t.isolate == isolate);
+            *tasks = kept;
+            taken
+        };
+        for task in tasks {
+            task.abort_handle.abort();
+        }
+    }
+}
+
+/// Channels that have been contacted from Dart, see [`MessageChannel::channel_ready`].
+#[derive(Default)]
+pub(super) struct ChannelReadiness {
+    contacted: RefCell<HashMap<String, IsolateId>>,
+    waiting: RefCell<HashMap<String, Vec<FutureCompleter<IsolateId>>>>,
+}
+
+impl ChannelReadiness {
+    fn wait(&self, channel: &str) -> CompletableFuture<IsolateId> {
+        let (future, completer) = FutureCompleter::new();
+        let contacted = self.contacted.borrow().get(channel).cloned();
+        match contacted {
+            Some(isolate) => completer.complete(isolate),
+            None => self
+                .waiting
+                .borrow_mut()
+                .entry(channel.into())
+                .or_default()
+                .push(completer),
+        }
+        future
+    }
+
+    pub(super) fn contact(&self, isolate: IsolateId, channel: &str) {
+        if self.contacted.borrow().contains_key(channel) {
+            return;
+        }
+        self.contacted.borrow_mut().insert(channel.into(), isolate);
+        let waiting = self.waiting.borrow_mut().remove(channel);
+        for completer in waiting.unwrap_or_default() {
+            completer.complete(isolate);
+        }
+    }
+
+    pub(super) fn reset_channel(&self, channel: &str) {
+        self.contacted.borrow_mut().remove(channel);
+    }
+
+    fn move_waiting(&self, old_channel: &str, new_channel: &str) {
+        let waiting = self.waiting.borrow_mut().remove(old_channel);
+        let contacted = self.contacted.borrow().get(new_channel).cloned();
+        for completer in waiting.unwrap_or_default() {
+            match contacted {
+                Some(isolate) => completer.complete(isolate),
+                None => self
+                    .waiting
+                    .borrow_mut()
+                    .entry(new_channel.into())
+                    .or_default()
+                    .push(completer),
+            }
+        }
+    }
+
+    pub(super) fn reset_isolate(&self, isolate: IsolateId) {
+        self.contacted.borrow_mut().retain(|_, i| *i != isolate);
+    }
+}
+
+/// Global reply timeout, see [`MessageChannel::set_reply_watchdog`].
+#[derive(Default)]
+pub(super) struct ReplyWatchdog {
+    timeout: Cell<Option<Duration>>,
+}
+
+impl ReplyWatchdog {
+    /// Schedules `on_timeout` if the watchdog is enabled. Dropping returned
+    /// handle disarms the watchdog.
+    pub(super) fn arm<F>(&self, channel: &str, on_timeout: F) -> Option<Handle>
+    where
+        F: FnOnce() -> bool + 'static,
+    {
+        let timeout = self.timeout.get()?;
+        let channel = channel.to_owned();
+        let handle = Context::get().run_loop().schedule(timeout, move || {
+            if on_timeout() {
+                Context::get()
+                    .run_loop()
+                    .emit_trace_event(TraceEvent::ReplyTimedOut { channel, timeout });
+            }
+        });
+        Some(handle)
+    }
+}
+
+/// Kinds of registered delegates, keyed by channel.
+#[derive(Default)]
+pub(super) struct HandlerKinds {
+    kinds: RefCell<HashMap<String, HandlerKind>>,
+}
+
+impl HandlerKinds {
+    pub(super) fn insert(&self, channel: &str, kind: HandlerKind) {
+        self.kinds.borrow_mut().insert(channel.into(), kind);
+    }
+
+    pub(super) fn remove(&self, channel: &str) {
+        self.kinds.borrow_mut().remove(channel);
+    }
+}
+
 pub(crate) static RUN_LOOP_SENDER: OnceCell<RunLoopSender> = OnceCell::new();
@@ -1,18 +1,44 @@
 use core::panic;
 use std::{
+    any::Any,
+    cell::RefCell,
     fmt::Display,
+    panic::{catch_unwind, AssertUnwindSafe},
     rc::{Rc, Weak},
 };
 
-use crate::{value::Value, Context, GetMessageChannel, TryFromError};
+use crate::{
+    isolate_run_loop_sender,
+    util::{BlockingVariable, CompletableFuture},
+    value::Value,
+    Context, GetMessageChannel, MessageChannel, PlatformResult, TryFromError,
+};
+
+use super::{HandlerKind, IsolateId, MessageChannelDelegate, SendMessageError};
 
-use super::{IsolateId, MessageChannelDelegate, SendMessageError};
+/// Error code used by handlers to report that the method is not implemented.
+/// Replies with this code are reported as [`MethodCallError::NotImplemented`].
+pub const NOT_IMPLEMENTED_ERROR_CODE: &str = "not_implemented";
 
 #[derive(Debug)]
 pub enum MethodCallError {
     SendError(SendMessageError),
     PlatformError(PlatformError),
     ConversionError(TryFromError),
+    /// Target channel has no handler or the handler doesn't implement the method.
+    NotImplemented,
+}
+
+impl MethodCallError {
+    /// Converts error from sending the method call. Missing channel or handler
+    /// is reported as [`MethodCallError::NotImplemented`].
+    pub(crate) fn from_send_error(error: SendMessageError) -> Self {
+        match error {
+            SendMessageError::ChannelNotFound { .. }
+            | SendMessageError::HandlerNotRegistered { .. } => Self::NotImplemented,
+            error => Self::SendError(error),
+        }
+    }
 }
 
 impl Display for MethodCallError {
@@ -21,6 +47,7 @@ impl Display for MethodCallError {
             MethodCallError::SendError(e) => write!(f, "error sending message: {}", e),
             MethodCallError::PlatformError(e) => write!(f, "platform error: {}", e),
             MethodCallError::ConversionError(e) => write!(f, "conversion error: {}", e),
+            MethodCallError::NotImplemented => write!(f, "method not implemented"),
         }
     }
 }
@@ -34,12 +61,40 @@ pub struct PlatformError {
     pub detail: Value,
 }
 
+impl PlatformError {
+    /// Creates error with detail converted from typed value. Receiver can
+    /// convert the detail back using [`PlatformError::detail_as`].
+    pub fn with_typed_detail<T: Into<Value>>(
+        code: impl Into<String>,
+        message: impl Into<String>,
+        detail: T,
+    ) -> Self {
+        PlatformError {
+            code: code.into(),
+            message: Some(message.into()),
+            detail: detail.into(),
+        }
+    }
+
+    /// Converts the error detail to typed value. The detail is cloned.
+    pub fn detail_as<T: TryFrom<Value>>(&self) -> Result<T, T::Error> {
+        self.detail.clone().try_into()
+    }
+}
+
+/// Allows using `?` on argument conversion in handlers returning
+/// [`PlatformResult`]. The detail contains path of the value that failed to
+/// convert (see [`TryFromError::path`]) or is null if the error is not nested.
 impl From<TryFromError> for PlatformError {
     fn from(err: TryFromError) -> Self {
+        let detail = match err.path() {
+            "" => Value::Null,
+            path => path.into(),
+        };
         PlatformError {
-            code: "try_from_error".into(),
+            code: "invalid_arguments".into(),
             message: Some(err.to_string()),
-            detail: Value::Null,
+            detail,
         }
     }
 }
@@ -56,6 +111,25 @@ impl Display for PlatformError {
 
 impl std::error::Error for PlatformError {}
 
+/// Converts result to the `["ok", value]` or `["err", code, message, detail]`
+/// envelope used to reply to method calls.
+impl<T: Into<Value>, E: Into<PlatformError>> From<Result<T, E>> for Value {
+    fn from(result: Result<T, E>) -> Self {
+        match result {
+            Ok(value) => Value::List(vec!["ok".into(), value.into()]),
+            Err(err) => {
+                let err: PlatformError = err.into();
+                Value::List(vec![
+                    "err".into(),
+                    err.code.into(),
+                    err.message.into(),
+                    err.detail,
+                ])
+            }
+        }
+    }
+}
+
 #[derive(Debug)]
 pub struct MethodCall {
     pub method: String,
@@ -63,6 +137,14 @@ pub struct MethodCall {
     pub isolate: IsolateId,
 }
 
+impl MethodCall {
+    /// Consumes the call and returns its arguments. Arguments are moved,
+    /// not cloned; values received from Dart are owned by the call.
+    pub fn into_args(self) -> Value {
+        self.args
+    }
+}
+
 pub trait MethodHandler: Sized + 'static {
     fn on_method_call(&self, call: MethodCall, reply: MethodCallReply);
 
@@ -71,6 +153,8 @@ pub trait MethodHandler: Sized + 'static {
     fn assign_weak_self(&self, _weak_self: Weak<Self>) {}
 
     /// Keep the method invoker if you want to call methods on engines.
+    /// Called again with new invoker when the handler is moved to another
+    /// channel through [`RegisteredMethodHandler::rechannel`].
     fn assign_invoker(&self, _invoker: MethodInvoker) {}
 
     /// Called when isolate is about to be destroyed.
@@ -80,6 +164,43 @@ pub trait MethodHandler: Sized + 'static {
     fn register(self, channel: &str) -> RegisteredMethodHandler<Self> {
         RegisteredMethodHandler::new(channel, self)
     }
+
+    /// Register handler for handling platform channel methods without keeping
+    /// it alive. Once the handler is dropped, method calls on this channel will
+    /// fail with [`SendMessageError::HandlerNotRegistered`].
+    fn register_weak(handler: &Rc<Self>, channel: &str) -> RegisteredMethodHandler<Self> {
+        RegisteredMethodHandler::new_weak(channel, handler)
+    }
+}
+
+/// Synchronous method handler that returns the result instead of replying
+/// through [`MethodCallReply`]. The returned result is sent to the caller
+/// automatically.
+///
+/// Handler is stored in a `RefCell`, which implements [`MethodHandler`].
+/// Calling method on the same handler from within [`SimpleMethodHandler::handle`]
+/// results in `handler_panic` error because the handler is already borrowed.
+pub trait SimpleMethodHandler: Sized + 'static {
+    fn handle(&mut self, call: MethodCall) -> PlatformResult;
+
+    /// Called when isolate is about to be destroyed.
+    fn on_isolate_destroyed(&mut self, _isolate: IsolateId) {}
+
+    /// Register self for handling platform channel methods.
+    fn register(self, channel: &str) -> RegisteredMethodHandler<RefCell<Self>> {
+        RefCell::new(self).register(channel)
+    }
+}
+
+impl<T: SimpleMethodHandler> MethodHandler for RefCell<T> {
+    fn on_method_call(&self, call: MethodCall, reply: MethodCallReply) {
+        let result = self.borrow_mut().handle(call);
+        reply.send(result);
+    }
+
+    fn on_isolate_destroyed(&self, isolate: IsolateId) {
+        self.borrow_mut().on_isolate_destroyed(isolate);
+    }
 }
 
 #[derive(Clone)]
@@ -125,18 +246,80 @@ impl MethodInvoker {
     {
         let args: Value = args.into();
         let call: Value = vec![Value::String(method.into()), args].into();
-        Context::get().message_channel().send_message(
-            target_isolate,
-            &self.channel_name,
-            call,
-            move |res| match res {
-                Ok(value) => {
-                    let result = unpack_result(value).expect("Malformed message");
-                    reply(result);
-                }
-                Err(err) => reply(Err(MethodCallError::SendError(err))),
-            },
-        );
+        Context::get()
+            .message_channel()
+            .send_message(
+                target_isolate,
+                &self.channel_name,
+                call,
+                move |res| match res {
+                    Ok(value) => {
+                        let result = unpack_result(value).expect("Malformed message");
+                        reply(result);
+                    }
+                    Err(err) => reply(Err(MethodCallError::from_send_error(err))),
+                },
+            )
+            .detach();
+    }
+}
+
+impl MessageChannel {
+    /// Calls method on Dart channel from thread other than the run loop thread
+    /// and blocks until the reply arrives. The call itself is made on the run
+    /// loop thread.
+    ///
+    /// Panics if called on the run loop thread, where blocking would prevent
+    /// the reply from ever being delivered.
+    ///
+    /// If the run loop or the target isolate goes away before the reply
+    /// arrives, returns [`SendMessageError::IsolateShutDown`] instead of
+    /// blocking forever.
+    pub fn call_method_blocking(
+        target_isolate: IsolateId,
+        channel: &str,
+        method: &str,
+        args: Value,
+    ) -> Result<Value, MethodCallError> {
+        let sender = isolate_run_loop_sender(target_isolate);
+        if sender.is_run_loop_thread() {
+            panic!("call_method_blocking must not be called on run loop thread");
+        }
+        let result = BlockingVariable::new();
+        let reply = BlockingReply(Some(result.clone()));
+        let invoker = MethodInvoker {
+            channel_name: channel.into(),
+        };
+        let method = method.to_owned();
+        sender.send(move || {
+            invoker.call_method(target_isolate, &method, args, move |res| {
+                reply.complete(res);
+            });
+        });
+        result.get_blocking()
+    }
+}
+
+// Completes blocking call with an error when dropped without reply, which
+// happens when either the callback scheduled on run loop or the reply
+// callback is dropped without being called.
+struct BlockingReply(Option<BlockingVariable<Result<Value, MethodCallError>>>);
+
+impl BlockingReply {
+    fn complete(mut self, result: Result<Value, MethodCallError>) {
+        if let Some(variable) = self.0.take() {
+            variable.set(result);
+        }
+    }
+}
+
+impl Drop for BlockingReply {
+    fn drop(&mut self) {
+        if let Some(variable) = self.0.take() {
+            variable.set(Err(MethodCallError::SendError(
+                SendMessageError::IsolateShutDown,
+            )));
+        }
     }
 }
 
@@ -146,21 +329,38 @@ pub struct MethodCallReply {
 
 impl MethodCallReply {
     pub fn send_ok<V: Into<Value>>(self, value: V) {
-        (self.reply)(Value::List(vec!["ok".into(), value.into()]));
+        (self.reply)(Ok::<V, PlatformError>(value).into());
     }
 
     pub fn send_err<E: Into<PlatformError>>(self, err: E) {
-        let err: PlatformError = err.into();
-        self.send_error(err.code, err.message, err.detail)
+        (self.reply)(Err::<Value, E>(err).into());
     }
 
     pub fn send_error(self, code: String, message: Option<String>, detail: Value) {
-        (self.reply)(Value::List(vec![
-            "err".into(),
-            code.into(),
-            message.map(|s| s.into()).unwrap_or(Value::Null),
+        self.send_err(PlatformError {
+            code,
+            message,
             detail,
-        ]));
+        })
+    }
+
+    /// Replies that the method is not implemented. The caller receives
+    /// [`MethodCallError::NotImplemented`].
+    pub fn send_not_implemented(self) {
+        self.send_error(NOT_IMPLEMENTED_ERROR_CODE.into(), None, Value::Null)
+    }
+
+    /// Sends result that has already been encoded with [`Value::encode`]. The
+    /// bytes are passed to the caller as they are, without being decoded and
+    /// encoded again.
+    pub fn send_raw_ok(self, bytes: Vec<u8>) {
+        (self.reply)(Value::List(vec!["ok_raw".into(), Value::U8List(bytes)]));
+    }
+
+    /// Sends error that has already been encoded with [`Value::encode`]. The
+    /// bytes must contain encoded `[code, message, detail]` list.
+    pub fn send_raw_err(self, bytes: Vec<u8>) {
+        (self.reply)(Value::List(vec!["err_raw".into(), Value::U8List(bytes)]));
     }
 
     pub fn send<V: Into<Value>, E: Into<PlatformError>>(self, result: Result<V, E>) {
@@ -172,6 +372,69 @@ impl MethodCallReply {
             }
         }
     }
+    /// Lets the run loop service other callbacks and then calls `continuation`
+    /// with this reply on next run loop turn.
+    ///
+    /// Synchronous handler can't be suspended in the middle of work, so long
+    /// running handler needs to be written in chunks: process a chunk, then
+    /// yield with continuation that processes the next one and eventually
+    /// replies.
+    ///
+    /// ```
+    /// use nativeshell_core::{MethodCall, MethodCallReply, MethodHandler};
+    ///
+    /// struct SumHandler {}
+    ///
+    /// fn sum(from: i64, total: i64, reply: MethodCallReply) {
+    ///     let to = (from + 1000).min(1_000_000);
+    ///     let total = total + (from..to).sum::<i64>();
+    ///     if to == 1_000_000 {
+    ///         reply.send_ok(total);
+    ///     } else {
+    ///         reply.yield_now(move |reply| sum(to, total, reply));
+    ///     }
+    /// }
+    ///
+    /// impl MethodHandler for SumHandler {
+    ///     fn on_method_call(&self, _call: MethodCall, reply: MethodCallReply) {
+    ///         sum(0, 0, reply);
+    ///     }
+    /// }
+    /// ```
+    pub fn yield_now<F: FnOnce(MethodCallReply) + 'static>(self, continuation: F) {
+        Context::get()
+            .run_loop()
+            .schedule_next(move || continuation(self))
+            .detach();
+    }
+}
+
+/// Debug build check that registered handler is only accessed on the thread
+/// where it was registered. Handlers are not thread safe; without the check
+/// wrong thread access would only surface later as confusing `RefCell` panic.
+pub(super) struct ThreadAffinity {
+    #[cfg(debug_assertions)]
+    thread: std::thread::ThreadId,
+}
+
+impl ThreadAffinity {
+    pub(super) fn new() -> Self {
+        Self {
+            #[cfg(debug_assertions)]
+            thread: std::thread::current().id(),
+        }
+    }
+
+    pub(super) fn check(&self, _owner: &str, _channel: &str) {
+        #[cfg(debug_assertions)]
+        if std::thread::current().id() != self.thread {
+            panic!(
+                "{} for channel \"{}\" accessed from wrong thread; handlers must only \
+                 be accessed on the thread they were registered on",
+                _owner, _channel
+            );
+        }
+    }
 }
 
 pub struct RegisteredMethodHandler<T: MethodHandler> {
@@ -185,44 +448,132 @@ impl<T: MethodHandler> RegisteredMethodHandler<T> {
     }
 
     fn new_ref(channel: &str, handler: Rc<T>) -> Self {
+        Self::new_inner(
+            channel,
+            Rc::downgrade(&handler),
+            Some(handler),
+            HandlerKind::Method,
+        )
+    }
+
+    fn new_weak(channel: &str, handler: &Rc<T>) -> Self {
+        Self::new_inner(channel, Rc::downgrade(handler), None, HandlerKind::Method)
+    }
+
+    pub(crate) fn new_with_kind(channel: &str, handler: T, kind: HandlerKind) -> Self {
+        let handler = Rc::new(handler);
+        Self::new_inner(channel, Rc::downgrade(&handler), Some(handler), kind)
+    }
+
+    fn new_inner(
+        channel: &str,
+        handler: Weak<T>,
+        strong_handler: Option<Rc<T>>,
+        kind: HandlerKind,
+    ) -> Self {
         let res = Self {
             inner: Rc::new(RegisteredMethodHandlerInner {
-                channel: channel.into(),
+                channel: RefCell::new(channel.into()),
+                kind,
                 handler,
+                _strong_handler: strong_handler,
+                affinity: ThreadAffinity::new(),
             }),
         };
         Context::get()
             .message_channel()
-            .register_delegate(&res.inner.channel, res.inner.clone());
+            .register_delegate_with_kind(channel, res.inner.clone(), kind);
         res.inner.init();
         res
     }
 
+    /// Name of the channel the handler is currently registered for.
+    pub fn channel(&self) -> String {
+        self.inner.channel.borrow().clone()
+    }
+
+    /// Moves the handler to `new_name`. Handler is unregistered from the old
+    /// channel and registered for the new one before any other message can
+    /// be processed, so no message is dispatched while the handler is
+    /// unreachable. Handler state is kept and [`MethodHandler::assign_invoker`]
+    /// is called again with invoker for the new channel.
+    ///
+    /// Like registering, this replaces any delegate already registered
+    /// for `new_name`.
+    pub fn rechannel(&self, new_name: &str) {
+        self.check_thread("RegisteredMethodHandler");
+        let old_name = self.inner.channel.replace(new_name.into());
+        if old_name == new_name {
+            return;
+        }
+        let context = Context::get();
+        let message_channel = context.message_channel();
+        message_channel.unregister_delegate(&old_name);
+        message_channel.register_delegate_with_kind(new_name, self.inner.clone(), self.inner.kind);
+        message_channel.move_channel_ready(&old_name, new_name);
+        self.inner.assign_invoker();
+    }
+
+    /// Returns future that resolves once Dart has contacted this channel. See
+    /// [`MessageChannel::channel_ready`](crate::MessageChannel::channel_ready).
+    pub fn ready(&self) -> CompletableFuture<IsolateId> {
+        self.check_thread("RegisteredMethodHandler");
+        Context::get()
+            .message_channel()
+            .channel_ready(&self.inner.channel.borrow())
+    }
+
+    /// Returns the registered handler. Panics if the handler was registered
+    /// with [`MethodHandler::register_weak`] and has already been dropped.
     pub fn handler(&self) -> Rc<T> {
-        self.inner.handler.clone()
+        self.check_thread("RegisteredMethodHandler");
+        self.inner
+            .handler
+            .upgrade()
+            .expect("Method handler has already been dropped")
+    }
+
+    /// Panics in debug build if called on thread other than the one where
+    /// the handler was registered. `owner` is used in the panic message.
+    pub(super) fn check_thread(&self, owner: &str) {
+        self.inner
+            .affinity
+            .check(owner, &self.inner.channel.borrow());
     }
 }
 
 impl<T: MethodHandler> Drop for RegisteredMethodHandler<T> {
     fn drop(&mut self) {
+        self.check_thread("RegisteredMethodHandler");
         Context::get()
             .message_channel()
-            .unregister_delegate(&self.inner.channel);
+            .unregister_delegate(&self.inner.channel.borrow());
     }
 }
 
 struct RegisteredMethodHandlerInner<T: MethodHandler> {
-    channel: String,
-    handler: Rc<T>,
+    channel: RefCell<String>,
+    kind: HandlerKind,
+    handler: Weak<T>,
+    // Keeps the handler alive unless registered through `register_weak`.
+    _strong_handler: Option<Rc<T>>,
+    affinity: ThreadAffinity,
 }
 
 impl<T: MethodHandler> RegisteredMethodHandlerInner<T> {
     fn init(&self) {
-        let weak = Rc::downgrade(&self.handler);
-        self.handler.assign_weak_self(weak);
-        self.handler.assign_invoker(MethodInvoker {
-            channel_name: self.channel.clone(),
-        });
+        if let Some(handler) = self.handler.upgrade() {
+            handler.assign_weak_self(self.handler.clone());
+        }
+        self.assign_invoker();
+    }
+
+    fn assign_invoker(&self) {
+        if let Some(handler) = self.handler.upgrade() {
+            handler.assign_invoker(MethodInvoker {
+                channel_name: self.channel.borrow().clone(),
+            });
+        }
     }
 }
 
@@ -236,15 +587,56 @@ impl<T: MethodHandler> MessageChannelDelegate for RegisteredMethodHandlerInner<T
         reply: Box<dyn FnOnce(Value) -> bool>,
     ) {
         if let Some(call) = unpack_method_call(message, isolate) {
-            let reply = MethodCallReply { reply };
-            self.handler.on_method_call(call, reply);
+            // Reply is shared so that caller still gets a reply if the handler
+            // panics before replying.
+            let reply = Rc::new(RefCell::new(Some(reply)));
+            let reply_clone = reply.clone();
+            let call_reply = MethodCallReply {
+                reply: Box::new(move |value| {
+                    let reply = reply_clone.borrow_mut().take();
+                    reply.map(|reply| reply(value)).unwrap_or(false)
+                }),
+            };
+            let handler = self
+                .handler
+                .upgrade()
+                .expect("Method handler has already been dropped");
+            let res = catch_unwind(AssertUnwindSafe(|| {
+                handler.on_method_call(call, call_reply);
+            }));
+            if let Err(payload) = res {
+                let reply = reply.borrow_mut().take();
+                if let Some(reply) = reply {
+                    MethodCallReply { reply }.send_error(
+                        "handler_panic".into(),
+                        Some(panic_message(payload.as_ref())),
+                        Value::Null,
+                    );
+                }
+            }
         } else {
             panic!("malformed method call message");
         }
     }
 
     fn on_isolate_exited(&self, isolate: IsolateId) {
-        self.handler.on_isolate_destroyed(isolate);
+        if let Some(handler) = self.handler.upgrade() {
+            handler.on_isolate_destroyed(isolate);
+        }
+    }
+
+    fn has_handler(&self) -> bool {
+        self.handler.strong_count() > 0
+    }
+}
+
+fn panic_message(payload: &(dyn Any + Send)) -> String {
+    if let Some(message) = payload.downcast_ref::<&str>() {
+        (*message).into()
+    } else if let Some(message) = payload.downcast_ref::<String>() {
+        message.clone()
+    } else {
+        "method handler panicked".into()
     }
 }
 
@@ -254,12 +646,19 @@ pub(crate) fn unpack_result(value: Value) -> Option<Result<Value, MethodCallErro
     let ty: String = iter.next()?.try_into().ok()?;
     match ty.as_str() {
         "ok" => Some(Ok(iter.next()?)),
+        "ok_raw" => Some(Ok(decode_raw(iter.next()?)?)),
+        "err_raw" => {
+            let error: Vec<Value> = decode_raw(iter.next()?)?.try_into().ok()?;
+            let mut items = vec!["err".into()];
+            items.extend(error);
+            unpack_result(Value::List(items))
+        }
         "err" => {
-            let code = iter.next()?.try_into().ok()?;
-            let message = match iter.next()? {
-                Value::String(s) => Some(s),
-                _ => None,
-            };
+            let code: String = iter.next()?.try_into().ok()?;
+            if code == NOT_IMPLEMENTED_ERROR_CODE {
+                return Some(Err(MethodCallError::NotImplemented));
+            }
+            let message = iter.next()?.try_into().ok();
             let detail = iter.next()?;
             Some(Err(MethodCallError::PlatformError(PlatformError {
                 code,
@@ -271,6 +670,13 @@ pub(crate) fn unpack_result(value: Value) -> Option<Result<Value, MethodCallErro
     }
 }
 
+fn decode_raw(value: Value) -> Option<Value> {
+    match &value {
+        Value::U8List(bytes) => Value::decode(bytes).ok(),
+        _ => None,
+    }
+}
+
 pub(crate) fn unpack_method_call(value: Value, isolate: IsolateId) -> Option<MethodCall> {
     let vec: Vec<Value> = value.try_into().ok()?;
     let mut iter = vec.into_iter();
@@ -1,16 +1,24 @@
 #[path = "message_channel_common.rs"]
 mod common;
 use std::{
-    cell::{Cell, RefCell},
+    cell::RefCell,
     collections::HashMap,
     rc::{Rc, Weak},
+    sync::{
+        atomic::{AtomicI64, Ordering},
+        Mutex, MutexGuard,
+    },
 };
 
+use once_cell::sync::Lazy;
+
 pub use common::*;
 
 use crate::{
-    unpack_result, util::FutureCompleter, Context, FinalizableHandleState, IsolateId,
-    MethodCallError, PlatformResult, Value,
+    unpack_result,
+    util::{Capsule, FutureCompleter},
+    Context, DecodeOptions, FinalizableHandleState, Handle, IsolateId, MethodCallError,
+    PlatformResult, RunLoopSender, Value,
 };
 
 #[derive(Debug)]
@@ -77,6 +85,21 @@ impl MockIsolate {
         });
     }
 
+    /// Registers handler that stores messages received on `channel` instead of
+    /// replying. Test can then take the messages and reply to them later, in
+    /// any order.
+    pub fn capture_messages(&self, channel: &str) -> CapturedMessages {
+        let captured = CapturedMessages::default();
+        let captured_clone = captured.clone();
+        self.register_message_handler(channel, move |message, reply| {
+            captured_clone
+                .messages
+                .borrow_mut()
+                .push(CapturedMessage { message, reply });
+        });
+        captured
+    }
+
     pub fn attach(self, channel: &MessageChannel) -> Rc<AttachedMockIsolate> {
         let isolate_id = channel.inner.register_isolate(self);
         Rc::new(AttachedMockIsolate {
@@ -86,6 +109,49 @@ impl MockIsolate {
     }
 }
 
+/// Messages captured by [`MockIsolate::capture_messages`].
+#[derive(Clone, Default)]
+pub struct CapturedMessages {
+    messages: Rc<RefCell<Vec<CapturedMessage>>>,
+}
+
+impl CapturedMessages {
+    pub fn len(&self) -> usize {
+        self.messages.borrow().len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.messages.borrow().is_empty()
+    }
+
+    /// Removes and returns captured messages in the order they were received.
+    pub fn take(&self) -> Vec<CapturedMessage> {
+        self.messages.take()
+    }
+}
+
+/// Message received by mock isolate that has not been replied to yet.
+pub struct CapturedMessage {
+    pub message: Value,
+    reply: Option<Box<dyn FnOnce(Value)>>,
+}
+
+impl CapturedMessage {
+    /// Whether the message was sent with [`MessageChannel::send_message`] and
+    /// expects reply. Messages from [`MessageChannel::post_message`] don't.
+    pub fn expects_reply(&self) -> bool {
+        self.reply.is_some()
+    }
+
+    /// Replies to the message. For method calls use [`Value::from`] with
+    /// `Result` to build the reply.
+    pub fn reply(self, value: Value) {
+        if let Some(reply) = self.reply {
+            reply(value);
+        }
+    }
+}
+
 /// Isolate attached to a message channel. Can be used to send messages
 /// to message channel (like a Dart isolate).
 pub struct AttachedMockIsolate {
@@ -98,33 +164,95 @@ impl AttachedMockIsolate {
         self.isolate_id
     }
 
+    /// Registers message handler after the isolate has been attached. Messages
+    /// waiting for the handler (see [`MessageChannel::send_message_when_ready`])
+    /// are delivered.
+    pub fn register_message_handler<F: Fn(Value, Option<Box<dyn FnOnce(Value)>>) + 'static>(
+        &self,
+        channel: &str,
+        handler: F,
+    ) {
+        if let Some(message_channel) = self.channel.upgrade() {
+            if let Some(isolate) = message_channel.isolates.borrow().get(&self.isolate_id) {
+                isolate.register_message_handler(channel, handler);
+            }
+            Context::get()
+                .message_channel()
+                .deliver_queued_messages(self.isolate_id, channel);
+        }
+    }
+
+    /// Simulates `MessageChannel.ping()` called in Dart. See
+    /// [`MessageChannel::channel_ready`].
+    pub fn ping(&self, channel: &str) {
+        if let Some(message_channel) = self.channel.upgrade() {
+            message_channel
+                .channel_readiness
+                .contact(self.isolate_id, channel);
+        }
+    }
+
+    /// Number of tasks spawned with [`RunLoop::spawn_for_isolate`](crate::RunLoop::spawn_for_isolate)
+    /// for this isolate that are still running.
+    pub fn task_count(&self) -> usize {
+        self.channel
+            .upgrade()
+            .map(|channel| channel.isolate_tasks.count(self.isolate_id))
+            .unwrap_or(0)
+    }
+
+    /// Simulates Dart garbage collecting the object attached to finalizable
+    /// handle with given id (see [`FinalizableHandle::id`](crate::FinalizableHandle::id)).
+    /// The finalizer is invoked on run loop, same as when called from Dart.
+    ///
+    /// Returns `false` if the handle was not sent to this isolate or has been
+    /// finalized already.
+    pub fn simulate_gc(&self, handle_id: isize) -> bool {
+        FinalizableHandleState::simulate_gc(handle_id, self.isolate_id)
+    }
+
     pub fn send_message<F: FnOnce(Result<Value, SendMessageError>) + 'static>(
         &self,
         channel: &str,
         message: Value,
         reply: F,
     ) {
-        match self.channel.upgrade() {
-            Some(message_channel) => {
-                let delegates = message_channel.delegates.borrow();
-                let channel = channel.to_owned();
-                let delegate = delegates.get(&channel);
-                match delegate {
-                    Some(delegate) => {
-                        delegate.on_message(
-                            self.isolate_id,
-                            message,
-                            Box::new(move |value| {
-                                reply(Ok(value));
-                                true
-                            }),
-                        );
-                    }
-                    None => reply(Err(SendMessageError::ChannelNotFound { channel })),
-                }
+        deliver_message(&self.channel, self.isolate_id, channel, message, reply);
+    }
+
+    /// Like [`AttachedMockIsolate::send_message`], but instead of delivering
+    /// the message immediately it is queued on the run loop, same as messages
+    /// received from Dart. Queued messages count towards the inbound backlog
+    /// (see [`MessageChannel::set_saturation_observer`]).
+    pub fn send_message_queued<F: FnOnce(Result<Value, SendMessageError>) + 'static>(
+        &self,
+        channel: &str,
+        message: Value,
+        reply: F,
+    ) {
+        let message_channel = match self.channel.upgrade() {
+            Some(message_channel) => message_channel,
+            None => {
+                reply(Err(SendMessageError::MessageRefused));
+                return;
             }
-            None => reply(Err(SendMessageError::MessageRefused)),
-        }
+        };
+        message_channel.inbound_backlog.message_received();
+        let weak_channel = self.channel.clone();
+        let isolate_id = self.isolate_id;
+        let channel = channel.to_owned();
+        let mut deliver = Capsule::new(move || {
+            if let Some(message_channel) = weak_channel.upgrade() {
+                message_channel.inbound_backlog.message_processed();
+            }
+            deliver_message(&weak_channel, isolate_id, &channel, message, reply);
+        });
+        // Go through sender to be queued same as messages from Dart
+        Context::get().run_loop_sender().send(move || {
+            if let Ok(deliver) = deliver.take() {
+                deliver();
+            }
+        });
     }
 
     pub async fn send_message_async(
@@ -149,7 +277,7 @@ impl AttachedMockIsolate {
         let call = vec![Value::String(method.into()), argument];
         self.send_message(channel, call.into(), move |result| match result {
             Ok(value) => reply(unpack_result(value).unwrap()),
-            Err(error) => reply(Err(MethodCallError::SendError(error))),
+            Err(error) => reply(Err(MethodCallError::from_send_error(error))),
         });
     }
 
@@ -167,6 +295,40 @@ impl AttachedMockIsolate {
     }
 }
 
+fn deliver_message<F: FnOnce(Result<Value, SendMessageError>) + 'static>(
+    message_channel: &Weak<MessageChannelInner>,
+    isolate_id: IsolateId,
+    channel: &str,
+    message: Value,
+    reply: F,
+) {
+    match message_channel.upgrade() {
+        Some(message_channel) => {
+            message_channel
+                .channel_readiness
+                .contact(isolate_id, channel);
+            let delegates = message_channel.delegates.borrow();
+            let channel = channel.to_owned();
+            let delegate = delegates.get(&channel);
+            match delegate {
+                Some(delegate) if delegate.has_handler() => {
+                    delegate.on_message(
+                        isolate_id,
+                        message,
+                        Box::new(move |value| {
+                            reply(Ok(value));
+                            true
+                        }),
+                    );
+                }
+                Some(_) => reply(Err(SendMessageError::HandlerNotRegistered { channel })),
+                None => reply(Err(SendMessageError::ChannelNotFound { channel })),
+            }
+        }
+        None => reply(Err(SendMessageError::MessageRefused)),
+    }
+}
+
 impl Drop for AttachedMockIsolate {
     fn drop(&mut self) {
         if let Some(channel) = self.channel.upgrade() {
@@ -186,26 +348,69 @@ impl MessageChannel {
             .ok();
         Self {
             inner: Rc::new(MessageChannelInner {
-                next_isolate: Cell::new(1),
                 isolates: RefCell::new(HashMap::new()),
                 delegates: RefCell::new(HashMap::new()),
+                queued_messages: QueuedMessages::default(),
+                outbound_queue: OutboundQueue::default(),
+                inbound_backlog: InboundBacklog::default(),
+                isolate_tasks: IsolateTasks::default(),
+                channel_readiness: ChannelReadiness::default(),
+                handler_kinds: HandlerKinds::default(),
+                reply_watchdog: ReplyWatchdog::default(),
             }),
         }
     }
 
+    /// Sends message to the handler registered for `channel` in target isolate
+    /// and calls `reply` with the result.
+    ///
+    /// Messages sent to an isolate, either through `send_message` or
+    /// [`MessageChannel::post_message`], are delivered in the order in which
+    /// these methods were called.
+    ///
+    /// Dropping or cancelling the returned handle discards the reply; `reply`
+    /// will not be called. The message itself is still delivered. Call
+    /// [`Handle::detach`] to keep the reply without holding the handle.
+    #[must_use]
     pub fn send_message<F>(
         &self,
         target_isolate: IsolateId,
         channel: &str,
         message: Value,
         reply: F,
-    ) where
+    ) -> Handle
+    where
         F: FnOnce(Result<Value, SendMessageError>) + 'static,
     {
+        let slot = Rc::new(RefCell::new(Some(reply)));
+        let slot_clone = slot.clone();
+        let watchdog = Rc::new(RefCell::new(self.inner.reply_watchdog.arm(
+            channel,
+            move || {
+                let reply = slot_clone.borrow_mut().take();
+                match reply {
+                    Some(reply) => {
+                        reply(Err(SendMessageError::Timeout));
+                        true
+                    }
+                    None => false,
+                }
+            },
+        )));
+        let slot_clone = slot.clone();
+        let watchdog_clone = watchdog.clone();
+        let reply = move |result: Result<Value, SendMessageError>| {
+            watchdog_clone.borrow_mut().take();
+            let reply = slot_clone.borrow_mut().take();
+            if let Some(reply) = reply {
+                reply(result);
+            }
+        };
         let isolates = self.inner.isolates.borrow();
         let isolate = isolates.get(&target_isolate);
         match isolate {
             Some(isolate) => {
+                FinalizableHandleState::attach_mock_handles(&message, target_isolate);
                 let handlers = isolate.handlers.borrow();
                 let channel = channel.to_owned();
                 let handler = handlers.get(&channel);
@@ -218,8 +423,16 @@ impl MessageChannel {
             }
             None => reply(Err(SendMessageError::InvalidIsolate)),
         }
+        Handle::new(move || {
+            watchdog.borrow_mut().take();
+            let reply = slot.borrow_mut().take();
+            drop(reply);
+        })
     }
 
+    /// Posts message to the handler registered for `channel` in target isolate
+    /// without waiting for reply. Ordering relative to other messages is same
+    /// as for [`MessageChannel::send_message`].
     pub fn post_message(
         &self,
         target_isolate: IsolateId,
@@ -230,6 +443,7 @@ impl MessageChannel {
         let isolate = isolates.get(&target_isolate);
         match isolate {
             Some(isolate) => {
+                FinalizableHandleState::attach_mock_handles(&message, target_isolate);
                 let handlers = isolate.handlers.borrow();
                 let channel = channel.to_owned();
                 let handler = handlers.get(&channel);
@@ -245,30 +459,95 @@ impl MessageChannel {
     pub fn register_delegate<F>(&self, channel: &str, delegate: Rc<F>)
     where
         F: MessageChannelDelegate + 'static,
+    {
+        self.register_delegate_with_options(channel, delegate, Default::default());
+    }
+
+    pub fn register_delegate_with_options<F>(
+        &self,
+        channel: &str,
+        delegate: Rc<F>,
+        options: RegisterDelegateOptions,
+    ) where
+        F: MessageChannelDelegate + 'static,
     {
         self.inner
             .delegates
             .borrow_mut()
-            .insert(channel.into(), delegate);
+            .insert(channel.into(), delegate.clone());
+        self.inner
+            .handler_kinds
+            .insert(channel, HandlerKind::Delegate);
+        if options.replay_joined_isolates {
+            let mut isolates: Vec<IsolateId> =
+                self.inner.isolates.borrow().keys().cloned().collect();
+            isolates.sort_unstable();
+            for isolate_id in isolates {
+                delegate.on_isolate_joined(isolate_id);
+            }
+        }
     }
 
     pub fn unregister_delegate(&self, channel: &str) {
         self.inner.delegates.borrow_mut().remove(channel);
+        self.inner.channel_readiness.reset_channel(channel);
+        self.inner.handler_kinds.remove(channel);
+    }
+
+    fn queued_messages(&self) -> &QueuedMessages {
+        &self.inner.queued_messages
+    }
+
+    fn outbound_queue(&self) -> &OutboundQueue {
+        &self.inner.outbound_queue
+    }
+
+    fn inbound_backlog(&self) -> &InboundBacklog {
+        &self.inner.inbound_backlog
+    }
+
+    fn isolate_tasks(&self) -> &IsolateTasks {
+        &self.inner.isolate_tasks
+    }
+
+    fn channel_readiness(&self) -> &ChannelReadiness {
+        &self.inner.channel_readiness
+    }
+
+    fn handler_kinds(&self) -> &HandlerKinds {
+        &self.inner.handler_kinds
     }
 
+    fn reply_watchdog(&self) -> &ReplyWatchdog {
+        &self.inner.reply_watchdog
+    }
+
+    fn has_isolate(&self, isolate_id: IsolateId) -> bool {
+        self.inner.isolates.borrow().contains_key(&isolate_id)
+    }
+
+    /// Mock message channel does not decode messages, options are ignored.
+    pub fn set_decode_options(&self, _options: DecodeOptions) {}
+
     pub(crate) fn request_update_external_size(&self, _target_isolate: IsolateId, _handle: isize) {}
 }
 
 struct MessageChannelInner {
-    next_isolate: Cell<IsolateId>,
     isolates: RefCell<HashMap<IsolateId, MockIsolate>>,
     delegates: RefCell<HashMap<String, Rc<dyn MessageChannelDelegate>>>,
+    queued_messages: QueuedMessages,
+    outbound_queue: OutboundQueue,
+    inbound_backlog: InboundBacklog,
+    isolate_tasks: IsolateTasks,
+    channel_readiness: ChannelReadiness,
+    handler_kinds: HandlerKinds,
+    reply_watchdog: ReplyWatchdog,
 }
 
 impl MessageChannelInner {
     fn register_isolate(&self, isolate: MockIsolate) -> IsolateId {
-        let isolate_id = self.next_isolate.get();
-        self.next_isolate.set(isolate_id + 1);
+        let isolate_id = NEXT_ISOLATE.fetch_add(1, Ordering::Relaxed);
+        isolate_senders().insert(isolate_id, Context::get().run_loop().new_sender());
         self.isolates.borrow_mut().insert(isolate_id, isolate);
         let delegates = self.delegates.borrow();
         for d in delegates.values() {
@@ -278,10 +557,38 @@ impl MessageChannelInner {
     }
 
     fn unregister_isolate(&self, isolate: IsolateId) {
+        self.isolates.borrow_mut().remove(&isolate);
+        self.queued_messages.fail(isolate);
+        self.outbound_queue.fail(isolate);
+        self.isolate_tasks.abort(isolate);
+        self.channel_readiness.reset_isolate(isolate);
         FinalizableHandleState::get().finalize_all(isolate);
+        isolate_senders().remove(&isolate);
         let delegates = self.delegates.borrow();
         for d in delegates.values() {
             d.on_isolate_exited(isolate);
         }
     }
 }
+
+// Tests may run in parallel, each thread with own message channel. Isolate ids
+// are unique within process so that isolates can be mapped to run loops.
+static NEXT_ISOLATE: AtomicI64 = AtomicI64::new(1);
+
+static ISOLATE_SENDERS: Lazy<Mutex<HashMap<IsolateId, RunLoopSender>>> =
+    Lazy::new(|| Mutex::new(HashMap::new()));
+
+fn isolate_senders() -> MutexGuard<'static, HashMap<IsolateId, RunLoopSender>> {
+    ISOLATE_SENDERS.lock().unwrap_or_else(|e| e.into_inner())
+}
+
+/// Returns sender for run loop of the thread that isolate is attached on.
+pub(crate) fn isolate_run_loop_sender(isolate_id: IsolateId) -> RunLoopSender {
+    let sender = isolate_senders().get(&isolate_id).cloned();
+    sender.unwrap_or_else(|| {
+        RUN_LOOP_SENDER
+            .get()
+            .expect("MessageChannel was not initialized!")
+            .clone()
+    })
+}
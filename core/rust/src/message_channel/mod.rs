@@ -27,6 +27,7 @@ pub type IsolateId = i64;
 pub enum FunctionResult {
     NoError = 0,
     InvalidStructSize = 1,
+    AlreadyInitialized = 2,
 }
 
 #[no_mangle]
@@ -77,6 +78,7 @@ pub extern "C" fn nativeshell_init_message_channel_context(_data: *mut c_void) -
             return FunctionResult::InvalidStructSize;
         }
         nativeshell_init_ffi(context.ffi_data);
+        init_vec_allocator();
         context.register_isolate = register_isolate as *mut _;
         context.send_message = post_message as *mut _;
         context.attach_weak_persistent_handle = attach_weak_persistent_handle as *mut _;
@@ -1,6 +1,93 @@
-use std::mem::ManuallyDrop;
+use std::{
+    ffi::c_void,
+    mem::{align_of, size_of, ManuallyDrop},
+    ptr::NonNull,
+};
+
+use once_cell::sync::OnceCell;
+
+pub type VecAllocFn = unsafe extern "C" fn(size: usize, align: usize) -> *mut c_void;
+pub type VecFreeFn = unsafe extern "C" fn(data: *mut c_void, size: usize, align: usize);
+
+struct VecAllocator {
+    alloc: VecAllocFn,
+    free: VecFreeFn,
+}
+
+// Frozen when message channel context is initialized, vectors allocated by
+// Dart must be released by the same allocator.
+static VEC_ALLOCATOR: OnceCell<Option<VecAllocator>> = OnceCell::new();
+
+fn vec_allocator() -> Option<&'static VecAllocator> {
+    VEC_ALLOCATOR.get().and_then(|a| a.as_ref())
+}
+
+/// Replaces allocator used for vectors allocated by Dart (i.e. message buffers
+/// and typed data sent to Rust). Must be called before
+/// `nativeshell_init_message_channel_context`; Fails with
+/// `FunctionResult::AlreadyInitialized` afterwards or if the allocator has
+/// already been set.
+///
+/// Vectors are copied into Rust allocated memory when received, so memory
+/// allocated by `alloc_fn` is only held while the data is in transit.
+#[no_mangle]
+pub extern "C" fn nativeshell_set_vec_allocator(
+    alloc_fn: VecAllocFn,
+    free_fn: VecFreeFn,
+) -> super::FunctionResult {
+    let allocator = VecAllocator {
+        alloc: alloc_fn,
+        free: free_fn,
+    };
+    match VEC_ALLOCATOR.set(Some(allocator)) {
+        Ok(()) => super::FunctionResult::NoError,
+        Err(_) => super::FunctionResult::AlreadyInitialized,
+    }
+}
+
+/// Freezes the vector allocator.
+pub(super) fn init_vec_allocator() {
+    VEC_ALLOCATOR.get_or_init(|| None);
+}
+
+unsafe fn custom_allocate<T: Copy + Default>(allocator: &VecAllocator, size: usize) -> *mut T {
+    if size == 0 || size_of::<T>() == 0 {
+        return NonNull::dangling().as_ptr();
+    }
+    let res = (allocator.alloc)(size * size_of::<T>(), align_of::<T>()) as *mut T;
+    assert!(!res.is_null(), "Vector allocation failed");
+    for i in 0..size {
+        res.add(i).write(T::default());
+    }
+    res
+}
+
+unsafe fn custom_free<T>(allocator: &VecAllocator, data: *mut T, size: usize) {
+    if size != 0 && size_of::<T>() != 0 {
+        (allocator.free)(data as *mut _, size * size_of::<T>(), align_of::<T>());
+    }
+}
+
+/// Takes ownership of vector allocated by one of the `allocate_vec_*` functions.
+pub(super) unsafe fn take_vec<T: Copy>(data: *mut T, len: usize) -> Vec<T> {
+    match vec_allocator() {
+        Some(allocator) => {
+            let res = std::slice::from_raw_parts(data, len).to_vec();
+            custom_free(allocator, data, len);
+            res
+        }
+        None => Vec::from_raw_parts(data, len, len),
+    }
+}
+
+unsafe fn free_vec<T: Copy>(data: *mut T, len: u64) {
+    let _ = take_vec(data, len as usize);
+}
 
 unsafe fn allocate_vec<T: Copy + Default>(size: u64) -> *mut T {
+    if let Some(allocator) = vec_allocator() {
+        return custom_allocate(allocator, size as usize);
+    }
     let mut v = Vec::<T>::with_capacity(size as usize);
     v.resize(size as usize, T::default());
     assert!(v.capacity() == v.len());
@@ -46,39 +133,39 @@ pub(super) unsafe extern "C" fn allocate_vec_f64(size: u64) -> *mut f64 {
 }
 
 pub(super) unsafe extern "C" fn free_vec_i8(data: *mut i8, len: u64) {
-    let _ = Vec::from_raw_parts(data, len as usize, len as usize);
+    free_vec(data, len)
 }
 
 pub(super) unsafe extern "C" fn free_vec_u8(data: *mut u8, len: u64) {
-    let _ = Vec::from_raw_parts(data, len as usize, len as usize);
+    free_vec(data, len)
 }
 
 pub(super) unsafe extern "C" fn free_vec_i16(data: *mut i16, len: u64) {
-    let _ = Vec::from_raw_parts(data, len as usize, len as usize);
+    free_vec(data, len)
 }
 
 pub(super) unsafe extern "C" fn free_vec_u16(data: *mut u16, len: u64) {
-    let _ = Vec::from_raw_parts(data, len as usize, len as usize);
+    free_vec(data, len)
 }
 
 pub(super) unsafe extern "C" fn free_vec_i32(data: *mut i32, len: u64) {
-    let _ = Vec::from_raw_parts(data, len as usize, len as usize);
+    free_vec(data, len)
 }
 
 pub(super) unsafe extern "C" fn free_vec_u32(data: *mut u32, len: u64) {
-    let _ = Vec::from_raw_parts(data, len as usize, len as usize);
+    free_vec(data, len)
 }
 
 pub(super) unsafe extern "C" fn free_vec_i64(data: *mut i64, len: u64) {
-    let _ = Vec::from_raw_parts(data, len as usize, len as usize);
+    free_vec(data, len)
 }
 
 pub(super) unsafe extern "C" fn free_vec_f32(data: *mut f32, len: u64) {
-    let _ = Vec::from_raw_parts(data, len as usize, len as usize);
+    free_vec(data, len)
 }
 
 pub(super) unsafe extern "C" fn free_vec_f64(data: *mut f64, len: u64) {
-    let _ = Vec::from_raw_parts(data, len as usize, len as usize);
+    free_vec(data, len)
 }
 
 unsafe fn modify<T: Copy + Default, F: FnOnce(&mut Vec<T>)>(
@@ -95,6 +182,12 @@ unsafe fn modify<T: Copy + Default, F: FnOnce(&mut Vec<T>)>(
 }
 
 pub(super) unsafe extern "C" fn resize_vec_u8(data: *mut u8, size: u64, new_size: u64) -> *mut u8 {
+    if let Some(allocator) = vec_allocator() {
+        let res = custom_allocate::<u8>(allocator, new_size as usize);
+        std::ptr::copy_nonoverlapping(data, res, size.min(new_size) as usize);
+        custom_free(allocator, data, size as usize);
+        return res;
+    }
     modify(data, size, |v| {
         let new_size = new_size as usize;
         if new_size > v.capacity() {
@@ -104,3 +197,70 @@ pub(super) unsafe extern "C" fn resize_vec_u8(data: *mut u8, size: u64, new_size
         v.shrink_to_fit();
     })
 }
+
+#[cfg(test)]
+mod tests {
+    use std::{
+        alloc::{GlobalAlloc, Layout, System},
+        ffi::c_void,
+        sync::atomic::{AtomicUsize, Ordering},
+    };
+
+    use crate::{codec::VALUE_STRING, message_channel::FunctionResult, DecodeOptions, Value};
+
+    use super::{allocate_vec_i32, free_vec_i32, nativeshell_set_vec_allocator, resize_vec_u8};
+
+    static ALLOCATED: AtomicUsize = AtomicUsize::new(0);
+    static FREED: AtomicUsize = AtomicUsize::new(0);
+
+    // Delegates to system allocator, so vectors allocated before the
+    // allocator was installed (i.e. by other tests) can still be freed.
+    unsafe extern "C" fn counting_alloc(size: usize, align: usize) -> *mut c_void {
+        ALLOCATED.fetch_add(1, Ordering::SeqCst);
+        System.alloc(Layout::from_size_align(size, align).unwrap()) as *mut _
+    }
+
+    unsafe extern "C" fn counting_free(data: *mut c_void, size: usize, align: usize) {
+        FREED.fetch_add(1, Ordering::SeqCst);
+        System.dealloc(
+            data as *mut _,
+            Layout::from_size_align(size, align).unwrap(),
+        )
+    }
+
+    #[test]
+    fn test_custom_allocator() {
+        let res = nativeshell_set_vec_allocator(counting_alloc, counting_free);
+        assert!(matches!(res, FunctionResult::NoError));
+        let res = nativeshell_set_vec_allocator(counting_alloc, counting_free);
+        assert!(matches!(res, FunctionResult::AlreadyInitialized));
+
+        unsafe {
+            let data = allocate_vec_i32(4);
+            assert_eq!(std::slice::from_raw_parts(data, 4), &[0, 0, 0, 0]);
+            assert_eq!(ALLOCATED.load(Ordering::SeqCst), 1);
+            free_vec_i32(data, 4);
+            assert!(FREED.load(Ordering::SeqCst) >= 1);
+
+            // Large string as sent from Dart; Message buffer is allocated
+            // first and then resized once the size is known.
+            let string = super::allocate_vec::<u8>(3);
+            std::ptr::copy_nonoverlapping(b"abc".as_ptr(), string, 3);
+            let message = super::allocate_vec::<u8>(4);
+            let message = resize_vec_u8(message, 4, 10);
+            assert_eq!(ALLOCATED.load(Ordering::SeqCst), 4);
+            let mut buf = vec![VALUE_STRING];
+            buf.extend_from_slice(&(string as u64).to_ne_bytes());
+            buf.push(3);
+            std::ptr::copy_nonoverlapping(buf.as_ptr(), message, buf.len());
+
+            let freed = FREED.load(Ordering::SeqCst);
+            let buf = super::take_vec(message, 10);
+            let value =
+                super::super::codec::Deserializer::deserialize(&buf, &DecodeOptions::default());
+            assert_eq!(value, Ok(Value::String("abc".into())));
+            // message buffer and string
+            assert!(FREED.load(Ordering::SeqCst) >= freed + 2);
+        }
+    }
+}
@@ -0,0 +1,183 @@
+use std::{borrow::Cow, fs::File, io, path::Path};
+
+use memmap2::Mmap;
+
+use crate::{
+    codec::{decode_prefix, DecodedValue},
+    DecodeError, DecodeOptions, StringDecoding, Value,
+};
+
+/// Value decoded from memory mapped file by [`MappedValues::iter`].
+///
+/// Strings and byte lists borrow from the mapping, so the value can't outlive
+/// [`MappedValues`] it was decoded from. Other values, including typed lists
+/// with wider elements (which are not necessarily aligned within the file),
+/// are decoded into owned [`Value`]. Use [`MappedValue::to_value`] to get a
+/// value independent of the mapping.
+#[derive(Clone, Debug, PartialEq)]
+pub enum MappedValue<'a> {
+    /// Borrowed unless [`StringDecoding::Lossy`] had to replace invalid UTF-8.
+    String(Cow<'a, str>),
+    I8List(&'a [i8]),
+    U8List(&'a [u8]),
+    List(Vec<MappedValue<'a>>),
+    Map(Vec<(MappedValue<'a>, MappedValue<'a>)>),
+    Value(Value),
+}
+
+impl MappedValue<'_> {
+    /// Copies borrowed data out of the mapping.
+    pub fn to_value(&self) -> Value {
+        match self {
+            MappedValue::String(s) => Value::String(s.to_string()),
+            MappedValue::I8List(v) => Value::I8List(v.to_vec()),
+            MappedValue::U8List(v) => Value::U8List(v.to_vec()),
+            MappedValue::List(list) => Value::List(list.iter().map(Self::to_value).collect()),
+            MappedValue::Map(map) => Value::Map(
+                map.iter()
+                    .map(|(k, v)| (k.to_value(), v.to_value()))
+                    .collect::<Vec<_>>()
+                    .into(),
+            ),
+            MappedValue::Value(v) => v.clone(),
+        }
+    }
+}
+
+impl From<MappedValue<'_>> for Value {
+    fn from(value: MappedValue<'_>) -> Self {
+        match value {
+            MappedValue::Value(v) => v,
+            value => value.to_value(),
+        }
+    }
+}
+
+impl<'a> DecodedValue<'a> for MappedValue<'a> {
+    fn from_value(value: Value) -> Self {
+        MappedValue::Value(value)
+    }
+
+    fn from_string(bytes: &'a [u8], decoding: StringDecoding) -> Result<Self, DecodeError> {
+        let string = match std::str::from_utf8(bytes) {
+            Ok(string) => Cow::Borrowed(string),
+            Err(_) => Cow::Owned(Value::validate_utf8(bytes.to_vec(), decoding)?),
+        };
+        Ok(MappedValue::String(string))
+    }
+
+    fn from_bytes(bytes: &'a [u8], signed: bool) -> Self {
+        if signed {
+            // i8 and u8 have same size and alignment.
+            let bytes =
+                unsafe { std::slice::from_raw_parts(bytes.as_ptr() as *const i8, bytes.len()) };
+            MappedValue::I8List(bytes)
+        } else {
+            MappedValue::U8List(bytes)
+        }
+    }
+
+    fn from_list(list: Vec<Self>) -> Self {
+        MappedValue::List(list)
+    }
+
+    fn from_map(map: Vec<(Self, Self)>) -> Self {
+        MappedValue::Map(map)
+    }
+}
+
+impl Value {
+    /// Memory maps file at `path` for decoding values stored in it. The file is
+    /// expected to contain values encoded with [`Value::encode`] written one
+    /// after another. Values are decoded lazily through [`MappedValues::iter`]
+    /// so the file doesn't need to fit in memory, only the value currently
+    /// being decoded does.
+    ///
+    /// Contents of the file must not be modified while mapped; doing so is
+    /// undefined behavior.
+    pub fn decode_mmap<P: AsRef<Path>>(path: P) -> io::Result<MappedValues> {
+        Self::decode_mmap_with_options(path, DecodeOptions::default())
+    }
+
+    /// Same as [`Value::decode_mmap`] but allows specifying decode options.
+    /// Limits apply to each value separately.
+    pub fn decode_mmap_with_options<P: AsRef<Path>>(
+        path: P,
+        options: DecodeOptions,
+    ) -> io::Result<MappedValues> {
+        let file = File::open(path)?;
+        Ok(MappedValues {
+            mmap: unsafe { Mmap::map(&file)? },
+            options,
+        })
+    }
+}
+
+/// Memory mapped file returned by [`Value::decode_mmap`]. Unmapped when dropped.
+#[derive(Debug)]
+pub struct MappedValues {
+    mmap: Mmap,
+    options: DecodeOptions,
+}
+
+impl MappedValues {
+    /// Returns iterator over values in the file. Decoded values borrow from
+    /// the mapping.
+    pub fn iter(&self) -> MappedValuesIter<'_> {
+        MappedValuesIter {
+            data: &self.mmap,
+            position: 0,
+            options: &self.options,
+        }
+    }
+
+    /// Contents of the mapped file.
+    pub fn as_bytes(&self) -> &[u8] {
+        &self.mmap
+    }
+}
+
+impl<'a> IntoIterator for &'a MappedValues {
+    type Item = Result<MappedValue<'a>, DecodeError>;
+    type IntoIter = MappedValuesIter<'a>;
+
+    fn into_iter(self) -> Self::IntoIter {
+        self.iter()
+    }
+}
+
+/// Iterator returned by [`MappedValues::iter`]. Stops after first error.
+#[derive(Debug, Clone)]
+pub struct MappedValuesIter<'a> {
+    data: &'a [u8],
+    position: usize,
+    options: &'a DecodeOptions,
+}
+
+impl MappedValuesIter<'_> {
+    /// Offset in the file of the next value to be decoded.
+    pub fn position(&self) -> usize {
+        self.position
+    }
+}
+
+impl<'a> Iterator for MappedValuesIter<'a> {
+    type Item = Result<MappedValue<'a>, DecodeError>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let remaining = &self.data[self.position..];
+        if remaining.is_empty() {
+            return None;
+        }
+        match decode_prefix(remaining, self.options) {
+            Ok((value, len)) => {
+                self.position += len;
+                Some(Ok(value))
+            }
+            Err(error) => {
+                self.position = self.data.len();
+                Some(Err(error))
+            }
+        }
+    }
+}
@@ -18,6 +18,7 @@ pub struct PlatformRunLoop {
     pipes: [c_int; 2],
     state: Rc<State>,
     state_ptr: *const State,
+    stopped: Cell<bool>,
 }
 
 struct Timer {
@@ -92,6 +93,7 @@ impl PlatformRunLoop {
             pipes,
             state,
             state_ptr,
+            stopped: Cell::new(false),
         }
     }
 
@@ -158,10 +160,50 @@ impl PlatformRunLoop {
         }
     }
 
+    // Flutter owns the looper; this runs nested loop that only processes
+    // callbacks and timers scheduled through this run loop until `stop` is
+    // called. Stop requested before `run` makes the next `run` return.
+    pub fn run(&self) {
+        while !self.stopped.replace(false) {
+            self.poll_once();
+        }
+    }
+
+    pub fn stop(&self) {
+        self.stopped.set(true);
+    }
+
+    pub fn tick(&self) -> bool {
+        let pending_callbacks: Vec<SenderCallback> = {
+            let mut callbacks = self.state.callbacks.lock().unwrap();
+            callbacks.callbacks.drain(0..).collect()
+        };
+        let pending_timers = self.state.get_pending_timers();
+        let did_work = !pending_callbacks.is_empty() || !pending_timers.is_empty();
+        for c in pending_callbacks {
+            c();
+        }
+        self.state.process_pending_timers(pending_timers);
+        did_work
+    }
+
     pub fn unschedule(&self, handle: HandleType) {
         self.state.unschedule(handle);
     }
 
+    pub fn next_scheduled_deadline(&self) -> Option<Instant> {
+        self.state
+            .timers
+            .borrow()
+            .values()
+            .map(|x| x.scheduled)
+            .min()
+    }
+
+    pub fn pending_timer_count(&self) -> usize {
+        self.state.timers.borrow().len()
+    }
+
     #[must_use]
     pub fn schedule<F>(&self, in_time: Duration, callback: F) -> HandleType
     where
@@ -170,6 +212,20 @@ impl PlatformRunLoop {
         self.state.schedule(in_time, callback)
     }
 
+    // Timer coalescing is not supported, tolerance is ignored.
+    #[must_use]
+    pub fn schedule_with_tolerance<F>(
+        &self,
+        in_time: Duration,
+        _tolerance: Duration,
+        callback: F,
+    ) -> HandleType
+    where
+        F: FnOnce() + 'static,
+    {
+        self.schedule(in_time, callback)
+    }
+
     pub fn new_sender(&self) -> PlatformRunLoopSender {
         PlatformRunLoopSender {
             callbacks: self.state.callbacks.clone(),
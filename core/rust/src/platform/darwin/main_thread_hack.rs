@@ -6,9 +6,11 @@ use objc::{
     sel, sel_impl,
 };
 use once_cell::sync::Lazy;
-use std::cell::Cell;
-
-static mut FAKE_MAIN_THREAD: usize = 0;
+use std::{
+    cell::Cell,
+    sync::{Mutex, Once},
+    thread::{self, ThreadId},
+};
 
 extern "C" {
     static mut _CFMainPThread: usize;
@@ -18,7 +20,7 @@ extern "C" {
 }
 
 extern "C" fn is_main_thread(_class: &Class, _sel: Sel) -> bool {
-    unsafe { FAKE_MAIN_THREAD == pthread_self() }
+    IS_FAKE_MAIN_THREAD.try_with(|v| v.get()).unwrap_or(false)
 }
 
 static NS_THREAD_REPLACEMENT: Lazy<&'static Class> = Lazy::new(|| unsafe {
@@ -32,9 +34,40 @@ static NS_THREAD_REPLACEMENT: Lazy<&'static Class> = Lazy::new(|| unsafe {
 });
 
 thread_local! {
-    static ALREADY_DONE : Cell<bool> = Cell::new(false);
+    // Every test thread that runs a run loop is treated as main thread.
+    static IS_FAKE_MAIN_THREAD: Cell<bool> = Cell::new(false);
+    static FAKE_MAIN_THREAD_OWNER: FakeMainThreadOwner = FakeMainThreadOwner::acquire();
+}
+
+// Main CFRunLoop and NSApplication are process global, so only one live thread
+// can pretend to be main thread at a time. Released when the thread exits.
+static FAKE_MAIN_THREAD: Mutex<Option<ThreadId>> = Mutex::new(None);
+
+struct FakeMainThreadOwner {}
+
+impl FakeMainThreadOwner {
+    fn acquire() -> Self {
+        let mut owner = FAKE_MAIN_THREAD.lock().unwrap_or_else(|e| e.into_inner());
+        if let Some(owner) = *owner {
+            panic!(
+                "Thread {:?} is already running main run loop; running NSApplication \
+                 on multiple threads at once is not supported.",
+                owner
+            );
+        }
+        *owner = Some(thread::current().id());
+        Self {}
+    }
+}
+
+impl Drop for FakeMainThreadOwner {
+    fn drop(&mut self) {
+        *FAKE_MAIN_THREAD.lock().unwrap_or_else(|e| e.into_inner()) = None;
+    }
 }
 
+static SWIZZLE: Once = Once::new();
+
 /// NSApplication is braindead and insist on running on main thread. Unfortunataly
 /// Rust test harness is already blocking main thread so we need some swizzling
 /// to convince the NSApplication that this is main thread.
@@ -45,13 +78,15 @@ thread_local! {
 ///
 /// That said, this should be good enough for basic unit tests.
 pub fn ensure_ns_app_thinks_it_is_main_thread() {
-    let already_done = ALREADY_DONE.with(|v| v.replace(true));
+    FAKE_MAIN_THREAD_OWNER.with(|_| {});
+    let already_done = IS_FAKE_MAIN_THREAD.with(|v| v.replace(true));
     if !already_done {
-        unsafe {
-            FAKE_MAIN_THREAD = pthread_self();
+        SWIZZLE.call_once(|| unsafe {
             let m1 = class_getClassMethod(class!(NSThread), sel!(isMainThread));
             let m2 = class_getClassMethod(*NS_THREAD_REPLACEMENT, sel!(isMainThread));
             method_exchangeImplementations(m1, m2);
+        });
+        unsafe {
             _CFRunLoopSetCurrent(CFRunLoopGetMain());
             _CFMainPThread = pthread_self();
         }
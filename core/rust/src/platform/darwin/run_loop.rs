@@ -25,7 +25,7 @@ use super::sys::cocoa::*;
 #[cfg(target_os = "macos")]
 use objc::{class, msg_send, sel, sel_impl};
 
-use super::sys::to_nsstring;
+use super::sys::{to_nsstring, CFRunLoopTimerSetTolerance};
 
 pub type HandleType = usize;
 pub const INVALID_HANDLE: HandleType = 0;
@@ -34,6 +34,7 @@ type Callback = Box<dyn FnOnce()>;
 
 struct Timer {
     scheduled: Instant,
+    tolerance: Duration,
     callback: Callback,
 }
 
@@ -160,12 +161,26 @@ impl State {
         }
     }
 
+    fn next_timer(&self) -> Option<Instant> {
+        self.timers.values().map(|x| x.scheduled).min()
+    }
+
+    // Largest tolerance for timer firing at `next` that doesn't delay any
+    // pending timer past its own tolerance.
+    fn next_tolerance(&self, next: Instant) -> Duration {
+        self.timers
+            .values()
+            .map(|t| (t.scheduled + t.tolerance).saturating_duration_since(next))
+            .min()
+            .unwrap_or_default()
+    }
+
     fn next_instant(&self) -> Instant {
         if !self.callbacks.is_empty() {
             Instant::now()
         } else {
-            let min = self.timers.values().map(|x| x.scheduled).min();
-            min.unwrap_or_else(|| Instant::now() + Duration::from_secs(60 * 60))
+            self.next_timer()
+                .unwrap_or_else(|| Instant::now() + Duration::from_secs(60 * 60))
         }
     }
 
@@ -202,7 +217,14 @@ impl State {
             let timer =
                 CFRunLoopTimer::new(fire_date, 0.0, 0, 0, Self::on_timer, &mut context as *mut _);
             self.timer = Some(timer.clone());
+            let tolerance = self.next_tolerance(next);
             unsafe {
+                if !tolerance.is_zero() {
+                    CFRunLoopTimerSetTolerance(
+                        timer.as_concrete_TypeRef(),
+                        tolerance.as_secs_f64(),
+                    );
+                }
                 CFRunLoopAddTimer(
                     CFRunLoopGetMain(),
                     timer.as_concrete_TypeRef(),
@@ -243,8 +265,9 @@ impl State {
         let _ = ManuallyDrop::new(state);
     }
 
-    fn poll(state: Arc<Mutex<State>>) {
+    fn poll(state: Arc<Mutex<State>>) -> bool {
         let execution = state.lock().unwrap().get_pending_execution();
+        let did_work = !execution.callbacks.is_empty() || !execution.timers.is_empty();
         for c in execution.callbacks {
             c();
         }
@@ -255,12 +278,15 @@ impl State {
             let state_clone = state.clone();
             state.lock().unwrap().schedule(state_clone);
         }
+        did_work
     }
 }
 
 pub struct PlatformRunLoop {
     next_handle: Cell<HandleType>,
     state: Arc<Mutex<State>>,
+    #[cfg(target_os = "ios")]
+    stopped: Cell<bool>,
 }
 
 pub struct PollSession {
@@ -282,6 +308,8 @@ impl PlatformRunLoop {
         Self {
             next_handle: Cell::new(INVALID_HANDLE + 1),
             state: Arc::new(Mutex::new(State::new())),
+            #[cfg(target_os = "ios")]
+            stopped: Cell::new(false),
         }
     }
 
@@ -299,6 +327,18 @@ impl PlatformRunLoop {
     }
 
     pub fn schedule<F>(&self, in_time: Duration, callback: F) -> HandleType
+    where
+        F: FnOnce() + 'static,
+    {
+        self.schedule_with_tolerance(in_time, Duration::ZERO, callback)
+    }
+
+    pub fn schedule_with_tolerance<F>(
+        &self,
+        in_time: Duration,
+        tolerance: Duration,
+        callback: F,
+    ) -> HandleType
     where
         F: FnOnce() + 'static,
     {
@@ -311,6 +351,7 @@ impl PlatformRunLoop {
             handle,
             Timer {
                 scheduled: Instant::now() + in_time,
+                tolerance,
                 callback: Box::new(callback),
             },
         );
@@ -320,6 +361,14 @@ impl PlatformRunLoop {
         handle
     }
 
+    pub fn next_scheduled_deadline(&self) -> Option<Instant> {
+        self.state.lock().unwrap().next_timer()
+    }
+
+    pub fn pending_timer_count(&self) -> usize {
+        self.state.lock().unwrap().timers.len()
+    }
+
     #[cfg(target_os = "macos")]
     pub fn run(&self) {
         #[cfg(any(test, feature = "mock"))]
@@ -359,6 +408,22 @@ impl PlatformRunLoop {
         }
     }
 
+    // Flutter owns the run loop on iOS; this runs nested loop until `stop` is
+    // called. Stop requested before `run` makes the next `run` return.
+    #[cfg(target_os = "ios")]
+    pub fn run(&self) {
+        while !self.stopped.replace(false) {
+            unsafe { CFRunLoopRunInMode(kCFRunLoopDefaultMode, 1.0, 1) };
+        }
+    }
+
+    #[cfg(target_os = "ios")]
+    pub fn stop(&self) {
+        self.stopped.set(true);
+        use core_foundation::runloop::{CFRunLoopGetCurrent, CFRunLoopStop};
+        unsafe { CFRunLoopStop(CFRunLoopGetCurrent()) };
+    }
+
     pub fn poll_once(&self, poll_session: &mut PollSession) {
         let mode = self.state.lock().unwrap().run_loop_mode.clone();
         if !poll_session.timed_out {
@@ -373,6 +438,19 @@ impl PlatformRunLoop {
         }
     }
 
+    pub fn pump_private_mode_until<F: Fn() -> bool>(&self, predicate: F) {
+        let mode = self.state.lock().unwrap().run_loop_mode.clone();
+        while !predicate() {
+            // Run loop only returns early after handling source; short timeout
+            // makes sure the predicate is checked after timers fire as well.
+            unsafe { CFRunLoopRunInMode(*mode as CFStringRef, 0.01, 1) };
+        }
+    }
+
+    pub fn tick(&self) -> bool {
+        State::poll(self.state.clone())
+    }
+
     pub fn new_sender(&self) -> PlatformRunLoopSender {
         PlatformRunLoopSender {
             state: self.state.clone(),
@@ -11,6 +11,13 @@ extern "C" {}
 #[link(name = "AppKit", kind = "framework")]
 extern "C" {}
 
+extern "C" {
+    pub fn CFRunLoopTimerSetTolerance(
+        timer: core_foundation::runloop::CFRunLoopTimerRef,
+        tolerance: core_foundation::date::CFTimeInterval,
+    );
+}
+
 #[allow(non_camel_case_types)]
 #[allow(non_snake_case)]
 #[allow(dead_code)]
@@ -73,10 +80,17 @@ pub mod cocoa {
     }
 
     #[cfg(target_pointer_width = "32")]
-    type NSUInteger = std::os::raw::c_uint;
+    pub type NSUInteger = std::os::raw::c_uint;
 
     #[cfg(target_pointer_width = "64")]
-    type NSUInteger = std::os::raw::c_ulong;
+    pub type NSUInteger = std::os::raw::c_ulong;
+
+    #[repr(C)]
+    #[derive(Copy, Clone, Debug)]
+    pub struct NSRange {
+        pub location: NSUInteger,
+        pub length: NSUInteger,
+    }
 
     pub trait NSArray: Sized {
         unsafe fn arrayWithObjects(_: Self, objects: &[id]) -> id {
@@ -137,7 +151,7 @@ pub unsafe fn from_nsstring(ns_string: id) -> String {
     let len = msg_send![ns_string, lengthOfBytesUsingEncoding: UTF8_ENCODING];
 
     let bytes = slice::from_raw_parts(bytes, len);
-    std::str::from_utf8(bytes).unwrap().into()
+    String::from_utf8_lossy(bytes).into_owned()
 }
 
 pub fn to_nsdata(data: &[u8]) -> StrongPtr {
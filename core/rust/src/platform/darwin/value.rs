@@ -1,4 +1,4 @@
-use std::ffi::c_void;
+use std::{ffi::c_void, os::raw::c_char};
 
 use core_foundation::{
     base::{CFGetTypeID, CFTypeRef},
@@ -19,10 +19,29 @@ use crate::{
 };
 
 use super::sys::{
-    cocoa::{id, NSArray, NSDictionary},
+    cocoa::{id, NSArray, NSDictionary, NSUInteger},
     from_nsdata, from_nsstring,
 };
 
+/// Key that marks a [`Value::Map`] as `NSAttributedString` representation.
+/// Requires the `attributed_string` feature.
+///
+/// The map has following format:
+/// ```text
+/// {
+///   "nativeshell_attributed_string": "text",
+///   "runs": [
+///     { "location": 0, "length": 4, "attributes": { "NSLink": "https://..." } },
+///   ],
+/// }
+/// ```
+/// Run location and length are in UTF-16 code units. Attribute values are
+/// converted using the same rules as other values; attributes with values that
+/// can not be represented as [`Value`] (i.e. `NSFont` or `NSColor`) are omitted
+/// when converting from `NSAttributedString`.
+#[cfg(feature = "attributed_string")]
+pub const ATTRIBUTED_STRING_MARKER: &str = "nativeshell_attributed_string";
+
 /// Trait for converting Value from and to Objective C objects.
 pub trait ValueObjcConversion: Sized {
     fn to_objc(&self) -> Result<StrongPtr, TryFromError>;
@@ -39,6 +58,31 @@ impl ValueObjcConversion for Value {
     }
 }
 
+/// Helpers for converting multiple values to Cocoa collections in one call.
+pub trait ValueSliceObjcConversion {
+    /// Converts values to `NSArray`. `Value::Null` items are stored as `NSNull`.
+    fn to_ns_array(&self) -> Result<StrongPtr, TryFromError>;
+}
+
+impl ValueSliceObjcConversion for [Value] {
+    fn to_ns_array(&self) -> Result<StrongPtr, TryFromError> {
+        autoreleasepool(|| unsafe { _values_to_objc(self).map(|f| StrongPtr::retain(f)) })
+    }
+}
+
+/// Helpers for converting key-value pairs to `NSDictionary` in one call.
+pub trait ValueEntriesObjcConversion {
+    /// Converts entries to `NSDictionary`. `Value::Null` keys and values are
+    /// stored as `NSNull`.
+    fn to_ns_dictionary(&self) -> Result<StrongPtr, TryFromError>;
+}
+
+impl ValueEntriesObjcConversion for [(Value, Value)] {
+    fn to_ns_dictionary(&self) -> Result<StrongPtr, TryFromError> {
+        autoreleasepool(|| unsafe { _entries_to_objc(self).map(|f| StrongPtr::retain(f)) })
+    }
+}
+
 //
 //
 //
@@ -47,7 +91,86 @@ extern "C" {
     pub fn CFNumberIsFloatType(number: CFNumberRef) -> bool;
 }
 
+// Arrays and dictionaries are converted using explicit stack so that deeply
+// nested objects don't overflow native stack.
 unsafe fn _value_from_objc(obj: id) -> Result<Value, TryFromError> {
+    enum Frame {
+        // Array and items converted so far.
+        Array(id, Vec<Value>),
+        // Dictionary, its keys and keys and values converted so far (interleaved).
+        Dictionary(id, id, Vec<Value>),
+    }
+    let mut stack = Vec::<Frame>::new();
+    let mut next = obj;
+    loop {
+        let mut converted = if msg_send![next, isKindOfClass: class!(NSArray)] {
+            stack.push(Frame::Array(next, Vec::new()));
+            None
+        } else if msg_send![next, isKindOfClass: class!(NSDictionary)] {
+            let keys: id = msg_send![next, allKeys];
+            stack.push(Frame::Dictionary(next, keys, Vec::new()));
+            None
+        } else {
+            Some(_leaf_value_from_objc(next)?)
+        };
+        // Store the value in its parent; repeat for parents that are complete.
+        loop {
+            let child = match stack.last_mut() {
+                None => return Ok(converted.unwrap_or(Value::Null)),
+                Some(Frame::Array(array, items)) => {
+                    items.extend(converted.take());
+                    let index = items.len() as NSUInteger;
+                    if index < NSArray::count(*array) {
+                        Some(NSArray::objectAtIndex(*array, index))
+                    } else {
+                        None
+                    }
+                }
+                Some(Frame::Dictionary(dictionary, keys, items)) => {
+                    items.extend(converted.take());
+                    let index = items.len() as NSUInteger;
+                    if index < NSArray::count(*keys) * 2 {
+                        let key = NSArray::objectAtIndex(*keys, index / 2);
+                        if index % 2 == 0 {
+                            Some(key)
+                        } else {
+                            Some(NSDictionary::valueForKey_(*dictionary, key))
+                        }
+                    } else {
+                        None
+                    }
+                }
+            };
+            match child {
+                Some(child) => {
+                    next = child;
+                    break;
+                }
+                None => {
+                    converted = match stack.pop() {
+                        Some(Frame::Array(_, items)) => Some(Value::List(items)),
+                        Some(Frame::Dictionary(_, _, items)) => {
+                            let mut entries = Vec::<(Value, Value)>::with_capacity(items.len() / 2);
+                            let mut items = items.into_iter();
+                            while let (Some(key), Some(value)) = (items.next(), items.next()) {
+                                entries.push((key, value));
+                            }
+                            Some(entries.into())
+                        }
+                        None => unreachable!(),
+                    }
+                }
+            }
+        }
+    }
+}
+
+// Converts object other than NSArray and NSDictionary.
+unsafe fn _leaf_value_from_objc(obj: id) -> Result<Value, TryFromError> {
+    #[cfg(feature = "attributed_string")]
+    if msg_send![obj, isKindOfClass: class!(NSAttributedString)] {
+        return attributed_string::from_objc(obj);
+    }
     if obj.is_null() || obj == msg_send![class!(NSNull), null] {
         Ok(Value::Null)
     } else if msg_send![obj, isKindOfClass: class!(NSNumber)] {
@@ -62,6 +185,8 @@ unsafe fn _value_from_objc(obj: id) -> Result<Value, TryFromError> {
                 &mut value as *mut _ as *mut c_void,
             );
             Ok(Value::F64(value))
+        } else if let Some(value) = _int32_from_objc(obj) {
+            Ok(value)
         } else {
             let mut value = 0i64;
             CFNumberGetValue(
@@ -75,28 +200,6 @@ unsafe fn _value_from_objc(obj: id) -> Result<Value, TryFromError> {
         Ok(Value::String(from_nsstring(obj)))
     } else if msg_send![obj, isKindOfClass: class!(NSData)] {
         Ok(Value::U8List(from_nsdata(obj)))
-    } else if msg_send![obj, isKindOfClass: class!(NSArray)] {
-        let len = NSArray::count(obj);
-        let mut res = Vec::<Value>::with_capacity(len as usize);
-        for i in 0..len {
-            let item = NSArray::objectAtIndex(obj, i);
-            res.push(_value_from_objc(item)?);
-        }
-        Ok(Value::List(res))
-    } else if msg_send![obj, isKindOfClass: class!(NSDictionary)] {
-        let mut entries = Vec::<(Value, Value)>::new();
-        let keys = NSDictionary::keyEnumerator(obj);
-        loop {
-            let key: id = msg_send![keys, nextObject];
-            if key.is_null() {
-                break;
-            } else {
-                let value = NSDictionary::valueForKey_(obj, key);
-                entries.push((_value_from_objc(key)?, _value_from_objc(value)?));
-            }
-        }
-
-        Ok(entries.into())
     } else {
         let class_name: id = msg_send![obj, className];
         Err(TryFromError::OtherError(format!(
@@ -106,14 +209,125 @@ unsafe fn _value_from_objc(obj: id) -> Result<Value, TryFromError> {
     }
 }
 
-unsafe fn _value_to_objc(value: &Value) -> Result<id, TryFromError> {
-    unsafe fn fix_null(v: id) -> id {
-        if v.is_null() {
-            msg_send![class!(NSNull), null]
-        } else {
-            v
+// Numbers with `int` or `unsigned int` type encoding (i.e. created through
+// `numberWithInt:`) keep their width; other integers are converted to `Value::I64`.
+unsafe fn _int32_from_objc(obj: id) -> Option<Value> {
+    let ty: *const c_char = msg_send![obj, objCType];
+    if ty.is_null() {
+        return None;
+    }
+    match *ty as u8 {
+        b'i' => Some(Value::I32(msg_send![obj, intValue])),
+        b'I' => Some(Value::U32(msg_send![obj, unsignedIntValue])),
+        _ => None,
+    }
+}
+
+unsafe fn fix_null(v: id) -> id {
+    if v.is_null() {
+        msg_send![class!(NSNull), null]
+    } else {
+        v
+    }
+}
+
+unsafe fn _values_to_objc(values: &[Value]) -> Result<id, TryFromError> {
+    let res = values
+        .iter()
+        .map(|v| _value_to_objc(v).map(|v| fix_null(v)))
+        .collect::<Result<Vec<_>, TryFromError>>()?;
+    Ok(NSArray::arrayWithObjects(nil, &res))
+}
+
+unsafe fn _entries_to_objc(entries: &[(Value, Value)]) -> Result<id, TryFromError> {
+    let mut keys = Vec::<id>::with_capacity(entries.len());
+    let mut objects = Vec::<id>::with_capacity(entries.len());
+    for item in entries.iter() {
+        keys.push(fix_null(_value_to_objc(&item.0)?));
+        objects.push(fix_null(_value_to_objc(&item.1)?));
+    }
+    Ok(NSDictionary::dictionaryWithObjects_forKeys_(
+        nil,
+        NSArray::arrayWithObjects(nil, &objects),
+        NSArray::arrayWithObjects(nil, &keys),
+    ))
+}
+
+// Lists and maps are converted using explicit stack so that deeply nested
+// values don't overflow native stack.
+unsafe fn _value_to_objc<'a>(value: &'a Value) -> Result<id, TryFromError> {
+    enum Frame<'a> {
+        // List and items converted so far.
+        List(&'a [Value], Vec<id>),
+        // Map and keys and values converted so far (interleaved).
+        Map(&'a [(Value, Value)], Vec<id>),
+    }
+    let mut stack = Vec::<Frame>::new();
+    let mut next = value;
+    loop {
+        let mut converted = match next {
+            Value::List(items) => {
+                stack.push(Frame::List(items, Vec::with_capacity(items.len())));
+                None
+            }
+            #[cfg(feature = "attributed_string")]
+            Value::Map(items) if attributed_string::is_attributed_string(items) => {
+                Some(attributed_string::to_objc(items)?)
+            }
+            Value::Map(items) => {
+                stack.push(Frame::Map(items, Vec::with_capacity(items.len() * 2)));
+                None
+            }
+            value => Some(_leaf_value_to_objc(value)?),
+        };
+        // Store the object in its parent; repeat for parents that are complete.
+        loop {
+            let child: Option<&'a Value> = match stack.last_mut() {
+                None => return Ok(converted.unwrap_or(nil)),
+                Some(Frame::List(items, objects)) => {
+                    let items: &'a [Value] = *items;
+                    objects.extend(converted.take().map(|o| fix_null(o)));
+                    items.get(objects.len())
+                }
+                Some(Frame::Map(entries, objects)) => {
+                    let entries: &'a [(Value, Value)] = *entries;
+                    objects.extend(converted.take().map(|o| fix_null(o)));
+                    let index = objects.len();
+                    entries
+                        .get(index / 2)
+                        .map(|e| if index % 2 == 0 { &e.0 } else { &e.1 })
+                }
+            };
+            match child {
+                Some(child) => {
+                    next = child;
+                    break;
+                }
+                None => {
+                    converted = match stack.pop() {
+                        Some(Frame::List(_, objects)) => {
+                            Some(NSArray::arrayWithObjects(nil, &objects))
+                        }
+                        Some(Frame::Map(_, objects)) => {
+                            let keys: Vec<id> = objects.iter().step_by(2).cloned().collect();
+                            let objects: Vec<id> =
+                                objects.iter().skip(1).step_by(2).cloned().collect();
+                            Some(NSDictionary::dictionaryWithObjects_forKeys_(
+                                nil,
+                                NSArray::arrayWithObjects(nil, &objects),
+                                NSArray::arrayWithObjects(nil, &keys),
+                            ))
+                        }
+                        None => unreachable!(),
+                    }
+                }
+            }
         }
     }
+}
+
+// Converts values other than lists and maps.
+unsafe fn _leaf_value_to_objc(value: &Value) -> Result<id, TryFromError> {
     unsafe fn transform_slice<T>(s: &[T]) -> &[u8] {
         std::slice::from_raw_parts(s.as_ptr() as *const u8, s.len() * std::mem::size_of::<T>())
     }
@@ -122,6 +336,8 @@ unsafe fn _value_to_objc(value: &Value) -> Result<id, TryFromError> {
         Value::Null => Ok(nil),
         Value::Bool(v) => Ok(msg_send![class!(NSNumber), numberWithBool: *v]),
         Value::I64(v) => Ok(msg_send![class!(NSNumber), numberWithLongLong: *v]),
+        Value::I32(v) => Ok(msg_send![class!(NSNumber), numberWithInt: *v]),
+        Value::U32(v) => Ok(msg_send![class!(NSNumber), numberWithUnsignedInt: *v]),
         Value::F64(v) => Ok(msg_send![class!(NSNumber), numberWithDouble: *v]),
         Value::String(s) => Ok(to_nsstring(s).autorelease()),
         Value::U8List(d) => Ok(to_nsdata(d).autorelease()),
@@ -133,26 +349,7 @@ unsafe fn _value_to_objc(value: &Value) -> Result<id, TryFromError> {
         Value::I64List(d) => Ok(to_nsdata(transform_slice(d)).autorelease()),
         Value::F32List(d) => Ok(to_nsdata(transform_slice(d)).autorelease()),
         Value::F64List(d) => Ok(to_nsdata(transform_slice(d)).autorelease()),
-        Value::List(items) => {
-            let res = items
-                .iter()
-                .map(|v| _value_to_objc(v).map(|v| fix_null(v)))
-                .collect::<Result<Vec<_>, TryFromError>>()?;
-            Ok(NSArray::arrayWithObjects(nil, &res))
-        }
-        Value::Map(items) => {
-            let mut keys = Vec::<id>::with_capacity(items.len());
-            let mut objects = Vec::<id>::with_capacity(items.len());
-            for item in items.iter() {
-                keys.push(fix_null(_value_to_objc(&item.0)?));
-                objects.push(fix_null(_value_to_objc(&item.1)?));
-            }
-            Ok(NSDictionary::dictionaryWithObjects_forKeys_(
-                nil,
-                NSArray::arrayWithObjects(nil, &objects),
-                NSArray::arrayWithObjects(nil, &keys),
-            ))
-        }
+        Value::BoolList(d) => Ok(to_nsdata(transform_slice(d)).autorelease()),
         other => Err(TryFromError::OtherError(format!(
             "Unable to convert {:?} to Objc",
             other,
@@ -160,6 +357,114 @@ unsafe fn _value_to_objc(value: &Value) -> Result<id, TryFromError> {
     }
 }
 
+#[cfg(feature = "attributed_string")]
+mod attributed_string {
+    use objc::{class, msg_send, sel, sel_impl};
+
+    use crate::{
+        platform::sys::{
+            cocoa::{id, NSDictionary, NSRange, NSUInteger},
+            from_nsstring, to_nsstring,
+        },
+        TryFromError, Value, ValueTupleList,
+    };
+
+    use super::{_value_from_objc, _value_to_objc, ATTRIBUTED_STRING_MARKER};
+
+    pub(super) fn is_attributed_string(items: &ValueTupleList) -> bool {
+        items
+            .iter()
+            .any(|(k, _)| matches!(k, Value::String(k) if k == ATTRIBUTED_STRING_MARKER))
+    }
+
+    fn get<'a>(map: &'a Value, key: &str) -> Result<&'a Value, TryFromError> {
+        map.get_by(&key.into()).ok_or_else(|| {
+            TryFromError::OtherError(format!("attributed string is missing \"{}\"", key))
+        })
+    }
+
+    fn get_usize(map: &Value, key: &str) -> Result<NSUInteger, TryFromError> {
+        match get(map, key)? {
+            Value::I64(v) => Ok((*v).try_into()?),
+            Value::I32(v) => Ok((*v).try_into()?),
+            Value::U32(v) => Ok((*v).try_into()?),
+            _ => Err(TryFromError::BadType),
+        }
+    }
+
+    pub(super) unsafe fn to_objc(items: &ValueTupleList) -> Result<id, TryFromError> {
+        let map = Value::Map(items.clone());
+        let text = match get(&map, ATTRIBUTED_STRING_MARKER)? {
+            Value::String(text) => text,
+            _ => return Err(TryFromError::BadType),
+        };
+        let res: id = msg_send![class!(NSMutableAttributedString), alloc];
+        let res: id = msg_send![res, initWithString: *to_nsstring(text)];
+        let res: id = msg_send![res, autorelease];
+        let runs = match map.get_by(&"runs".into()) {
+            Some(Value::List(runs)) => runs.as_slice(),
+            Some(Value::Null) | None => &[],
+            Some(_) => return Err(TryFromError::BadType),
+        };
+        for run in runs {
+            let range = NSRange {
+                location: get_usize(run, "location")?,
+                length: get_usize(run, "length")?,
+            };
+            let attributes = _value_to_objc(get(run, "attributes")?)?;
+            let () = msg_send![res, setAttributes: attributes range: range];
+        }
+        Ok(res)
+    }
+
+    pub(super) unsafe fn from_objc(obj: id) -> Result<Value, TryFromError> {
+        let string: id = msg_send![obj, string];
+        let length: NSUInteger = msg_send![obj, length];
+        let mut runs = Vec::new();
+        let mut index: NSUInteger = 0;
+        while index < length {
+            let mut range = NSRange {
+                location: 0,
+                length: 0,
+            };
+            let attributes: id =
+                msg_send![obj, attributesAtIndex: index effectiveRange: &mut range as *mut NSRange];
+            // Attributes that can not be converted to Value are skipped.
+            let mut entries = Vec::<(Value, Value)>::new();
+            let keys = NSDictionary::keyEnumerator(attributes);
+            loop {
+                let key: id = msg_send![keys, nextObject];
+                if key.is_null() {
+                    break;
+                }
+                let value = NSDictionary::valueForKey_(attributes, key);
+                if let (Ok(key), Ok(value)) = (_value_from_objc(key), _value_from_objc(value)) {
+                    entries.push((key, value));
+                }
+            }
+            runs.push(Value::Map(
+                vec![
+                    ("location".into(), (range.location as i64).into()),
+                    ("length".into(), (range.length as i64).into()),
+                    ("attributes".into(), Value::Map(entries.into())),
+                ]
+                .into(),
+            ));
+            index = range.location + range.length;
+        }
+        Ok(Value::Map(
+            vec![
+                (
+                    ATTRIBUTED_STRING_MARKER.into(),
+                    from_nsstring(string).into(),
+                ),
+                ("runs".into(), Value::List(runs)),
+            ]
+            .into(),
+        ))
+    }
+}
+
 #[cfg(test)]
 mod test {
     use objc::{class, msg_send, sel, sel_impl};
@@ -170,7 +475,7 @@ mod test {
                 cocoa::{nil, NSArray, NSDictionary},
                 to_nsdata, to_nsstring,
             },
-            value::ValueObjcConversion,
+            value::{ValueEntriesObjcConversion, ValueObjcConversion, ValueSliceObjcConversion},
         },
         Value,
     };
@@ -277,4 +582,74 @@ mod test {
         let equals: bool = unsafe { msg_send![*objc, isEqual: object1] };
         assert!(equals);
     }
+
+    #[test]
+    fn test_to_ns_array() {
+        let values: Vec<Value> = vec![
+            "Obj1".into(),
+            true.into(),
+            5i64.into(),
+            Value::Null,
+            vec![("Key".into(), 10f64.into())].into(),
+        ];
+        let array = values.to_ns_array().unwrap();
+        let len = unsafe { NSArray::count(*array) };
+        assert_eq!(len, values.len() as u64);
+        for (i, value) in values.iter().enumerate() {
+            let item = unsafe { NSArray::objectAtIndex(*array, i as u64) };
+            assert_eq!(&Value::from_objc(item).unwrap(), value);
+        }
+
+        let entries: Vec<(Value, Value)> =
+            vec![("Key".into(), vec![1i64.into(), Value::Null].into())];
+        let dictionary = entries.to_ns_dictionary().unwrap();
+        assert_eq!(Value::from_objc(*dictionary).unwrap(), entries.into());
+    }
+
+    #[test]
+    #[cfg(feature = "attributed_string")]
+    fn test_attributed_string() {
+        use crate::platform::{sys::cocoa::NSRange, value::ATTRIBUTED_STRING_MARKER};
+        use objc::runtime::Object;
+
+        let string: *mut Object = unsafe {
+            let string: *mut Object = msg_send![class!(NSMutableAttributedString), alloc];
+            let string: *mut Object =
+                msg_send![string, initWithString: *to_nsstring("Hello World")];
+            let range = NSRange {
+                location: 6,
+                length: 5,
+            };
+            let () = msg_send![string, addAttribute: *to_nsstring("NSLink")
+                                                value: *to_nsstring("https://example.com")
+                                                range: range];
+            string
+        };
+        let value = Value::from_objc(string).unwrap();
+        let run = |location: i64, length: i64, attributes: Vec<(Value, Value)>| -> Value {
+            vec![
+                ("location".into(), location.into()),
+                ("length".into(), length.into()),
+                ("attributes".into(), attributes.into()),
+            ]
+            .into()
+        };
+        let expected: Value = vec![
+            (ATTRIBUTED_STRING_MARKER.into(), "Hello World".into()),
+            (
+                "runs".into(),
+                Value::List(vec![
+                    run(0, 6, vec![]),
+                    run(6, 5, vec![("NSLink".into(), "https://example.com".into())]),
+                ]),
+            ),
+        ]
+        .into();
+        assert_eq!(value, expected);
+
+        let objc = value.to_objc().unwrap();
+        let equals: bool = unsafe { msg_send![*objc, isEqual: string] };
+        assert!(equals);
+        let () = unsafe { msg_send![string, release] };
+    }
 }
@@ -3,7 +3,7 @@ use std::{
     collections::HashMap,
     os::raw::c_uint,
     rc::Rc,
-    time::Duration,
+    time::{Duration, Instant},
 };
 
 use crate::Context;
@@ -15,9 +15,78 @@ type SourceId = c_uint;
 pub type HandleType = usize;
 pub const INVALID_HANDLE: HandleType = 0;
 
+// Maximum number of main context iterations done by `tick`.
+const MAX_TICK_ITERATIONS: usize = 100;
+
 pub struct PlatformRunLoop {
     next_handle: Cell<HandleType>,
-    timers: Rc<RefCell<HashMap<HandleType, SourceId>>>,
+    timers: Rc<RefCell<HashMap<HandleType, (SourceId, Instant)>>>,
+    context: MainContext,
+    // Loops started by `run` on other than default context, innermost last.
+    main_loops: RefCell<Vec<*mut GMainLoop>>,
+}
+
+// Owned reference to GMainContext, which is thread safe.
+struct MainContext(*mut GMainContext);
+
+unsafe impl Send for MainContext {}
+unsafe impl Sync for MainContext {}
+
+impl MainContext {
+    // Thread default context; main context unless other context was pushed
+    // for current thread.
+    fn thread_default() -> Self {
+        #[cfg(any(test, feature = "mock"))]
+        TEST_CONTEXT.with(|_| {});
+        Self(unsafe { g_main_context_ref_thread_default() })
+    }
+
+    fn is_default(&self) -> bool {
+        self.0 == unsafe { g_main_context_default() }
+    }
+}
+
+impl Clone for MainContext {
+    fn clone(&self) -> Self {
+        Self(unsafe { g_main_context_ref(self.0) })
+    }
+}
+
+impl Drop for MainContext {
+    fn drop(&mut self) {
+        unsafe { g_main_context_unref(self.0) };
+    }
+}
+
+// When testing every thread gets its own context so that run loops on
+// different threads don't interfere with each other.
+#[cfg(any(test, feature = "mock"))]
+struct TestContext(*mut GMainContext);
+
+#[cfg(any(test, feature = "mock"))]
+impl TestContext {
+    fn new() -> Self {
+        unsafe {
+            let context = g_main_context_new();
+            g_main_context_push_thread_default(context);
+            Self(context)
+        }
+    }
+}
+
+#[cfg(any(test, feature = "mock"))]
+impl Drop for TestContext {
+    fn drop(&mut self) {
+        unsafe {
+            g_main_context_pop_thread_default(self.0);
+            g_main_context_unref(self.0);
+        }
+    }
+}
+
+#[cfg(any(test, feature = "mock"))]
+thread_local! {
+    static TEST_CONTEXT: TestContext = TestContext::new();
 }
 
 #[derive(Copy, Clone, Debug, PartialEq, Eq)]
@@ -36,18 +105,21 @@ unsafe extern "C" fn destroy_closure<F: FnMut() -> gboolean + 'static>(ptr: gpoi
     let _ = Box::<RefCell<F>>::from_raw(ptr as *mut _);
 }
 
-pub fn timeout_add_local<F>(interval: Duration, func: F) -> SourceId
+fn timeout_add_local<F>(context: &MainContext, interval: Duration, func: F) -> SourceId
 where
     F: FnMut() -> gboolean + 'static,
 {
     unsafe {
-        g_timeout_add_full(
-            G_PRIORITY_DEFAULT,
-            interval.as_millis() as _,
+        let source = g_timeout_source_new(interval.as_millis() as _);
+        g_source_set_callback(
+            source,
             Some(trampoline::<F>),
             into_raw(func),
             Some(destroy_closure::<F>),
-        )
+        );
+        let source_id = g_source_attach(source, context.0);
+        g_source_unref(source);
+        source_id
     }
 }
 
@@ -57,16 +129,31 @@ impl PlatformRunLoop {
         Self {
             next_handle: Cell::new(INVALID_HANDLE + 1),
             timers: Rc::new(RefCell::new(HashMap::new())),
+            context: MainContext::thread_default(),
+            main_loops: RefCell::new(Vec::new()),
         }
     }
 
     pub fn unschedule(&self, handle: HandleType) {
         let source = self.timers.borrow_mut().remove(&handle);
-        if let Some(source) = source {
-            unsafe { g_source_remove(source) };
+        if let Some((source, _)) = source {
+            unsafe {
+                let source = g_main_context_find_source_by_id(self.context.0, source);
+                if !source.is_null() {
+                    g_source_destroy(source);
+                }
+            }
         }
     }
 
+    pub fn next_scheduled_deadline(&self) -> Option<Instant> {
+        self.timers.borrow().values().map(|x| x.1).min()
+    }
+
+    pub fn pending_timer_count(&self) -> usize {
+        self.timers.borrow().len()
+    }
+
     fn next_handle(&self) -> HandleType {
         let r = self.next_handle.get();
         self.next_handle.replace(r + 1);
@@ -81,8 +168,9 @@ impl PlatformRunLoop {
         let callback = Rc::new(RefCell::new(Some(callback)));
         let handle = self.next_handle();
 
+        let scheduled = Instant::now() + in_time;
         let timers = self.timers.clone();
-        let source_id = timeout_add_local(in_time, move || {
+        let source_id = timeout_add_local(&self.context, in_time, move || {
             timers.borrow_mut().remove(&handle);
             let f = callback
                 .borrow_mut()
@@ -91,29 +179,81 @@ impl PlatformRunLoop {
             f();
             G_SOURCE_REMOVE
         });
-        self.timers.borrow_mut().insert(handle, source_id);
+        self.timers
+            .borrow_mut()
+            .insert(handle, (source_id, scheduled));
         handle
     }
 
+    // Timer coalescing is not supported, tolerance is ignored.
+    #[must_use]
+    pub fn schedule_with_tolerance<F>(
+        &self,
+        in_time: Duration,
+        _tolerance: Duration,
+        callback: F,
+    ) -> HandleType
+    where
+        F: FnOnce() + 'static,
+    {
+        self.schedule(in_time, callback)
+    }
+
     pub fn run(&self) {
-        unsafe { gtk_main() };
+        if self.context.is_default() {
+            unsafe { gtk_main() };
+            return;
+        }
+        unsafe {
+            let main_loop = g_main_loop_new(self.context.0, GFALSE);
+            self.main_loops.borrow_mut().push(main_loop);
+            g_main_loop_run(main_loop);
+            self.main_loops.borrow_mut().pop();
+            g_main_loop_unref(main_loop);
+        }
     }
 
     pub fn stop(&self) {
-        unsafe { gtk_main_quit() };
+        if self.context.is_default() {
+            unsafe { gtk_main_quit() };
+        } else if let Some(main_loop) = self.main_loops.borrow().last() {
+            unsafe { g_main_loop_quit(*main_loop) };
+        }
     }
 
     pub fn poll_once(&self) {
-        unsafe { gtk_main_iteration() };
+        if self.context.is_default() {
+            unsafe { gtk_main_iteration() };
+        } else {
+            unsafe { g_main_context_iteration(self.context.0, GTRUE) };
+        }
+    }
+
+    pub fn tick(&self) -> bool {
+        // Each non-blocking iteration only dispatches ready sources of the
+        // highest priority, so iterate until nothing is ready. Bounded so that
+        // sources that keep rescheduling themselves can't stall the caller.
+        let mut did_work = false;
+        for _ in 0..MAX_TICK_ITERATIONS {
+            if unsafe { g_main_context_iteration(self.context.0, GFALSE) } == GFALSE {
+                break;
+            }
+            did_work = true;
+        }
+        did_work
     }
 
     pub fn new_sender(&self) -> PlatformRunLoopSender {
-        PlatformRunLoopSender {}
+        PlatformRunLoopSender {
+            context: self.context.clone(),
+        }
     }
 }
 
 #[derive(Clone)]
-pub struct PlatformRunLoopSender {}
+pub struct PlatformRunLoopSender {
+    context: MainContext,
+}
 
 #[allow(unused_variables)]
 impl PlatformRunLoopSender {
@@ -126,8 +266,10 @@ impl PlatformRunLoopSender {
         // which is not expected and may lead to deadlocks.
         let context = Context::current();
         if let Some(context) = context {
-            context.run_loop().schedule_next(callback).detach();
-            return;
+            if context.run_loop().platform_run_loop.context.0 == self.context.0 {
+                context.run_loop().schedule_next(callback).detach();
+                return;
+            }
         }
 
         unsafe extern "C" fn trampoline<F: FnOnce() + 'static>(func: gpointer) -> gboolean {
@@ -144,7 +286,7 @@ impl PlatformRunLoopSender {
         let callback = Box::into_raw(Box::new(Some(callback)));
         unsafe {
             g_main_context_invoke_full(
-                g_main_context_default(),
+                self.context.0,
                 0,
                 Some(trampoline::<F>),
                 callback as gpointer,
@@ -7,29 +7,54 @@ pub mod glib {
     pub type GDestroyNotify = Option<unsafe extern "C" fn(gpointer)>;
     pub const GFALSE: c_int = 0;
     pub const G_SOURCE_REMOVE: gboolean = GFALSE;
-    pub const G_PRIORITY_DEFAULT: c_int = 0;
+
+    pub const GTRUE: c_int = 1;
 
     #[repr(C)]
     pub struct GMainContext(c_void);
+    #[repr(C)]
+    pub struct GMainLoop(c_void);
+    #[repr(C)]
+    pub struct GSource(c_void);
 
     #[link(name = "glib-2.0")]
     extern "C" {
-        pub fn g_source_remove(tag: c_uint) -> gboolean;
-        pub fn g_timeout_add_full(
+        pub fn g_main_context_invoke_full(
+            context: *mut GMainContext,
             priority: c_int,
-            interval: c_uint,
             function: GSourceFunc,
             data: gpointer,
             notify: GDestroyNotify,
-        ) -> c_uint;
-        pub fn g_main_context_invoke_full(
+        );
+        pub fn g_main_context_default() -> *mut GMainContext;
+        pub fn g_main_context_new() -> *mut GMainContext;
+        pub fn g_main_context_ref(context: *mut GMainContext) -> *mut GMainContext;
+        pub fn g_main_context_unref(context: *mut GMainContext);
+        pub fn g_main_context_ref_thread_default() -> *mut GMainContext;
+        pub fn g_main_context_push_thread_default(context: *mut GMainContext);
+        pub fn g_main_context_pop_thread_default(context: *mut GMainContext);
+        pub fn g_main_context_find_source_by_id(
             context: *mut GMainContext,
-            priority: c_int,
+            source_id: c_uint,
+        ) -> *mut GSource;
+        pub fn g_main_loop_new(context: *mut GMainContext, is_running: gboolean) -> *mut GMainLoop;
+        pub fn g_main_loop_run(main_loop: *mut GMainLoop);
+        pub fn g_main_loop_quit(main_loop: *mut GMainLoop);
+        pub fn g_main_loop_unref(main_loop: *mut GMainLoop);
+        pub fn g_timeout_source_new(interval: c_uint) -> *mut GSource;
+        pub fn g_source_set_callback(
+            source: *mut GSource,
             function: GSourceFunc,
             data: gpointer,
             notify: GDestroyNotify,
         );
-        pub fn g_main_context_default() -> *mut GMainContext;
+        pub fn g_source_attach(source: *mut GSource, context: *mut GMainContext) -> c_uint;
+        pub fn g_source_destroy(source: *mut GSource);
+        pub fn g_source_unref(source: *mut GSource);
+        pub fn g_main_context_iteration(
+            context: *mut GMainContext,
+            may_block: gboolean,
+        ) -> gboolean;
     }
     #[link(name = "gtk-3")]
     extern "C" {
@@ -143,6 +143,33 @@ impl State {
         self.next_timer()
     }
 
+    fn tick(&self) -> bool {
+        let callbacks: Vec<SenderCallback> = {
+            let mut callbacks = self.sender_callbacks.lock().unwrap();
+            callbacks.drain(0..).collect()
+        };
+        let now = Instant::now();
+        let pending: Vec<HandleType> = self
+            .timers
+            .borrow()
+            .iter()
+            .filter(|v| v.1.scheduled <= now)
+            .map(|v| *v.0)
+            .collect();
+        let did_work = !callbacks.is_empty() || !pending.is_empty();
+        for c in callbacks {
+            c()
+        }
+        for handle in pending {
+            let timer = self.timers.borrow_mut().remove(&handle);
+            if let Some(timer) = timer {
+                (timer.callback)();
+            }
+        }
+        self.wake_up_at(self.next_timer());
+        did_work
+    }
+
     fn process_callbacks(&self) {
         let callbacks: Vec<SenderCallback> = {
             let mut callbacks = self.sender_callbacks.lock().unwrap();
@@ -264,6 +291,19 @@ impl PlatformRunLoop {
         self.state.hwnd.get()
     }
 
+    pub fn next_scheduled_deadline(&self) -> Option<Instant> {
+        self.state
+            .timers
+            .borrow()
+            .values()
+            .map(|x| x.scheduled)
+            .min()
+    }
+
+    pub fn pending_timer_count(&self) -> usize {
+        self.state.timers.borrow().len()
+    }
+
     #[must_use]
     pub fn schedule<F>(&self, in_time: Duration, callback: F) -> HandleType
     where
@@ -272,6 +312,20 @@ impl PlatformRunLoop {
         self.state.schedule(in_time, callback)
     }
 
+    // Timer coalescing is not supported, tolerance is ignored.
+    #[must_use]
+    pub fn schedule_with_tolerance<F>(
+        &self,
+        in_time: Duration,
+        _tolerance: Duration,
+        callback: F,
+    ) -> HandleType
+    where
+        F: FnOnce() + 'static,
+    {
+        self.schedule(in_time, callback)
+    }
+
     pub fn run(&self) {
         self.state.run();
     }
@@ -284,6 +338,10 @@ impl PlatformRunLoop {
         self.state.poll_once(poll_session);
     }
 
+    pub fn tick(&self) -> bool {
+        self.state.tick()
+    }
+
     pub fn new_sender(&self) -> PlatformRunLoopSender {
         self.state.new_sender()
     }
@@ -1,52 +1,161 @@
 use std::{
-    cell::{RefCell, UnsafeCell},
+    cell::{Cell, RefCell, UnsafeCell},
+    fmt::{Debug, Display},
     future::Future,
     marker::PhantomData,
     rc::Rc,
     sync::{
-        atomic::{AtomicUsize, Ordering},
-        Arc,
+        atomic::{AtomicBool, AtomicU64, AtomicUsize, Ordering},
+        Arc, Mutex,
     },
-    task::Poll,
-    time::Duration,
+    task::{Poll, Waker},
+    time::{Duration, Instant},
 };
 
 use futures::{
-    future::LocalBoxFuture,
+    channel::oneshot,
+    future::{select, Either, LocalBoxFuture},
+    pin_mut,
+    stream::poll_fn,
     task::{waker_ref, ArcWake},
-    FutureExt,
+    FutureExt, Stream,
 };
 
-use crate::util::{BlockingVariable, FutureCompleter};
+use crate::{
+    util::{BlockingVariable, FutureCompleter},
+    Context, DecodeError,
+};
 
 use super::{
-    platform::run_loop::{PlatformRunLoop, PlatformRunLoopSender},
+    platform::run_loop::{HandleType, PlatformRunLoop, PlatformRunLoopSender},
     Handle,
 };
 
+/// Run loop bound to the thread it was created on. `RunLoop` is neither `Send`
+/// nor `Sync`; use [`RunLoopSender`] to schedule callbacks from other threads.
 pub struct RunLoop {
     pub platform_run_loop: Rc<PlatformRunLoop>,
+    blocking: Cell<bool>,
+    sender_backlog: Arc<SenderBacklog>,
+    activity: Arc<Activity>,
+    trace_hook: TraceHook,
 }
 
 impl RunLoop {
     pub fn new() -> Self {
         Self {
             platform_run_loop: Rc::new(PlatformRunLoop::new()),
+            blocking: Cell::new(false),
+            sender_backlog: Arc::new(SenderBacklog {
+                pending: AtomicUsize::new(0),
+                limit: AtomicUsize::new(usize::MAX),
+            }),
+            activity: Arc::new(Activity::new()),
+            trace_hook: TraceHook::default(),
         }
     }
 
+    /// Sets hook that receives diagnostic events emitted by this run loop and
+    /// by message channel (see [`TraceEvent`]). Without hook the events are
+    /// ignored.
+    pub fn set_trace_hook<F>(&self, hook: F)
+    where
+        F: Fn(&TraceEvent) + 'static,
+    {
+        self.trace_hook.0.replace(Some(Rc::new(hook)));
+    }
+
+    pub(crate) fn emit_trace_event(&self, event: TraceEvent) {
+        self.trace_hook.emit(event);
+    }
+
+    /// Sets maximum number of callbacks sent through [`RunLoopSender`]s that may
+    /// be waiting for execution before [`RunLoopSender::try_send`] starts failing.
+    /// `None` means no limit (default).
+    pub fn set_sender_backlog_limit(&self, limit: Option<usize>) {
+        self.sender_backlog
+            .limit
+            .store(limit.unwrap_or(usize::MAX), Ordering::SeqCst);
+    }
+
+    /// Sets threshold above which callbacks executed by this run loop, including
+    /// callbacks sent through [`RunLoopSender`] and polling of spawned futures,
+    /// are reported as [`TraceEvent::SlowCallback`]. `None` disables the
+    /// reporting (default).
+    pub fn set_slow_callback_threshold(&self, threshold: Option<Duration>) {
+        let nanos = threshold.map(|t| (t.as_nanos() as u64).max(1)).unwrap_or(0);
+        self.activity.slow_threshold.store(nanos, Ordering::Relaxed);
+    }
+
     #[must_use]
     pub fn schedule<F>(&self, in_time: Duration, callback: F) -> Handle
     where
         F: FnOnce() + 'static,
     {
         let run_loop = self.platform_run_loop.clone();
+        let callback = self.activity.wrap(callback);
         let handle = run_loop.schedule(in_time, callback);
         Handle::new(move || {
             run_loop.unschedule(handle);
         })
     }
 
+    /// Same as [`RunLoop::schedule`], but `label` is included in
+    /// [`TraceEvent::SlowCallback`] if the callback takes longer than
+    /// the slow callback threshold.
+    #[must_use]
+    pub fn schedule_labeled<F>(&self, label: &'static str, in_time: Duration, callback: F) -> Handle
+    where
+        F: FnOnce() + 'static,
+    {
+        let run_loop = self.platform_run_loop.clone();
+        let trace_hook = self.trace_hook.clone();
+        let callback = self
+            .activity
+            .wrap_labeled(Some(label), callback, move |event| trace_hook.emit(event));
+        let handle = run_loop.schedule(in_time, callback);
+        Handle::new(move || {
+            run_loop.unschedule(handle);
+        })
+    }
+
+    /// Same as [`RunLoop::schedule`] but allows the callback to be delayed by up
+    /// to `tolerance` so that the system can coalesce timer wake-ups and save
+    /// power. Only honored on macOS and iOS, other platforms ignore tolerance.
+    #[must_use]
+    pub fn schedule_with_tolerance<F>(
+        &self,
+        in_time: Duration,
+        tolerance: Duration,
+        callback: F,
+    ) -> Handle
+    where
+        F: FnOnce() + 'static,
+    {
+        let run_loop = self.platform_run_loop.clone();
+        let callback = self.activity.wrap(callback);
+        let handle = run_loop.schedule_with_tolerance(in_time, tolerance, callback);
+        Handle::new(move || {
+            run_loop.unschedule(handle);
+        })
+    }
+
+    /// Same as [`RunLoop::schedule`], but the callback receives the instant at
+    /// which it was scheduled to fire and the instant at which it actually
+    /// fired. The difference can be used to measure timer latency and correct
+    /// drift of repeating timers.
+    #[must_use]
+    pub fn schedule_with_timing<F>(&self, in_time: Duration, callback: F) -> Handle
+    where
+        F: FnOnce(Instant, Instant) + 'static,
+    {
+        let scheduled = Instant::now() + in_time;
+        self.schedule(in_time, move || {
+            let actual = Instant::now();
+            callback(scheduled, actual)
+        })
+    }
+
     /// Convenience method to schedule callback on next run loop turn.
     #[must_use]
     pub fn schedule_next<F>(&self, callback: F) -> Handle
@@ -56,6 +165,78 @@ impl RunLoop {
         self.schedule(Duration::from_secs(0), callback)
     }
 
+    /// Returns the instant at which the earliest pending timer is scheduled or
+    /// `None` if there are no timers scheduled.
+    pub fn next_scheduled_deadline(&self) -> Option<Instant> {
+        self.platform_run_loop.next_scheduled_deadline()
+    }
+
+    /// Returns number of callbacks sent through [`RunLoopSender`]s that are
+    /// waiting for execution and number of timers that are currently scheduled.
+    /// Nothing is executed or removed. Intended for tests.
+    #[cfg(any(test, feature = "mock"))]
+    pub fn pending_count(&self) -> (usize, usize) {
+        (
+            self.sender_backlog.pending.load(Ordering::SeqCst),
+            self.pending_timers(),
+        )
+    }
+
+    /// Returns number of timers that are currently scheduled, including
+    /// callbacks scheduled through [`RunLoop::schedule_next`].
+    pub fn pending_timers(&self) -> usize {
+        self.platform_run_loop.pending_timer_count()
+    }
+
+    /// Whether the run loop has no work to do right now: no callbacks sent
+    /// through [`RunLoopSender`]s are waiting for execution and no timer is due
+    /// within [`RunLoop::IDLE_WINDOW`]. Timers scheduled further in future do
+    /// not make the run loop busy.
+    pub fn is_idle(&self) -> bool {
+        if self.sender_backlog.pending.load(Ordering::SeqCst) > 0 {
+            return false;
+        }
+        match self.next_scheduled_deadline() {
+            Some(deadline) => deadline > Instant::now() + Self::IDLE_WINDOW,
+            None => true,
+        }
+    }
+
+    /// Timers due within this window make the run loop busy. See
+    /// [`RunLoop::is_idle`].
+    pub const IDLE_WINDOW: Duration = Duration::from_millis(1);
+
+    /// Invokes `callback` once the run loop has been idle for `duration`. Run
+    /// loop is idle when it doesn't execute any callbacks scheduled through
+    /// [`RunLoop::schedule`] or [`RunLoopSender`], which includes timers, task
+    /// wake-ups and messages received from Dart.
+    ///
+    /// The callback is invoked once per idle period; after any activity the
+    /// run loop must be idle for `duration` again before the callback is
+    /// invoked next time. Dropping the returned handle stops the monitoring.
+    #[must_use]
+    pub fn on_idle_for<F>(&self, duration: Duration, callback: F) -> Handle
+    where
+        F: FnMut() + 'static,
+    {
+        let watcher = Rc::new(IdleWatcher {
+            platform_run_loop: self.platform_run_loop.clone(),
+            activity: self.activity.clone(),
+            duration,
+            callback: RefCell::new(Box::new(callback)),
+            fired_for: Cell::new(None),
+            timer: Cell::new(None),
+            cancelled: Cell::new(false),
+        });
+        watcher.schedule();
+        Handle::new(move || {
+            watcher.cancelled.set(true);
+            if let Some(timer) = watcher.timer.take() {
+                watcher.platform_run_loop.unschedule(timer);
+            }
+        })
+    }
+
     /// Returns future that will complete in provided duration.
     pub async fn wait(&self, duration: Duration) {
         let (future, completer) = FutureCompleter::<()>::new();
@@ -66,20 +247,146 @@ impl RunLoop {
         future.await
     }
 
-    #[cfg(any(target_os = "macos", target_os = "linux", target_os = "windows"))]
+    /// Returns future that resolves with output of `future` or with [`Elapsed`] if
+    /// `future` doesn't complete within `duration`. The timer is cancelled when
+    /// the future completes first; the future is dropped when the timer fires first.
+    pub fn with_timeout<F: Future>(
+        &self,
+        future: F,
+        duration: Duration,
+    ) -> impl Future<Output = Result<F::Output, Elapsed>> {
+        let (timeout, completer) = FutureCompleter::<()>::new();
+        let handle = self.schedule(duration, move || {
+            completer.complete(());
+        });
+        async move {
+            // Dropping the handle cancels the timer.
+            let _handle = handle;
+            pin_mut!(future);
+            match select(future, timeout).await {
+                Either::Left((value, _)) => Ok(value),
+                Either::Right(_) => Err(Elapsed),
+            }
+        }
+    }
+
+    /// Returns stream that yields every `period`, starting `period` from now.
+    /// Ticks are scheduled relative to the previous deadline so that they
+    /// don't drift; if the consumer falls behind, missed ticks are skipped
+    /// and the next tick is `period` after the late one. Dropping the stream
+    /// cancels the timer.
+    ///
+    /// The stream must be polled on run loop thread.
+    pub fn interval(&self, period: Duration) -> impl Stream<Item = ()> {
+        assert!(!period.is_zero(), "Interval period must be non-zero");
+        let platform_run_loop = self.platform_run_loop.clone();
+        let activity = self.activity.clone();
+        let fired = Rc::new(Cell::new(false));
+        let waker = Rc::new(RefCell::new(None::<Waker>));
+        let mut deadline = Instant::now() + period;
+        let mut timer = None::<Handle>;
+        poll_fn(move |cx| {
+            if fired.replace(false) {
+                if let Some(mut timer) = timer.take() {
+                    timer.detach();
+                }
+                let now = Instant::now();
+                // Platform timers may fire slightly early (i.e. rounded to
+                // milliseconds); reschedule for the remainder in that case.
+                if now >= deadline {
+                    deadline += period;
+                    if deadline <= now {
+                        deadline = now + period;
+                    }
+                    return Poll::Ready(Some(()));
+                }
+            }
+            waker.replace(Some(cx.waker().clone()));
+            if timer.is_none() {
+                let fired = fired.clone();
+                let waker = waker.clone();
+                let callback = activity.wrap(move || {
+                    fired.set(true);
+                    if let Some(waker) = waker.take() {
+                        waker.wake();
+                    }
+                });
+                let platform_run_loop = platform_run_loop.clone();
+                let handle = platform_run_loop
+                    .schedule(deadline.saturating_duration_since(Instant::now()), callback);
+                timer = Some(Handle::new(move || {
+                    platform_run_loop.unschedule(handle);
+                }));
+            }
+            Poll::Pending
+        })
+    }
+
+    /// Runs the run loop until [`RunLoop::stop`] is called.
+    ///
+    /// On Android and iOS, where Flutter owns the event loop, this runs a nested
+    /// loop intended for tests and synchronous operations. On Android the nested
+    /// loop only processes callbacks and timers scheduled through this run loop.
     pub fn run(&self) {
         self.platform_run_loop.run()
     }
 
-    #[cfg(any(target_os = "macos", target_os = "linux", target_os = "windows"))]
     pub fn stop(&self) {
         self.platform_run_loop.stop()
     }
 
+    /// Executes callbacks and timers that are ready at the time of the call and
+    /// returns immediately without waiting for more work. Returns `true` if
+    /// anything was executed.
+    ///
+    /// Intended for embeddings where the host owns the event loop and can't call
+    /// [`RunLoop::run`] (i.e. calling `tick` once per frame).
+    pub fn tick(&self) -> bool {
+        self.platform_run_loop.tick()
+    }
+
+    /// Runs the run loop in private mode until `predicate` returns `true`.
+    /// Only callbacks and timers scheduled through this run loop (including
+    /// [`RunLoopSender`] and message channel) are processed; other application
+    /// events are held back until the pump returns. Useful for keeping
+    /// NativeShell responsive during synchronous operations, such as showing
+    /// native modal.
+    ///
+    /// Predicate is checked after every processed callback and periodically
+    /// in between.
+    #[cfg(any(target_os = "macos", target_os = "ios"))]
+    pub fn pump_private_mode_until<F: Fn() -> bool>(&self, predicate: F) {
+        self.platform_run_loop.pump_private_mode_until(predicate)
+    }
+
+    /// Runs the future to completion with this run loop being the executor and
+    /// returns its output. The run loop is running until the future completes.
+    ///
+    /// Panics if called while another `block_on` is in progress on this run loop.
+    pub fn block_on<T: 'static>(&self, future: impl Future<Output = T> + 'static) -> T {
+        if self.blocking.replace(true) {
+            panic!("RunLoop::block_on can not be called recursively.");
+        }
+        let result = Rc::new(RefCell::new(None));
+        let result_clone = result.clone();
+        let platform_run_loop = self.platform_run_loop.clone();
+        self.spawn(async move {
+            let value = future.await;
+            result_clone.replace(Some(value));
+            platform_run_loop.stop();
+        });
+        self.platform_run_loop.run();
+        self.blocking.set(false);
+        let result = result.borrow_mut().take();
+        result.expect("RunLoop stopped before future completed.")
+    }
+
     pub fn new_sender(&self) -> RunLoopSender {
         RunLoopSender {
             thread_id: get_thread_id(),
             platform_sender: self.platform_run_loop.new_sender(),
+            backlog: self.sender_backlog.clone(),
+            activity: self.activity.clone(),
         }
     }
 
@@ -91,29 +398,318 @@ impl RunLoop {
             future: UnsafeCell::new(future),
             value: RefCell::new(None),
             waker: RefCell::new(None),
+            trace_hook: self.trace_hook.clone(),
+            scheduled: AtomicBool::new(false),
         });
         ArcWake::wake_by_ref(&task);
         JoinHandle {
             task,
+            detached: false,
             _data: PhantomData {},
         }
     }
+
+    /// Spawns the future on this run loop and returns receiver for its result.
+    /// Unlike [`JoinHandle`] the receiver is `Send`, so the result can be
+    /// awaited or polled on another thread. The task keeps running when the
+    /// receiver is dropped. Receiver resolves with
+    /// [`Canceled`](futures::channel::oneshot::Canceled) if the task is
+    /// dropped before completing, i.e. when the run loop is dropped.
+    pub fn spawn_with_result<T: Send + 'static>(
+        &self,
+        future: impl Future<Output = T> + 'static,
+    ) -> oneshot::Receiver<T> {
+        let (sender, receiver) = oneshot::channel();
+        self.spawn(async move {
+            // Receiver may have been dropped.
+            let _ = sender.send(future.await);
+        })
+        .detach();
+        receiver
+    }
+}
+
+struct SenderBacklog {
+    pending: AtomicUsize,
+    limit: AtomicUsize,
+}
+
+// Time of last callback executed by run loop, used for idle detection.
+// Also measures callback duration when slow callback threshold is set.
+struct Activity {
+    start: Instant,
+    last: AtomicU64,           // nanoseconds since start
+    slow_threshold: AtomicU64, // nanoseconds, 0 if disabled
+}
+
+impl Activity {
+    fn new() -> Self {
+        Self {
+            start: Instant::now(),
+            last: AtomicU64::new(0),
+            slow_threshold: AtomicU64::new(0),
+        }
+    }
+
+    fn record(&self) {
+        let elapsed = self.start.elapsed().as_nanos() as u64;
+        self.last.store(elapsed, Ordering::Relaxed);
+    }
+
+    fn last(&self) -> Instant {
+        self.start + Duration::from_nanos(self.last.load(Ordering::Relaxed))
+    }
+
+    // Slow callbacks are reported to trace hook of the current context.
+    fn wrap<F: FnOnce()>(self: &Arc<Self>, callback: F) -> impl FnOnce() {
+        self.wrap_labeled(None, callback, |event| {
+            if let Some(context) = Context::current() {
+                context.run_loop().trace_hook.emit(event);
+            }
+        })
+    }
+
+    fn wrap_labeled<F: FnOnce(), R: FnOnce(TraceEvent)>(
+        self: &Arc<Self>,
+        label: Option<&'static str>,
+        callback: F,
+        report_slow: R,
+    ) -> impl FnOnce() {
+        let activity = self.clone();
+        move || {
+            activity.record();
+            let threshold = activity.slow_threshold.load(Ordering::Relaxed);
+            if threshold == 0 {
+                callback();
+                return;
+            }
+            let start = Instant::now();
+            callback();
+            let duration = start.elapsed();
+            if duration.as_nanos() > threshold as u128 {
+                report_slow(TraceEvent::SlowCallback { duration, label });
+            }
+        }
+    }
+}
+
+struct IdleWatcher {
+    platform_run_loop: Rc<PlatformRunLoop>,
+    activity: Arc<Activity>,
+    duration: Duration,
+    callback: RefCell<Box<dyn FnMut()>>,
+    // Last activity after which the callback was invoked.
+    fired_for: Cell<Option<Instant>>,
+    timer: Cell<Option<HandleType>>,
+    // Handle may be dropped from within the callback.
+    cancelled: Cell<bool>,
+}
+
+impl IdleWatcher {
+    // Timer is scheduled on platform run loop directly so that it doesn't
+    // count as activity.
+    fn schedule(self: &Rc<Self>) {
+        if self.cancelled.get() {
+            return;
+        }
+        let last = self.activity.last();
+        let deadline = if self.fired_for.get() == Some(last) {
+            Instant::now() + self.duration
+        } else {
+            last + self.duration
+        };
+        let watcher = self.clone();
+        let timer = self.platform_run_loop.schedule(
+            deadline.saturating_duration_since(Instant::now()),
+            move || watcher.check(),
+        );
+        self.timer.set(Some(timer));
+    }
+
+    fn check(self: &Rc<Self>) {
+        let last = self.activity.last();
+        if self.fired_for.get() != Some(last) && last.elapsed() >= self.duration {
+            self.fired_for.set(Some(last));
+            (self.callback.borrow_mut())();
+        }
+        self.schedule();
+    }
 }
 
 // Can be used to send callbacks from other threads to be executed on run loop thread
+/// `RunLoopSender` is `Send` and `Sync` on all platforms, so it can be cloned
+/// or shared between threads.
 #[derive(Clone)]
 pub struct RunLoopSender {
     thread_id: usize,
     platform_sender: PlatformRunLoopSender,
+    backlog: Arc<SenderBacklog>,
+    activity: Arc<Activity>,
+}
+
+// Compile time check that RunLoopSender and SendableHandle stay Send + Sync.
+const _: fn() = || {
+    fn assert_send_sync<T: Send + Sync>() {}
+    assert_send_sync::<RunLoopSender>();
+    assert_send_sync::<SendableHandle>();
+};
+
+impl Debug for RunLoopSender {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("RunLoopSender")
+            .field("thread_id", &self.thread_id)
+            .finish_non_exhaustive()
+    }
 }
 
+/// Error returned by [`RunLoop::with_timeout`] when the timeout elapsed before
+/// the future completed.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Elapsed;
+
+impl Display for Elapsed {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "deadline has elapsed")
+    }
+}
+
+impl std::error::Error for Elapsed {}
+
+pub enum TrySendError<F> {
+    /// Number of pending callbacks has reached the limit set through
+    /// [`RunLoop::set_sender_backlog_limit`]. Contains the rejected callback.
+    Full(F),
+}
+
+impl<F> Debug for TrySendError<F> {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            TrySendError::Full(_) => write!(f, "Full(..)"),
+        }
+    }
+}
+
+impl<F> Display for TrySendError<F> {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            TrySendError::Full(_) => write!(f, "run loop sender backlog is full"),
+        }
+    }
+}
+
+impl<F> std::error::Error for TrySendError<F> {}
+
 impl RunLoopSender {
     /// Schedules the callback to be executed on run loop and returns immediately.
     pub fn send<F>(&self, callback: F)
     where
         F: FnOnce() + 'static + Send,
     {
-        self.platform_sender.send(callback)
+        self.backlog.pending.fetch_add(1, Ordering::SeqCst);
+        let backlog = self.backlog.clone();
+        let callback = self.activity.wrap(callback);
+        self.platform_sender.send(move || {
+            backlog.pending.fetch_sub(1, Ordering::SeqCst);
+            callback();
+        })
+    }
+
+    /// Same as [`RunLoopSender::send`], but `label` is included in
+    /// [`TraceEvent::SlowCallback`] if the callback takes longer than the
+    /// slow callback threshold. Slow callbacks are reported to run loop of
+    /// the [`Context`] current on run loop thread.
+    pub fn send_labeled<F>(&self, label: &'static str, callback: F)
+    where
+        F: FnOnce() + 'static + Send,
+    {
+        self.backlog.pending.fetch_add(1, Ordering::SeqCst);
+        let backlog = self.backlog.clone();
+        let callback = self.activity.wrap_labeled(Some(label), callback, |event| {
+            if let Some(context) = Context::current() {
+                context.run_loop().trace_hook.emit(event);
+            }
+        });
+        self.platform_sender.send(move || {
+            backlog.pending.fetch_sub(1, Ordering::SeqCst);
+            callback();
+        })
+    }
+
+    /// Schedules the callback to be executed on run loop unless the number of
+    /// pending callbacks has reached the limit configured on run loop, in which
+    /// case the callback is returned back in [`TrySendError::Full`].
+    pub fn try_send<F>(&self, callback: F) -> Result<(), TrySendError<F>>
+    where
+        F: FnOnce() + 'static + Send,
+    {
+        let limit = self.backlog.limit.load(Ordering::SeqCst);
+        let reserved =
+            self.backlog
+                .pending
+                .fetch_update(Ordering::SeqCst, Ordering::SeqCst, |pending| {
+                    if pending < limit {
+                        Some(pending + 1)
+                    } else {
+                        None
+                    }
+                });
+        if reserved.is_err() {
+            return Err(TrySendError::Full(callback));
+        }
+        let backlog = self.backlog.clone();
+        let callback = self.activity.wrap(callback);
+        self.platform_sender.send(move || {
+            backlog.pending.fetch_sub(1, Ordering::SeqCst);
+            callback();
+        });
+        Ok(())
+    }
+
+    /// Schedules the callback to be executed on run loop after `in_time`. Unlike
+    /// [`RunLoop::schedule`] this can be called from any thread; the timer is
+    /// created once the request reaches run loop thread, so the delay is measured
+    /// from that point. Dropping the returned handle cancels the callback.
+    ///
+    /// Requires a [`Context`] on run loop thread.
+    #[must_use]
+    pub fn schedule<F>(&self, in_time: Duration, callback: F) -> SendableHandle
+    where
+        F: FnOnce() + 'static + Send,
+    {
+        let state = Arc::new(Mutex::new(ScheduledState::default()));
+        let activity = self.activity.clone();
+        let state_clone = state.clone();
+        self.send(move || {
+            let context = match Context::current() {
+                Some(context) => context,
+                None => return,
+            };
+            let mut s = state_clone.lock().unwrap();
+            if s.cancelled {
+                return;
+            }
+            let state = state_clone.clone();
+            let callback = activity.wrap(move || {
+                let fire = {
+                    let mut state = state.lock().unwrap();
+                    state.timer.take();
+                    !state.cancelled
+                };
+                if fire {
+                    callback();
+                }
+            });
+            s.timer = Some(
+                context
+                    .run_loop()
+                    .platform_run_loop
+                    .schedule(in_time, callback),
+            );
+        });
+        SendableHandle {
+            state: Some(state),
+            sender: self.clone(),
+        }
     }
 
     /// Schedules the callback on run loop and blocks until it is invoked.
@@ -124,7 +720,7 @@ impl RunLoopSender {
         F: FnOnce() -> R + 'static + Send,
         R: Send + 'static,
     {
-        if get_thread_id() == self.thread_id {
+        if self.is_run_loop_thread() {
             callback()
         } else {
             let var = BlockingVariable::<R>::new();
@@ -135,6 +731,62 @@ impl RunLoopSender {
             var.get_blocking()
         }
     }
+
+    /// Whether current thread is the thread of run loop this sender belongs to.
+    pub(crate) fn is_run_loop_thread(&self) -> bool {
+        get_thread_id() == self.thread_id
+    }
+}
+
+#[derive(Default)]
+struct ScheduledState {
+    cancelled: bool,
+    timer: Option<HandleType>,
+}
+
+/// Handle returned by [`RunLoopSender::schedule`]. Unlike [`Handle`] it is
+/// `Send` and `Sync` and can be cancelled from any thread. Dropping the handle
+/// cancels the scheduled callback unless it has been detached.
+pub struct SendableHandle {
+    state: Option<Arc<Mutex<ScheduledState>>>,
+    sender: RunLoopSender,
+}
+
+impl SendableHandle {
+    pub fn cancel(&mut self) {
+        if let Some(state) = self.state.take() {
+            let timer = {
+                let mut state = state.lock().unwrap();
+                state.cancelled = true;
+                state.timer.take()
+            };
+            if let Some(timer) = timer {
+                self.sender.send(move || {
+                    if let Some(context) = Context::current() {
+                        context.run_loop().platform_run_loop.unschedule(timer);
+                    }
+                });
+            }
+        }
+    }
+
+    pub fn detach(&mut self) {
+        self.state.take();
+    }
+}
+
+impl Debug for SendableHandle {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("SendableHandle")
+            .field("active", &self.state.is_some())
+            .finish()
+    }
+}
+
+impl Drop for SendableHandle {
+    fn drop(&mut self) {
+        self.cancel();
+    }
 }
 
 fn get_thread_id() -> usize {
@@ -147,6 +799,78 @@ fn next_thread_id() -> usize {
     unsafe { COUNTER.fetch_add(1, Ordering::SeqCst) }
 }
 
+/// Diagnostic event emitted by run loop or message channel, see
+/// [`RunLoop::set_trace_hook`].
+#[derive(Debug, Clone)]
+#[non_exhaustive]
+pub enum TraceEvent {
+    /// [`JoinHandle`] was dropped while its task had completed with value that
+    /// was never retrieved. Only emitted in debug builds.
+    UnconsumedJoinHandle { type_name: &'static str },
+    /// Callback took longer than threshold set through
+    /// [`RunLoop::set_slow_callback_threshold`]. `label` is set for callbacks
+    /// scheduled with [`RunLoop::schedule_labeled`] or
+    /// [`RunLoopSender::send_labeled`].
+    SlowCallback {
+        duration: Duration,
+        label: Option<&'static str>,
+    },
+    /// Reply for message sent on `channel` did not arrive within timeout set
+    /// through [`MessageChannel::set_reply_watchdog`](crate::MessageChannel::set_reply_watchdog).
+    ReplyTimedOut { channel: String, timeout: Duration },
+    /// Message received from Dart could not be decoded and was dropped.
+    /// `channel` is set if the message was sent to a channel and expected
+    /// reply; Dart side is notified in that case.
+    MessageDecodeFailed {
+        channel: Option<String>,
+        error: DecodeError,
+    },
+}
+
+impl Display for TraceEvent {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            TraceEvent::UnconsumedJoinHandle { type_name } => write!(
+                f,
+                "JoinHandle<{}> dropped with unconsumed result; \
+                 await the handle or call JoinHandle::detach",
+                type_name
+            ),
+            TraceEvent::SlowCallback { duration, label } => write!(
+                f,
+                "callback {} took {:?}",
+                label.unwrap_or("<unlabeled>"),
+                duration
+            ),
+            TraceEvent::ReplyTimedOut { channel, timeout } => write!(
+                f,
+                "reply for message on channel \"{}\" timed out after {:?}",
+                channel, timeout
+            ),
+            TraceEvent::MessageDecodeFailed { channel, error } => match channel {
+                Some(channel) => write!(
+                    f,
+                    "failed to decode message on channel \"{}\": {}",
+                    channel, error
+                ),
+                None => write!(f, "failed to decode message: {}", error),
+            },
+        }
+    }
+}
+
+#[derive(Clone, Default)]
+struct TraceHook(Rc<RefCell<Option<Rc<dyn Fn(&TraceEvent)>>>>);
+
+impl TraceHook {
+    fn emit(&self, event: TraceEvent) {
+        let hook = self.0.borrow().clone();
+        if let Some(hook) = hook {
+            hook(&event);
+        }
+    }
+}
+
 //
 //
 //
@@ -156,6 +880,11 @@ struct Task<T> {
     future: UnsafeCell<LocalBoxFuture<'static, T>>,
     value: RefCell<Option<T>>,
     waker: RefCell<Option<std::task::Waker>>,
+    trace_hook: TraceHook,
+    // Set while poll is queued. Task has at most one poll queued, so a task that
+    // keeps waking itself goes to the back of the queue after each poll and
+    // can't starve other tasks.
+    scheduled: AtomicBool,
 }
 
 // Tasks can only be spawned on run loop thread and will only be executed
@@ -176,9 +905,14 @@ impl<T: 'static> Task<T> {
 
 impl<T: 'static> ArcWake for Task<T> {
     fn wake_by_ref(arc_self: &std::sync::Arc<Self>) {
+        if arc_self.scheduled.swap(true, Ordering::AcqRel) {
+            return;
+        }
         let arc_self = arc_self.clone();
         let sender = arc_self.sender.clone();
         sender.send(move || {
+            // Wake during poll must queue another poll.
+            arc_self.scheduled.store(false, Ordering::Release);
             if arc_self.value.borrow().is_none() {
                 if let Poll::Ready(value) = arc_self.poll() {
                     *arc_self.value.borrow_mut() = Some(value);
@@ -195,12 +929,45 @@ impl<T: 'static> ArcWake for Task<T> {
 
 pub struct JoinHandle<T> {
     task: Arc<Task<T>>,
+    detached: bool,
     // Task has unsafe `Send` and `Sync`, but that is only because we know
     // it will not be polled from another thread. This is to ensure that
     // JoinHandle is neither Send nor Sync.
     _data: PhantomData<*const ()>,
 }
 
+impl<T> Debug for JoinHandle<T> {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("JoinHandle")
+            .field("completed", &self.task.value.borrow().is_some())
+            .finish_non_exhaustive()
+    }
+}
+
+impl<T> JoinHandle<T> {
+    /// Drops the handle without waiting for the result. In debug builds
+    /// dropping a handle whose task has completed with a value that was not
+    /// retrieved emits [`TraceEvent::UnconsumedJoinHandle`]; detaching the
+    /// handle signals that the result is intentionally ignored.
+    pub fn detach(mut self) {
+        self.detached = true;
+    }
+}
+
+impl<T> Drop for JoinHandle<T> {
+    fn drop(&mut self) {
+        if cfg!(debug_assertions)
+            && !self.detached
+            && std::mem::size_of::<T>() > 0
+            && self.task.value.borrow().is_some()
+        {
+            self.task.trace_hook.emit(TraceEvent::UnconsumedJoinHandle {
+                type_name: std::any::type_name::<T>(),
+            });
+        }
+    }
+}
+
 impl<T: 'static> Future for JoinHandle<T> {
     type Output = T;
 
@@ -219,19 +986,20 @@ impl<T: 'static> Future for JoinHandle<T> {
     }
 }
 
-// IMPORTANT
-// Tests must be run with  cargo test -- --test-threads=1
-// otherwise they will likely crash
 #[cfg(test)]
 mod tests {
     use crate::{
         util::{Capsule, FutureCompleter},
-        RunLoop,
+        Elapsed, RunLoop, RunLoopSender, TraceEvent,
     };
+    use futures::{pin_mut, StreamExt};
     use std::{
         cell::RefCell,
         rc::Rc,
-        sync::{Arc, Mutex},
+        sync::{
+            atomic::{AtomicUsize, Ordering},
+            Arc, Mutex,
+        },
         thread,
         time::{Duration, Instant},
     };
@@ -279,6 +1047,31 @@ mod tests {
         assert_eq!(*stop_called.lock().unwrap(), true);
     }
 
+    #[test]
+    fn test_spawn_with_result() {
+        let run_loop = Rc::new(RunLoop::new());
+        let run_loop_clone = run_loop.clone();
+        let receiver = run_loop.spawn_with_result(async move {
+            wait(run_loop_clone, Duration::from_millis(10)).await;
+            42
+        });
+        let rl = Capsule::new(run_loop.clone());
+        let sender = run_loop.new_sender();
+        let worker = thread::spawn(move || {
+            let mut receiver = receiver;
+            let value = loop {
+                match receiver.try_recv().unwrap() {
+                    Some(value) => break value,
+                    None => thread::sleep(Duration::from_millis(1)),
+                }
+            };
+            sender.send(move || rl.get_ref().unwrap().stop());
+            value
+        });
+        run_loop.run();
+        assert_eq!(worker.join().unwrap(), 42);
+    }
+
     async fn wait(run_loop: Rc<RunLoop>, duration: Duration) {
         let (future, completer) = FutureCompleter::<()>::new();
         run_loop
@@ -289,6 +1082,32 @@ mod tests {
         future.await
     }
 
+    #[test]
+    #[cfg(any(target_os = "android", target_os = "ios"))]
+    fn test_run_mobile() {
+        let run_loop = Rc::new(RunLoop::new());
+        let fired = Rc::new(RefCell::new(false));
+        let fired_clone = fired.clone();
+        run_loop
+            .schedule(Duration::from_millis(10), move || {
+                fired_clone.replace(true);
+            })
+            .detach();
+        // Bounds the nested loop.
+        let run_loop_clone = run_loop.clone();
+        run_loop
+            .schedule(Duration::from_millis(50), move || run_loop_clone.stop())
+            .detach();
+        let start = Instant::now();
+        run_loop.run();
+        assert!(*fired.borrow());
+        assert!(start.elapsed() >= Duration::from_millis(50));
+
+        // Stop requested before running makes run return immediately.
+        run_loop.stop();
+        run_loop.run();
+    }
+
     #[test]
     fn test_async() {
         let run_loop = Rc::new(RunLoop::new());
@@ -302,4 +1121,403 @@ mod tests {
         run_loop.run();
         assert!(start.elapsed() >= Duration::from_millis(50));
     }
+
+    #[test]
+    #[cfg(debug_assertions)]
+    fn test_unconsumed_join_handle() {
+        let run_loop = Rc::new(RunLoop::new());
+        let events = Rc::new(RefCell::new(Vec::new()));
+        let events_clone = events.clone();
+        run_loop.set_trace_hook(move |event| {
+            events_clone.borrow_mut().push(event.clone());
+        });
+        let dropped = run_loop.spawn(async { 10 });
+        let detached = run_loop.spawn(async { 20 });
+        let unit = run_loop.spawn(async {});
+        let w = wait(run_loop.clone(), Duration::from_millis(10));
+        let run_loop_clone = run_loop.clone();
+        run_loop
+            .spawn(async move {
+                w.await;
+                run_loop_clone.stop();
+            })
+            .detach();
+        run_loop.run();
+        drop(unit);
+        detached.detach();
+        assert!(events.borrow().is_empty());
+        drop(dropped);
+        assert!(matches!(
+            events.borrow().as_slice(),
+            [TraceEvent::UnconsumedJoinHandle { type_name }] if *type_name == "i32"
+        ));
+    }
+
+    #[test]
+    fn test_slow_callback() {
+        let run_loop = Rc::new(RunLoop::new());
+        let events = Rc::new(RefCell::new(Vec::new()));
+        let events_clone = events.clone();
+        run_loop.set_trace_hook(move |event| {
+            events_clone.borrow_mut().push(event.clone());
+        });
+        run_loop.set_slow_callback_threshold(Some(Duration::from_millis(10)));
+        run_loop
+            .schedule_labeled("slow", Duration::ZERO, || {
+                thread::sleep(Duration::from_millis(20));
+            })
+            .detach();
+        run_loop
+            .schedule_labeled("fast", Duration::ZERO, || {})
+            .detach();
+        let run_loop_clone = run_loop.clone();
+        run_loop
+            .schedule(Duration::from_millis(10), move || run_loop_clone.stop())
+            .detach();
+        run_loop.run();
+        assert!(matches!(
+            events.borrow().as_slice(),
+            [TraceEvent::SlowCallback { duration, label: Some("slow") }]
+                if *duration >= Duration::from_millis(20)
+        ));
+    }
+
+    #[test]
+    fn test_try_send() {
+        let run_loop = Rc::new(RunLoop::new());
+        run_loop.set_sender_backlog_limit(Some(10));
+        let sender = run_loop.new_sender();
+        let executed = Arc::new(AtomicUsize::new(0));
+        let run_loop_clone = run_loop.clone();
+        let executed_clone = executed.clone();
+        let thread_sender = sender.clone();
+        run_loop.block_on(async move {
+            // Keep run loop thread busy while the other thread is sending.
+            let results: Vec<bool> = thread::spawn(move || {
+                (0..20)
+                    .map(|_| {
+                        let executed = executed_clone.clone();
+                        thread_sender
+                            .try_send(move || {
+                                executed.fetch_add(1, Ordering::SeqCst);
+                            })
+                            .is_ok()
+                    })
+                    .collect()
+            })
+            .join()
+            .unwrap();
+            assert!(results[..10].iter().all(|r| *r));
+            assert!(results[10..].iter().all(|r| !*r));
+            while executed.load(Ordering::SeqCst) < 10 {
+                run_loop_clone.wait(Duration::from_millis(1)).await;
+            }
+            assert_eq!(executed.load(Ordering::SeqCst), 10);
+        });
+        assert!(sender.try_send(|| {}).is_ok());
+    }
+
+    #[test]
+    fn test_pending_count() {
+        let run_loop = Rc::new(RunLoop::new());
+        assert_eq!(run_loop.pending_count(), (0, 0));
+        let run_loop_clone = run_loop.clone();
+        let sender = run_loop.new_sender();
+        run_loop.block_on(async move {
+            // Make sure the run loop is running.
+            run_loop_clone.wait(Duration::from_millis(1)).await;
+            let _h1 = run_loop_clone.schedule(Duration::from_secs(10), || {});
+            let _h2 = run_loop_clone.schedule(Duration::from_secs(20), || {});
+            let mut h3 = run_loop_clone.schedule(Duration::from_secs(30), || {});
+            h3.cancel();
+            // Keep run loop thread busy while the other thread is sending.
+            thread::spawn(move || {
+                for _ in 0..3 {
+                    sender.send(|| {});
+                }
+            })
+            .join()
+            .unwrap();
+            assert_eq!(run_loop_clone.pending_count(), (3, 2));
+        });
+        assert_eq!(run_loop.pending_count().1, 0);
+    }
+
+    #[test]
+    fn test_next_scheduled_deadline() {
+        let run_loop = RunLoop::new();
+        assert_eq!(run_loop.next_scheduled_deadline(), None);
+        let start = Instant::now();
+        let _h1 = run_loop.schedule(Duration::from_millis(100), || {});
+        let mut h2 = run_loop.schedule(Duration::from_millis(50), || {});
+        let _h3 = run_loop.schedule(Duration::from_millis(200), || {});
+        let end = Instant::now();
+        let deadline = run_loop.next_scheduled_deadline().unwrap();
+        assert!(deadline >= start + Duration::from_millis(50));
+        assert!(deadline <= end + Duration::from_millis(50));
+
+        h2.cancel();
+        let deadline = run_loop.next_scheduled_deadline().unwrap();
+        assert!(deadline >= start + Duration::from_millis(100));
+        assert!(deadline <= end + Duration::from_millis(100));
+    }
+
+    #[test]
+    fn test_block_on() {
+        let run_loop = Rc::new(RunLoop::new());
+        let run_loop_clone = run_loop.clone();
+        let start = Instant::now();
+        let res = run_loop.block_on(async move {
+            run_loop_clone.wait(Duration::from_millis(50)).await;
+            10
+        });
+        assert_eq!(res, 10);
+        assert!(start.elapsed() >= Duration::from_millis(50));
+    }
+
+    #[test]
+    #[cfg(target_os = "macos")]
+    fn test_pump_private_mode_until() {
+        let run_loop = RunLoop::new();
+        let flag = Rc::new(RefCell::new(false));
+        let flag_clone = flag.clone();
+        let start = Instant::now();
+        run_loop
+            .schedule(Duration::from_millis(20), move || {
+                flag_clone.replace(true);
+            })
+            .detach();
+        run_loop.pump_private_mode_until(|| *flag.borrow());
+        assert_eq!(*flag.borrow(), true);
+        assert!(start.elapsed() >= Duration::from_millis(20));
+    }
+
+    #[test]
+    fn test_sender_debug() {
+        let run_loop = RunLoop::new();
+        let sender: RunLoopSender = run_loop.new_sender();
+        let debug = format!("{:?}", sender);
+        assert!(debug.starts_with("RunLoopSender {"));
+        assert!(debug.contains("thread_id"));
+
+        // Structs holding sender can derive Debug.
+        #[derive(Debug)]
+        struct Worker {
+            name: &'static str,
+            sender: RunLoopSender,
+        }
+        let worker = Worker {
+            name: "worker",
+            sender: run_loop.new_sender(),
+        };
+        let debug = format!("{:?}", worker);
+        assert!(debug.starts_with("Worker { name: \"worker\", sender: RunLoopSender {"));
+        assert_eq!(worker.name, "worker");
+        worker.sender.send(|| {});
+    }
+
+    #[test]
+    fn test_with_timeout() {
+        let run_loop = Rc::new(RunLoop::new());
+        let run_loop_clone = run_loop.clone();
+        run_loop.block_on(async move {
+            let rl = run_loop_clone.clone();
+            let res = run_loop_clone
+                .with_timeout(
+                    async move {
+                        rl.wait(Duration::from_millis(10)).await;
+                        10
+                    },
+                    Duration::from_secs(10),
+                )
+                .await;
+            assert_eq!(res, Ok(10));
+            // timeout timer is cancelled
+            assert_eq!(run_loop_clone.pending_count().1, 0);
+
+            let resource = Rc::new(());
+            let resource_clone = resource.clone();
+            let rl = run_loop_clone.clone();
+            let res = run_loop_clone
+                .with_timeout(
+                    async move {
+                        rl.wait(Duration::from_secs(10)).await;
+                        drop(resource_clone);
+                        10
+                    },
+                    Duration::from_millis(10),
+                )
+                .await;
+            assert_eq!(res, Err(Elapsed));
+            // future is dropped
+            assert_eq!(Rc::strong_count(&resource), 1);
+        });
+    }
+
+    #[test]
+    fn test_parallel_run_loops() {
+        let threads: Vec<_> = (0..4)
+            .map(|i| {
+                thread::spawn(move || {
+                    let run_loop = Rc::new(RunLoop::new());
+                    let run_loop_clone = run_loop.clone();
+                    run_loop.block_on(async move {
+                        run_loop_clone.wait(Duration::from_millis(10)).await;
+                        i
+                    })
+                })
+            })
+            .collect();
+        let results: Vec<_> = threads.into_iter().map(|t| t.join().unwrap()).collect();
+        assert_eq!(results, vec![0, 1, 2, 3]);
+    }
+
+    #[test]
+    fn test_tick() {
+        let rl = RunLoop::new();
+        // Drain whatever is left in the (process global) platform run loop.
+        while rl.tick() {}
+
+        let start = Instant::now();
+        assert!(!rl.tick());
+        assert!(start.elapsed() < Duration::from_millis(500));
+
+        let called = Rc::new(RefCell::new(Vec::new()));
+        let called_clone = called.clone();
+        rl.schedule_next(move || called_clone.borrow_mut().push(1))
+            .detach();
+        let called_clone = called.clone();
+        rl.schedule_next(move || called_clone.borrow_mut().push(2))
+            .detach();
+        let called_clone = called.clone();
+        let _later = rl.schedule(Duration::from_secs(60 * 60), move || {
+            called_clone.borrow_mut().push(3)
+        });
+
+        let start = Instant::now();
+        while called.borrow().len() < 2 {
+            assert!(rl.tick());
+        }
+        let mut called = called.borrow().clone();
+        called.sort_unstable();
+        assert_eq!(called, vec![1, 2]);
+
+        // Only the distant timer is left, tick must not wait for it.
+        assert!(!rl.tick());
+        assert!(start.elapsed() < Duration::from_millis(500));
+    }
+
+    #[test]
+    #[cfg(target_os = "linux")]
+    fn test_tick_iterates() {
+        use std::cell::Cell;
+
+        // Keeps rescheduling itself until stopped.
+        fn reschedule(rl: Rc<RunLoop>, count: Rc<Cell<usize>>, stop: Rc<Cell<bool>>) {
+            if stop.get() {
+                return;
+            }
+            count.set(count.get() + 1);
+            rl.clone()
+                .schedule_next(move || reschedule(rl, count, stop))
+                .detach();
+        }
+        let rl = Rc::new(RunLoop::new());
+        while rl.tick() {}
+
+        let count = Rc::new(Cell::new(0));
+        let stop = Rc::new(Cell::new(false));
+        reschedule(rl.clone(), count.clone(), stop.clone());
+        // Work scheduled while ticking is done in the same tick, but tick
+        // still returns even though there is always more work.
+        assert!(rl.tick());
+        assert!(count.get() > 2);
+
+        stop.set(true);
+        while rl.tick() {}
+    }
+
+    #[test]
+    fn test_schedule_with_tolerance() {
+        let run_loop = Rc::new(RunLoop::new());
+        let run_loop_clone = run_loop.clone();
+        let in_time = Duration::from_millis(10);
+        let tolerance = Duration::from_millis(500);
+        let elapsed = run_loop.block_on(async move {
+            let start = Instant::now();
+            let (future, completer) = FutureCompleter::new();
+            let _handle = run_loop_clone.schedule_with_tolerance(in_time, tolerance, move || {
+                completer.complete(start.elapsed());
+            });
+            future.await
+        });
+        assert!(elapsed >= in_time);
+        assert!(elapsed <= in_time + tolerance);
+    }
+
+    #[test]
+    fn test_schedule_with_timing() {
+        let run_loop = Rc::new(RunLoop::new());
+        let run_loop_clone = run_loop.clone();
+        let in_time = Duration::from_millis(20);
+        let (scheduled, actual) = run_loop.block_on(async move {
+            let (future, completer) = FutureCompleter::new();
+            let _handle = run_loop_clone.schedule_with_timing(in_time, move |scheduled, actual| {
+                completer.complete((scheduled, actual));
+            });
+            future.await
+        });
+        assert!(actual >= scheduled);
+        assert!(actual - scheduled < Duration::from_millis(500));
+    }
+
+    #[test]
+    fn test_is_idle() {
+        let run_loop = Rc::new(RunLoop::new());
+        let run_loop_clone = run_loop.clone();
+        run_loop.block_on(async move {
+            run_loop_clone.wait(Duration::from_millis(1)).await;
+            assert!(run_loop_clone.is_idle());
+            assert_eq!(run_loop_clone.pending_timers(), 0);
+
+            let _h1 = run_loop_clone.schedule_next(|| {});
+            assert!(!run_loop_clone.is_idle());
+            // Distant timers don't make the run loop busy.
+            let _h2 = run_loop_clone.schedule(Duration::from_secs(10), || {});
+            assert_eq!(run_loop_clone.pending_timers(), 2);
+
+            run_loop_clone.wait(Duration::from_millis(1)).await;
+            assert_eq!(run_loop_clone.pending_timers(), 1);
+            assert!(run_loop_clone.is_idle());
+        });
+    }
+
+    #[test]
+    fn test_interval() {
+        let run_loop = Rc::new(RunLoop::new());
+        let run_loop_clone = run_loop.clone();
+        let period = Duration::from_millis(20);
+        let ticks = run_loop.block_on(async move {
+            let start = Instant::now();
+            let interval = run_loop_clone.interval(period);
+            run_loop_clone
+                .spawn(async move {
+                    let mut ticks = Vec::new();
+                    pin_mut!(interval);
+                    for _ in 0..3 {
+                        interval.next().await;
+                        ticks.push(start.elapsed());
+                    }
+                    ticks
+                })
+                .await
+        });
+        assert_eq!(ticks.len(), 3);
+        for (i, tick) in ticks.iter().enumerate() {
+            assert!(*tick >= period * (i as u32 + 1));
+        }
+        assert!(ticks[2] < period * 3 + Duration::from_millis(500));
+        // Stream was dropped together with the task.
+        assert_eq!(run_loop.pending_timers(), 0);
+    }
 }
@@ -0,0 +1,130 @@
+use std::{
+    hash::{Hash, Hasher},
+    io,
+    sync::Arc,
+};
+
+#[cfg(unix)]
+use std::os::fd::{AsFd, AsRawFd, BorrowedFd, OwnedFd};
+#[cfg(windows)]
+use std::os::windows::io::{AsHandle, AsRawHandle, BorrowedHandle, OwnedHandle};
+
+use crate::{FinalizableHandle, IsolateId, Value};
+
+/// First item of the list that [`Value::SharedBuffer`] is sent to Dart as.
+pub const SHARED_BUFFER_MARKER: &str = "nativeshell_shared_buffer";
+
+#[cfg(unix)]
+pub type SharedBufferHandle = OwnedFd;
+#[cfg(windows)]
+pub type SharedBufferHandle = OwnedHandle;
+
+/// Shared memory region identified by operating system handle (file descriptor
+/// on Unix, `HANDLE` on Windows). Allows passing large buffers to Dart without
+/// copying them through the message codec.
+///
+/// `SharedBuffer` owns the handle and closes it when dropped. Every time
+/// the buffer is sent to Dart the handle is duplicated and Dart receives
+/// `[SHARED_BUFFER_MARKER, handle, len, FinalizableHandle]`, which Dart
+/// `SharedBuffer.fromValue` turns into `SharedBuffer` that can map the memory.
+/// The duplicated handle is owned by the [`FinalizableHandle`] and closed once
+/// the Dart object gets garbage collected, or right away if the message is
+/// never delivered. If the handle can not be duplicated Dart receives `null`.
+#[derive(Debug)]
+pub struct SharedBuffer {
+    handle: SharedBufferHandle,
+    len: usize,
+}
+
+impl SharedBuffer {
+    /// Creates buffer for `len` bytes of shared memory represented by `handle`.
+    /// Takes ownership of the handle.
+    pub fn new(handle: SharedBufferHandle, len: usize) -> Self {
+        Self { handle, len }
+    }
+
+    pub fn len(&self) -> usize {
+        self.len
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+
+    /// Returns new handle referring to the same memory. Caller owns the result.
+    pub fn try_clone_handle(&self) -> io::Result<SharedBufferHandle> {
+        self.handle.try_clone()
+    }
+
+    fn raw_handle(&self) -> i64 {
+        #[cfg(unix)]
+        {
+            self.handle.as_raw_fd() as i64
+        }
+        #[cfg(windows)]
+        {
+            self.handle.as_raw_handle() as i64
+        }
+    }
+
+    // Value sent to Dart in place of the buffer.
+    #[cfg_attr(feature = "mock", allow(dead_code))]
+    pub(crate) fn to_token(&self, isolate_id: IsolateId) -> Value {
+        match self.try_clone_handle() {
+            Ok(handle) => {
+                #[cfg(unix)]
+                let raw = handle.as_raw_fd() as i64;
+                #[cfg(windows)]
+                let raw = handle.as_raw_handle() as i64;
+                // Dropping the finalizer closes the handle.
+                let owner = FinalizableHandle::new(self.len as isize, isolate_id, move || {
+                    drop(handle);
+                });
+                Value::List(vec![
+                    SHARED_BUFFER_MARKER.into(),
+                    raw.into(),
+                    (self.len as i64).into(),
+                    Arc::new(owner).into(),
+                ])
+            }
+            Err(_) => Value::Null,
+        }
+    }
+}
+
+#[cfg(unix)]
+impl AsFd for SharedBuffer {
+    fn as_fd(&self) -> BorrowedFd<'_> {
+        self.handle.as_fd()
+    }
+}
+
+#[cfg(windows)]
+impl AsHandle for SharedBuffer {
+    fn as_handle(&self) -> BorrowedHandle<'_> {
+        self.handle.as_handle()
+    }
+}
+
+// Buffers are compared by identity of their handles.
+
+impl PartialEq for SharedBuffer {
+    fn eq(&self, other: &Self) -> bool {
+        self.raw_handle() == other.raw_handle() && self.len == other.len
+    }
+}
+
+impl Eq for SharedBuffer {}
+
+impl PartialOrd for SharedBuffer {
+    fn partial_cmp(&self, other: &Self) -> Option<std::cmp::Ordering> {
+        (self.raw_handle(), self.len).partial_cmp(&(other.raw_handle(), other.len))
+    }
+}
+
+impl Hash for SharedBuffer {
+    fn hash<H: Hasher>(&self, state: &mut H) {
+        self.raw_handle().hash(state);
+        self.len.hash(state);
+    }
+}
@@ -0,0 +1,507 @@
+use core::mem;
+
+use crate::{
+    codec::{Element, Reader, Writer},
+    DecodeError, DecodeOptions, PlatformError, PlatformResult, Value,
+};
+
+// Type tags used by Flutter StandardMessageCodec.
+const NULL: u8 = 0;
+const TRUE: u8 = 1;
+const FALSE: u8 = 2;
+const INT32: u8 = 3;
+const INT64: u8 = 4;
+const LARGE_INT: u8 = 5;
+const FLOAT64: u8 = 6;
+const STRING: u8 = 7;
+const UINT8LIST: u8 = 8;
+const INT32LIST: u8 = 9;
+const INT64LIST: u8 = 10;
+const FLOAT64LIST: u8 = 11;
+const LIST: u8 = 12;
+const MAP: u8 = 13;
+const FLOAT32LIST: u8 = 14;
+
+const ENVELOPE_SUCCESS: u8 = 0;
+const ENVELOPE_ERROR: u8 = 1;
+
+/// Encodes and decodes values in format of Flutter `StandardMessageCodec`.
+///
+/// Typed lists that the Flutter codec doesn't have (`I8List`, `I16List`,
+/// `U16List`, `U32List` and `BoolList`) are encoded as generic lists. Panics
+/// when encoding Dart objects, finalizable handles or shared buffers.
+pub struct StandardMessageCodec {}
+
+impl StandardMessageCodec {
+    pub fn encode(value: &Value) -> Vec<u8> {
+        let mut buf = Vec::new();
+        Self::write_value(&mut Writer::new(&mut buf), value);
+        buf
+    }
+
+    pub fn decode(bytes: &[u8]) -> Result<Value, DecodeError> {
+        Self::decode_with_options(bytes, &DecodeOptions::default())
+    }
+
+    /// Same as [`StandardMessageCodec::decode`] but allows specifying how
+    /// strings are decoded and limits for untrusted input.
+    pub fn decode_with_options(
+        bytes: &[u8],
+        options: &DecodeOptions,
+    ) -> Result<Value, DecodeError> {
+        options.limits.check_total_bytes(bytes.len())?;
+        let mut reader = Reader::new(bytes);
+        let value = Self::read_value(&mut reader, options)?;
+        if reader.ended() {
+            Ok(value)
+        } else {
+            Err(DecodeError::Malformed)
+        }
+    }
+
+    fn write_value(writer: &mut Writer, value: &Value) {
+        match value {
+            Value::Null => writer.write_u8(NULL),
+            Value::Bool(v) => writer.write_u8(if *v { TRUE } else { FALSE }),
+            Value::I64(n) => match i32::try_from(*n) {
+                Ok(n) => {
+                    writer.write_u8(INT32);
+                    writer.write_i32(n);
+                }
+                Err(_) => {
+                    writer.write_u8(INT64);
+                    writer.write_i64(*n);
+                }
+            },
+            Value::I32(n) => {
+                writer.write_u8(INT32);
+                writer.write_i32(*n);
+            }
+            Value::U32(n) => Self::write_value(writer, &Value::I64(*n as i64)),
+            Value::F64(n) => {
+                writer.write_u8(FLOAT64);
+                writer.align_to(8);
+                writer.write_f64(*n);
+            }
+            Value::String(s) => {
+                writer.write_u8(STRING);
+                writer.write_size(s.len());
+                writer.write_string(s);
+            }
+            Value::U8List(v) => Self::write_list(writer, UINT8LIST, v),
+            Value::I32List(v) => Self::write_list(writer, INT32LIST, v),
+            Value::I64List(v) => Self::write_list(writer, INT64LIST, v),
+            Value::F32List(v) => Self::write_list(writer, FLOAT32LIST, v),
+            Value::F64List(v) => Self::write_list(writer, FLOAT64LIST, v),
+            Value::I8List(v) => Self::write_generic_list(writer, v),
+            Value::I16List(v) => Self::write_generic_list(writer, v),
+            Value::U16List(v) => Self::write_generic_list(writer, v),
+            Value::U32List(v) => Self::write_generic_list(writer, v),
+            Value::BoolList(v) => Self::write_generic_list(writer, v),
+            Value::List(list) => {
+                writer.write_u8(LIST);
+                writer.write_size(list.len());
+                list.iter().for_each(|v| Self::write_value(writer, v));
+            }
+            Value::Map(map) => {
+                writer.write_u8(MAP);
+                writer.write_size(map.len());
+                map.iter().for_each(|(k, v)| {
+                    Self::write_value(writer, k);
+                    Self::write_value(writer, v);
+                });
+            }
+            Value::Dart(_) | Value::FinalizableHandle(_) => {
+                panic!("Dart objects and finalizable handles can not be encoded");
+            }
+            #[cfg(feature = "shared_buffer")]
+            Value::SharedBuffer(_) => {
+                panic!("Shared buffers can not be encoded");
+            }
+        }
+    }
+
+    fn write_list<T: Element>(writer: &mut Writer, tag: u8, list: &[T]) {
+        writer.write_u8(tag);
+        writer.write_size(list.len());
+        writer.align_to(T::SIZE);
+        list.iter().for_each(|v| v.write(writer.0));
+    }
+
+    fn write_generic_list<T: Into<Value> + Copy>(writer: &mut Writer, list: &[T]) {
+        writer.write_u8(LIST);
+        writer.write_size(list.len());
+        list.iter()
+            .for_each(|v| Self::write_value(writer, &(*v).into()));
+    }
+
+    // Lists and maps are read using explicit stack of partially read values,
+    // so that deeply nested input doesn't overflow native stack. Every item
+    // takes at least one byte, so lengths exceeding remaining input are
+    // rejected before anything is allocated.
+    fn read_value(reader: &mut Reader, options: &DecodeOptions) -> Result<Value, DecodeError> {
+        let mut stack = Vec::<ReadFrame>::new();
+        loop {
+            let mut value = match reader.checked(1)?.read_u8() {
+                NULL => Value::Null,
+                TRUE => Value::Bool(true),
+                FALSE => Value::Bool(false),
+                INT32 => Value::I64(reader.checked(4)?.read_u32() as i32 as i64),
+                INT64 => Value::I64(reader.checked(8)?.read_i64()),
+                // Integers that don't fit into 64 bits, stored as hexadecimal string.
+                LARGE_INT | STRING => {
+                    let len = reader.read_checked_size()?;
+                    let bytes = reader.checked(len)?.read_bytes(len).to_vec();
+                    Value::String(Value::validate_utf8(bytes, options.string_decoding)?)
+                }
+                FLOAT64 => {
+                    reader.align_to(8);
+                    Value::F64(reader.checked(8)?.read_f64())
+                }
+                UINT8LIST => Value::U8List(Self::read_list(reader, options)?),
+                INT32LIST => Value::I32List(Self::read_list(reader, options)?),
+                INT64LIST => Value::I64List(Self::read_list(reader, options)?),
+                FLOAT32LIST => Value::F32List(Self::read_list(reader, options)?),
+                FLOAT64LIST => Value::F64List(Self::read_list(reader, options)?),
+                LIST => {
+                    let len = Self::read_collection_len(reader, options, stack.len(), 1)?;
+                    if len > 0 {
+                        stack.push(ReadFrame::List(Vec::new(), len));
+                        continue;
+                    }
+                    Value::List(Vec::new())
+                }
+                MAP => {
+                    let len = Self::read_collection_len(reader, options, stack.len(), 2)?;
+                    if len > 0 {
+                        stack.push(ReadFrame::Map(Vec::new(), len, None));
+                        continue;
+                    }
+                    Value::Map(Vec::new().into())
+                }
+                _ => return Err(DecodeError::Malformed),
+            };
+            // Store the value in its parent; repeat for parents that are complete.
+            loop {
+                match stack.last_mut() {
+                    None => return Ok(value),
+                    Some(ReadFrame::List(list, len)) => {
+                        list.push(value);
+                        if list.len() < *len {
+                            break;
+                        }
+                        value = match stack.pop() {
+                            Some(ReadFrame::List(list, _)) => Value::List(list),
+                            _ => unreachable!(),
+                        };
+                    }
+                    Some(ReadFrame::Map(map, len, key)) => match key.take() {
+                        None => {
+                            key.replace(value);
+                            break;
+                        }
+                        Some(key) => {
+                            map.push((key, value));
+                            if map.len() < *len {
+                                break;
+                            }
+                            value = match stack.pop() {
+                                Some(ReadFrame::Map(map, _, _)) => Value::Map(map.into()),
+                                _ => unreachable!(),
+                            };
+                        }
+                    },
+                }
+            }
+        }
+    }
+
+    // Reads length of list or map at given depth whose items take at least
+    // `min_item_size` bytes each.
+    fn read_collection_len(
+        reader: &mut Reader,
+        options: &DecodeOptions,
+        depth: usize,
+        min_item_size: usize,
+    ) -> Result<usize, DecodeError> {
+        let len = reader.read_checked_size()?;
+        options.limits.check_collection_length(len)?;
+        options.limits.check_depth(depth + 1)?;
+        match len.checked_mul(min_item_size) {
+            Some(size) if size <= reader.remaining() => Ok(len),
+            _ => Err(DecodeError::Malformed),
+        }
+    }
+
+    fn read_list<T: Element>(
+        reader: &mut Reader,
+        options: &DecodeOptions,
+    ) -> Result<Vec<T>, DecodeError> {
+        let len = reader.read_checked_size()?;
+        options.limits.check_collection_length(len)?;
+        let size = len.checked_mul(T::SIZE).ok_or(DecodeError::Malformed)?;
+        reader.align_to(T::SIZE);
+        let bytes = reader.checked(size)?.read_bytes(size);
+        Ok(bytes.chunks_exact(T::SIZE).map(T::read).collect())
+    }
+}
+
+// List or map being read by `StandardMessageCodec::read_value`.
+enum ReadFrame {
+    // Items read so far and expected length.
+    List(Vec<Value>, usize),
+    // Entries read so far, expected length and key of entry whose value is
+    // being read.
+    Map(Vec<(Value, Value)>, usize, Option<Value>),
+}
+
+/// Encodes method calls and replies in format of Flutter `StandardMethodCodec`.
+/// Values are encoded using [`StandardMessageCodec`].
+///
+/// Method call is encoded as method name followed by arguments. Successful
+/// reply is encoded as `0` followed by the result; error reply as `1` followed
+/// by error code, message and detail.
+pub struct StandardMethodCodec {}
+
+impl StandardMethodCodec {
+    pub fn encode_call(method: &str, args: &Value) -> Vec<u8> {
+        let mut buf = Vec::new();
+        let mut writer = Writer::new(&mut buf);
+        StandardMessageCodec::write_value(&mut writer, &Value::String(method.into()));
+        StandardMessageCodec::write_value(&mut writer, args);
+        buf
+    }
+
+    /// Returns method name and arguments.
+    pub fn decode_call(bytes: &[u8]) -> Result<(String, Value), DecodeError> {
+        Self::decode_call_with_options(bytes, &DecodeOptions::default())
+    }
+
+    /// Same as [`StandardMethodCodec::decode_call`] but allows specifying
+    /// decode options.
+    pub fn decode_call_with_options(
+        bytes: &[u8],
+        options: &DecodeOptions,
+    ) -> Result<(String, Value), DecodeError> {
+        options.limits.check_total_bytes(bytes.len())?;
+        let mut reader = Reader::new(bytes);
+        let mut method = StandardMessageCodec::read_value(&mut reader, options)?;
+        let args = StandardMessageCodec::read_value(&mut reader, options)?;
+        match &mut method {
+            Value::String(method) if reader.ended() => Ok((mem::take(method), args)),
+            _ => Err(DecodeError::Malformed),
+        }
+    }
+
+    pub fn encode_success(value: &Value) -> Vec<u8> {
+        let mut buf = vec![ENVELOPE_SUCCESS];
+        StandardMessageCodec::write_value(&mut Writer::new(&mut buf), value);
+        buf
+    }
+
+    pub fn encode_error(code: &str, message: Option<&str>, detail: &Value) -> Vec<u8> {
+        let mut buf = vec![ENVELOPE_ERROR];
+        let mut writer = Writer::new(&mut buf);
+        StandardMessageCodec::write_value(&mut writer, &Value::String(code.into()));
+        StandardMessageCodec::write_value(&mut writer, &message.into());
+        StandardMessageCodec::write_value(&mut writer, detail);
+        buf
+    }
+
+    /// Decodes reply encoded by [`StandardMethodCodec::encode_success`] or
+    /// [`StandardMethodCodec::encode_error`]. Error stack trace, which Flutter
+    /// may append to error replies, is ignored.
+    pub fn decode_reply(bytes: &[u8]) -> Result<PlatformResult, DecodeError> {
+        Self::decode_reply_with_options(bytes, &DecodeOptions::default())
+    }
+
+    /// Same as [`StandardMethodCodec::decode_reply`] but allows specifying
+    /// decode options.
+    pub fn decode_reply_with_options(
+        bytes: &[u8],
+        options: &DecodeOptions,
+    ) -> Result<PlatformResult, DecodeError> {
+        options.limits.check_total_bytes(bytes.len())?;
+        let mut reader = Reader::new(bytes);
+        match reader.checked(1)?.read_u8() {
+            ENVELOPE_SUCCESS => {
+                let value = StandardMessageCodec::read_value(&mut reader, options)?;
+                if reader.ended() {
+                    Ok(Ok(value))
+                } else {
+                    Err(DecodeError::Malformed)
+                }
+            }
+            ENVELOPE_ERROR => {
+                let mut code = StandardMessageCodec::read_value(&mut reader, options)?;
+                let mut message = StandardMessageCodec::read_value(&mut reader, options)?;
+                let detail = StandardMessageCodec::read_value(&mut reader, options)?;
+                let message = match &mut message {
+                    Value::Null => None,
+                    Value::String(message) => Some(mem::take(message)),
+                    _ => return Err(DecodeError::Malformed),
+                };
+                match &mut code {
+                    Value::String(code) => Ok(Err(PlatformError {
+                        code: mem::take(code),
+                        message,
+                        detail,
+                    })),
+                    _ => Err(DecodeError::Malformed),
+                }
+            }
+            _ => Err(DecodeError::Malformed),
+        }
+    }
+}
+
+// Expected bytes assume little endian host, same as Flutter on supported platforms.
+#[cfg(all(test, target_endian = "little"))]
+mod tests {
+    use crate::{DecodeError, DecodeLimits, DecodeOptions, PlatformError, Value};
+
+    use super::{StandardMessageCodec, StandardMethodCodec};
+
+    #[test]
+    fn test_message_codec() {
+        let value = Value::List(vec![
+            1.into(),
+            Value::I64(1 << 40),
+            "a".into(),
+            Value::Null,
+            true.into(),
+        ]);
+        let bytes = StandardMessageCodec::encode(&value);
+        let mut expected = vec![12, 5, 3, 1, 0, 0, 0, 4];
+        expected.extend_from_slice(&(1i64 << 40).to_le_bytes());
+        expected.extend_from_slice(&[7, 1, b'a', 0, 1]);
+        assert_eq!(bytes, expected);
+        assert_eq!(StandardMessageCodec::decode(&bytes), Ok(value));
+
+        // Typed data is aligned to element size from the start of message.
+        let value = Value::List(vec![Value::F64List(vec![1.5])]);
+        let bytes = StandardMessageCodec::encode(&value);
+        let mut expected = vec![12, 1, 11, 1, 0, 0, 0, 0];
+        expected.extend_from_slice(&1.5f64.to_le_bytes());
+        assert_eq!(bytes, expected);
+        assert_eq!(StandardMessageCodec::decode(&bytes), Ok(value));
+
+        // Sizes of 254 and more are prefixed.
+        let bytes = StandardMessageCodec::encode(&Value::U8List(vec![7; 300]));
+        assert_eq!(&bytes[..4], &[8, 254, 44, 1]);
+        assert_eq!(bytes.len(), 304);
+
+        let bytes = StandardMessageCodec::encode(&Value::BoolList(vec![true, false]));
+        assert_eq!(bytes, vec![12, 2, 1, 2]);
+
+        assert_eq!(
+            StandardMessageCodec::decode(&[12, 2, 0]),
+            Err(DecodeError::Malformed)
+        );
+    }
+
+    #[test]
+    fn test_method_call() {
+        let bytes = StandardMethodCodec::encode_call("foo", &42.into());
+        assert_eq!(bytes, vec![7, 3, b'f', b'o', b'o', 3, 42, 0, 0, 0]);
+        assert_eq!(
+            StandardMethodCodec::decode_call(&bytes),
+            Ok(("foo".into(), 42.into()))
+        );
+        assert_eq!(
+            StandardMethodCodec::decode_call(&[3, 1, 0, 0, 0, 0]),
+            Err(DecodeError::Malformed)
+        );
+    }
+
+    #[test]
+    fn test_success_envelope() {
+        let bytes = StandardMethodCodec::encode_success(&"hi".into());
+        assert_eq!(bytes, vec![0, 7, 2, b'h', b'i']);
+        assert_eq!(
+            StandardMethodCodec::decode_reply(&bytes).unwrap().unwrap(),
+            Value::from("hi")
+        );
+
+        let bytes = StandardMethodCodec::encode_success(&Value::F64(1.5));
+        let mut expected = vec![0, 6, 0, 0, 0, 0, 0, 0];
+        expected.extend_from_slice(&1.5f64.to_le_bytes());
+        assert_eq!(bytes, expected);
+        assert_eq!(
+            StandardMethodCodec::decode_reply(&bytes).unwrap().unwrap(),
+            Value::F64(1.5)
+        );
+    }
+
+    // Decodes error reply into its parts.
+    fn decode_error(bytes: &[u8]) -> (String, Option<String>, Value) {
+        match StandardMethodCodec::decode_reply(bytes) {
+            Ok(Err(PlatformError {
+                code,
+                message,
+                detail,
+            })) => (code, message, detail),
+            other => panic!("unexpected reply {:?}", other),
+        }
+    }
+
+    #[test]
+    fn test_error_envelope() {
+        let bytes = StandardMethodCodec::encode_error("E1", Some("msg"), &Value::Null);
+        assert_eq!(bytes, vec![1, 7, 2, b'E', b'1', 7, 3, b'm', b's', b'g', 0]);
+        let expected = ("E1".to_owned(), Some("msg".to_owned()), Value::Null);
+        assert_eq!(decode_error(&bytes), expected);
+
+        // Stack trace appended by Flutter is ignored.
+        let mut with_stack_trace = bytes;
+        with_stack_trace.extend_from_slice(&[7, 1, b's']);
+        assert_eq!(decode_error(&with_stack_trace), expected);
+
+        let bytes = StandardMethodCodec::encode_error("E2", None, &1.into());
+        assert_eq!(bytes, vec![1, 7, 2, b'E', b'2', 0, 3, 1, 0, 0, 0]);
+        assert_eq!(decode_error(&bytes), ("E2".to_owned(), None, 1.into()));
+
+        assert!(matches!(
+            StandardMethodCodec::decode_reply(&[2]),
+            Err(DecodeError::Malformed)
+        ));
+    }
+
+    #[test]
+    fn test_malicious_input() {
+        // Deeply nested single item lists don't overflow the stack.
+        const DEPTH: usize = 100_000;
+        let mut bytes = [12, 1].repeat(DEPTH);
+        bytes.push(0);
+        let value = StandardMessageCodec::decode(&bytes).unwrap();
+        assert_eq!(value.max_depth(), DEPTH);
+
+        let options = DecodeOptions {
+            limits: DecodeLimits {
+                max_depth: Some(DEPTH - 1),
+                ..Default::default()
+            },
+            ..Default::default()
+        };
+        assert_eq!(
+            StandardMessageCodec::decode_with_options(&bytes, &options),
+            Err(DecodeError::LimitExceeded)
+        );
+        assert!(matches!(
+            StandardMethodCodec::decode_reply_with_options(&[&[0], &bytes[..]].concat(), &options),
+            Err(DecodeError::LimitExceeded)
+        ));
+
+        // Lengths exceeding remaining input are rejected up front.
+        for tag in [12, 13, 8, 11] {
+            assert_eq!(
+                StandardMessageCodec::decode(&[tag, 255, 255, 255, 255, 255, 0]),
+                Err(DecodeError::Malformed)
+            );
+        }
+        assert_eq!(
+            StandardMessageCodec::decode(&[13, 2, 0, 0, 0]),
+            Err(DecodeError::Malformed)
+        );
+    }
+}
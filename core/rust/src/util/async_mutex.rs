@@ -0,0 +1,199 @@
+use std::{
+    cell::UnsafeCell,
+    future::Future,
+    ops::{Deref, DerefMut},
+    pin::Pin,
+    sync::{Condvar, Mutex, MutexGuard},
+    task::{Context, Poll, Waker},
+};
+
+/// Mutex that can be shared between run loop and other threads. Unlike
+/// `std::sync::Mutex`, waiting for the lock on run loop thread doesn't block
+/// the thread; [`AsyncMutex::lock`] returns future that completes once the
+/// lock is acquired, so the run loop keeps processing other tasks in the
+/// meanwhile. Threads without run loop can use [`AsyncMutex::blocking_lock`].
+///
+/// Waiting tasks acquire the lock in the order in which they started waiting.
+pub struct AsyncMutex<T: ?Sized> {
+    state: Mutex<State>,
+    unlocked: Condvar,
+    value: UnsafeCell<T>,
+}
+
+#[derive(Default)]
+struct State {
+    locked: bool,
+    next_waiter: usize,
+    waiters: Vec<(usize, Waker)>,
+}
+
+impl State {
+    fn first_waker(&self) -> Option<Waker> {
+        self.waiters.first().map(|w| w.1.clone())
+    }
+}
+
+unsafe impl<T: ?Sized + Send> Send for AsyncMutex<T> {}
+unsafe impl<T: ?Sized + Send> Sync for AsyncMutex<T> {}
+
+impl<T> AsyncMutex<T> {
+    pub fn new(value: T) -> Self {
+        Self {
+            state: Mutex::new(State::default()),
+            unlocked: Condvar::new(),
+            value: UnsafeCell::new(value),
+        }
+    }
+
+    pub fn into_inner(self) -> T {
+        self.value.into_inner()
+    }
+}
+
+impl<T: ?Sized> AsyncMutex<T> {
+    /// Returns future that resolves to guard once the lock is acquired.
+    pub fn lock(&self) -> AsyncMutexLockFuture<'_, T> {
+        AsyncMutexLockFuture {
+            mutex: self,
+            waiter: None,
+        }
+    }
+
+    /// Acquires the lock, blocking current thread until it is available.
+    /// Must not be called on run loop thread.
+    pub fn blocking_lock(&self) -> AsyncMutexGuard<'_, T> {
+        let mut state = self.state();
+        while state.locked {
+            state = self.unlocked.wait(state).unwrap_or_else(|e| e.into_inner());
+        }
+        state.locked = true;
+        AsyncMutexGuard { mutex: self }
+    }
+
+    /// Acquires the lock if it is available.
+    pub fn try_lock(&self) -> Option<AsyncMutexGuard<'_, T>> {
+        let mut state = self.state();
+        if state.locked {
+            None
+        } else {
+            state.locked = true;
+            Some(AsyncMutexGuard { mutex: self })
+        }
+    }
+
+    pub fn get_mut(&mut self) -> &mut T {
+        self.value.get_mut()
+    }
+
+    fn state(&self) -> MutexGuard<'_, State> {
+        // State is never left inconsistent, poisoning can be ignored.
+        self.state.lock().unwrap_or_else(|e| e.into_inner())
+    }
+
+    fn unlock(&self) {
+        let waker = {
+            let mut state = self.state();
+            state.locked = false;
+            state.first_waker()
+        };
+        self.unlocked.notify_one();
+        // Wake outside of state lock in case the waker polls synchronously.
+        if let Some(waker) = waker {
+            waker.wake();
+        }
+    }
+}
+
+impl<T: Default> Default for AsyncMutex<T> {
+    fn default() -> Self {
+        Self::new(T::default())
+    }
+}
+
+/// Future returned by [`AsyncMutex::lock`].
+pub struct AsyncMutexLockFuture<'a, T: ?Sized> {
+    mutex: &'a AsyncMutex<T>,
+    waiter: Option<usize>,
+}
+
+impl<'a, T: ?Sized> Future for AsyncMutexLockFuture<'a, T> {
+    type Output = AsyncMutexGuard<'a, T>;
+
+    fn poll(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Output> {
+        let this = self.get_mut();
+        let mut state = this.mutex.state();
+        // Tasks that started waiting earlier go first.
+        let is_first = match this.waiter {
+            Some(waiter) => state.waiters.first().map(|w| w.0) == Some(waiter),
+            None => state.waiters.is_empty(),
+        };
+        if !state.locked && is_first {
+            state.locked = true;
+            if let Some(waiter) = this.waiter.take() {
+                state.waiters.retain(|w| w.0 != waiter);
+            }
+            return Poll::Ready(AsyncMutexGuard { mutex: this.mutex });
+        }
+        match this.waiter {
+            Some(waiter) => {
+                if let Some(w) = state.waiters.iter_mut().find(|w| w.0 == waiter) {
+                    w.1.clone_from(cx.waker());
+                }
+            }
+            None => {
+                let waiter = state.next_waiter;
+                state.next_waiter += 1;
+                state.waiters.push((waiter, cx.waker().clone()));
+                this.waiter = Some(waiter);
+            }
+        }
+        Poll::Pending
+    }
+}
+
+impl<T: ?Sized> Drop for AsyncMutexLockFuture<'_, T> {
+    fn drop(&mut self) {
+        if let Some(waiter) = self.waiter.take() {
+            let waker = {
+                let mut state = self.mutex.state();
+                state.waiters.retain(|w| w.0 != waiter);
+                // This waiter might have been woken up to take the lock.
+                if state.locked {
+                    None
+                } else {
+                    state.first_waker()
+                }
+            };
+            if let Some(waker) = waker {
+                waker.wake();
+            }
+        }
+    }
+}
+
+/// Releases the lock when dropped.
+pub struct AsyncMutexGuard<'a, T: ?Sized> {
+    mutex: &'a AsyncMutex<T>,
+}
+
+unsafe impl<T: ?Sized + Sync> Sync for AsyncMutexGuard<'_, T> {}
+
+impl<T: ?Sized> Deref for AsyncMutexGuard<'_, T> {
+    type Target = T;
+
+    fn deref(&self) -> &T {
+        unsafe { &*self.mutex.value.get() }
+    }
+}
+
+impl<T: ?Sized> DerefMut for AsyncMutexGuard<'_, T> {
+    fn deref_mut(&mut self) -> &mut T {
+        unsafe { &mut *self.mutex.value.get() }
+    }
+}
+
+impl<T: ?Sized> Drop for AsyncMutexGuard<'_, T> {
+    fn drop(&mut self) {
+        self.mutex.unlock();
+    }
+}
@@ -1,4 +1,4 @@
-use std::{cell::RefCell, rc::Rc, task::Poll};
+use std::{cell::RefCell, pin::Pin, rc::Rc, task::Poll};
 
 use futures::Future;
 
@@ -60,3 +60,72 @@ impl<T> Future for CompletableFuture<T> {
         }
     }
 }
+
+//
+// Racing two futures
+//
+
+/// Result of [`select2`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Either<A, B> {
+    Left(A),
+    Right(B),
+}
+
+/// Polls both futures and resolves with output of whichever completes first.
+/// The other future is dropped. If both are ready at the same time the first
+/// one wins.
+///
+/// Futures don't need to be `Send` or `Unpin`; the returned future is meant to
+/// be awaited from a task running on [`RunLoop`](crate::RunLoop).
+pub fn select2<A: Future, B: Future>(a: A, b: B) -> Select2<A, B> {
+    Select2 {
+        a: Box::pin(a),
+        b: Box::pin(b),
+    }
+}
+
+/// Future returned by [`select2`].
+pub struct Select2<A, B> {
+    a: Pin<Box<A>>,
+    b: Pin<Box<B>>,
+}
+
+impl<A: Future, B: Future> Future for Select2<A, B> {
+    type Output = Either<A::Output, B::Output>;
+
+    fn poll(mut self: Pin<&mut Self>, cx: &mut std::task::Context<'_>) -> Poll<Self::Output> {
+        if let Poll::Ready(value) = self.a.as_mut().poll(cx) {
+            return Poll::Ready(Either::Left(value));
+        }
+        if let Poll::Ready(value) = self.b.as_mut().poll(cx) {
+            return Poll::Ready(Either::Right(value));
+        }
+        Poll::Pending
+    }
+}
+
+/// Awaits two futures using [`select2`] and evaluates the branch of the one
+/// that completed first. Can only be used inside async code.
+///
+/// ```
+/// # use std::future::Future;
+/// async fn reply_or_cancel(
+///     reply: impl Future<Output = String>,
+///     cancelled: impl Future<Output = ()>,
+/// ) -> Option<String> {
+///     nativeshell_core::select2! {
+///         reply = reply => Some(reply),
+///         _ = cancelled => None,
+///     }
+/// }
+/// ```
+#[macro_export]
+macro_rules! select2 {
+    ($pa:pat = $a:expr => $ea:expr, $pb:pat = $b:expr => $eb:expr $(,)?) => {
+        match $crate::util::select2($a, $b).await {
+            $crate::util::Either::Left($pa) => $ea,
+            $crate::util::Either::Right($pb) => $eb,
+        }
+    };
+}
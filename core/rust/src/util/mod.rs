@@ -1,11 +1,15 @@
+mod async_mutex;
 mod black_box;
 mod blocking_variable;
 mod capsule;
 mod cell;
 mod future;
+mod task_local;
 
+pub use async_mutex::*;
 pub use black_box::*;
 pub use blocking_variable::*;
 pub use capsule::*;
 pub use cell::*;
 pub use future::*;
+pub use task_local::*;
@@ -0,0 +1,142 @@
+use std::{
+    cell::RefCell,
+    future::Future,
+    pin::Pin,
+    task::{Context, Poll},
+    thread::LocalKey,
+};
+
+/// Declares task-local keys of type [`TaskLocalKey`]. Value of a task-local
+/// is set for the duration of a future using [`TaskLocalKey::scope`] and can
+/// be accessed from anything running inside the future, including nested
+/// awaited futures.
+///
+/// ```
+/// nativeshell_core::task_local! {
+///     pub static TRACE_ID: String;
+/// }
+/// ```
+#[macro_export]
+macro_rules! task_local {
+    () => {};
+    ($(#[$attr:meta])* $vis:vis static $name:ident: $t:ty; $($rest:tt)*) => {
+        $(#[$attr])*
+        $vis static $name: $crate::util::TaskLocalKey<$t> = {
+            ::std::thread_local! {
+                static __KEY: ::std::cell::RefCell<::std::option::Option<$t>> =
+                    const { ::std::cell::RefCell::new(::std::option::Option::None) };
+            }
+            $crate::util::TaskLocalKey { inner: __KEY }
+        };
+        $crate::task_local!($($rest)*);
+    };
+}
+
+/// Key for task-local value. Declared using [`task_local!`](crate::task_local).
+pub struct TaskLocalKey<T: 'static> {
+    #[doc(hidden)]
+    pub inner: LocalKey<RefCell<Option<T>>>,
+}
+
+impl<T: 'static> TaskLocalKey<T> {
+    /// Returns future that makes `value` available to this key while `future`
+    /// is being polled. The value is dropped once the future completes.
+    pub fn scope<F: Future>(&'static self, value: T, future: F) -> TaskLocalFuture<T, F> {
+        TaskLocalFuture {
+            key: self,
+            slot: Some(value),
+            future: Some(Box::pin(future)),
+        }
+    }
+
+    /// Calls `f` with reference to the current value.
+    ///
+    /// Panics if called outside of [`TaskLocalKey::scope`].
+    pub fn with<R, F: FnOnce(&T) -> R>(&'static self, f: F) -> R {
+        self.inner.with(|value| {
+            let value = value.borrow();
+            f(value
+                .as_ref()
+                .expect("task-local value accessed outside of scope"))
+        })
+    }
+
+    /// Returns copy of the current value.
+    ///
+    /// Panics if called outside of [`TaskLocalKey::scope`].
+    pub fn get(&'static self) -> T
+    where
+        T: Clone,
+    {
+        self.with(|value| value.clone())
+    }
+
+    /// Returns copy of the current value or `None` if called outside of
+    /// [`TaskLocalKey::scope`].
+    pub fn try_get(&'static self) -> Option<T>
+    where
+        T: Clone,
+    {
+        self.inner.with(|value| value.borrow().clone())
+    }
+
+    // Moves the value from slot to thread local storage for the duration of `f`.
+    fn enter<R>(&'static self, slot: &mut Option<T>, f: impl FnOnce() -> R) -> R {
+        struct Guard<'a, T: 'static> {
+            key: &'static LocalKey<RefCell<Option<T>>>,
+            slot: &'a mut Option<T>,
+        }
+
+        impl<T: 'static> Drop for Guard<'_, T> {
+            fn drop(&mut self) {
+                self.key
+                    .with(|value| std::mem::swap(&mut *value.borrow_mut(), self.slot));
+            }
+        }
+
+        self.inner
+            .with(|value| std::mem::swap(&mut *value.borrow_mut(), slot));
+        let _guard = Guard {
+            key: &self.inner,
+            slot,
+        };
+        f()
+    }
+}
+
+/// Future returned by [`TaskLocalKey::scope`].
+pub struct TaskLocalFuture<T: 'static, F: Future> {
+    key: &'static TaskLocalKey<T>,
+    slot: Option<T>,
+    future: Option<Pin<Box<F>>>,
+}
+
+// Value is never pinned, future is boxed.
+impl<T: 'static, F: Future> Unpin for TaskLocalFuture<T, F> {}
+
+impl<T: 'static, F: Future> Future for TaskLocalFuture<T, F> {
+    type Output = F::Output;
+
+    fn poll(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Output> {
+        let this = self.get_mut();
+        let future = this
+            .future
+            .as_mut()
+            .expect("TaskLocalFuture polled after completion");
+        let res = this.key.enter(&mut this.slot, || future.as_mut().poll(cx));
+        if res.is_ready() {
+            this.future.take();
+            this.slot.take();
+        }
+        res
+    }
+}
+
+impl<T: 'static, F: Future> Drop for TaskLocalFuture<T, F> {
+    fn drop(&mut self) {
+        // Inner future may access the value while being dropped.
+        if let Some(future) = self.future.take() {
+            self.key.enter(&mut self.slot, || drop(future));
+        }
+    }
+}
@@ -1,15 +1,44 @@
-use std::{
-    any::TypeId, cmp::Ordering, collections::HashMap, convert::Infallible, fmt::Display,
-    hash::Hash, num::TryFromIntError, ops::Deref, sync::Arc,
+use core::{
+    any::TypeId,
+    cmp::Ordering,
+    convert::Infallible,
+    fmt::Display,
+    hash::Hash,
+    mem,
+    num::TryFromIntError,
+    ops::{Bound, Deref, RangeBounds},
 };
 
+use alloc::{
+    borrow::ToOwned,
+    boxed::Box,
+    format,
+    string::{String, ToString},
+    vec,
+    vec::Vec,
+};
+#[cfg(feature = "std")]
+use std::{collections::HashMap, sync::Arc};
+
+#[cfg(feature = "shared_buffer")]
+use crate::SharedBuffer;
+#[cfg(feature = "std")]
 use crate::{ffi::raw, FinalizableHandle};
+use crate::{DecodeError, StringDecoding};
 
-#[derive(Clone, Debug, PartialEq, PartialOrd)]
+// Clone, PartialEq, Debug and Drop are implemented without recursion so that
+// deeply nested values don't overflow the stack.
+#[derive(PartialOrd)]
 pub enum Value {
     Null,
     Bool(bool),
     I64(i64),
+    /// 32-bit integers keep their width on Rust side. Dart doesn't distinguish
+    /// integer widths so these are received as `int`; integers coming from
+    /// Dart are always `I64`. This is opt-in; `i32` and `u32` convert to `I64`,
+    /// use [`Value::i32`] and [`Value::u32`] to keep the width.
+    I32(i32),
+    U32(u32),
     F64(f64),
     String(String),
     I8List(Vec<i8>),
@@ -21,6 +50,13 @@ pub enum Value {
     I64List(Vec<i64>),
     F32List(Vec<f32>),
     F64List(Vec<f64>),
+    /// Compact list of booleans, one byte per element. On the wire this is sent as
+    /// `U8List` containing `0` and `1`, so on Dart side it is received as
+    /// `Uint8List`. Lists coming back from Dart will be `U8List`, which can still
+    /// be converted to `Vec<bool>`. This is opt-in; `Vec<bool>` converts to
+    /// `List` of `Bool` values, which Dart receives as `List<bool>`; use
+    /// [`Value::bool_list`] to create it.
+    BoolList(Vec<bool>),
     List(Vec<Value>),
     // Map is stored as a list of tuples. It can be converted from and into HashMap
     // if required. For usual flow (convert struct into value -> send to dart,
@@ -29,6 +65,7 @@ pub enum Value {
     Map(ValueTupleList),
 
     /// Special Dart objects. These can only be sent from Rust to Dart
+    #[cfg(feature = "std")]
     Dart(DartObject),
 
     /// Can only be send from Rust to Dart. On Dart side this will be a
@@ -39,9 +76,16 @@ pub enum Value {
     /// and it will always result in the same Dart object.
     ///
     /// If the [`FinalizableHandle`] has already finalized it will be received as `null`.
+    #[cfg(feature = "std")]
     FinalizableHandle(Arc<FinalizableHandle>),
+
+    /// Can only be sent from Rust to Dart. Dart receives a duplicate of the
+    /// shared memory handle, see [`SharedBuffer`] for details.
+    #[cfg(feature = "shared_buffer")]
+    SharedBuffer(Arc<SharedBuffer>),
 }
 
+#[cfg(feature = "std")]
 #[derive(Clone, Debug, PartialEq, Eq, PartialOrd, Hash)]
 pub enum DartObject {
     SendPort(raw::DartCObjectSendPort),
@@ -49,7 +93,7 @@ pub enum DartObject {
 }
 
 /// Wrapper for Value tuple that ensures that the underyling list is sorted
-#[derive(Clone, Debug, PartialEq, Eq, PartialOrd, Hash)]
+#[derive(Clone, Debug, Default, PartialEq, Eq, PartialOrd, Hash)]
 pub struct ValueTupleList(Vec<(Value, Value)>);
 
 impl Default for Value {
@@ -80,9 +124,21 @@ impl_from!(Value::F64, f32);
 impl_from!(Value::F64, f64);
 impl_from!(Value::String, String);
 impl_from!(Value::String, &str);
+impl_from!(Value::String, char);
 impl_from!(Value::Map, Vec<(Value, Value)>);
+#[cfg(feature = "std")]
 impl_from!(Value::Dart, DartObject);
+#[cfg(feature = "std")]
 impl_from!(Value::FinalizableHandle, Arc<FinalizableHandle>);
+#[cfg(feature = "shared_buffer")]
+impl_from!(Value::SharedBuffer, Arc<SharedBuffer>);
+
+#[cfg(feature = "shared_buffer")]
+impl From<SharedBuffer> for Value {
+    fn from(buffer: SharedBuffer) -> Self {
+        Value::SharedBuffer(Arc::new(buffer))
+    }
+}
 
 impl<T: Into<Value>> From<Option<T>> for Value {
     fn from(v: Option<T>) -> Self {
@@ -101,23 +157,23 @@ impl<T: Into<Value> + 'static> From<Vec<T>> for Value {
         // https://github.com/rust-lang/rust/issues/31844
         let type_id = TypeId::of::<T>();
         if type_id == TypeId::of::<i8>() {
-            Value::I8List(unsafe { std::mem::transmute(vec) })
+            Value::I8List(unsafe { core::mem::transmute(vec) })
         } else if type_id == TypeId::of::<u8>() {
-            Value::U8List(unsafe { std::mem::transmute(vec) })
+            Value::U8List(unsafe { core::mem::transmute(vec) })
         } else if type_id == TypeId::of::<i16>() {
-            Value::I16List(unsafe { std::mem::transmute(vec) })
+            Value::I16List(unsafe { core::mem::transmute(vec) })
         } else if type_id == TypeId::of::<u16>() {
-            Value::U16List(unsafe { std::mem::transmute(vec) })
+            Value::U16List(unsafe { core::mem::transmute(vec) })
         } else if type_id == TypeId::of::<i32>() {
-            Value::I32List(unsafe { std::mem::transmute(vec) })
+            Value::I32List(unsafe { core::mem::transmute(vec) })
         } else if type_id == TypeId::of::<u32>() {
-            Value::U32List(unsafe { std::mem::transmute(vec) })
+            Value::U32List(unsafe { core::mem::transmute(vec) })
         } else if type_id == TypeId::of::<i64>() {
-            Value::I64List(unsafe { std::mem::transmute(vec) })
+            Value::I64List(unsafe { core::mem::transmute(vec) })
         } else if type_id == TypeId::of::<f32>() {
-            Value::F32List(unsafe { std::mem::transmute(vec) })
+            Value::F32List(unsafe { core::mem::transmute(vec) })
         } else if type_id == TypeId::of::<f64>() {
-            Value::F64List(unsafe { std::mem::transmute(vec) })
+            Value::F64List(unsafe { core::mem::transmute(vec) })
         } else {
             Value::List(vec.into_iter().map(|v| v.into()).collect())
         }
@@ -132,6 +188,7 @@ impl From<()> for Value {
 
 // Allow converting any HashMap to Value as long as both key and value are
 // convertible to Value.
+#[cfg(feature = "std")]
 impl<K: Into<Value>, V: Into<Value>> From<HashMap<K, V>> for Value {
     fn from(map: HashMap<K, V>) -> Self {
         let values: Vec<(Value, Value)> =
@@ -146,10 +203,69 @@ pub enum TryFromError {
     IntConversionError,
     FloatConversionError,
     OtherError(String),
+    /// Conversion of value nested in a map or list failed. `path` locates the
+    /// value, i.e. `["items"][3]`.
+    InPath {
+        path: String,
+        error: Box<TryFromError>,
+    },
+}
+
+impl TryFromError {
+    /// Error for value that doesn't correspond to any variant of enum
+    /// `enum_name`. Used by [`value_enum!`](crate::value_enum).
+    pub fn unknown_variant(enum_name: &str, value: &Value) -> Self {
+        TryFromError::OtherError(format!("Unknown {} variant: {:?}", enum_name, value))
+    }
+
+    /// Prepends `segment` (map key or list index) to the path of this error.
+    /// Called by converters of container types when converting nested value
+    /// fails.
+    pub fn prepend_path<S: core::fmt::Debug>(self, segment: S) -> Self {
+        match self {
+            TryFromError::InPath { path, error } => TryFromError::InPath {
+                path: format!("[{:?}]{}", segment, path),
+                error,
+            },
+            error => TryFromError::InPath {
+                path: format!("[{:?}]", segment),
+                error: Box::new(error),
+            },
+        }
+    }
+
+    /// Like [`TryFromError::prepend_path`] with map key. String and integer
+    /// keys are formatted without the `Value` variant name.
+    pub fn prepend_key(self, key: &Value) -> Self {
+        match key {
+            Value::String(key) => self.prepend_path(key),
+            Value::I64(key) => self.prepend_path(key),
+            Value::I32(key) => self.prepend_path(key),
+            Value::U32(key) => self.prepend_path(key),
+            key => self.prepend_path(key),
+        }
+    }
+
+    /// Returns location of the value that failed to convert or empty string
+    /// if the error is not nested.
+    pub fn path(&self) -> &str {
+        match self {
+            TryFromError::InPath { path, .. } => path,
+            _ => "",
+        }
+    }
+
+    /// Returns the underlying error without path.
+    pub fn root_cause(&self) -> &TryFromError {
+        match self {
+            TryFromError::InPath { error, .. } => error,
+            error => error,
+        }
+    }
 }
 
 impl Display for TryFromError {
-    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
         match self {
             TryFromError::BadType => write!(f, "Could not convert value from unrelated type."),
             TryFromError::IntConversionError => {
@@ -161,10 +277,14 @@ impl Display for TryFromError {
             TryFromError::OtherError(str) => {
                 write!(f, "{}", str)
             }
+            TryFromError::InPath { path, error } => {
+                write!(f, "{} (at {})", error, path)
+            }
         }
     }
 }
 
+#[cfg(feature = "std")]
 impl std::error::Error for TryFromError {}
 
 impl From<TryFromIntError> for TryFromError {
@@ -179,13 +299,18 @@ impl From<Infallible> for TryFromError {
     }
 }
 
+// Value implements Drop, so payload can't be moved out of it; it is taken
+// (or cloned, for payloads without default) instead.
 macro_rules! impl_try_from {
     ($variant:path, $for_type:ty) => {
+        impl_try_from!($variant, $for_type, mem::take);
+    };
+    ($variant:path, $for_type:ty, $take:path) => {
         impl TryFrom<Value> for $for_type {
             type Error = TryFromError;
-            fn try_from(v: Value) -> Result<Self, Self::Error> {
-                match v {
-                    $variant(d) => Ok(d.into()),
+            fn try_from(mut v: Value) -> Result<Self, Self::Error> {
+                match &mut v {
+                    $variant(d) => Ok($take(d).into()),
                     _ => Err(TryFromError::BadType),
                 }
             }
@@ -193,14 +318,17 @@ macro_rules! impl_try_from {
     };
 }
 
-macro_rules! impl_try_from2 {
-    ($variant:path, $for_type:ty) => {
+// Integers can be extracted from any integer variant as long as the value fits.
+macro_rules! impl_try_from_int {
+    ($for_type:ty) => {
         impl TryFrom<Value> for $for_type {
             type Error = TryFromError;
             fn try_from(v: Value) -> Result<Self, Self::Error> {
                 use ::core::convert::TryInto;
                 match v {
-                    $variant(d) => Ok(d.try_into().map_err(TryFromError::from)?),
+                    Value::I64(d) => Ok(d.try_into().map_err(TryFromError::from)?),
+                    Value::I32(d) => Ok(d.try_into().map_err(TryFromError::from)?),
+                    Value::U32(d) => Ok(d.try_into().map_err(TryFromError::from)?),
                     _ => Err(TryFromError::BadType),
                 }
             }
@@ -220,39 +348,47 @@ impl TryFrom<Value> for () {
 }
 
 impl_try_from!(Value::Bool, bool);
-impl_try_from2!(Value::I64, u8);
-impl_try_from2!(Value::I64, i8);
-impl_try_from2!(Value::I64, u16);
-impl_try_from2!(Value::I64, i16);
-impl_try_from2!(Value::I64, i32);
-impl_try_from2!(Value::I64, u32);
-impl_try_from!(Value::I64, i64);
+impl_try_from_int!(u8);
+impl_try_from_int!(i8);
+impl_try_from_int!(u16);
+impl_try_from_int!(i16);
+impl_try_from_int!(i32);
+impl_try_from_int!(u32);
+impl_try_from_int!(i64);
 impl_try_from!(Value::F64, f64);
 impl_try_from!(Value::String, String);
 impl_try_from!(Value::Map, ValueTupleList);
 impl_try_from!(Value::Map, Vec<(Value, Value)>);
-impl_try_from!(Value::Dart, DartObject);
-impl_try_from!(Value::FinalizableHandle, Arc<FinalizableHandle>);
+#[cfg(feature = "std")]
+impl_try_from!(Value::Dart, DartObject, DartObject::clone);
+#[cfg(feature = "std")]
+impl_try_from!(
+    Value::FinalizableHandle,
+    Arc<FinalizableHandle>,
+    Arc::<FinalizableHandle>::clone
+);
+
+// Widening f32 to f64 is exact, so any f32 stored in Value::F64 can be
+// converted back without loss. Values that don't fit f32 exactly are refused.
+fn f64_to_f32_exact(v: f64) -> Option<f32> {
+    if v.is_nan() {
+        Some(f32::NAN)
+    } else {
+        let f = v as f32;
+        if (f as f64) != v {
+            None
+        } else {
+            Some(f)
+        }
+    }
+}
 
 impl TryFrom<Value> for f32 {
     type Error = TryFromError;
 
     fn try_from(value: Value) -> Result<Self, Self::Error> {
         match value {
-            Value::F64(v) => {
-                if v.is_nan() {
-                    Ok(f32::NAN)
-                } else if v.is_infinite() {
-                    Ok(f32::INFINITY)
-                } else {
-                    let f = v as f32;
-                    if (f as f64) != v {
-                        Err(Self::Error::FloatConversionError)
-                    } else {
-                        Ok(f)
-                    }
-                }
-            }
+            Value::F64(v) => f64_to_f32_exact(v).ok_or(Self::Error::FloatConversionError),
             _ => Err(Self::Error::BadType),
         }
     }
@@ -260,6 +396,7 @@ impl TryFrom<Value> for f32 {
 
 // Allow converting to any Kind of HashMap as long as key and value
 // are types that can be converted from Value.
+#[cfg(feature = "std")]
 impl<
         K: TryFrom<Value, Error = E1> + Eq + Hash,
         V: TryFrom<Value, Error = E2>,
@@ -269,15 +406,13 @@ impl<
 {
     type Error = TryFromError;
 
-    fn try_from(value: Value) -> Result<Self, Self::Error> {
-        match value {
-            Value::Map(map) => map
+    fn try_from(mut value: Value) -> Result<Self, Self::Error> {
+        match &mut value {
+            Value::Map(map) => mem::take(map)
                 .into_iter()
                 .map(|(k, v)| {
-                    Ok((
-                        k.try_into().map_err(|e: E1| e.into())?,
-                        v.try_into().map_err(|e: E2| e.into())?,
-                    ))
+                    let value = v.try_into().map_err(|e: E2| e.into().prepend_key(&k))?;
+                    Ok((k.try_into().map_err(|e: E1| e.into())?, value))
                 })
                 .collect(),
             _ => Err(TryFromError::BadType),
@@ -287,7 +422,7 @@ impl<
 
 fn try_extract<T: 'static, V: 'static>(list: Vec<T>) -> Result<Vec<V>, TryFromError> {
     if TypeId::of::<V>() == TypeId::of::<T>() {
-        Ok(unsafe { std::mem::transmute(list) })
+        Ok(unsafe { core::mem::transmute(list) })
     } else {
         Err(TryFromError::BadType)
     }
@@ -296,21 +431,26 @@ fn try_extract<T: 'static, V: 'static>(list: Vec<T>) -> Result<Vec<V>, TryFromEr
 impl<V: TryFrom<Value, Error = E> + 'static, E: Into<TryFromError>> TryFrom<Value> for Vec<V> {
     type Error = TryFromError;
 
-    fn try_from(value: Value) -> Result<Self, Self::Error> {
-        match value {
-            Value::List(list) => list
+    fn try_from(mut value: Value) -> Result<Self, Self::Error> {
+        match &mut value {
+            Value::List(list) => mem::take(list)
                 .into_iter()
-                .map(|v| v.try_into().map_err(|e: E| e.into()))
+                .enumerate()
+                .map(|(i, v)| v.try_into().map_err(|e: E| e.into().prepend_path(i)))
                 .collect(),
-            Value::I8List(list) => try_extract(list),
-            Value::U8List(list) => try_extract(list),
-            Value::I16List(list) => try_extract(list),
-            Value::U16List(list) => try_extract(list),
-            Value::I32List(list) => try_extract(list),
-            Value::U32List(list) => try_extract(list),
-            Value::I64List(list) => try_extract(list),
-            Value::F32List(list) => try_extract(list),
-            Value::F64List(list) => try_extract(list),
+            Value::I8List(list) => try_extract(mem::take(list)),
+            Value::U8List(list) if TypeId::of::<V>() == TypeId::of::<bool>() => {
+                try_extract(list.iter().map(|v| *v != 0).collect::<Vec<bool>>())
+            }
+            Value::U8List(list) => try_extract(mem::take(list)),
+            Value::I16List(list) => try_extract(mem::take(list)),
+            Value::U16List(list) => try_extract(mem::take(list)),
+            Value::I32List(list) => try_extract(mem::take(list)),
+            Value::U32List(list) => try_extract(mem::take(list)),
+            Value::I64List(list) => try_extract(mem::take(list)),
+            Value::F32List(list) => try_extract(mem::take(list)),
+            Value::F64List(list) => try_extract(mem::take(list)),
+            Value::BoolList(list) => try_extract(mem::take(list)),
             _ => Err(TryFromError::BadType),
         }
     }
@@ -318,14 +458,14 @@ impl<V: TryFrom<Value, Error = E> + 'static, E: Into<TryFromError>> TryFrom<Valu
 
 impl Eq for Value {}
 
-fn hash_f64<H: std::hash::Hasher>(value: f64, state: &mut H) {
+fn hash_f64<H: core::hash::Hasher>(value: f64, state: &mut H) {
     // normalize NAN
     let value: f64 = if value.is_nan() { f64::NAN } else { value };
     let transmuted: u64 = value.to_bits();
     state.write_u64(transmuted);
 }
 
-fn hash_f32<H: std::hash::Hasher>(value: f32, state: &mut H) {
+fn hash_f32<H: core::hash::Hasher>(value: f32, state: &mut H) {
     // normalize NAN
     let value: f32 = if value.is_nan() { f32::NAN } else { value };
     let transmuted: u32 = value.to_bits();
@@ -333,31 +473,1398 @@ fn hash_f32<H: std::hash::Hasher>(value: f32, state: &mut H) {
 }
 
 #[allow(clippy::derive_hash_xor_eq)]
-impl std::hash::Hash for Value {
-    fn hash<H: std::hash::Hasher>(&self, state: &mut H) {
+impl core::hash::Hash for Value {
+    // Lists and maps are traversed using explicit stack, so that hashing deeply
+    // nested value doesn't overflow native stack.
+    fn hash<H: core::hash::Hasher>(&self, state: &mut H) {
+        let mut stack = Vec::new();
+        let mut next = Some(self);
+        while let Some(value) = next.take().or_else(|| stack.pop()) {
+            match value {
+                Value::Null => state.write_u64(640),
+                Value::Bool(v) => v.hash(state),
+                Value::I64(v) => v.hash(state),
+                Value::I32(v) => v.hash(state),
+                Value::U32(v) => v.hash(state),
+                Value::F64(v) => hash_f64(*v, state),
+                Value::String(v) => v.hash(state),
+                Value::I8List(v) => v.hash(state),
+                Value::U8List(v) => v.hash(state),
+                Value::I16List(v) => v.hash(state),
+                Value::U16List(v) => v.hash(state),
+                Value::I32List(v) => v.hash(state),
+                Value::U32List(v) => v.hash(state),
+                Value::I64List(v) => v.hash(state),
+                Value::F32List(v) => v.iter().for_each(|x| hash_f32(*x, state)),
+                Value::F64List(v) => v.iter().for_each(|x| hash_f64(*x, state)),
+                Value::BoolList(v) => v.hash(state),
+                Value::List(v) => {
+                    state.write_usize(v.len());
+                    stack.extend(v.iter().rev());
+                }
+                Value::Map(v) => {
+                    state.write_usize(v.len());
+                    v.iter().rev().for_each(|e| {
+                        stack.push(&e.1);
+                        stack.push(&e.0);
+                    });
+                }
+                #[cfg(feature = "std")]
+                Value::Dart(v) => v.hash(state),
+                #[cfg(feature = "std")]
+                Value::FinalizableHandle(v) => v.hash(state),
+                #[cfg(feature = "shared_buffer")]
+                Value::SharedBuffer(v) => v.hash(state),
+            }
+        }
+    }
+}
+
+impl Value {
+    // Children of list or map, map entries having key and value as consecutive
+    // children.
+    fn child(&self, index: usize) -> Option<&Value> {
+        match self {
+            Value::List(list) => list.get(index),
+            Value::Map(map) => map
+                .get(index / 2)
+                .map(|e| if index % 2 == 0 { &e.0 } else { &e.1 }),
+            _ => None,
+        }
+    }
+
+    fn has_children(&self) -> bool {
+        match self {
+            Value::List(list) => !list.is_empty(),
+            Value::Map(map) => !map.is_empty(),
+            _ => false,
+        }
+    }
+
+    // Clones value other than list or map, or empty list or map.
+    fn clone_single(&self) -> Value {
+        match self {
+            Value::Null => Value::Null,
+            Value::Bool(v) => Value::Bool(*v),
+            Value::I64(v) => Value::I64(*v),
+            Value::I32(v) => Value::I32(*v),
+            Value::U32(v) => Value::U32(*v),
+            Value::F64(v) => Value::F64(*v),
+            Value::String(v) => Value::String(v.clone()),
+            Value::I8List(v) => Value::I8List(v.clone()),
+            Value::U8List(v) => Value::U8List(v.clone()),
+            Value::I16List(v) => Value::I16List(v.clone()),
+            Value::U16List(v) => Value::U16List(v.clone()),
+            Value::I32List(v) => Value::I32List(v.clone()),
+            Value::U32List(v) => Value::U32List(v.clone()),
+            Value::I64List(v) => Value::I64List(v.clone()),
+            Value::F32List(v) => Value::F32List(v.clone()),
+            Value::F64List(v) => Value::F64List(v.clone()),
+            Value::BoolList(v) => Value::BoolList(v.clone()),
+            Value::List(_) => Value::List(Vec::new()),
+            Value::Map(_) => Value::Map(ValueTupleList::default()),
+            #[cfg(feature = "std")]
+            Value::Dart(v) => Value::Dart(v.clone()),
+            #[cfg(feature = "std")]
+            Value::FinalizableHandle(v) => Value::FinalizableHandle(v.clone()),
+            #[cfg(feature = "shared_buffer")]
+            Value::SharedBuffer(v) => Value::SharedBuffer(v.clone()),
+        }
+    }
+
+    // Compares values without looking at children of lists and maps.
+    fn eq_single(&self, other: &Value) -> bool {
+        match (self, other) {
+            (Value::Null, Value::Null) => true,
+            (Value::Bool(a), Value::Bool(b)) => a == b,
+            (Value::I64(a), Value::I64(b)) => a == b,
+            (Value::I32(a), Value::I32(b)) => a == b,
+            (Value::U32(a), Value::U32(b)) => a == b,
+            (Value::F64(a), Value::F64(b)) => a == b,
+            (Value::String(a), Value::String(b)) => a == b,
+            (Value::I8List(a), Value::I8List(b)) => a == b,
+            (Value::U8List(a), Value::U8List(b)) => a == b,
+            (Value::I16List(a), Value::I16List(b)) => a == b,
+            (Value::U16List(a), Value::U16List(b)) => a == b,
+            (Value::I32List(a), Value::I32List(b)) => a == b,
+            (Value::U32List(a), Value::U32List(b)) => a == b,
+            (Value::I64List(a), Value::I64List(b)) => a == b,
+            (Value::F32List(a), Value::F32List(b)) => a == b,
+            (Value::F64List(a), Value::F64List(b)) => a == b,
+            (Value::BoolList(a), Value::BoolList(b)) => a == b,
+            (Value::List(a), Value::List(b)) => a.len() == b.len(),
+            (Value::Map(a), Value::Map(b)) => a.len() == b.len(),
+            #[cfg(feature = "std")]
+            (Value::Dart(a), Value::Dart(b)) => a == b,
+            #[cfg(feature = "std")]
+            (Value::FinalizableHandle(a), Value::FinalizableHandle(b)) => a == b,
+            #[cfg(feature = "shared_buffer")]
+            (Value::SharedBuffer(a), Value::SharedBuffer(b)) => a == b,
+            _ => false,
+        }
+    }
+}
+
+impl Clone for Value {
+    fn clone(&self) -> Self {
+        // Lists and maps being cloned along with their already cloned children.
+        let mut stack = Vec::<(&Value, Vec<Value>)>::new();
+        let mut next = self;
+        loop {
+            let mut value = if next.has_children() {
+                stack.push((next, Vec::new()));
+                None
+            } else {
+                Some(next.clone_single())
+            };
+            // Store the value in its parent; repeat for parents that are complete.
+            loop {
+                let (source, children) = match stack.last_mut() {
+                    Some(frame) => frame,
+                    None => return value.unwrap(),
+                };
+                children.extend(value.take());
+                if let Some(child) = source.child(children.len()) {
+                    next = child;
+                    break;
+                }
+                let (source, children) = stack.pop().unwrap();
+                value = Some(match source {
+                    Value::List(_) => Value::List(children),
+                    _ => {
+                        let mut children = children.into_iter();
+                        let mut entries = Vec::with_capacity(children.len() / 2);
+                        while let (Some(k), Some(v)) = (children.next(), children.next()) {
+                            entries.push((k, v));
+                        }
+                        // Entries are already sorted.
+                        Value::Map(ValueTupleList(entries))
+                    }
+                });
+            }
+        }
+    }
+}
+
+impl PartialEq for Value {
+    fn eq(&self, other: &Self) -> bool {
+        let mut stack = vec![(self, other)];
+        while let Some((a, b)) = stack.pop() {
+            if !a.eq_single(b) {
+                return false;
+            }
+            let mut index = 0;
+            while let (Some(a), Some(b)) = (a.child(index), b.child(index)) {
+                stack.push((a, b));
+                index += 1;
+            }
+        }
+        true
+    }
+}
+
+impl Drop for Value {
+    fn drop(&mut self) {
+        // Lists and maps are moved out of their parents before being dropped,
+        // so that dropping them doesn't recurse.
+        fn take_children(value: &mut Value, stack: &mut Vec<Value>) {
+            match value {
+                Value::List(list) => {
+                    stack.extend(list.drain(..).filter(Value::has_children));
+                }
+                Value::Map(map) => {
+                    for (k, v) in map.0.drain(..) {
+                        stack.extend([k, v].into_iter().filter(Value::has_children));
+                    }
+                }
+                _ => {}
+            }
+        }
+        if !self.has_children() {
+            return;
+        }
+        let mut stack = Vec::new();
+        take_children(self, &mut stack);
+        while let Some(mut value) = stack.pop() {
+            take_children(&mut value, &mut stack);
+        }
+    }
+}
+
+impl core::fmt::Debug for Value {
+    // Same output as derived implementation, including pretty printing.
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        use core::fmt::Write;
+
+        // Formats value other than list or map.
+        struct Single<'a>(&'a Value);
+
+        impl core::fmt::Debug for Single<'_> {
+            fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+                match self.0 {
+                    Value::Null => f.write_str("Null"),
+                    Value::Bool(v) => f.debug_tuple("Bool").field(v).finish(),
+                    Value::I64(v) => f.debug_tuple("I64").field(v).finish(),
+                    Value::I32(v) => f.debug_tuple("I32").field(v).finish(),
+                    Value::U32(v) => f.debug_tuple("U32").field(v).finish(),
+                    Value::F64(v) => f.debug_tuple("F64").field(v).finish(),
+                    Value::String(v) => f.debug_tuple("String").field(v).finish(),
+                    Value::I8List(v) => f.debug_tuple("I8List").field(v).finish(),
+                    Value::U8List(v) => f.debug_tuple("U8List").field(v).finish(),
+                    Value::I16List(v) => f.debug_tuple("I16List").field(v).finish(),
+                    Value::U16List(v) => f.debug_tuple("U16List").field(v).finish(),
+                    Value::I32List(v) => f.debug_tuple("I32List").field(v).finish(),
+                    Value::U32List(v) => f.debug_tuple("U32List").field(v).finish(),
+                    Value::I64List(v) => f.debug_tuple("I64List").field(v).finish(),
+                    Value::F32List(v) => f.debug_tuple("F32List").field(v).finish(),
+                    Value::F64List(v) => f.debug_tuple("F64List").field(v).finish(),
+                    Value::BoolList(v) => f.debug_tuple("BoolList").field(v).finish(),
+                    Value::List(v) => f.debug_tuple("List").field(v).finish(),
+                    Value::Map(v) => f.debug_tuple("Map").field(v).finish(),
+                    #[cfg(feature = "std")]
+                    Value::Dart(v) => f.debug_tuple("Dart").field(v).finish(),
+                    #[cfg(feature = "std")]
+                    Value::FinalizableHandle(v) => {
+                        f.debug_tuple("FinalizableHandle").field(v).finish()
+                    }
+                    #[cfg(feature = "shared_buffer")]
+                    Value::SharedBuffer(v) => f.debug_tuple("SharedBuffer").field(v).finish(),
+                }
+            }
+        }
+
+        // Indents every line written through it when pretty printing.
+        struct Writer<'a, 'b> {
+            f: &'a mut core::fmt::Formatter<'b>,
+            indent: usize,
+            on_newline: bool,
+        }
+
+        impl Write for Writer<'_, '_> {
+            fn write_str(&mut self, s: &str) -> core::fmt::Result {
+                for line in s.split_inclusive('\n') {
+                    if self.on_newline {
+                        for _ in 0..self.indent {
+                            self.f.write_str("    ")?;
+                        }
+                    }
+                    self.on_newline = line.ends_with('\n');
+                    self.f.write_str(line)?;
+                }
+                Ok(())
+            }
+        }
+
+        // Opens and closes tuple (`name(...)`) or list (`[...]`).
+        fn open(w: &mut Writer, pretty: bool, prefix: &str) -> core::fmt::Result {
+            w.write_str(prefix)?;
+            if pretty {
+                w.write_str("\n")?;
+                w.indent += 1;
+            }
+            Ok(())
+        }
+
+        fn close(w: &mut Writer, pretty: bool, suffix: &str) -> core::fmt::Result {
+            if pretty {
+                w.indent -= 1;
+            }
+            w.write_str(suffix)
+        }
+
+        // Separates items of tuple or list.
+        fn item_end(w: &mut Writer, pretty: bool, last: bool) -> core::fmt::Result {
+            match (pretty, last) {
+                (true, _) => w.write_str(",\n"),
+                (false, false) => w.write_str(", "),
+                (false, true) => Ok(()),
+            }
+        }
+
+        let pretty = f.alternate();
+        let mut w = Writer {
+            f,
+            indent: 0,
+            on_newline: false,
+        };
+        // Lists and maps being written with index of next child.
+        let mut stack = Vec::<(&Value, usize)>::new();
+        let mut next = Some(self);
+        loop {
+            if let Some(value) = next.take() {
+                match value {
+                    Value::List(list) => {
+                        open(&mut w, pretty, "List(")?;
+                        open(&mut w, pretty && !list.is_empty(), "[")?;
+                        stack.push((value, 0));
+                    }
+                    Value::Map(map) => {
+                        open(&mut w, pretty, "Map(")?;
+                        open(&mut w, pretty, "ValueTupleList(")?;
+                        open(&mut w, pretty && !map.is_empty(), "[")?;
+                        stack.push((value, 0));
+                    }
+                    value => {
+                        if pretty {
+                            write!(w, "{:#?}", Single(value))?;
+                        } else {
+                            write!(w, "{:?}", Single(value))?;
+                        }
+                    }
+                }
+            }
+            let (value, index) = match stack.last_mut() {
+                Some(frame) => frame,
+                None => return Ok(()),
+            };
+            let is_map = matches!(value, Value::Map(_));
+            let len = match value {
+                Value::List(list) => list.len(),
+                Value::Map(map) => map.len() * 2,
+                _ => unreachable!(),
+            };
+            if *index > 0 {
+                // Child at `index - 1` has been written.
+                let last = *index == len;
+                if is_map && *index % 2 == 1 {
+                    item_end(&mut w, pretty, false)?;
+                } else if is_map {
+                    item_end(&mut w, pretty, true)?;
+                    close(&mut w, pretty, ")")?;
+                    item_end(&mut w, pretty, last)?;
+                } else {
+                    item_end(&mut w, pretty, last)?;
+                }
+            }
+            if *index < len {
+                if is_map && *index % 2 == 0 {
+                    open(&mut w, pretty, "(")?;
+                }
+                next = value.child(*index);
+                *index += 1;
+                continue;
+            }
+            stack.pop();
+            close(&mut w, pretty && len > 0, "]")?;
+            if is_map {
+                item_end(&mut w, pretty, true)?;
+                close(&mut w, pretty, ")")?;
+            }
+            item_end(&mut w, pretty, true)?;
+            close(&mut w, pretty, ")")?;
+        }
+    }
+}
+
+impl Value {
+    /// Creates [`Value::I32`]. Unlike `Value::from(v)`, which produces
+    /// `I64`, the value keeps its width when encoded.
+    pub fn i32(v: i32) -> Value {
+        Value::I32(v)
+    }
+
+    /// Creates [`Value::U32`]. Unlike `Value::from(v)`, which produces
+    /// `I64`, the value keeps its width when encoded.
+    pub fn u32(v: u32) -> Value {
+        Value::U32(v)
+    }
+
+    /// Creates [`Value::BoolList`]. Unlike `Value::from(v)`, which produces
+    /// `List` of `Bool` values, the list is stored and encoded compactly.
+    pub fn bool_list(v: Vec<bool>) -> Value {
+        Value::BoolList(v)
+    }
+
+    /// Returns value for given key if this is a map containing the key. Keys
+    /// are compared for equality, so any key type (i.e. integer or boolean)
+    /// can be used. Returns `None` for values other than map.
+    pub fn get_by(&self, key: &Value) -> Option<&Value> {
+        match self {
+            Value::Map(map) => map.iter().find(|e| &e.0 == key).map(|e| &e.1),
+            _ => None,
+        }
+    }
+
+    /// Returns the number as `f32` if this is `F64` value exactly representable
+    /// as `f32`. Any `f32` converted to `Value` (which widens it to `F64`)
+    /// round-trips through this method, including after being sent to Dart
+    /// as `double` and back. Returns `None` for other values.
+    pub fn as_f32(&self) -> Option<f32> {
+        match self {
+            Value::F64(v) => f64_to_f32_exact(*v),
+            _ => None,
+        }
+    }
+
+    /// Returns the character if value is a string consisting of exactly one
+    /// `char`. Grapheme clusters made of multiple code points (i.e. emoji with
+    /// modifiers) return `None`, as do other values.
+    pub fn as_char(&self) -> Option<char> {
+        match self {
+            Value::String(s) => {
+                let mut chars = s.chars();
+                match (chars.next(), chars.next()) {
+                    (Some(c), None) => Some(c),
+                    _ => None,
+                }
+            }
+            _ => None,
+        }
+    }
+
+    /// Convenience for [`Value::get_by`] with integer key. Matches `I32` and
+    /// `U32` keys with the same value as well.
+    pub fn get_i64_key(&self, key: i64) -> Option<&Value> {
+        match self {
+            Value::Map(map) => map
+                .iter()
+                .find(|e| match e.0 {
+                    Value::I64(k) => k == key,
+                    Value::I32(k) => k as i64 == key,
+                    Value::U32(k) => k as i64 == key,
+                    _ => false,
+                })
+                .map(|e| &e.1),
+            _ => None,
+        }
+    }
+
+    /// Returns number of elements of list or typed list, or number of entries
+    /// of map. Returns `None` for other values.
+    pub fn len(&self) -> Option<usize> {
+        match self {
+            Value::I8List(v) => Some(v.len()),
+            Value::U8List(v) => Some(v.len()),
+            Value::I16List(v) => Some(v.len()),
+            Value::U16List(v) => Some(v.len()),
+            Value::I32List(v) => Some(v.len()),
+            Value::U32List(v) => Some(v.len()),
+            Value::I64List(v) => Some(v.len()),
+            Value::F32List(v) => Some(v.len()),
+            Value::F64List(v) => Some(v.len()),
+            Value::BoolList(v) => Some(v.len()),
+            Value::List(v) => Some(v.len()),
+            Value::Map(v) => Some(v.len()),
+            _ => None,
+        }
+    }
+
+    /// Whether the value is an empty list, typed list or map. Returns `None`
+    /// for other values.
+    pub fn is_empty(&self) -> Option<bool> {
+        self.len().map(|len| len == 0)
+    }
+
+    /// Returns content of `U8List` or `I8List` (reinterpreted as `u8`) as bytes.
+    /// Returns `None` for other values.
+    pub fn as_bytes(&self) -> Option<&[u8]> {
+        match self {
+            Value::U8List(list) => Some(list),
+            Value::I8List(list) => {
+                Some(unsafe { core::slice::from_raw_parts(list.as_ptr() as *const u8, list.len()) })
+            }
+            _ => None,
+        }
+    }
+
+    /// Like [`Value::as_bytes`] but takes ownership of the list without copying.
+    /// Values other than `U8List` and `I8List` are returned back as error.
+    pub fn into_bytes(mut self) -> Result<Vec<u8>, Value> {
+        match &mut self {
+            Value::U8List(list) => Ok(mem::take(list)),
+            Value::I8List(list) => {
+                Ok(unsafe { mem::transmute::<Vec<i8>, Vec<u8>>(mem::take(list)) })
+            }
+            _ => Err(self),
+        }
+    }
+
+    /// Converts map with string keys to `HashMap`. Entries with keys other than
+    /// string are dropped if `drop_non_string_keys` is set, otherwise such
+    /// entry makes the conversion fail. Returns `None` for values other than
+    /// map. Converting `HashMap<String, Value>` back is done with [`Value::from`].
+    #[cfg(feature = "std")]
+    pub fn into_string_map(mut self, drop_non_string_keys: bool) -> Option<HashMap<String, Value>> {
+        match &mut self {
+            Value::Map(map) => {
+                let mut res = HashMap::with_capacity(map.len());
+                for (mut key, value) in mem::take(map) {
+                    match &mut key {
+                        Value::String(key) => {
+                            res.insert(mem::take(key), value);
+                        }
+                        _ if drop_non_string_keys => {}
+                        _ => return None,
+                    }
+                }
+                Some(res)
+            }
+            _ => None,
+        }
+    }
+
+    /// Converts map with string keys to list of entries in the order in which
+    /// they are stored in the map. Returns `None` for values other than map or
+    /// if any of the keys is not a string.
+    ///
+    /// Note that [`ValueTupleList`] keeps entries sorted by key, so the order
+    /// is the key order rather than the order in which entries were inserted.
+    /// The order is deterministic and is the same order in which the map is
+    /// encoded.
+    pub fn into_ordered_string_entries(mut self) -> Option<Vec<(String, Value)>> {
+        match &mut self {
+            Value::Map(map) => mem::take(map)
+                .into_iter()
+                .map(|(mut key, value)| match &mut key {
+                    Value::String(key) => Some((mem::take(key), value)),
+                    _ => None,
+                })
+                .collect(),
+            _ => None,
+        }
+    }
+
+    /// Creates map from string keyed entries, counterpart of
+    /// [`Value::into_ordered_string_entries`]. Like any map the entries are
+    /// sorted by key; order of `entries` is not preserved.
+    pub fn from_string_entries_sorted(entries: Vec<(String, Value)>) -> Value {
+        Value::Map(
+            entries
+                .into_iter()
+                .map(|(key, value)| (Value::String(key), value))
+                .collect::<Vec<_>>()
+                .into(),
+        )
+    }
+
+    /// Removes entries of map for which `f` returns `false`. Does nothing for
+    /// values other than map.
+    pub fn retain_map<F: FnMut(&Value, &Value) -> bool>(&mut self, mut f: F) {
+        if let Value::Map(map) = self {
+            map.0.retain(|e| f(&e.0, &e.1));
+        }
+    }
+
+    /// Removes items of list for which `f` returns `false`. Does nothing for
+    /// values other than list.
+    pub fn retain_list<F: FnMut(&Value) -> bool>(&mut self, f: F) {
+        if let Value::List(list) = self {
+            list.retain(f);
+        }
+    }
+
+    /// Replaces each entry of map with the entry returned by `f` or removes
+    /// it if `f` returns `None`. Does nothing for values other than map.
+    pub fn filter_map_map<F>(&mut self, f: F)
+    where
+        F: FnMut((Value, Value)) -> Option<(Value, Value)>,
+    {
+        if let Value::Map(map) = self {
+            let entries = core::mem::take(&mut map.0);
+            // Keys may have changed, `new` restores the ordering.
+            *map = ValueTupleList::new(entries.into_iter().filter_map(f).collect());
+        }
+    }
+
+    /// Replaces each item of list with the value returned by `f` or removes
+    /// it if `f` returns `None`. Does nothing for values other than list.
+    pub fn filter_map_list<F: FnMut(Value) -> Option<Value>>(&mut self, f: F) {
+        if let Value::List(list) = self {
+            *list = core::mem::take(list).into_iter().filter_map(f).collect();
+        }
+    }
+
+    /// Returns map with `mime` key set to content type and `data` key set to
+    /// `U8List` with the bytes. This is the convention for passing binary
+    /// data together with its type; read it back with
+    /// [`Value::as_tagged_binary`].
+    pub fn tagged_binary(mime: &str, bytes: Vec<u8>) -> Value {
+        Value::Map(
+            vec![
+                ("mime".into(), mime.into()),
+                ("data".into(), Value::U8List(bytes)),
+            ]
+            .into(),
+        )
+    }
+
+    /// Returns content type and bytes if this is a map created by
+    /// [`Value::tagged_binary`], i.e. map with exactly `mime` string and `data`
+    /// `U8List` entries. Returns `None` for other values.
+    pub fn as_tagged_binary(&self) -> Option<(&str, &[u8])> {
+        match self {
+            Value::Map(map) if map.len() == 2 => {
+                match (self.get_by(&"mime".into()), self.get_by(&"data".into())) {
+                    (Some(Value::String(mime)), Some(Value::U8List(data))) => Some((mime, data)),
+                    _ => None,
+                }
+            }
+            _ => None,
+        }
+    }
+
+    /// Returns element type and little-endian byte layout of typed list, as it
+    /// would appear in Dart `ByteData` with `Endian.little`. `BoolList` is
+    /// returned as `U8` list of `0` and `1`, same as it is sent to Dart.
+    /// Returns `None` for other values.
+    pub fn typed_list_bytes(&self) -> Option<(ElementType, Vec<u8>)> {
+        macro_rules! le_bytes {
+            ($list:expr) => {
+                $list.iter().flat_map(|v| v.to_le_bytes()).collect()
+            };
+        }
+        match self {
+            Value::I8List(list) => Some((ElementType::I8, le_bytes!(list))),
+            Value::U8List(list) => Some((ElementType::U8, list.clone())),
+            Value::I16List(list) => Some((ElementType::I16, le_bytes!(list))),
+            Value::U16List(list) => Some((ElementType::U16, le_bytes!(list))),
+            Value::I32List(list) => Some((ElementType::I32, le_bytes!(list))),
+            Value::U32List(list) => Some((ElementType::U32, le_bytes!(list))),
+            Value::I64List(list) => Some((ElementType::I64, le_bytes!(list))),
+            Value::F32List(list) => Some((ElementType::F32, le_bytes!(list))),
+            Value::F64List(list) => Some((ElementType::F64, le_bytes!(list))),
+            Value::BoolList(list) => {
+                Some((ElementType::U8, list.iter().map(|b| *b as u8).collect()))
+            }
+            _ => None,
+        }
+    }
+
+    /// Reverse of [`Value::typed_list_bytes`]. Creates typed list from
+    /// little-endian `bytes`. Returns `None` if length of `bytes` is not
+    /// multiple of element size.
+    pub fn from_typed_bytes(element_type: ElementType, bytes: &[u8]) -> Option<Value> {
+        macro_rules! from_le_bytes {
+            ($t:ty) => {
+                bytes
+                    .chunks_exact(core::mem::size_of::<$t>())
+                    .map(|c| <$t>::from_le_bytes(c.try_into().unwrap()))
+                    .collect()
+            };
+        }
+        if !bytes
+            .chunks_exact(element_type.size())
+            .remainder()
+            .is_empty()
+        {
+            return None;
+        }
+        let value = match element_type {
+            ElementType::I8 => Value::I8List(from_le_bytes!(i8)),
+            ElementType::U8 => Value::U8List(bytes.to_vec()),
+            ElementType::I16 => Value::I16List(from_le_bytes!(i16)),
+            ElementType::U16 => Value::U16List(from_le_bytes!(u16)),
+            ElementType::I32 => Value::I32List(from_le_bytes!(i32)),
+            ElementType::U32 => Value::U32List(from_le_bytes!(u32)),
+            ElementType::I64 => Value::I64List(from_le_bytes!(i64)),
+            ElementType::F32 => Value::F32List(from_le_bytes!(f32)),
+            ElementType::F64 => Value::F64List(from_le_bytes!(f64)),
+        };
+        Some(value)
+    }
+
+    /// Concatenates typed lists into single list of the same type. Returns
+    /// [`TryFromError::BadType`] if any of the values is not a typed list or
+    /// if the lists are not all of the same type. Empty `values` results in
+    /// empty `U8List`.
+    pub fn concat_typed_lists(values: Vec<Value>) -> Result<Value, TryFromError> {
+        let mut values = values.into_iter();
+        let mut res = match values.next() {
+            Some(value) if value.is_typed_list() => value,
+            Some(_) => return Err(TryFromError::BadType),
+            None => return Ok(Value::U8List(Vec::new())),
+        };
+        for mut value in values {
+            match (&mut res, &mut value) {
+                (Value::I8List(res), Value::I8List(list)) => res.append(list),
+                (Value::U8List(res), Value::U8List(list)) => res.append(list),
+                (Value::I16List(res), Value::I16List(list)) => res.append(list),
+                (Value::U16List(res), Value::U16List(list)) => res.append(list),
+                (Value::I32List(res), Value::I32List(list)) => res.append(list),
+                (Value::U32List(res), Value::U32List(list)) => res.append(list),
+                (Value::I64List(res), Value::I64List(list)) => res.append(list),
+                (Value::F32List(res), Value::F32List(list)) => res.append(list),
+                (Value::F64List(res), Value::F64List(list)) => res.append(list),
+                (Value::BoolList(res), Value::BoolList(list)) => res.append(list),
+                _ => return Err(TryFromError::BadType),
+            }
+        }
+        Ok(res)
+    }
+
+    /// Returns typed list of the same type containing elements in `range`.
+    /// Returns `None` if the value is not a typed list or the range is out of
+    /// bounds.
+    pub fn slice_typed_list<R: RangeBounds<usize>>(&self, range: R) -> Option<Value> {
+        let range: (Bound<usize>, Bound<usize>) =
+            (range.start_bound().cloned(), range.end_bound().cloned());
+        let value = match self {
+            Value::I8List(list) => Value::I8List(list.get(range)?.to_vec()),
+            Value::U8List(list) => Value::U8List(list.get(range)?.to_vec()),
+            Value::I16List(list) => Value::I16List(list.get(range)?.to_vec()),
+            Value::U16List(list) => Value::U16List(list.get(range)?.to_vec()),
+            Value::I32List(list) => Value::I32List(list.get(range)?.to_vec()),
+            Value::U32List(list) => Value::U32List(list.get(range)?.to_vec()),
+            Value::I64List(list) => Value::I64List(list.get(range)?.to_vec()),
+            Value::F32List(list) => Value::F32List(list.get(range)?.to_vec()),
+            Value::F64List(list) => Value::F64List(list.get(range)?.to_vec()),
+            Value::BoolList(list) => Value::BoolList(list.get(range)?.to_vec()),
+            _ => return None,
+        };
+        Some(value)
+    }
+
+    fn is_typed_list(&self) -> bool {
+        matches!(
+            self,
+            Value::I8List(_)
+                | Value::U8List(_)
+                | Value::I16List(_)
+                | Value::U16List(_)
+                | Value::I32List(_)
+                | Value::U32List(_)
+                | Value::I64List(_)
+                | Value::F32List(_)
+                | Value::F64List(_)
+                | Value::BoolList(_)
+        )
+    }
+
+    /// Converts UTF-8 encoded bytes to string. Depending on `decoding`, invalid
+    /// sequences either result in [`DecodeError::InvalidUtf8`] or are replaced with
+    /// `U+FFFD REPLACEMENT CHARACTER`.
+    pub fn validate_utf8(bytes: Vec<u8>, decoding: StringDecoding) -> Result<String, DecodeError> {
+        match String::from_utf8(bytes) {
+            Ok(string) => Ok(string),
+            Err(err) => match decoding {
+                StringDecoding::Strict => Err(DecodeError::InvalidUtf8),
+                StringDecoding::Lossy => Ok(String::from_utf8_lossy(err.as_bytes()).into_owned()),
+            },
+        }
+    }
+
+    /// Replaces string map keys with result of `parser`. Keys for which the parser
+    /// returns `None` are kept as they are. Applied recursively to nested maps and
+    /// lists. Useful to restore non-string keys of maps that went through JSON,
+    /// i.e. `value.coerce_map_keys(|k| k.parse().ok().map(Value::I64))`.
+    pub fn coerce_map_keys(self, parser: impl Fn(&str) -> Option<Value>) -> Value {
+        self.coerce_map_keys_with(&parser)
+    }
+
+    fn coerce_map_keys_with(mut self, parser: &dyn Fn(&str) -> Option<Value>) -> Value {
+        match &mut self {
+            Value::Map(map) => {
+                let entries: Vec<(Value, Value)> = mem::take(map)
+                    .into_iter()
+                    .map(|(key, value)| {
+                        let key = match &key {
+                            Value::String(string) => parser(string).unwrap_or(key),
+                            _ => key,
+                        };
+                        (key, value.coerce_map_keys_with(parser))
+                    })
+                    .collect();
+                Value::Map(entries.into())
+            }
+            Value::List(list) => Value::List(
+                mem::take(list)
+                    .into_iter()
+                    .map(|value| value.coerce_map_keys_with(parser))
+                    .collect(),
+            ),
+            _ => self,
+        }
+    }
+
+    /// Flattens nested maps and lists into `(key_path, value)` pairs. Path segments
+    /// are joined by `separator` and list items use their index as segment
+    /// (i.e. `a.0.b`). Maps with non-string keys, empty maps and empty lists are
+    /// kept as values. Flattening non-container value results in single pair with
+    /// empty key path.
+    ///
+    /// Keys containing the separator are not escaped, so they can not be told apart
+    /// from nested keys; [`Value::unflatten`] will turn them into nested maps.
+    pub fn flatten(&self, separator: &str) -> Vec<(String, Value)> {
+        let mut res = Vec::new();
+        self.flatten_into("", separator, &mut res);
+        res
+    }
+
+    fn flatten_into(&self, prefix: &str, separator: &str, res: &mut Vec<(String, Value)>) {
+        let path = |segment: &str| {
+            if prefix.is_empty() {
+                segment.to_owned()
+            } else {
+                format!("{}{}{}", prefix, separator, segment)
+            }
+        };
+        match self {
+            Value::Map(map)
+                if !map.is_empty() && map.iter().all(|e| matches!(e.0, Value::String(_))) =>
+            {
+                for (key, value) in map.iter() {
+                    if let Value::String(key) = key {
+                        value.flatten_into(&path(key), separator, res);
+                    }
+                }
+            }
+            Value::List(list) if !list.is_empty() => {
+                for (index, value) in list.iter().enumerate() {
+                    value.flatten_into(&path(&index.to_string()), separator, res);
+                }
+            }
+            value => res.push((prefix.into(), value.clone())),
+        }
+    }
+
+    /// Reverse of [`Value::flatten`]. Rebuilds nested value from `(key_path, value)`
+    /// pairs. Nodes where path segments are exactly `0..n` become lists, other
+    /// nodes become maps with string keys.
+    ///
+    /// If a key path is a prefix of another key path (i.e. `a` and `a.b`) the
+    /// later pair replaces the earlier one.
+    pub fn unflatten<I: IntoIterator<Item = (String, Value)>>(pairs: I, separator: &str) -> Value {
+        let mut root = FlatNode::Branch(Vec::new());
+        for (path, value) in pairs {
+            if path.is_empty() {
+                root = FlatNode::Leaf(value);
+                continue;
+            }
+            let mut node = &mut root;
+            for segment in path.split(separator) {
+                node = node.child(segment);
+            }
+            *node = FlatNode::Leaf(value);
+        }
+        root.into_value()
+    }
+
+    /// Returns nesting depth of lists and maps within the value, counted the
+    /// same way as [`DecodeLimits::max_depth`](crate::DecodeLimits::max_depth):
+    /// top level list or map has depth of 1, other values have depth of 0.
+    pub fn max_depth(&self) -> usize {
+        let mut max_depth = 0;
+        let mut stack = vec![(self, 0)];
+        while let Some((value, depth)) = stack.pop() {
+            match value {
+                Value::List(list) => {
+                    max_depth = max_depth.max(depth + 1);
+                    stack.extend(list.iter().map(|v| (v, depth + 1)));
+                }
+                Value::Map(map) => {
+                    max_depth = max_depth.max(depth + 1);
+                    for (k, v) in map.iter() {
+                        stack.push((k, depth + 1));
+                        stack.push((v, depth + 1));
+                    }
+                }
+                _ => {}
+            }
+        }
+        max_depth
+    }
+
+    /// Returns compact JSON representation of the value with stable output,
+    /// suitable for snapshot tests.
+    ///
+    /// * Map entries are ordered by key. Keys that are not strings are
+    ///   converted to their JSON representation (i.e. `1` becomes `"1"`).
+    /// * Floats use the shortest representation that round-trips (`1.0`,
+    ///   `0.1`, `1e100`). Non-finite floats become `"NaN"`, `"Infinity"` and
+    ///   `"-Infinity"` strings.
+    /// * Typed lists become JSON arrays.
+    /// * Values that can not be represented in JSON become sentinel strings:
+    ///   `"<finalizable_handle>"`, `"<dart_object>"` and `"<shared_buffer>"`.
+    pub fn to_canonical_json(&self) -> String {
+        let mut res = String::new();
+        self.write_canonical_json(&mut res);
+        res
+    }
+
+    fn write_canonical_json(&self, out: &mut String) {
+        fn write_list<T, F: Fn(&T, &mut String)>(list: &[T], out: &mut String, f: F) {
+            out.push('[');
+            for (i, item) in list.iter().enumerate() {
+                if i > 0 {
+                    out.push(',');
+                }
+                f(item, out);
+            }
+            out.push(']');
+        }
+        fn write_f64(value: f64, out: &mut String) {
+            if value.is_nan() {
+                out.push_str("\"NaN\"");
+            } else if value.is_infinite() {
+                out.push_str(if value > 0.0 {
+                    "\"Infinity\""
+                } else {
+                    "\"-Infinity\""
+                });
+            } else {
+                out.push_str(&format!("{:?}", value));
+            }
+        }
+        match self {
+            Value::Null => out.push_str("null"),
+            Value::Bool(v) => out.push_str(if *v { "true" } else { "false" }),
+            Value::I64(v) => out.push_str(&v.to_string()),
+            Value::I32(v) => out.push_str(&v.to_string()),
+            Value::U32(v) => out.push_str(&v.to_string()),
+            Value::F64(v) => write_f64(*v, out),
+            Value::String(v) => write_json_string(v, out),
+            Value::I8List(v) => write_list(v, out, |v, out| out.push_str(&v.to_string())),
+            Value::U8List(v) => write_list(v, out, |v, out| out.push_str(&v.to_string())),
+            Value::I16List(v) => write_list(v, out, |v, out| out.push_str(&v.to_string())),
+            Value::U16List(v) => write_list(v, out, |v, out| out.push_str(&v.to_string())),
+            Value::I32List(v) => write_list(v, out, |v, out| out.push_str(&v.to_string())),
+            Value::U32List(v) => write_list(v, out, |v, out| out.push_str(&v.to_string())),
+            Value::I64List(v) => write_list(v, out, |v, out| out.push_str(&v.to_string())),
+            Value::F32List(v) => write_list(v, out, |v, out| {
+                // Shortest representation of the f32, not of the widened f64.
+                if v.is_finite() {
+                    out.push_str(&format!("{:?}", v))
+                } else {
+                    write_f64(*v as f64, out)
+                }
+            }),
+            Value::F64List(v) => write_list(v, out, |v, out| write_f64(*v, out)),
+            Value::BoolList(v) => write_list(v, out, |v, out| {
+                out.push_str(if *v { "true" } else { "false" })
+            }),
+            Value::List(v) => write_list(v, out, |v, out| v.write_canonical_json(out)),
+            Value::Map(map) => {
+                let mut entries: Vec<(String, String)> = map
+                    .iter()
+                    .map(|(k, v)| {
+                        let key = match k {
+                            Value::String(key) => key.clone(),
+                            key => key.to_canonical_json(),
+                        };
+                        (key, v.to_canonical_json())
+                    })
+                    .collect();
+                // Values only matter if non-string keys collide with string keys.
+                entries.sort();
+                out.push('{');
+                for (i, (key, value)) in entries.iter().enumerate() {
+                    if i > 0 {
+                        out.push(',');
+                    }
+                    write_json_string(key, out);
+                    out.push(':');
+                    out.push_str(value);
+                }
+                out.push('}');
+            }
+            #[cfg(feature = "std")]
+            Value::Dart(_) => out.push_str("\"<dart_object>\""),
+            #[cfg(feature = "std")]
+            Value::FinalizableHandle(_) => out.push_str("\"<finalizable_handle>\""),
+            #[cfg(feature = "shared_buffer")]
+            Value::SharedBuffer(_) => out.push_str("\"<shared_buffer>\""),
+        }
+    }
+
+    /// Returns copy of the value suitable for logging. Strings and typed lists
+    /// are kept as long as their total size in bytes fits in `max_bytes`; the
+    /// ones that don't fit are replaced by `"<...truncated N bytes...>"` string,
+    /// where `N` is size of the replaced content. Lists and maps are preserved,
+    /// budget is consumed in depth-first order. Map keys and scalars are kept
+    /// and don't count towards the budget.
+    pub fn truncated(&self, max_bytes: usize) -> Value {
+        let mut remaining = max_bytes;
+        self.truncated_with(&mut remaining)
+    }
+
+    fn truncated_with(&self, remaining: &mut usize) -> Value {
+        let len = match self {
+            Value::List(list) => {
+                return Value::List(list.iter().map(|v| v.truncated_with(remaining)).collect())
+            }
+            Value::Map(map) => {
+                let entries: Vec<(Value, Value)> = map
+                    .iter()
+                    .map(|(k, v)| (k.clone(), v.truncated_with(remaining)))
+                    .collect();
+                return Value::Map(entries.into());
+            }
+            Value::String(s) => s.len(),
+            Value::I8List(list) => list.len(),
+            Value::U8List(list) => list.len(),
+            Value::I16List(list) => list.len() * 2,
+            Value::U16List(list) => list.len() * 2,
+            Value::I32List(list) => list.len() * 4,
+            Value::U32List(list) => list.len() * 4,
+            Value::I64List(list) => list.len() * 8,
+            Value::F32List(list) => list.len() * 4,
+            Value::F64List(list) => list.len() * 8,
+            Value::BoolList(list) => list.len(),
+            value => return value.clone(),
+        };
+        if len <= *remaining {
+            *remaining -= len;
+            self.clone()
+        } else {
+            Value::String(format!("<...truncated {} bytes...>", len))
+        }
+    }
+}
+
+/// Element type of typed list, see [`Value::typed_list_bytes`].
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum ElementType {
+    I8,
+    U8,
+    I16,
+    U16,
+    I32,
+    U32,
+    I64,
+    F32,
+    F64,
+}
+
+impl ElementType {
+    /// Size of single element in bytes.
+    pub fn size(&self) -> usize {
         match self {
-            Value::Null => state.write_u64(640),
-            Value::Bool(v) => v.hash(state),
-            Value::I64(v) => v.hash(state),
-            Value::F64(v) => hash_f64(*v, state),
-            Value::String(v) => v.hash(state),
-            Value::I8List(v) => v.hash(state),
-            Value::U8List(v) => v.hash(state),
-            Value::I16List(v) => v.hash(state),
-            Value::U16List(v) => v.hash(state),
-            Value::I32List(v) => v.hash(state),
-            Value::U32List(v) => v.hash(state),
-            Value::I64List(v) => v.hash(state),
-            Value::F32List(v) => v.iter().for_each(|x| hash_f32(*x, state)),
-            Value::F64List(v) => v.iter().for_each(|x| hash_f64(*x, state)),
-            Value::List(v) => v.hash(state),
-            Value::Map(v) => v.hash(state),
-            Value::Dart(v) => v.hash(state),
-            Value::FinalizableHandle(v) => v.hash(state),
+            ElementType::I8 | ElementType::U8 => 1,
+            ElementType::I16 | ElementType::U16 => 2,
+            ElementType::I32 | ElementType::U32 | ElementType::F32 => 4,
+            ElementType::I64 | ElementType::F64 => 8,
         }
     }
 }
 
+enum FlatNode {
+    Leaf(Value),
+    Branch(Vec<(String, FlatNode)>),
+}
+
+impl FlatNode {
+    fn child(&mut self, segment: &str) -> &mut FlatNode {
+        if let FlatNode::Leaf(_) = self {
+            *self = FlatNode::Branch(Vec::new());
+        }
+        match self {
+            FlatNode::Branch(children) => {
+                let index = match children.iter().position(|c| c.0 == segment) {
+                    Some(index) => index,
+                    None => {
+                        children.push((segment.into(), FlatNode::Branch(Vec::new())));
+                        children.len() - 1
+                    }
+                };
+                &mut children[index].1
+            }
+            FlatNode::Leaf(_) => unreachable!(),
+        }
+    }
+
+    fn into_value(self) -> Value {
+        match self {
+            FlatNode::Leaf(value) => value,
+            FlatNode::Branch(children) => {
+                let mut indices: Vec<Option<usize>> = children
+                    .iter()
+                    .map(|c| c.0.parse::<usize>().ok().filter(|i| i.to_string() == c.0))
+                    .collect();
+                indices.sort();
+                let is_list = !children.is_empty()
+                    && indices
+                        .iter()
+                        .enumerate()
+                        .all(|(i, index)| *index == Some(i));
+                if is_list {
+                    let mut children = children;
+                    children.sort_by_key(|c| c.0.parse::<usize>().unwrap());
+                    Value::List(children.into_iter().map(|c| c.1.into_value()).collect())
+                } else {
+                    let entries: Vec<(Value, Value)> = children
+                        .into_iter()
+                        .map(|c| (c.0.into(), c.1.into_value()))
+                        .collect();
+                    Value::Map(entries.into())
+                }
+            }
+        }
+    }
+}
+
+/// Single difference between two values, as reported by [`Value::differences`].
+#[derive(Clone, Debug, PartialEq)]
+pub struct ValueDifference {
+    /// Location of the difference within the value, i.e. `$["key"][1]`.
+    pub path: String,
+    /// Expected value; `None` if the actual value has unexpected item.
+    pub expected: Option<Value>,
+    /// Actual value; `None` if the actual value is missing item.
+    pub actual: Option<Value>,
+}
+
+impl Display for ValueDifference {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        match (&self.expected, &self.actual) {
+            (Some(expected), Some(actual)) => write!(
+                f,
+                "{}: expected {:?}, actual {:?}",
+                self.path, expected, actual
+            ),
+            (Some(expected), None) => write!(f, "{}: missing {:?}", self.path, expected),
+            (None, Some(actual)) => write!(f, "{}: unexpected {:?}", self.path, actual),
+            (None, None) => write!(f, "{}", self.path),
+        }
+    }
+}
+
+impl Value {
+    /// Returns structural differences between this (actual) value and expected
+    /// value. Lists and maps are compared item by item, everything else
+    /// (including typed lists) is compared as a whole. Returns empty vector if
+    /// values are equal.
+    pub fn differences(&self, expected: &Value) -> Vec<ValueDifference> {
+        let mut res = Vec::new();
+        self.differences_into("$".into(), expected, &mut res);
+        res
+    }
+
+    fn differences_into(&self, path: String, expected: &Value, res: &mut Vec<ValueDifference>) {
+        match (self, expected) {
+            (Value::List(actual), Value::List(expected)) => {
+                for i in 0..actual.len().max(expected.len()) {
+                    let path = format!("{}[{}]", path, i);
+                    match (actual.get(i), expected.get(i)) {
+                        (Some(a), Some(e)) => a.differences_into(path, e, res),
+                        (a, e) => res.push(ValueDifference {
+                            path,
+                            expected: e.cloned(),
+                            actual: a.cloned(),
+                        }),
+                    }
+                }
+            }
+            (Value::Map(actual), Value::Map(expected)) => {
+                let find = |map: &ValueTupleList, key: &Value| -> Option<Value> {
+                    map.iter().find(|e| &e.0 == key).map(|e| e.1.clone())
+                };
+                for (key, e) in expected.iter() {
+                    let path = format!("{}[{:?}]", path, KeyPath(key));
+                    match find(actual, key) {
+                        Some(a) => a.differences_into(path, e, res),
+                        None => res.push(ValueDifference {
+                            path,
+                            expected: Some(e.clone()),
+                            actual: None,
+                        }),
+                    }
+                }
+                for (key, a) in actual.iter() {
+                    if find(expected, key).is_none() {
+                        res.push(ValueDifference {
+                            path: format!("{}[{:?}]", path, KeyPath(key)),
+                            expected: None,
+                            actual: Some(a.clone()),
+                        });
+                    }
+                }
+            }
+            (actual, expected) => {
+                if actual != expected {
+                    res.push(ValueDifference {
+                        path,
+                        expected: Some(expected.clone()),
+                        actual: Some(actual.clone()),
+                    });
+                }
+            }
+        }
+    }
+
+    /// Compares values treating map entries with `Null` value as absent, so
+    /// `{"a": 1, "b": null}` equals `{"a": 1}`. Applies recursively to maps
+    /// nested in maps and lists. `Null` list items are not removed and `Null`
+    /// still differs from other values outside of map entries.
+    pub fn eq_ignoring_nulls(&self, other: &Value) -> bool {
+        match (self, other) {
+            (Value::List(a), Value::List(b)) => {
+                a.len() == b.len() && a.iter().zip(b.iter()).all(|(a, b)| a.eq_ignoring_nulls(b))
+            }
+            (Value::Map(a), Value::Map(b)) => {
+                // Both maps are sorted by key, so non-null entries can be
+                // compared pairwise.
+                let mut a = a.iter().filter(|e| e.1 != Value::Null);
+                let mut b = b.iter().filter(|e| e.1 != Value::Null);
+                loop {
+                    match (a.next(), b.next()) {
+                        (None, None) => return true,
+                        (Some(a), Some(b)) if a.0 == b.0 && a.1.eq_ignoring_nulls(&b.1) => {}
+                        _ => return false,
+                    }
+                }
+            }
+            (a, b) => a == b,
+        }
+    }
+}
+
+// Formats string map keys as `"key"` and other keys using their debug representation.
+struct KeyPath<'a>(&'a Value);
+
+impl core::fmt::Debug for KeyPath<'_> {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        match self.0 {
+            Value::String(s) => write!(f, "{:?}", s),
+            other => write!(f, "{:?}", other),
+        }
+    }
+}
+
+/// Asserts that two [`Value`]s are equal. On failure the panic message lists
+/// every structural difference (see [`Value::differences`]) instead of dumping
+/// both values.
+///
+/// ```
+/// use nativeshell_core::{assert_value_eq, Value};
+///
+/// let actual = Value::List(vec![1.into(), "a".into()]);
+/// assert_value_eq!(actual, Value::List(vec![1.into(), "a".into()]));
+/// ```
+///
+/// Failing assertion panics with message such as:
+///
+/// ```text
+/// assertion failed: `(actual == expected)`, 3 difference(s):
+///   $["a"][1]: expected I64(2), actual I64(3)
+///   $["b"]: missing String("x")
+///   $["c"]: unexpected Null
+/// ```
+#[macro_export]
+macro_rules! assert_value_eq {
+    ($actual:expr, $expected:expr $(,)?) => {{
+        let actual: &$crate::Value = &$actual;
+        let expected: &$crate::Value = &$expected;
+        let differences = actual.differences(expected);
+        if !differences.is_empty() {
+            let mut message = format!(
+                "assertion failed: `(actual == expected)`, {} difference(s):",
+                differences.len()
+            );
+            for difference in differences {
+                message.push_str(&format!("\n  {}", difference));
+            }
+            panic!("{}", message);
+        }
+    }};
+}
+
+/// Declares fieldless enum together with `From<Enum> for Value` and
+/// `TryFrom<Value> for Enum` conversions, mirroring how Dart serializes enums.
+///
+/// With `by index` the variant is represented by its discriminant as
+/// [`Value::I64`] (which matches Dart `Enum.index` unless discriminants are
+/// explicitly assigned). With `by name` it is represented by the variant name as
+/// [`Value::String`] (matching Dart `Enum.name`).
+///
+/// ```
+/// use nativeshell_core::{value_enum, Value};
+///
+/// value_enum! {
+///     #[derive(Debug, PartialEq)]
+///     pub enum Color by index { Red, Green, Blue }
+/// }
+///
+/// value_enum! {
+///     #[derive(Debug, PartialEq)]
+///     pub enum Shape by name { Circle, Square }
+/// }
+///
+/// assert_eq!(Value::from(Color::Blue), Value::I64(2));
+/// assert_eq!(Color::try_from(Value::I64(1)), Ok(Color::Green));
+/// assert_eq!(Value::from(Shape::Square), Value::String("Square".into()));
+/// assert_eq!(Shape::try_from(Value::from("Circle")), Ok(Shape::Circle));
+/// ```
+///
+/// Converting value of other type fails with [`TryFromError::BadType`], value
+/// that matches no variant fails with [`TryFromError::unknown_variant`].
+#[macro_export]
+macro_rules! value_enum {
+    (
+        $(#[$meta:meta])*
+        $vis:vis enum $name:ident by index {
+            $($variant:ident $(= $discriminant:expr)?),* $(,)?
+        }
+    ) => {
+        $(#[$meta])*
+        $vis enum $name {
+            $($variant $(= $discriminant)?),*
+        }
+
+        impl ::core::convert::From<$name> for $crate::Value {
+            fn from(value: $name) -> Self {
+                $crate::Value::I64(value as i64)
+            }
+        }
+
+        impl ::core::convert::TryFrom<$crate::Value> for $name {
+            type Error = $crate::TryFromError;
+
+            fn try_from(value: $crate::Value) -> ::core::result::Result<Self, Self::Error> {
+                match value {
+                    $(
+                        $crate::Value::I64(index) if index == $name::$variant as i64 => {
+                            Ok($name::$variant)
+                        }
+                    )*
+                    $crate::Value::I64(_) => Err($crate::TryFromError::unknown_variant(
+                        stringify!($name),
+                        &value,
+                    )),
+                    _ => Err($crate::TryFromError::BadType),
+                }
+            }
+        }
+    };
+    (
+        $(#[$meta:meta])*
+        $vis:vis enum $name:ident by name {
+            $($variant:ident $(= $discriminant:expr)?),* $(,)?
+        }
+    ) => {
+        $(#[$meta])*
+        $vis enum $name {
+            $($variant $(= $discriminant)?),*
+        }
+
+        impl ::core::convert::From<$name> for $crate::Value {
+            fn from(value: $name) -> Self {
+                match value {
+                    $($name::$variant => $crate::Value::from(stringify!($variant)),)*
+                }
+            }
+        }
+
+        impl ::core::convert::TryFrom<$crate::Value> for $name {
+            type Error = $crate::TryFromError;
+
+            fn try_from(value: $crate::Value) -> ::core::result::Result<Self, Self::Error> {
+                match &value {
+                    $(
+                        $crate::Value::String(name) if name == stringify!($variant) => {
+                            Ok($name::$variant)
+                        }
+                    )*
+                    $crate::Value::String(_) => Err($crate::TryFromError::unknown_variant(
+                        stringify!($name),
+                        &value,
+                    )),
+                    _ => Err($crate::TryFromError::BadType),
+                }
+            }
+        }
+    };
+}
+
 impl ValueTupleList {
     pub fn new(mut value: Vec<(Value, Value)>) -> Self {
         // Sort the list so tht hash and compares are deterministic
@@ -365,7 +1872,7 @@ impl ValueTupleList {
             .windows(2)
             .any(|w| w[0].0.partial_cmp(&w[1].0) != Some(Ordering::Less))
         {
-            value.sort_by(|a, b| a.0.partial_cmp(&b.0).unwrap_or(std::cmp::Ordering::Equal));
+            value.sort_by(|a, b| a.0.partial_cmp(&b.0).unwrap_or(core::cmp::Ordering::Equal));
         }
         Self(value)
     }
@@ -382,7 +1889,7 @@ impl Deref for ValueTupleList {
 impl IntoIterator for ValueTupleList {
     type Item = (Value, Value);
 
-    type IntoIter = std::vec::IntoIter<(Value, Value)>;
+    type IntoIter = alloc::vec::IntoIter<(Value, Value)>;
 
     fn into_iter(self) -> Self::IntoIter {
         self.0.into_iter()
@@ -395,6 +1902,7 @@ impl From<Vec<(Value, Value)>> for ValueTupleList {
     }
 }
 
+#[cfg(feature = "std")]
 impl From<HashMap<Value, Value>> for ValueTupleList {
     fn from(map: HashMap<Value, Value>) -> Self {
         let vec: Vec<_> = map.into_iter().collect();
@@ -408,12 +1916,14 @@ impl From<ValueTupleList> for Vec<(Value, Value)> {
     }
 }
 
+#[cfg(feature = "std")]
 impl From<ValueTupleList> for HashMap<Value, Value> {
     fn from(value: ValueTupleList) -> Self {
         value.into_iter().collect()
     }
 }
 
+#[cfg(feature = "std")]
 impl From<DartObject> for crate::ffi::DartValue {
     fn from(object: DartObject) -> Self {
         match object {
@@ -423,9 +1933,27 @@ impl From<DartObject> for crate::ffi::DartValue {
     }
 }
 
+fn write_json_string(value: &str, out: &mut String) {
+    out.push('"');
+    for c in value.chars() {
+        match c {
+            '"' => out.push_str("\\\""),
+            '\\' => out.push_str("\\\\"),
+            '\n' => out.push_str("\\n"),
+            '\r' => out.push_str("\\r"),
+            '\t' => out.push_str("\\t"),
+            c if (c as u32) < 0x20 => out.push_str(&format!("\\u{:04x}", c as u32)),
+            c => out.push(c),
+        }
+    }
+    out.push('"');
+}
+
 #[cfg(test)]
 mod tests {
-    use crate::{TryFromError, Value};
+    use std::collections::HashMap;
+
+    use crate::{ElementType, TryFromError, Value, ValueDifference};
 
     #[test]
     fn test_equality() {
@@ -434,6 +1962,547 @@ mod tests {
         assert_eq!(v1, v2);
     }
 
+    #[test]
+    fn test_debug() {
+        // Same output as derived implementation.
+        let value = Value::List(vec![
+            Value::Null,
+            Value::Map(
+                vec![
+                    ("a".into(), Value::I32List(vec![1, 2])),
+                    (1.into(), Value::List(vec![])),
+                ]
+                .into(),
+            ),
+            Value::Map(vec![].into()),
+            Value::List(vec![3.into()]),
+        ]);
+        assert_eq!(
+            format!("{:?}", value),
+            r#"List([Null, Map(ValueTupleList([(I64(1), List([])), (String("a"), I32List([1, 2]))])), Map(ValueTupleList([])), List([I64(3)])])"#
+        );
+        assert_eq!(
+            format!("{:#?}", value),
+            r#"List(
+    [
+        Null,
+        Map(
+            ValueTupleList(
+                [
+                    (
+                        I64(
+                            1,
+                        ),
+                        List(
+                            [],
+                        ),
+                    ),
+                    (
+                        String(
+                            "a",
+                        ),
+                        I32List(
+                            [
+                                1,
+                                2,
+                            ],
+                        ),
+                    ),
+                ],
+            ),
+        ),
+        Map(
+            ValueTupleList(
+                [],
+            ),
+        ),
+        List(
+            [
+                I64(
+                    3,
+                ),
+            ],
+        ),
+    ],
+)"#
+        );
+    }
+
+    #[test]
+    fn test_f32() {
+        for f in [
+            1.1f32,
+            -0.3,
+            f32::MAX,
+            f32::MIN_POSITIVE,
+            f32::INFINITY,
+            f32::NEG_INFINITY,
+        ] {
+            let value: Value = f.into();
+            assert_eq!(value, Value::F64(f as f64));
+            let decoded = Value::decode(&value.encode()).unwrap();
+            assert_eq!(decoded.as_f32(), Some(f));
+            let converted: f32 = decoded.try_into().unwrap();
+            assert_eq!(converted, f);
+        }
+        assert!(Value::from(f32::NAN).as_f32().unwrap().is_nan());
+
+        for f in [1.1f64, f64::MAX, 1e-50] {
+            let value = Value::F64(f);
+            assert_eq!(value.as_f32(), None);
+            let converted: Result<f32, _> = value.try_into();
+            assert_eq!(converted, Err(TryFromError::FloatConversionError));
+        }
+        assert_eq!(Value::I64(1).as_f32(), None);
+    }
+
+    crate::value_enum! {
+        #[derive(Debug, Clone, Copy, PartialEq)]
+        enum IndexEnum by index { First, Second, Tenth = 10 }
+    }
+
+    crate::value_enum! {
+        #[derive(Debug, Clone, Copy, PartialEq)]
+        enum NameEnum by name { First, Second }
+    }
+
+    #[test]
+    fn test_value_enum() {
+        for variant in [IndexEnum::First, IndexEnum::Second, IndexEnum::Tenth] {
+            let value = Value::from(variant);
+            assert_eq!(IndexEnum::try_from(value), Ok(variant));
+        }
+        assert_eq!(Value::from(IndexEnum::Second), Value::I64(1));
+        assert_eq!(Value::from(IndexEnum::Tenth), Value::I64(10));
+        assert_eq!(
+            IndexEnum::try_from(Value::I64(2)),
+            Err(TryFromError::OtherError(
+                "Unknown IndexEnum variant: I64(2)".into()
+            ))
+        );
+        assert_eq!(
+            IndexEnum::try_from(Value::from("First")),
+            Err(TryFromError::BadType)
+        );
+
+        for variant in [NameEnum::First, NameEnum::Second] {
+            let value = Value::from(variant);
+            assert_eq!(NameEnum::try_from(value), Ok(variant));
+        }
+        assert_eq!(Value::from(NameEnum::Second), Value::from("Second"));
+        assert_eq!(
+            NameEnum::try_from(Value::from("Third")),
+            Err(TryFromError::unknown_variant(
+                "NameEnum",
+                &Value::from("Third")
+            ))
+        );
+        assert_eq!(
+            NameEnum::try_from(Value::I64(0)),
+            Err(TryFromError::BadType)
+        );
+    }
+
+    #[test]
+    fn test_string_map() {
+        let mut map = HashMap::<String, Value>::new();
+        map.insert("a".into(), 1.into());
+        map.insert("b".into(), Value::List(vec!["x".into()]));
+        let value = Value::from(map.clone());
+        assert_eq!(value.get_by(&"a".into()), Some(&Value::I64(1)));
+        assert_eq!(value.clone().into_string_map(false), Some(map.clone()));
+        assert_eq!(value.into_string_map(true), Some(map.clone()));
+
+        // Non-string key either fails the conversion or is dropped.
+        let value = Value::Map(
+            vec![
+                ("a".into(), 1.into()),
+                (10.into(), "ten".into()),
+                ("b".into(), Value::List(vec!["x".into()])),
+            ]
+            .into(),
+        );
+        assert_eq!(value.clone().into_string_map(false), None);
+        assert_eq!(value.into_string_map(true), Some(map));
+
+        assert_eq!(Value::Null.into_string_map(true), None);
+    }
+
+    #[test]
+    fn test_char() {
+        let v: Value = 'a'.into();
+        assert_eq!(v, Value::String("a".into()));
+        assert_eq!(v.as_char(), Some('a'));
+        assert_eq!(Value::from('ž').as_char(), Some('ž'));
+
+        assert_eq!(Value::String("ab".into()).as_char(), None);
+        assert_eq!(Value::String("".into()).as_char(), None);
+        assert_eq!(Value::String("👍🏽".into()).as_char(), None);
+        assert_eq!(Value::I64(97).as_char(), None);
+    }
+
+    #[test]
+    fn test_i32_u32() {
+        assert_eq!(Value::from(-5i32), Value::I64(-5));
+        assert_eq!(Value::from(u32::MAX), Value::I64(u32::MAX as i64));
+        assert_ne!(Value::I32(5), Value::I64(5));
+
+        assert_eq!(i32::try_from(Value::I32(-5)), Ok(-5));
+        assert_eq!(i32::try_from(Value::I64(-5)), Ok(-5));
+        assert_eq!(u32::try_from(Value::U32(7)), Ok(7));
+        assert_eq!(i64::try_from(Value::U32(u32::MAX)), Ok(u32::MAX as i64));
+        assert_eq!(u8::try_from(Value::I32(200)), Ok(200));
+        assert!(matches!(
+            i32::try_from(Value::U32(u32::MAX)),
+            Err(TryFromError::IntConversionError)
+        ));
+        assert!(matches!(
+            u32::try_from(Value::I32(-1)),
+            Err(TryFromError::IntConversionError)
+        ));
+        assert_eq!(i32::try_from(Value::F64(1.0)), Err(TryFromError::BadType));
+
+        for value in [Value::I32(1), Value::I32(i32::MIN), Value::U32(200)] {
+            assert_eq!(Value::decode(&value.encode()), Ok(value.clone()));
+            assert_eq!(Value::decode(&value.canonical_encode()), Ok(value));
+        }
+
+        let map = Value::Map(vec![(Value::U32(3), "three".into())].into());
+        assert_eq!(map.get_i64_key(3), Some(&"three".into()));
+        assert_eq!(Value::I32(-3).to_canonical_json(), "-3");
+    }
+
+    #[test]
+    fn test_len() {
+        assert_eq!(Value::I8List(vec![1]).len(), Some(1));
+        assert_eq!(Value::U8List(vec![1, 2]).len(), Some(2));
+        assert_eq!(Value::I16List(vec![1, 2, 3]).len(), Some(3));
+        assert_eq!(Value::U16List(vec![1]).len(), Some(1));
+        assert_eq!(Value::I32List(vec![1, 2]).len(), Some(2));
+        assert_eq!(Value::U32List(vec![]).len(), Some(0));
+        assert_eq!(Value::I64List(vec![1]).len(), Some(1));
+        assert_eq!(Value::F32List(vec![1.0, 2.0]).len(), Some(2));
+        assert_eq!(Value::F64List(vec![1.0]).len(), Some(1));
+        assert_eq!(Value::BoolList(vec![true, false]).len(), Some(2));
+        assert_eq!(Value::List(vec![Value::Null; 4]).len(), Some(4));
+        let map = Value::Map(vec![("a".into(), 1.into()), ("b".into(), 2.into())].into());
+        assert_eq!(map.len(), Some(2));
+        assert_eq!(Value::I64(5).len(), None);
+        assert_eq!(Value::String("abc".into()).len(), None);
+
+        assert_eq!(Value::U32List(vec![]).is_empty(), Some(true));
+        assert_eq!(map.is_empty(), Some(false));
+        assert_eq!(Value::Null.is_empty(), None);
+    }
+
+    #[test]
+    fn test_canonical_json() {
+        let entries: Vec<(Value, Value)> = vec![
+            ("b".into(), 1.5.into()),
+            (
+                "a".into(),
+                Value::List(vec![Value::Null, true.into(), 0.1.into()]),
+            ),
+            ("c\"\n".into(), vec![1i32, 2].into()),
+            (3.into(), 1e100.into()),
+        ];
+        let mut reversed = entries.clone();
+        reversed.reverse();
+        let v1 = Value::Map(entries.into());
+        let v2 = Value::Map(reversed.into());
+        let expected = r#"{"3":1e100,"a":[null,true,0.1],"b":1.5,"c\"\n":[1,2]}"#;
+        assert_eq!(v1.to_canonical_json(), expected);
+        assert_eq!(v2.to_canonical_json(), expected);
+
+        assert_eq!(Value::F64(1.0).to_canonical_json(), "1.0");
+        assert_eq!(Value::F64(f64::NAN).to_canonical_json(), r#""NaN""#);
+        assert_eq!(Value::F32List(vec![0.1]).to_canonical_json(), "[0.1]");
+        assert_eq!(
+            Value::String("\u{1}".into()).to_canonical_json(),
+            r#""\u0001""#
+        );
+    }
+
+    #[test]
+    fn test_ordered_string_entries() {
+        let entries: Vec<(String, Value)> = vec![
+            ("alpha".into(), 1.into()),
+            ("beta".into(), Value::List(vec![2.into()])),
+            ("gamma".into(), Value::Null),
+        ];
+        let value = Value::from_string_entries_sorted(entries.clone());
+        assert_eq!(
+            value.clone().into_ordered_string_entries(),
+            Some(entries.clone())
+        );
+
+        // Same entries in different order result in same map and same entries.
+        let mut reversed = entries.clone();
+        reversed.reverse();
+        let value2 = Value::from_string_entries_sorted(reversed);
+        assert_eq!(value2, value);
+        assert_eq!(value2.into_ordered_string_entries(), Some(entries));
+
+        let value = Value::Map(vec![("a".into(), 1.into()), (2.into(), 3.into())].into());
+        assert_eq!(value.into_ordered_string_entries(), None);
+        assert_eq!(Value::Null.into_ordered_string_entries(), None);
+    }
+
+    #[test]
+    fn test_retain_and_filter_map() {
+        let mut map = Value::Map(
+            vec![
+                ("name".into(), "payload".into()),
+                ("comment".into(), Value::Null),
+                ("size".into(), 10.into()),
+                ("owner".into(), Value::Null),
+            ]
+            .into(),
+        );
+        map.retain_map(|_, v| *v != Value::Null);
+        assert_eq!(
+            map,
+            Value::Map(
+                vec![
+                    ("name".into(), "payload".into()),
+                    ("size".into(), 10.into())
+                ]
+                .into()
+            )
+        );
+
+        map.filter_map_map(|(k, v)| match &k {
+            Value::String(k) if k == "size" => None,
+            Value::String(k) => Some((format!("_{}", k).into(), v)),
+            _ => Some((k, v)),
+        });
+        assert_eq!(
+            map,
+            Value::Map(vec![("_name".into(), "payload".into())].into())
+        );
+
+        let mut list = Value::List((0..10i64).map(Value::from).collect());
+        list.retain_list(|v| matches!(v, Value::I64(v) if v % 2 == 0));
+        assert_eq!(
+            list,
+            Value::List(vec![0.into(), 2.into(), 4.into(), 6.into(), 8.into()])
+        );
+
+        list.filter_map_list(|v| match v {
+            Value::I64(v) if v > 2 => Some((v * 10).into()),
+            _ => None,
+        });
+        assert_eq!(list, Value::List(vec![40.into(), 60.into(), 80.into()]));
+
+        // Other values are left alone.
+        let mut value = Value::String("abc".into());
+        value.retain_list(|_| false);
+        value.retain_map(|_, _| false);
+        assert_eq!(value, Value::String("abc".into()));
+    }
+
+    #[test]
+    fn test_tagged_binary() {
+        let png = vec![0x89, b'P', b'N', b'G', 0x0D, 0x0A, 0x1A, 0x0A];
+        let value = Value::tagged_binary("image/png", png.clone());
+        assert_eq!(value.get_by(&"mime".into()), Some(&"image/png".into()));
+        assert_eq!(
+            value.as_tagged_binary(),
+            Some(("image/png", png.as_slice()))
+        );
+
+        let decoded = Value::decode(&value.encode()).unwrap();
+        assert_eq!(
+            decoded.as_tagged_binary(),
+            Some(("image/png", png.as_slice()))
+        );
+
+        assert_eq!(Value::U8List(png.clone()).as_tagged_binary(), None);
+        let untyped = Value::Map(vec![("data".into(), Value::U8List(png))].into());
+        assert_eq!(untyped.as_tagged_binary(), None);
+        let extra_key = Value::Map(
+            vec![
+                ("mime".into(), "image/png".into()),
+                ("data".into(), Value::U8List(Vec::new())),
+                ("name".into(), "icon".into()),
+            ]
+            .into(),
+        );
+        assert_eq!(extra_key.as_tagged_binary(), None);
+    }
+
+    #[test]
+    fn test_truncated() {
+        let value = Value::Map(
+            vec![
+                ("name".into(), "image".into()),
+                ("data".into(), Value::U8List(vec![0; 1048576])),
+                (
+                    "meta".into(),
+                    Value::List(vec![Value::I32List(vec![1, 2]), 10.into()]),
+                ),
+            ]
+            .into(),
+        );
+        let expected = Value::Map(
+            vec![
+                ("name".into(), "image".into()),
+                ("data".into(), "<...truncated 1048576 bytes...>".into()),
+                (
+                    "meta".into(),
+                    Value::List(vec![Value::I32List(vec![1, 2]), 10.into()]),
+                ),
+            ]
+            .into(),
+        );
+        assert_eq!(value.truncated(1024), expected);
+        assert_eq!(value.truncated(2 * 1048576), value);
+
+        // "name" uses the whole budget.
+        let truncated = value.truncated(5);
+        assert_eq!(
+            truncated.get_by(&"meta".into()),
+            Some(&Value::List(vec![
+                "<...truncated 8 bytes...>".into(),
+                10.into()
+            ]))
+        );
+    }
+
+    #[test]
+    fn test_from_option() {
+        assert_eq!(Value::from(Some("abc")), Value::String("abc".into()));
+        assert_eq!(Value::from(Some(10)), Value::I64(10));
+        assert_eq!(Value::from(None::<String>), Value::Null);
+        assert_eq!(Value::from(Some(Some(true))), Value::Bool(true));
+        assert_eq!(Value::from(Some(None::<bool>)), Value::Null);
+    }
+
+    #[test]
+    fn test_coerce_map_keys() {
+        let value = Value::Map(
+            vec![
+                ("1".into(), "one".into()),
+                ("name".into(), "x".into()),
+                (
+                    "items".into(),
+                    Value::List(vec![Value::Map(vec![("20".into(), true.into())].into())]),
+                ),
+            ]
+            .into(),
+        );
+        let coerced = value.coerce_map_keys(|k| k.parse().ok().map(Value::I64));
+        let expected = Value::Map(
+            vec![
+                (1.into(), "one".into()),
+                ("name".into(), "x".into()),
+                (
+                    "items".into(),
+                    Value::List(vec![Value::Map(vec![(20.into(), true.into())].into())]),
+                ),
+            ]
+            .into(),
+        );
+        assert_eq!(coerced, expected);
+        assert_eq!(coerced.get_i64_key(1), Some(&"one".into()));
+    }
+
+    #[test]
+    fn test_typed_list_bytes() {
+        let lists = [
+            Value::I8List(vec![-1, 2]),
+            Value::U8List(vec![1, 255]),
+            Value::I16List(vec![-300, 2]),
+            Value::U16List(vec![60000, 2]),
+            Value::I32List(vec![-70000, 2]),
+            Value::U32List(vec![4000000000, 2]),
+            Value::I64List(vec![-5000000000, 2]),
+            Value::F32List(vec![1.5, -2.25]),
+            Value::F64List(vec![1.5, -2.25]),
+        ];
+        for list in lists {
+            let (element_type, bytes) = list.typed_list_bytes().unwrap();
+            assert_eq!(bytes.len(), 2 * element_type.size());
+            assert_eq!(Value::from_typed_bytes(element_type, &bytes), Some(list));
+        }
+
+        let (element_type, bytes) = Value::I32List(vec![1, -2]).typed_list_bytes().unwrap();
+        assert_eq!(element_type, ElementType::I32);
+        assert_eq!(bytes, [1, 0, 0, 0, 0xFE, 0xFF, 0xFF, 0xFF]);
+
+        let (element_type, bytes) = Value::BoolList(vec![true, false])
+            .typed_list_bytes()
+            .unwrap();
+        assert_eq!(element_type, ElementType::U8);
+        assert_eq!(bytes, [1, 0]);
+
+        assert_eq!(Value::from_typed_bytes(ElementType::I32, &[1, 2, 3]), None);
+        assert_eq!(Value::List(vec![]).typed_list_bytes(), None);
+    }
+
+    #[test]
+    fn test_concat_slice_typed_lists() {
+        let chunks = vec![
+            Value::F32List(vec![1.0, 2.0]),
+            Value::F32List(vec![]),
+            Value::F32List(vec![3.0]),
+        ];
+        assert_eq!(
+            Value::concat_typed_lists(chunks),
+            Ok(Value::F32List(vec![1.0, 2.0, 3.0]))
+        );
+        assert_eq!(
+            Value::concat_typed_lists(vec![Value::F32List(vec![1.0]), Value::F64List(vec![2.0]),]),
+            Err(TryFromError::BadType)
+        );
+        assert_eq!(
+            Value::concat_typed_lists(vec![Value::Null]),
+            Err(TryFromError::BadType)
+        );
+
+        let list = Value::U8List(vec![1, 2, 3, 4, 5]);
+        assert_eq!(list.slice_typed_list(1..3), Some(Value::U8List(vec![2, 3])));
+        assert_eq!(list.slice_typed_list(3..), Some(Value::U8List(vec![4, 5])));
+        assert_eq!(list.slice_typed_list(..=0), Some(Value::U8List(vec![1])));
+        assert_eq!(list.slice_typed_list(4..6), None);
+        assert_eq!(Value::Null.slice_typed_list(..), None);
+    }
+
+    #[test]
+    fn test_as_bytes() {
+        let u8_list = Value::U8List(vec![1, 255]);
+        let i8_list = Value::I8List(vec![1, -1]);
+        assert_eq!(u8_list.as_bytes(), Some(&[1u8, 255][..]));
+        assert_eq!(i8_list.as_bytes(), Some(&[1u8, 255][..]));
+        assert_eq!(u8_list.into_bytes(), Ok(vec![1u8, 255]));
+        assert_eq!(i8_list.into_bytes(), Ok(vec![1u8, 255]));
+
+        let list = Value::List(vec![1.into(), 255.into()]);
+        assert_eq!(list.as_bytes(), None);
+        assert_eq!(list.clone().into_bytes(), Err(list));
+        assert_eq!(Value::I16List(vec![1]).as_bytes(), None);
+    }
+
+    #[test]
+    fn test_try_from_error_path() {
+        let value = Value::List(vec![
+            Value::Map(vec![("a".into(), 1.into())].into()),
+            Value::Map(vec![("a".into(), 2.into()), (5.into(), "x".into())].into()),
+        ]);
+        let res: Result<Vec<std::collections::HashMap<Value, i64>>, _> = value.try_into();
+        let err = res.unwrap_err();
+        assert_eq!(err.path(), "[1][5]");
+        assert_eq!(err.root_cause(), &TryFromError::BadType);
+        assert_eq!(
+            err,
+            TryFromError::InPath {
+                path: "[1][5]".into(),
+                error: Box::new(TryFromError::BadType),
+            }
+        );
+        assert_eq!(TryFromError::BadType.path(), "");
+    }
+
     #[test]
     fn test_from_list() {
         let v: Value = (vec![1i8]).into();
@@ -463,6 +2532,9 @@ mod tests {
         let v: Value = (vec![1f64]).into();
         assert_eq!(v, Value::F64List(vec![1.0]));
 
+        let v: Value = (vec![true, false]).into();
+        assert_eq!(v, Value::List(vec![true.into(), false.into()]));
+
         let v: Value = (vec![Value::I64(10)]).into();
         assert_eq!(v, Value::List(vec![Value::I64(10)]));
 
@@ -508,6 +2580,14 @@ mod tests {
         let r: Vec<f64> = v.try_into()?;
         assert_eq!(r, vec![1f64]);
 
+        let v = Value::BoolList(vec![true, false]);
+        let r: Vec<bool> = v.try_into()?;
+        assert_eq!(r, vec![true, false]);
+
+        let v = Value::U8List(vec![1, 0]);
+        let r: Vec<bool> = v.try_into()?;
+        assert_eq!(r, vec![true, false]);
+
         let v = Value::List(vec![Value::I64(10)]);
         let r: Vec<i64> = v.try_into()?;
         assert_eq!(r, vec![10i64]);
@@ -531,4 +2611,148 @@ mod tests {
 
         Ok(())
     }
+
+    #[test]
+    fn test_flatten() {
+        let v = Value::Map(
+            vec![
+                (
+                    "a".into(),
+                    Value::Map(
+                        vec![
+                            ("b".into(), 10.into()),
+                            (
+                                "c".into(),
+                                Value::List(vec![
+                                    "x".into(),
+                                    Value::Map(vec![("d".into(), true.into())].into()),
+                                ]),
+                            ),
+                        ]
+                        .into(),
+                    ),
+                ),
+                ("e".into(), Value::List(vec![])),
+                ("f".into(), Value::Null),
+            ]
+            .into(),
+        );
+        let flat = v.flatten(".");
+        assert_eq!(
+            flat,
+            vec![
+                ("a.b".to_owned(), 10.into()),
+                ("a.c.0".to_owned(), "x".into()),
+                ("a.c.1.d".to_owned(), true.into()),
+                ("e".to_owned(), Value::List(vec![])),
+                ("f".to_owned(), Value::Null),
+            ]
+        );
+        assert_eq!(Value::unflatten(flat, "."), v);
+
+        // Out of order list indices
+        let list = Value::List((0..12).map(Value::from).collect());
+        let mut flat = list.flatten("/");
+        flat.reverse();
+        assert_eq!(Value::unflatten(flat, "/"), list);
+
+        let v: Value = 10.into();
+        assert_eq!(v.flatten("."), vec![("".to_owned(), 10.into())]);
+        assert_eq!(Value::unflatten(v.flatten("."), "."), v);
+    }
+
+    #[test]
+    fn test_differences() {
+        let expected = Value::Map(
+            vec![
+                ("a".into(), Value::List(vec![1.into(), 2.into()])),
+                ("b".into(), "x".into()),
+            ]
+            .into(),
+        );
+        assert_value_eq!(expected.clone(), expected);
+
+        let actual = Value::Map(
+            vec![
+                ("a".into(), Value::List(vec![1.into(), 3.into(), 4.into()])),
+                ("c".into(), Value::Null),
+            ]
+            .into(),
+        );
+        let differences: Vec<String> = actual
+            .differences(&expected)
+            .iter()
+            .map(|d| d.to_string())
+            .collect();
+        assert_eq!(
+            differences,
+            vec![
+                "$[\"a\"][1]: expected I64(2), actual I64(3)",
+                "$[\"a\"][2]: unexpected I64(4)",
+                "$[\"b\"]: missing String(\"x\")",
+                "$[\"c\"]: unexpected Null",
+            ]
+        );
+
+        assert_value_eq!(Value::I8List(vec![1]), Value::I8List(vec![1]));
+        assert_eq!(
+            Value::I64(1).differences(&Value::F64(1.0)),
+            vec![ValueDifference {
+                path: "$".into(),
+                expected: Some(Value::F64(1.0)),
+                actual: Some(Value::I64(1)),
+            }]
+        );
+    }
+
+    #[test]
+    fn test_eq_ignoring_nulls() {
+        let with_null = Value::Map(vec![("a".into(), 1.into()), ("b".into(), Value::Null)].into());
+        let without = Value::Map(vec![("a".into(), 1.into())].into());
+        assert_ne!(with_null, without);
+        assert!(with_null.eq_ignoring_nulls(&without));
+        assert!(without.eq_ignoring_nulls(&with_null));
+
+        let nested = |inner: &Value| {
+            Value::Map(
+                vec![
+                    ("list".into(), Value::List(vec![inner.clone()])),
+                    ("map".into(), inner.clone()),
+                ]
+                .into(),
+            )
+        };
+        assert!(nested(&with_null).eq_ignoring_nulls(&nested(&without)));
+
+        let other = Value::Map(vec![("a".into(), 2.into()), ("b".into(), Value::Null)].into());
+        assert!(!other.eq_ignoring_nulls(&without));
+        let extra = Value::Map(vec![("a".into(), 1.into()), ("c".into(), 3.into())].into());
+        assert!(!extra.eq_ignoring_nulls(&with_null));
+
+        // Only map entries are ignored.
+        let list = Value::List(vec![1.into(), Value::Null]);
+        assert!(!list.eq_ignoring_nulls(&Value::List(vec![1.into()])));
+        assert!(!Value::Null.eq_ignoring_nulls(&without));
+    }
+
+    #[test]
+    fn test_get_by() {
+        let map = Value::Map(
+            vec![
+                (1.into(), "one".into()),
+                (true.into(), "true".into()),
+                ("1".into(), "string".into()),
+                (Value::Null, 10.into()),
+            ]
+            .into(),
+        );
+        assert_eq!(map.get_i64_key(1), Some(&"one".into()));
+        assert_eq!(map.get_by(&1.into()), Some(&"one".into()));
+        assert_eq!(map.get_by(&true.into()), Some(&"true".into()));
+        assert_eq!(map.get_by(&"1".into()), Some(&"string".into()));
+        assert_eq!(map.get_by(&Value::Null), Some(&10.into()));
+        assert_eq!(map.get_by(&false.into()), None);
+        assert_eq!(map.get_i64_key(2), None);
+        assert_eq!(Value::List(vec![1.into()]).get_i64_key(0), None);
+    }
 }
@@ -0,0 +1,128 @@
+use std::{
+    collections::{hash_map::DefaultHasher, HashMap},
+    hash::{Hash, Hasher},
+    sync::{Arc, Mutex, Weak},
+};
+
+use crate::Value;
+
+/// Cache that deduplicates structurally equal values. Interning a value
+/// returns shared `Arc<Value>`; as long as the returned instance is alive,
+/// interning an equal value returns the same instance.
+///
+/// The interner only holds weak references so it never keeps values alive.
+/// Entries of dropped values are removed lazily when their hash bucket is
+/// touched or during [`ValueInterner::purge`].
+#[derive(Default)]
+pub struct ValueInterner {
+    values: Mutex<HashMap<u64, Vec<Weak<Value>>>>,
+}
+
+impl ValueInterner {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Returns existing instance equal to `value` or stores `value` and
+    /// returns it.
+    pub fn intern(&self, value: Value) -> Arc<Value> {
+        let hash = Self::hash(&value);
+        let mut values = self.values.lock().unwrap();
+        let bucket = values.entry(hash).or_default();
+        let mut existing = None;
+        bucket.retain(|v| match v.upgrade() {
+            Some(v) => {
+                if existing.is_none() && *v == value {
+                    existing = Some(v);
+                }
+                true
+            }
+            None => false,
+        });
+        if let Some(existing) = existing {
+            return existing;
+        }
+        let value = Arc::new(value);
+        bucket.push(Arc::downgrade(&value));
+        value
+    }
+
+    /// Returns number of interned values that are still alive.
+    pub fn len(&self) -> usize {
+        let values = self.values.lock().unwrap();
+        values
+            .values()
+            .flatten()
+            .filter(|v| v.strong_count() > 0)
+            .count()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// Removes entries for values that have been dropped.
+    pub fn purge(&self) {
+        let mut values = self.values.lock().unwrap();
+        values.retain(|_, bucket| {
+            bucket.retain(|v| v.strong_count() > 0);
+            !bucket.is_empty()
+        });
+    }
+
+    fn hash(value: &Value) -> u64 {
+        let mut hasher = DefaultHasher::new();
+        value.hash(&mut hasher);
+        hasher.finish()
+    }
+}
+
+impl std::fmt::Debug for ValueInterner {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("ValueInterner")
+            .field("len", &self.len())
+            .finish()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+
+    use crate::Value;
+
+    use super::ValueInterner;
+
+    fn config() -> Value {
+        Value::Map(
+            vec![
+                ("name".into(), "config".into()),
+                ("values".into(), vec![1i64, 2, 3].into()),
+            ]
+            .into(),
+        )
+    }
+
+    #[test]
+    fn test_intern() {
+        let interner = ValueInterner::new();
+        let v1 = interner.intern(config());
+        let v2 = interner.intern(config());
+        assert!(Arc::ptr_eq(&v1, &v2));
+        assert_eq!(interner.len(), 1);
+
+        let v3 = interner.intern(Value::String("other".into()));
+        assert!(!Arc::ptr_eq(&v1, &v3));
+        assert_eq!(interner.len(), 2);
+
+        drop(v1);
+        drop(v2);
+        assert_eq!(interner.len(), 1);
+        interner.purge();
+        assert_eq!(interner.len(), 1);
+
+        let v4 = interner.intern(config());
+        assert_eq!(*v4, config());
+        assert_eq!(interner.len(), 2);
+    }
+}
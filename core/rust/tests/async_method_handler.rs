@@ -0,0 +1,101 @@
+#[cfg(feature = "mock")]
+mod tests {
+    use std::{cell::RefCell, rc::Rc, time::Duration};
+
+    use async_trait::async_trait;
+    use nativeshell_core::{
+        AsyncMethodHandler, Context, GetMessageChannel, MethodCall, MethodCallError, MockIsolate,
+        PlatformResult, ProgressReply, Value,
+    };
+
+    struct Handler {}
+
+    #[async_trait(?Send)]
+    impl AsyncMethodHandler for Handler {
+        async fn on_method_call(&self, _call: MethodCall) -> PlatformResult {
+            Ok(Value::Null)
+        }
+
+        async fn on_method_call_with_progress(
+            &self,
+            call: MethodCall,
+            progress: ProgressReply,
+        ) -> PlatformResult {
+            progress.report(0.5).unwrap();
+            Context::get()
+                .run_loop()
+                .wait(Duration::from_millis(1))
+                .await;
+            progress.report(1.0).unwrap();
+            Ok(call.args)
+        }
+    }
+
+    #[test]
+    fn test_progress() {
+        Context::run_test(async {
+            let _handler = Handler {}.register("files");
+            let received = Rc::new(RefCell::new(Vec::<Value>::new()));
+            let received_clone = received.clone();
+            let mock = MockIsolate::new();
+            mock.register_message_handler("files/progress", move |value, _reply| {
+                received_clone.borrow_mut().push(value);
+            });
+            let isolate = mock.attach(&Context::get().message_channel());
+
+            let message = Value::List(vec!["copy".into(), "done".into(), 7.into()]);
+            let reply = isolate.send_message_async("files", message).await.unwrap();
+            received.borrow_mut().push(reply);
+
+            assert_eq!(
+                *received.borrow(),
+                vec![
+                    Value::List(vec![7.into(), 0.5.into()]),
+                    Value::List(vec![7.into(), 1.0.into()]),
+                    Value::List(vec!["ok".into(), "done".into()]),
+                ]
+            );
+
+            // Without progress id progress reports are dropped.
+            received.borrow_mut().clear();
+            let res = isolate
+                .call_method_async("files", "copy", "done".into())
+                .await;
+            assert_eq!(res.unwrap(), Value::from("done"));
+            assert!(received.borrow().is_empty());
+        });
+    }
+
+    struct Calculator {}
+
+    #[async_trait(?Send)]
+    impl AsyncMethodHandler for Calculator {
+        async fn on_method_call(&self, call: MethodCall) -> PlatformResult {
+            let values: Vec<i64> = call.args.try_into()?;
+            Ok(values.iter().sum::<i64>().into())
+        }
+    }
+
+    #[test]
+    fn test_invalid_arguments() {
+        Context::run_test(async {
+            let _handler = Calculator {}.register("calculator");
+            let isolate = MockIsolate::new().attach(&Context::get().message_channel());
+
+            let args = Value::List(vec![1.into(), 2.into()]);
+            let res = isolate.call_method_async("calculator", "sum", args).await;
+            assert_eq!(res.unwrap(), Value::I64(3));
+
+            let args = Value::List(vec![1.into(), "x".into()]);
+            let res = isolate.call_method_async("calculator", "sum", args).await;
+            match res {
+                Err(MethodCallError::PlatformError(err)) => {
+                    assert_eq!(err.code, "invalid_arguments");
+                    assert!(err.message.is_some());
+                    assert_eq!(err.detail, Value::from("[1]"));
+                }
+                _ => panic!("unexpected result {:?}", res),
+            }
+        });
+    }
+}
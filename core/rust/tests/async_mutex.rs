@@ -0,0 +1,74 @@
+#[cfg(feature = "mock")]
+mod tests {
+    use std::{sync::Arc, thread, time::Duration};
+
+    use nativeshell_core::{
+        util::{AsyncMutex, Capsule, FutureCompleter},
+        Context,
+    };
+
+    #[test]
+    fn test_async_mutex() {
+        Context::run_test(async {
+            let context = Context::get();
+            let run_loop = context.run_loop();
+            let mutex = Arc::new(AsyncMutex::new(Vec::<&str>::new()));
+            let events = Arc::new(std::sync::Mutex::new(Vec::<&str>::new()));
+
+            let first = {
+                let mutex = mutex.clone();
+                let events = events.clone();
+                run_loop.spawn(async move {
+                    let mut guard = mutex.lock().await;
+                    guard.push("first");
+                    events.lock().unwrap().push("first locked");
+                    Context::get()
+                        .run_loop()
+                        .wait(Duration::from_millis(30))
+                        .await;
+                    events.lock().unwrap().push("first released");
+                })
+            };
+            run_loop.wait(Duration::from_millis(1)).await;
+            assert!(mutex.try_lock().is_none());
+
+            let second = {
+                let mutex = mutex.clone();
+                let events = events.clone();
+                run_loop.spawn(async move {
+                    let mut guard = mutex.lock().await;
+                    guard.push("second");
+                    events.lock().unwrap().push("second locked");
+                })
+            };
+
+            // Run loop keeps running other tasks while second task waits.
+            run_loop.wait(Duration::from_millis(5)).await;
+            events.lock().unwrap().push("tick");
+
+            // Worker thread blocks until the lock is released.
+            let (worker_done, completer) = FutureCompleter::new();
+            let mut completer = Capsule::new_with_sender(completer, context.run_loop_sender());
+            let sender = context.run_loop_sender();
+            let worker_mutex = mutex.clone();
+            thread::spawn(move || {
+                worker_mutex.blocking_lock().push("worker");
+                sender.send(move || completer.take().unwrap().complete(()));
+            });
+
+            first.await;
+            second.await;
+            worker_done.await;
+
+            assert_eq!(
+                *events.lock().unwrap(),
+                vec!["first locked", "tick", "first released", "second locked"]
+            );
+            let values = mutex.lock().await.clone();
+            // Worker thread may acquire the lock before second task is polled.
+            assert_eq!(values[0], "first");
+            assert!(values.contains(&"second"));
+            assert!(values.contains(&"worker"));
+        });
+    }
+}
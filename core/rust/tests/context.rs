@@ -0,0 +1,331 @@
+#[cfg(feature = "mock")]
+mod tests {
+    use std::{
+        cell::{Cell, Ref},
+        future::Future,
+        panic,
+        pin::Pin,
+        rc::Rc,
+        sync::{
+            atomic::{AtomicBool, AtomicUsize, Ordering},
+            Arc, Barrier, Mutex,
+        },
+        task::{self, Poll},
+        thread,
+        time::{Duration, Instant},
+    };
+
+    use nativeshell_core::{
+        current_sender,
+        util::{Capsule, FutureCompleter},
+        Context, GetMessageChannel, IsolateId, MessageChannelDelegate, MockIsolate, Value,
+    };
+
+    #[test]
+    fn test_run_loop_sender() {
+        Context::run_test(async {
+            let senders = [
+                current_sender(),
+                Context::get().run_loop_sender(),
+                Context::get().run_loop().new_sender(),
+            ];
+            for sender in senders {
+                // same thread fast path
+                assert_eq!(sender.send_and_wait(|| 10), 10);
+
+                // cross thread delivery
+                let (future, completer) = FutureCompleter::new();
+                let mut completer = Capsule::new(completer);
+                let thread_sender = sender.clone();
+                thread::spawn(move || {
+                    thread_sender.send(move || {
+                        let completer = completer.take().unwrap();
+                        completer.complete(thread::current().id());
+                    });
+                });
+                assert_eq!(future.await, thread::current().id());
+            }
+        });
+    }
+
+    #[test]
+    fn test_sender_schedule() {
+        Context::run_test(async {
+            let context = Context::get();
+            let sender = context.run_loop_sender();
+            let (future, completer) = FutureCompleter::new();
+            let mut completer = Capsule::new_with_sender(completer, sender.clone());
+            let cancelled_fired = Arc::new(AtomicUsize::new(0));
+            let cancelled_fired_clone = cancelled_fired.clone();
+            let start = Instant::now();
+            thread::spawn(move || {
+                sender
+                    .schedule(Duration::from_millis(50), move || {
+                        completer.take().unwrap().complete(Instant::now());
+                    })
+                    .detach();
+                let handle = sender.schedule(Duration::from_millis(10), move || {
+                    cancelled_fired_clone.fetch_add(1, Ordering::SeqCst);
+                });
+                drop(handle);
+            });
+            let fired_at = future.await;
+            assert!(fired_at.duration_since(start) >= Duration::from_millis(50));
+            assert_eq!(cancelled_fired.load(Ordering::SeqCst), 0);
+        });
+    }
+
+    #[test]
+    fn test_run_test_from_multiple_threads() {
+        let completed = Arc::new(AtomicUsize::new(0));
+        let threads: Vec<_> = (0..4)
+            .map(|_| {
+                let completed = completed.clone();
+                thread::spawn(move || {
+                    Context::run_test(async move {
+                        Context::get()
+                            .run_loop()
+                            .wait(Duration::from_millis(5))
+                            .await;
+                        completed.fetch_add(1, Ordering::SeqCst);
+                    })
+                })
+            })
+            .collect();
+        for thread in threads {
+            thread.join().unwrap();
+        }
+        assert_eq!(completed.load(Ordering::SeqCst), 4);
+    }
+
+    #[test]
+    fn test_concurrent_run_loops() {
+        const THREADS: usize = 4;
+        let barrier = Arc::new(Barrier::new(THREADS));
+        let senders = Arc::new(Mutex::new(vec![None; THREADS]));
+        let replies = Arc::new(Mutex::new(Vec::new()));
+        let threads: Vec<_> = (0..THREADS)
+            .map(|i| {
+                let barrier = barrier.clone();
+                let senders = senders.clone();
+                let replies = replies.clone();
+                thread::spawn(move || {
+                    Context::run_test(async move {
+                        let sender = Context::get().run_loop_sender();
+                        senders.lock().unwrap()[i] = Some(sender.clone());
+                        // All run loops are alive at the same time past this point.
+                        barrier.wait();
+                        let next = senders.lock().unwrap()[(i + 1) % THREADS].clone().unwrap();
+                        let (future, completer) = FutureCompleter::new();
+                        let mut completer = Capsule::new(completer);
+                        next.send(move || {
+                            let other_thread = thread::current().id();
+                            sender.send(move || {
+                                completer.take().unwrap().complete(other_thread);
+                            });
+                        });
+                        let other_thread = future.await;
+                        assert_ne!(other_thread, thread::current().id());
+                        replies.lock().unwrap().push(other_thread);
+                        // Keep serving other threads until every thread got its reply.
+                        while replies.lock().unwrap().len() < THREADS {
+                            Context::get()
+                                .run_loop()
+                                .wait(Duration::from_millis(1))
+                                .await;
+                        }
+                    })
+                })
+            })
+            .collect();
+        let mut ids: Vec<_> = threads.iter().map(|t| t.thread().id()).collect();
+        for thread in threads {
+            thread.join().unwrap();
+        }
+        let mut replies = replies.lock().unwrap().clone();
+        ids.sort_by_key(|id| format!("{:?}", id));
+        replies.sort_by_key(|id| format!("{:?}", id));
+        assert_eq!(ids, replies);
+    }
+
+    #[test]
+    fn test_on_idle_for() {
+        Context::run_test(async {
+            let context = Context::get();
+            let run_loop = context.run_loop();
+            let fired = Rc::new(Cell::new(0));
+            let fired_clone = fired.clone();
+            let mut handle = context.on_idle_for(Duration::from_millis(50), move || {
+                fired_clone.set(fired_clone.get() + 1);
+            });
+
+            // Every timer firing is activity, keep resetting the idle timer.
+            for _ in 0..10 {
+                run_loop.wait(Duration::from_millis(10)).await;
+            }
+            assert_eq!(fired.get(), 0);
+
+            // Fires once per idle period.
+            run_loop.wait(Duration::from_millis(170)).await;
+            assert_eq!(fired.get(), 1);
+
+            // Activity (the wait above) starts new idle period.
+            run_loop.wait(Duration::from_millis(120)).await;
+            assert_eq!(fired.get(), 2);
+
+            handle.cancel();
+            run_loop.wait(Duration::from_millis(120)).await;
+            assert_eq!(fired.get(), 2);
+        });
+    }
+
+    struct EchoDelegate {}
+
+    impl MessageChannelDelegate for EchoDelegate {
+        fn on_isolate_joined(&self, _: IsolateId) {}
+
+        fn on_message(&self, _: IsolateId, message: Value, reply: Box<dyn FnOnce(Value) -> bool>) {
+            reply(message);
+        }
+
+        fn on_isolate_exited(&self, _: IsolateId) {}
+    }
+
+    struct Plugin {
+        dropped: Arc<AtomicBool>,
+    }
+
+    impl Plugin {
+        fn new(dropped: Arc<AtomicBool>) -> Self {
+            Context::get()
+                .message_channel()
+                .register_delegate("plugin", Rc::new(EchoDelegate {}));
+            Self { dropped }
+        }
+    }
+
+    impl Drop for Plugin {
+        fn drop(&mut self) {
+            // Message channel must still be available.
+            Context::get()
+                .message_channel()
+                .unregister_delegate("plugin");
+            self.dropped.store(true, Ordering::SeqCst);
+        }
+    }
+
+    trait GetPlugin {
+        fn plugin(&self, dropped: Arc<AtomicBool>) -> Ref<'_, Plugin>;
+    }
+
+    impl GetPlugin for Context {
+        fn plugin(&self, dropped: Arc<AtomicBool>) -> Ref<'_, Plugin> {
+            self.get_attachment(|| Plugin::new(dropped))
+        }
+    }
+
+    #[test]
+    fn test_extension_attachment() {
+        let dropped = Arc::new(AtomicBool::new(false));
+        let dropped_clone = dropped.clone();
+        // Context attachments are dropped when the thread exits.
+        thread::spawn(move || {
+            Context::run_test(async move {
+                Context::get().plugin(dropped_clone.clone());
+                let isolate = MockIsolate::new().attach(&Context::get().message_channel());
+                let reply = isolate.send_message_async("plugin", "hello".into()).await;
+                assert_eq!(reply.unwrap(), Value::from("hello"));
+                assert!(!dropped_clone.load(Ordering::SeqCst));
+            });
+        })
+        .join()
+        .unwrap();
+        assert!(dropped.load(Ordering::SeqCst));
+    }
+
+    #[test]
+    fn test_init_panic() {
+        Context::poison_next_init();
+        let res = panic::catch_unwind(Context::new);
+        assert!(res.is_err());
+        assert!(Context::current().is_none());
+
+        let context = Context::new();
+        let (future, completer) = FutureCompleter::new();
+        let mut completer = Capsule::new(completer);
+        context.run_loop().spawn(async move {
+            completer.take().unwrap().complete(10);
+        });
+        let result = Rc::new(Cell::new(0));
+        let result_clone = result.clone();
+        context.run_loop().spawn(async move {
+            result_clone.set(future.await);
+            Context::get().run_loop().stop();
+        });
+        context.run_loop().run();
+        assert_eq!(result.get(), 10);
+    }
+
+    // Keeps waking itself (twice per poll) until stopped.
+    struct BusyFuture {
+        polls: Rc<Cell<usize>>,
+        stop: Rc<Cell<bool>>,
+    }
+
+    impl Future for BusyFuture {
+        type Output = ();
+
+        fn poll(self: Pin<&mut Self>, cx: &mut task::Context<'_>) -> Poll<()> {
+            if self.stop.get() {
+                return Poll::Ready(());
+            }
+            self.polls.set(self.polls.get() + 1);
+            cx.waker().wake_by_ref();
+            cx.waker().wake_by_ref();
+            Poll::Pending
+        }
+    }
+
+    // Completes on second poll.
+    struct YieldNow(bool);
+
+    impl Future for YieldNow {
+        type Output = ();
+
+        fn poll(mut self: Pin<&mut Self>, cx: &mut task::Context<'_>) -> Poll<()> {
+            if self.0 {
+                Poll::Ready(())
+            } else {
+                self.0 = true;
+                cx.waker().wake_by_ref();
+                Poll::Pending
+            }
+        }
+    }
+
+    #[test]
+    fn test_task_fairness() {
+        Context::run_test(async {
+            let polls = Rc::new(Cell::new(0));
+            let stop = Rc::new(Cell::new(false));
+            let busy = Context::get().run_loop().spawn(BusyFuture {
+                polls: polls.clone(),
+                stop: stop.clone(),
+            });
+            let progress = Rc::new(Cell::new(0));
+            let progress_clone = progress.clone();
+            let other = Context::get().run_loop().spawn(async move {
+                for _ in 0..20 {
+                    YieldNow(false).await;
+                    progress_clone.set(progress_clone.get() + 1);
+                }
+            });
+            other.await;
+            assert_eq!(progress.get(), 20);
+            // Busy task is polled about once for every step of the other task.
+            assert!(polls.get() <= 25, "busy task polled {} times", polls.get());
+            stop.set(true);
+            busy.await;
+        });
+    }
+}
@@ -1,6 +1,6 @@
 #[cfg(feature = "nativeshell_derive")]
 mod tests {
-    use std::collections::HashMap;
+    use std::{collections::HashMap, panic};
 
     use nativeshell_core::{IntoValue, TryFromError, TryFromValue, Value};
 
@@ -651,4 +651,167 @@ mod tests {
         }
         Ok(())
     }
+
+    #[derive(Clone, PartialEq, Debug, IntoValue, TryFromValue)]
+    #[nativeshell(rename_all = "camelCase")]
+    struct Size {
+        width: f64,
+        height: f64,
+    }
+
+    #[derive(Clone, PartialEq, Debug, IntoValue, TryFromValue)]
+    #[nativeshell(rename_all = "camelCase")]
+    struct WindowGeometry {
+        title: String,
+        #[nativeshell(flatten)]
+        size: Size,
+        #[nativeshell(flatten)]
+        extra: HashMap<String, Value>,
+    }
+
+    #[derive(Clone, PartialEq, Debug, IntoValue, TryFromValue)]
+    struct ConflictingFlatten {
+        width: f64,
+        #[nativeshell(flatten)]
+        size: Size,
+    }
+
+    #[test]
+    fn test_flatten() -> Result<(), TryFromError> {
+        let mut extra = HashMap::new();
+        extra.insert("z".to_owned(), Value::I64(3));
+        let v1 = WindowGeometry {
+            title: "Window".into(),
+            size: Size {
+                width: 10.0,
+                height: 20.0,
+            },
+            extra,
+        };
+        let sv1: Value = v1.clone().into();
+        assert_eq!(
+            sv1,
+            Value::Map(
+                vec![
+                    ("title".into(), "Window".into()), //
+                    ("width".into(), 10.0.into()),
+                    ("height".into(), 20.0.into()),
+                    ("z".into(), 3.into()),
+                ]
+                .into()
+            )
+        );
+        let v1d: WindowGeometry = sv1.clone().try_into()?;
+        assert_eq!(v1d, v1);
+        let sv2: Value = v1d.into();
+        assert_eq!(sv2, sv1);
+        let v2d: WindowGeometry = sv2.try_into()?;
+        assert_eq!(v2d, v1);
+
+        let missing: Result<WindowGeometry, _> =
+            Value::Map(vec![("title".into(), "Window".into())].into()).try_into();
+        assert!(missing.is_err());
+
+        let conflicting = ConflictingFlatten {
+            width: 1.0,
+            size: Size {
+                width: 2.0,
+                height: 3.0,
+            },
+        };
+        let res = panic::catch_unwind(|| Value::from(conflicting));
+        let message = res.unwrap_err();
+        assert_eq!(
+            message.downcast_ref::<String>().unwrap(),
+            "duplicate key String(\"width\") in flattened field."
+        );
+        let sv = Value::Map(
+            vec![
+                ("width".into(), 1.0.into()), //
+                ("height".into(), 3.0.into()),
+            ]
+            .into(),
+        );
+        let res: Result<ConflictingFlatten, _> = sv.try_into();
+        assert!(matches!(res, Err(TryFromError::OtherError(_))));
+        Ok(())
+    }
+
+    #[derive(Debug, PartialEq, TryFromValue)]
+    struct Order {
+        items: Vec<OrderItem>,
+    }
+
+    #[derive(Debug, PartialEq, TryFromValue)]
+    struct OrderItem {
+        quantities: HashMap<String, Vec<i32>>,
+        dimensions: Dimensions,
+    }
+
+    #[derive(Debug, PartialEq, TryFromValue)]
+    struct Dimensions(f64, Dimension);
+
+    #[derive(Debug, PartialEq, TryFromValue)]
+    enum Dimension {
+        Metric { value: f64 },
+    }
+
+    #[test]
+    fn test_error_path() {
+        let item = |quantity: Value, dimension: Value| {
+            Value::Map(
+                vec![
+                    (
+                        "quantities".into(),
+                        Value::Map(vec![("a".into(), vec![quantity].into())].into()),
+                    ),
+                    (
+                        "dimensions".into(),
+                        vec![
+                            Value::F64(1.0),
+                            Value::Map(
+                                vec![(
+                                    "Metric".into(),
+                                    Value::Map(vec![("value".into(), dimension)].into()),
+                                )]
+                                .into(),
+                            ),
+                        ]
+                        .into(),
+                    ),
+                ]
+                .into(),
+            )
+        };
+        let order = |items: Vec<Value>| Value::Map(vec![("items".into(), items.into())].into());
+
+        let ok = item(1.into(), 2.0.into());
+        let res: Order = order(vec![ok.clone()]).try_into().unwrap();
+        assert_eq!(
+            res,
+            Order {
+                items: vec![OrderItem {
+                    quantities: HashMap::from([("a".into(), vec![1])]),
+                    dimensions: Dimensions(1.0, Dimension::Metric { value: 2.0 }),
+                }]
+            }
+        );
+
+        let res: Result<Order, _> =
+            order(vec![ok.clone(), item("x".into(), 2.0.into())]).try_into();
+        let err = res.unwrap_err();
+        assert_eq!(err.path(), r#"["items"][1]["quantities"]["a"][0]"#);
+        assert_eq!(err.root_cause(), &TryFromError::BadType);
+
+        let res: Result<Order, _> = order(vec![ok, item(1.into(), "x".into())]).try_into();
+        let err = res.unwrap_err();
+        assert_eq!(
+            err.path(),
+            r#"["items"][1]["dimensions"][1]["Metric"]["value"]"#
+        );
+        assert_eq!(
+            err.to_string(),
+            r#"Could not convert value from unrelated type. (at ["items"][1]["dimensions"][1]["Metric"]["value"])"#
+        );
+    }
 }
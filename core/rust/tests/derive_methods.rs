@@ -0,0 +1,81 @@
+#[cfg(all(feature = "mock", feature = "nativeshell_derive"))]
+mod tests {
+    use nativeshell_core::{
+        nativeshell_methods, Context, GetMessageChannel, MethodCallError, MethodHandler,
+        MockIsolate, PlatformError, Value,
+    };
+
+    struct Calculator {
+        offset: i64,
+    }
+
+    #[nativeshell_methods(rename_all = "camelCase")]
+    impl Calculator {
+        fn add_offset(&self, value: i64) -> i64 {
+            value + self.offset
+        }
+
+        fn divide(&self, a: i64, b: i64) -> Result<i64, PlatformError> {
+            if b == 0 {
+                Err(PlatformError {
+                    code: "division_by_zero".into(),
+                    message: None,
+                    detail: Value::Null,
+                })
+            } else {
+                Ok(a / b)
+            }
+        }
+
+        #[nativeshell(skip)]
+        #[allow(dead_code)]
+        fn skipped(&self) {}
+    }
+
+    fn error_code(res: Result<Value, MethodCallError>) -> String {
+        match res {
+            Err(MethodCallError::PlatformError(err)) => err.code,
+            res => panic!("unexpected result {:?}", res),
+        }
+    }
+
+    #[test]
+    fn test_methods() {
+        Context::run_test(async {
+            let _registration = Calculator { offset: 10 }.register("calculator");
+            let isolate = MockIsolate::new().attach(&Context::get().message_channel());
+
+            let res = isolate
+                .call_method_async("calculator", "addOffset", 5.into())
+                .await;
+            assert_eq!(res.unwrap(), Value::I64(15));
+
+            let args = Value::List(vec![10.into(), 2.into()]);
+            let res = isolate
+                .call_method_async("calculator", "divide", args)
+                .await;
+            assert_eq!(res.unwrap(), Value::I64(5));
+
+            let args = Value::List(vec![10.into(), 0.into()]);
+            let res = isolate
+                .call_method_async("calculator", "divide", args)
+                .await;
+            assert_eq!(error_code(res), "division_by_zero");
+
+            let res = isolate
+                .call_method_async("calculator", "divide", 1.into())
+                .await;
+            assert_eq!(error_code(res), "invalid_arguments");
+
+            let res = isolate
+                .call_method_async("calculator", "addOffset", "x".into())
+                .await;
+            assert_eq!(error_code(res), "invalid_arguments");
+
+            let res = isolate
+                .call_method_async("calculator", "skipped", Value::Null)
+                .await;
+            assert!(matches!(res, Err(MethodCallError::NotImplemented)));
+        });
+    }
+}
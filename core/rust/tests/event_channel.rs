@@ -0,0 +1,346 @@
+#[cfg(feature = "mock")]
+mod tests {
+    use std::{
+        cell::RefCell,
+        collections::HashMap,
+        rc::Rc,
+        thread,
+        time::{Duration, Instant},
+    };
+
+    use nativeshell_core::{
+        Context, EventHandler, EventSink, GetMessageChannel, HandlerKind, MethodCall,
+        MethodCallReply, MethodHandler, MockIsolate, RegisteredEventChannel, Value,
+        EVENT_CHUNK_MARKER,
+    };
+
+    #[derive(Default)]
+    struct Handler {
+        sink: Option<EventSink>,
+    }
+
+    impl EventHandler for Handler {
+        fn register_event_sink(&mut self, sink: EventSink, _listen_argument: Value) {
+            self.sink = Some(sink);
+        }
+
+        fn unregister_event_sink(&mut self, _sink_id: i64) {
+            self.sink = None;
+        }
+    }
+
+    // Reassembles chunks the same way Dart NativeEventChannel does.
+    fn reassemble(chunks: &[Value]) -> Value {
+        let mut bytes = Vec::new();
+        let mut encoded = false;
+        for (i, chunk) in chunks.iter().enumerate() {
+            let items: Vec<Value> = chunk.clone().try_into().unwrap();
+            assert_eq!(items[0], Value::String(EVENT_CHUNK_MARKER.into()));
+            assert_eq!(items[2], Value::I64(i as i64));
+            assert_eq!(items[3], Value::Bool(i == chunks.len() - 1));
+            encoded = items[4] == Value::Bool(true);
+            let chunk: Vec<u8> = items[5].clone().try_into().unwrap();
+            bytes.extend(chunk);
+        }
+        if encoded {
+            Value::decode(&bytes).unwrap()
+        } else {
+            Value::U8List(bytes)
+        }
+    }
+
+    #[test]
+    fn test_post_message_chunked() {
+        Context::run_test(async {
+            let channel = Handler::default().register("events");
+            let received = Rc::new(RefCell::new(Vec::<Value>::new()));
+            let received_clone = received.clone();
+            let mock = MockIsolate::new();
+            mock.register_message_handler("events", move |value, _reply| {
+                received_clone.borrow_mut().push(value);
+            });
+            let isolate = mock.attach(&Context::get().message_channel());
+            isolate
+                .call_method_async("events", "listen", Value::Null)
+                .await
+                .unwrap();
+
+            let payload: Vec<u8> = (0..2500).map(|i| (i % 251) as u8).collect();
+            let borrowed = channel.borrow();
+            let sink = borrowed.sink.as_ref().unwrap();
+            sink.post_message_chunked(payload.clone(), 1000).unwrap();
+            assert_eq!(received.borrow().len(), 3);
+            assert_eq!(reassemble(&received.borrow()), Value::U8List(payload));
+
+            received.borrow_mut().clear();
+            let value = Value::List(vec!["x".repeat(100).into(), Value::F64List(vec![1.0; 10])]);
+            sink.post_message_chunked(value.clone(), 16).unwrap();
+            assert_eq!(reassemble(&received.borrow()), value);
+        });
+    }
+
+    #[test]
+    fn test_sendable_sink() {
+        Context::run_test(async {
+            let channel = Handler::default().register("events");
+            let received = Rc::new(RefCell::new(Vec::<Value>::new()));
+            let received_clone = received.clone();
+            let mock = MockIsolate::new();
+            mock.register_message_handler("events", move |value, _reply| {
+                received_clone.borrow_mut().push(value);
+            });
+            let isolate = mock.attach(&Context::get().message_channel());
+            isolate
+                .call_method_async("events", "listen", Value::Null)
+                .await
+                .unwrap();
+
+            let sink = channel.borrow().sink.as_ref().unwrap().sendable();
+            let worker = thread::spawn(move || {
+                for i in 0..10 {
+                    sink.post(i);
+                }
+            });
+
+            let mut attempts = 0;
+            while received.borrow().len() < 10 {
+                assert!(attempts < 1000, "events not delivered");
+                attempts += 1;
+                Context::get()
+                    .run_loop()
+                    .wait(Duration::from_millis(1))
+                    .await;
+            }
+            worker.join().unwrap();
+            let expected: Vec<Value> = (0..10).map(Value::from).collect();
+            assert_eq!(*received.borrow(), expected);
+        });
+    }
+
+    #[test]
+    fn test_post_message_sampled() {
+        Context::run_test(async {
+            let channel = Handler::default().register("events");
+            let received = Rc::new(RefCell::new(Vec::<(Instant, Value)>::new()));
+            let received_clone = received.clone();
+            let mock = MockIsolate::new();
+            mock.register_message_handler("events", move |value, _reply| {
+                received_clone.borrow_mut().push((Instant::now(), value));
+            });
+            let isolate = mock.attach(&Context::get().message_channel());
+            isolate
+                .call_method_async("events", "listen", Value::Null)
+                .await
+                .unwrap();
+
+            let interval = Duration::from_millis(20);
+            let start = Instant::now();
+            for i in 0..100 {
+                channel
+                    .borrow()
+                    .sink
+                    .as_ref()
+                    .unwrap()
+                    .post_message_sampled(i, interval)
+                    .unwrap();
+                Context::get()
+                    .run_loop()
+                    .wait(Duration::from_millis(1))
+                    .await;
+            }
+            let elapsed = start.elapsed();
+            // Let the last pending message through.
+            Context::get()
+                .run_loop()
+                .wait(interval + Duration::from_millis(10))
+                .await;
+
+            let received = received.borrow();
+            // Allow some slack for timer granularity.
+            let min_gap = interval - Duration::from_millis(2);
+            for pair in received.windows(2) {
+                assert!(pair[1].0 - pair[0].0 >= min_gap);
+            }
+            let max_count = (elapsed.as_millis() / min_gap.as_millis()) as usize + 2;
+            assert!(received.len() <= max_count);
+            assert!(received.len() >= 3);
+            assert_eq!(received.first().unwrap().1, Value::I64(0));
+            assert_eq!(received.last().unwrap().1, Value::I64(99));
+        });
+    }
+
+    #[derive(Default)]
+    struct SharedHandler {
+        sinks: HashMap<i64, EventSink>,
+    }
+
+    impl EventHandler for SharedHandler {
+        fn register_event_sink(&mut self, sink: EventSink, _listen_argument: Value) {
+            self.sinks.insert(sink.id(), sink);
+        }
+
+        fn unregister_event_sink(&mut self, sink_id: i64) {
+            self.sinks.remove(&sink_id);
+        }
+    }
+
+    #[test]
+    fn test_shared_handler() {
+        Context::run_test(async {
+            let handler = Rc::new(RefCell::new(SharedHandler::default()));
+            let _first = RegisteredEventChannel::new_ref("first", handler.clone());
+            let _second = RegisteredEventChannel::new_ref("second", handler.clone());
+            let isolate = MockIsolate::new().attach(&Context::get().message_channel());
+
+            for channel in ["first", "second"] {
+                isolate
+                    .call_method_async(channel, "listen", Value::Null)
+                    .await
+                    .unwrap();
+            }
+            let channels = |handler: &SharedHandler| {
+                let mut channels: Vec<String> =
+                    handler.sinks.values().map(|s| s.channel_name()).collect();
+                channels.sort();
+                channels
+            };
+            assert_eq!(handler.borrow().sinks.len(), 2);
+            assert_eq!(channels(&handler.borrow()), vec!["first", "second"]);
+
+            isolate
+                .call_method_async("first", "cancel", Value::Null)
+                .await
+                .unwrap();
+            assert_eq!(channels(&handler.borrow()), vec!["second"]);
+
+            drop(isolate);
+            assert!(handler.borrow().sinks.is_empty());
+        });
+    }
+
+    struct NoopMethodHandler {}
+
+    impl MethodHandler for NoopMethodHandler {
+        fn on_method_call(&self, _call: MethodCall, reply: MethodCallReply) {
+            reply.send_ok(Value::Null);
+        }
+    }
+
+    #[test]
+    fn test_registered_channels() {
+        Context::run_test(async {
+            let _method = NoopMethodHandler {}.register("method_channel");
+            let event = Handler::default().register("event_channel");
+            {
+                let context = Context::get();
+                let channel = context.message_channel();
+                assert_eq!(
+                    channel.registered_channels(),
+                    vec![
+                        ("event_channel".to_owned(), HandlerKind::EventChannel),
+                        ("method_channel".to_owned(), HandlerKind::Method),
+                    ]
+                );
+            }
+            drop(event);
+            assert_eq!(
+                Context::get().message_channel().registered_channels(),
+                vec![("method_channel".to_owned(), HandlerKind::Method)]
+            );
+        });
+    }
+
+    #[test]
+    fn test_rechannel() {
+        Context::run_test(async {
+            let channel = Handler::default().register("events");
+            let received = Rc::new(RefCell::new(Vec::<(&str, Value)>::new()));
+            let mock = MockIsolate::new();
+            for name in ["events", "renamed"] {
+                let received = received.clone();
+                mock.register_message_handler(name, move |value, _reply| {
+                    received.borrow_mut().push((name, value));
+                });
+            }
+            let isolate = mock.attach(&Context::get().message_channel());
+            isolate
+                .call_method_async("events", "listen", Value::Null)
+                .await
+                .unwrap();
+            let sendable = channel.borrow().sink.as_ref().unwrap().sendable();
+            channel
+                .borrow()
+                .sink
+                .as_ref()
+                .unwrap()
+                .post_message(1)
+                .unwrap();
+
+            channel.rechannel("renamed");
+            assert_eq!(channel.channel(), "renamed");
+            assert_eq!(
+                Context::get().message_channel().registered_channels(),
+                vec![("renamed".to_owned(), HandlerKind::EventChannel)]
+            );
+            {
+                let borrowed = channel.borrow();
+                let sink = borrowed.sink.as_ref().unwrap();
+                assert_eq!(sink.channel_name(), "renamed");
+                sink.post_message(2).unwrap();
+            }
+            sendable.post(3);
+            let mut attempts = 0;
+            while received.borrow().len() < 3 {
+                assert!(attempts < 1000, "events not delivered");
+                attempts += 1;
+                Context::get()
+                    .run_loop()
+                    .wait(Duration::from_millis(1))
+                    .await;
+            }
+            assert_eq!(
+                *received.borrow(),
+                vec![
+                    ("events", Value::I64(1)),
+                    ("renamed", Value::I64(2)),
+                    ("renamed", Value::I64(3)),
+                ]
+            );
+
+            isolate
+                .call_method_async("renamed", "cancel", Value::Null)
+                .await
+                .unwrap();
+            assert!(channel.borrow().sink.is_none());
+        });
+    }
+
+    #[test]
+    #[cfg(debug_assertions)]
+    fn test_wrong_thread_access() {
+        // Registered channel is not Send; simulate accidental sharing.
+        struct Shared<'a>(&'a RegisteredEventChannel<Handler>);
+        unsafe impl Sync for Shared<'_> {}
+        unsafe impl Send for Shared<'_> {}
+
+        Context::run_test(async {
+            let channel = Handler::default().register("wrong_thread");
+            let shared = Shared(&channel);
+            let res = thread::scope(|s| {
+                s.spawn(|| {
+                    let shared = &shared;
+                    let _handler = shared.0.borrow();
+                })
+                .join()
+            });
+            let payload = res.unwrap_err();
+            let message = payload.downcast_ref::<String>().unwrap();
+            assert!(message.contains("RegisteredEventChannel"));
+            assert!(message.contains("\"wrong_thread\""));
+            assert!(message.contains("wrong thread"));
+
+            // Still usable on the owning thread.
+            assert!(channel.borrow().sink.is_none());
+        });
+    }
+}
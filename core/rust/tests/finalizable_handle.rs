@@ -0,0 +1,162 @@
+#[cfg(feature = "mock")]
+mod tests {
+    use std::{
+        cell::Cell,
+        rc::Rc,
+        sync::{
+            atomic::{AtomicUsize, Ordering},
+            Arc,
+        },
+        thread,
+    };
+
+    use nativeshell_core::{Context, FinalizableHandle, GetMessageChannel, MockIsolate, Value};
+
+    #[test]
+    fn test_attached() {
+        Context::run_test(async {
+            let isolate = MockIsolate::new().attach(&Context::get().message_channel());
+            let handle = Arc::new(FinalizableHandle::new(10, isolate.isolate_id(), || {}));
+
+            let attached = Rc::new(Cell::new(None));
+            let attached_clone = attached.clone();
+            let future = handle.attached();
+            Context::get().run_loop().spawn(async move {
+                attached_clone.set(Some(future.await));
+            });
+            Context::get().run_loop().wait(Default::default()).await;
+            assert!(!handle.is_attached());
+            assert_eq!(attached.get(), None);
+
+            Context::get()
+                .message_channel()
+                .post_message(
+                    isolate.isolate_id(),
+                    "channel",
+                    Value::FinalizableHandle(handle.clone()),
+                )
+                .unwrap();
+            Context::get().run_loop().wait(Default::default()).await;
+            assert!(handle.is_attached());
+            assert_eq!(attached.get(), Some(isolate.isolate_id()));
+
+            // already attached
+            assert_eq!(handle.attached().await, isolate.isolate_id());
+        });
+    }
+
+    #[test]
+    fn test_immediate_finalization() {
+        Context::run_test(async {
+            let isolate = MockIsolate::new().attach(&Context::get().message_channel());
+            let finalized = Rc::new(Cell::new(false));
+
+            let finalized_clone = finalized.clone();
+            let handle = FinalizableHandle::new(10, isolate.isolate_id(), move || {
+                finalized_clone.set(true);
+            });
+            handle.finalize();
+            // Scheduled on run loop by default.
+            assert!(!finalized.get());
+            Context::get().run_loop().wait(Default::default()).await;
+            assert!(finalized.get());
+
+            FinalizableHandle::set_immediate_finalization(true);
+            finalized.set(false);
+            let finalized_clone = finalized.clone();
+            let handle = FinalizableHandle::new(10, isolate.isolate_id(), move || {
+                finalized_clone.set(true);
+            });
+            handle.finalize();
+            assert!(finalized.get());
+            assert!(handle.is_finalized());
+            FinalizableHandle::set_immediate_finalization(false);
+        });
+    }
+
+    #[test]
+    fn test_simulate_gc() {
+        Context::run_test(async {
+            let isolate = MockIsolate::new().attach(&Context::get().message_channel());
+            let finalized = Rc::new(Cell::new(false));
+
+            let finalized_clone = finalized.clone();
+            let handle = Arc::new(FinalizableHandle::new(
+                10,
+                isolate.isolate_id(),
+                move || {
+                    finalized_clone.set(true);
+                },
+            ));
+            // Not sent to isolate yet.
+            assert!(!isolate.simulate_gc(handle.id()));
+
+            Context::get()
+                .message_channel()
+                .post_message(
+                    isolate.isolate_id(),
+                    "channel",
+                    Value::FinalizableHandle(handle.clone()),
+                )
+                .unwrap();
+            Context::get().run_loop().wait(Default::default()).await;
+            assert!(handle.is_attached());
+
+            assert!(isolate.simulate_gc(handle.id()));
+            assert!(!handle.is_attached());
+            assert!(!finalized.get());
+            Context::get().run_loop().wait(Default::default()).await;
+            assert!(finalized.get());
+            assert!(handle.is_finalized());
+
+            // Already finalized.
+            assert!(!isolate.simulate_gc(handle.id()));
+        });
+    }
+
+    #[test]
+    fn test_finalize_on_context_drop() {
+        let finalized = Arc::new(AtomicUsize::new(0));
+        let finalized_clone = finalized.clone();
+        // Outermost context is dropped when the thread exits.
+        let (attached, detached) = thread::spawn(move || {
+            let context = Context::new();
+            let isolate = MockIsolate::new().attach(&context.message_channel());
+            // Keep isolate attached until the context goes away; detaching it
+            // would schedule the finalizers on run loop.
+            context.get_attachment(|| isolate.clone());
+
+            let finalized = finalized_clone.clone();
+            let attached = Arc::new(FinalizableHandle::new(
+                10,
+                isolate.isolate_id(),
+                move || {
+                    // Context must still be accessible from finalizer.
+                    Context::get().message_channel();
+                    finalized.fetch_add(1, Ordering::SeqCst);
+                },
+            ));
+            context
+                .message_channel()
+                .post_message(
+                    isolate.isolate_id(),
+                    "channel",
+                    Value::FinalizableHandle(attached.clone()),
+                )
+                .unwrap();
+            assert!(attached.is_attached());
+
+            let finalized = finalized_clone.clone();
+            let detached = FinalizableHandle::new(10, isolate.isolate_id(), move || {
+                finalized.fetch_add(1, Ordering::SeqCst);
+            });
+            (attached, detached)
+        })
+        .join()
+        .unwrap();
+
+        assert_eq!(finalized.load(Ordering::SeqCst), 2);
+        assert!(attached.is_finalized());
+        assert!(detached.is_finalized());
+    }
+}
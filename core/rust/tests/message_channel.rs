@@ -0,0 +1,457 @@
+#[cfg(feature = "mock")]
+mod tests {
+    use std::{
+        cell::{Cell, RefCell},
+        rc::Rc,
+        time::Duration,
+    };
+
+    use futures::future;
+    use nativeshell_core::{
+        util::{Capsule, FutureCompleter},
+        Context, GetMessageChannel, IsolateId, MessageChannelDelegate, MessagePriority,
+        MockIsolate, PlatformError, RegisterDelegateOptions, SendMessageError, TraceEvent, Value,
+    };
+
+    #[test]
+    fn test_send_message_when_ready() {
+        Context::run_test(async {
+            let isolate = MockIsolate::new().attach(&Context::get().message_channel());
+
+            let (delivered, completer) = FutureCompleter::new();
+            let mut completer = Capsule::new(completer);
+            Context::get().message_channel().send_message_when_ready(
+                isolate.isolate_id(),
+                "startup",
+                "hello".into(),
+                Duration::from_secs(10),
+                move |res| completer.take().unwrap().complete(res),
+            );
+
+            let (timed_out, timeout_completer) = FutureCompleter::new();
+            let mut timeout_completer = Capsule::new(timeout_completer);
+            Context::get().message_channel().send_message_when_ready(
+                isolate.isolate_id(),
+                "never",
+                "hello".into(),
+                Duration::from_millis(10),
+                move |res| timeout_completer.take().unwrap().complete(res),
+            );
+
+            Context::get()
+                .run_loop()
+                .wait(Duration::from_millis(20))
+                .await;
+
+            isolate.register_message_handler("startup", |value, reply| {
+                let value: String = value.try_into().unwrap();
+                reply.unwrap()(format!("{} world", value).into());
+            });
+            assert_eq!(delivered.await.unwrap(), Value::from("hello world"));
+
+            assert!(matches!(
+                timed_out.await,
+                Err(SendMessageError::ChannelNotFound { channel }) if channel == "never"
+            ));
+        });
+    }
+
+    #[test]
+    fn test_cancel_send_message() {
+        Context::run_test(async {
+            let pending_replies = Rc::new(RefCell::new(Vec::new()));
+            let pending_replies_clone = pending_replies.clone();
+            let isolate = MockIsolate::new();
+            isolate.register_message_handler("slow", move |_, reply| {
+                pending_replies_clone.borrow_mut().push(reply.unwrap());
+            });
+            let context = Context::get();
+            let channel = context.message_channel();
+            let isolate = isolate.attach(&channel);
+
+            let cancelled_called = Rc::new(Cell::new(false));
+            let cancelled_called_clone = cancelled_called.clone();
+            let mut cancelled =
+                channel.send_message(isolate.isolate_id(), "slow", 1.into(), move |_| {
+                    cancelled_called_clone.set(true);
+                });
+            let kept_result = Rc::new(RefCell::new(None));
+            let kept_result_clone = kept_result.clone();
+            let _kept = channel.send_message(isolate.isolate_id(), "slow", 2.into(), move |res| {
+                kept_result_clone.replace(Some(res.unwrap()));
+            });
+
+            // Both messages are delivered, only the first reply is discarded.
+            assert_eq!(pending_replies.borrow().len(), 2);
+            cancelled.cancel();
+            for reply in pending_replies.take() {
+                reply(Value::Null);
+            }
+            assert!(!cancelled_called.get());
+            assert_eq!(kept_result.take(), Some(Value::Null));
+        });
+    }
+
+    #[test]
+    fn test_out_of_order_replies() {
+        Context::run_test(async {
+            let isolate = MockIsolate::new();
+            let captured = isolate.capture_messages("slow");
+            let context = Context::get();
+            let channel = context.message_channel();
+            let isolate = isolate.attach(&channel);
+
+            let results = Rc::new(RefCell::new(Vec::new()));
+            for i in 0..2i64 {
+                let results = results.clone();
+                channel
+                    .send_message(isolate.isolate_id(), "slow", i.into(), move |res| {
+                        results.borrow_mut().push((i, res.unwrap()));
+                    })
+                    .detach();
+            }
+            channel
+                .post_message(isolate.isolate_id(), "slow", 2i64.into())
+                .unwrap();
+
+            assert_eq!(captured.len(), 3);
+            let mut messages = captured.take();
+            assert!(captured.is_empty());
+            assert!(!messages.pop().unwrap().expects_reply());
+
+            // Complete the calls in reverse order.
+            for message in messages.into_iter().rev() {
+                let value = message.message.clone();
+                message.reply(Value::from(Ok::<_, PlatformError>(value)));
+            }
+            assert_eq!(
+                *results.borrow(),
+                vec![
+                    (1, Value::from(Ok::<_, PlatformError>(1i64))),
+                    (0, Value::from(Ok::<_, PlatformError>(0i64))),
+                ]
+            );
+        });
+    }
+
+    #[test]
+    fn test_message_order() {
+        Context::run_test(async {
+            let received = Rc::new(RefCell::new(Vec::<Value>::new()));
+            let isolate = MockIsolate::new();
+            let received_clone = received.clone();
+            isolate.register_message_handler("events", move |value, reply| {
+                received_clone.borrow_mut().push(value);
+                if let Some(reply) = reply {
+                    reply(Value::Null);
+                }
+            });
+            let context = Context::get();
+            let channel = context.message_channel();
+            let isolate = isolate.attach(&channel);
+
+            for i in 0..10i64 {
+                if i % 3 == 0 {
+                    channel
+                        .send_message(isolate.isolate_id(), "events", i.into(), |res| {
+                            res.unwrap();
+                        })
+                        .detach();
+                } else {
+                    channel
+                        .post_message(isolate.isolate_id(), "events", i.into())
+                        .unwrap();
+                }
+            }
+
+            let expected: Vec<Value> = (0..10i64).map(Value::from).collect();
+            assert_eq!(*received.borrow(), expected);
+        });
+    }
+
+    #[test]
+    fn test_message_priority() {
+        Context::run_test(async {
+            let received = Rc::new(RefCell::new(Vec::<Value>::new()));
+            let isolate = MockIsolate::new();
+            let received_clone = received.clone();
+            isolate.register_message_handler("events", move |value, reply| {
+                received_clone.borrow_mut().push(value);
+                if let Some(reply) = reply {
+                    reply(Value::Null);
+                }
+            });
+            let isolate = isolate.attach(&Context::get().message_channel());
+
+            let replied = Rc::new(Cell::new(false));
+            let replied_clone = replied.clone();
+            {
+                let context = Context::get();
+                let channel = context.message_channel();
+                channel
+                    .send_message_with_priority(
+                        isolate.isolate_id(),
+                        "events",
+                        "low 1".into(),
+                        MessagePriority::Low,
+                        move |res| {
+                            res.unwrap();
+                            replied_clone.set(true);
+                        },
+                    )
+                    .detach();
+                channel
+                    .post_message_with_priority(
+                        isolate.isolate_id(),
+                        "events",
+                        "low 2".into(),
+                        MessagePriority::Low,
+                    )
+                    .unwrap();
+                channel
+                    .post_message_with_priority(
+                        isolate.isolate_id(),
+                        "events",
+                        "normal".into(),
+                        MessagePriority::Normal,
+                    )
+                    .unwrap();
+            }
+            assert_eq!(*received.borrow(), vec![Value::from("normal")]);
+
+            Context::get().run_loop().wait(Duration::ZERO).await;
+            let expected: Vec<Value> = vec!["normal".into(), "low 1".into(), "low 2".into()];
+            assert_eq!(*received.borrow(), expected);
+            assert!(replied.get());
+        });
+    }
+
+    #[test]
+    fn test_saturation_observer() {
+        Context::run_test(async {
+            let isolate = MockIsolate::new().attach(&Context::get().message_channel());
+            Context::get()
+                .message_channel()
+                .register_delegate("flood", Rc::new(EchoDelegate {}));
+
+            let reports = Rc::new(RefCell::new(Vec::<usize>::new()));
+            let reports_clone = reports.clone();
+            {
+                let context = Context::get();
+                let channel = context.message_channel();
+                channel.set_saturation_thresholds(&[10, 50]);
+                channel.set_saturation_observer(Box::new(move |pending| {
+                    reports_clone.borrow_mut().push(pending);
+                }));
+            }
+
+            let flood = |count: usize| {
+                let (done, completer) = FutureCompleter::new();
+                let mut completer = Capsule::new(completer);
+                for i in 0..count {
+                    let last = i == count - 1;
+                    let completer = if last { completer.take().ok() } else { None };
+                    isolate.send_message_queued("flood", (i as i64).into(), move |res| {
+                        res.unwrap();
+                        if let Some(completer) = completer {
+                            completer.complete(());
+                        }
+                    });
+                }
+                done
+            };
+
+            flood(20).await;
+            assert_eq!(*reports.borrow(), vec![20, 9]);
+
+            flood(100).await;
+            assert_eq!(*reports.borrow(), vec![20, 9, 100, 49, 9]);
+        });
+    }
+
+    struct EchoDelegate {}
+
+    impl MessageChannelDelegate for EchoDelegate {
+        fn on_isolate_joined(&self, _: IsolateId) {}
+
+        fn on_message(&self, _: IsolateId, message: Value, reply: Box<dyn FnOnce(Value) -> bool>) {
+            reply(message);
+        }
+
+        fn on_isolate_exited(&self, _: IsolateId) {}
+    }
+
+    #[derive(Default)]
+    struct JoinRecorder {
+        joined: RefCell<Vec<IsolateId>>,
+    }
+
+    impl MessageChannelDelegate for JoinRecorder {
+        fn on_isolate_joined(&self, isolate: IsolateId) {
+            self.joined.borrow_mut().push(isolate);
+        }
+
+        fn on_message(&self, _: IsolateId, _: Value, _: Box<dyn FnOnce(Value) -> bool>) {}
+
+        fn on_isolate_exited(&self, _: IsolateId) {}
+    }
+
+    #[test]
+    fn test_replay_joined_isolates() {
+        Context::run_test(async {
+            let context = Context::get();
+            let channel = context.message_channel();
+            let isolate = MockIsolate::new().attach(&channel);
+            let exited = MockIsolate::new().attach(&channel);
+            drop(exited);
+
+            let without_replay = Rc::new(JoinRecorder::default());
+            channel.register_delegate("without_replay", without_replay.clone());
+            assert!(without_replay.joined.borrow().is_empty());
+
+            let with_replay = Rc::new(JoinRecorder::default());
+            channel.register_delegate_with_options(
+                "with_replay",
+                with_replay.clone(),
+                RegisterDelegateOptions {
+                    replay_joined_isolates: true,
+                },
+            );
+            assert_eq!(*with_replay.joined.borrow(), vec![isolate.isolate_id()]);
+
+            let late = MockIsolate::new().attach(&channel);
+            assert_eq!(
+                *with_replay.joined.borrow(),
+                vec![isolate.isolate_id(), late.isolate_id()]
+            );
+            assert_eq!(*without_replay.joined.borrow(), vec![late.isolate_id()]);
+        });
+    }
+
+    #[test]
+    fn test_spawn_for_isolate() {
+        Context::run_test(async {
+            let context = Context::get();
+            let run_loop = context.run_loop();
+            let isolate = MockIsolate::new().attach(&context.message_channel());
+
+            let completed = run_loop.spawn_for_isolate(isolate.isolate_id(), async { 10 });
+            assert_eq!(completed.await, Some(10));
+            assert_eq!(isolate.task_count(), 0);
+
+            // Completed task is removed even if its result is never retrieved.
+            run_loop
+                .spawn_for_isolate(isolate.isolate_id(), async { 10 })
+                .detach();
+            assert_eq!(isolate.task_count(), 1);
+            run_loop.wait(Duration::from_millis(1)).await;
+            assert_eq!(isolate.task_count(), 0);
+
+            // Dropping the handle doesn't cancel the task, it stays tied to the
+            // isolate until the isolate exits.
+            run_loop
+                .spawn_for_isolate(isolate.isolate_id(), future::pending::<()>())
+                .detach();
+            run_loop.wait(Duration::from_millis(1)).await;
+            assert_eq!(isolate.task_count(), 1);
+
+            let dropped = Rc::new(RefCell::new(false));
+            struct DropFlag(Rc<RefCell<bool>>);
+            impl Drop for DropFlag {
+                fn drop(&mut self) {
+                    *self.0.borrow_mut() = true;
+                }
+            }
+            let flag = DropFlag(dropped.clone());
+            let long = run_loop.spawn_for_isolate(isolate.isolate_id(), async move {
+                let _flag = flag;
+                Context::get()
+                    .run_loop()
+                    .wait(Duration::from_secs(100))
+                    .await;
+                10
+            });
+            run_loop.wait(Duration::from_millis(1)).await;
+
+            let isolate_id = isolate.isolate_id();
+            drop(isolate);
+            assert_eq!(long.await, None);
+            assert!(*dropped.borrow());
+
+            // Isolate no longer exists.
+            let late = run_loop.spawn_for_isolate(isolate_id, async { 10 });
+            assert_eq!(late.await, None);
+        });
+    }
+
+    #[test]
+    fn test_reply_watchdog() {
+        Context::run_test(async {
+            let mock = MockIsolate::new();
+            // Never replies; keep the reply callbacks alive.
+            let held = Rc::new(RefCell::new(Vec::new()));
+            let held_clone = held.clone();
+            mock.register_message_handler("silent", move |_value, reply| {
+                held_clone.borrow_mut().push(reply);
+            });
+            mock.register_message_handler("echo", |value, reply| {
+                reply.unwrap()(value);
+            });
+            let isolate = mock.attach(&Context::get().message_channel());
+            Context::get()
+                .message_channel()
+                .set_reply_watchdog(Some(Duration::from_millis(20)));
+            let events = Rc::new(RefCell::new(Vec::new()));
+            let events_clone = events.clone();
+            Context::get()
+                .run_loop()
+                .set_trace_hook(move |event| events_clone.borrow_mut().push(event.clone()));
+
+            let result = Rc::new(RefCell::new(None));
+            let result_clone = result.clone();
+            Context::get()
+                .message_channel()
+                .send_message(isolate.isolate_id(), "silent", Value::Null, move |res| {
+                    result_clone.replace(Some(res));
+                })
+                .detach();
+
+            let echo = Rc::new(Cell::new(0));
+            let echo_clone = echo.clone();
+            Context::get()
+                .message_channel()
+                .send_message(isolate.isolate_id(), "echo", 1.into(), move |res| {
+                    assert_eq!(res.unwrap(), Value::I64(1));
+                    echo_clone.set(echo_clone.get() + 1);
+                })
+                .detach();
+
+            Context::get()
+                .run_loop()
+                .wait(Duration::from_millis(5))
+                .await;
+            assert!(result.borrow().is_none());
+
+            Context::get()
+                .run_loop()
+                .wait(Duration::from_millis(50))
+                .await;
+            assert!(matches!(
+                result.borrow_mut().take(),
+                Some(Err(SendMessageError::Timeout))
+            ));
+            // Replied message is not affected by the watchdog.
+            assert_eq!(echo.get(), 1);
+            assert!(matches!(
+                events.borrow().as_slice(),
+                [TraceEvent::ReplyTimedOut { channel, timeout }]
+                    if channel == "silent" && *timeout == Duration::from_millis(20)
+            ));
+
+            // Late reply is ignored.
+            let reply = held.borrow_mut().pop().unwrap().unwrap();
+            reply(Value::Null);
+            assert!(result.borrow().is_none());
+        });
+    }
+}
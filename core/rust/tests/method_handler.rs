@@ -0,0 +1,543 @@
+#[cfg(feature = "mock")]
+mod tests {
+    use std::{
+        cell::{Cell, RefCell},
+        collections::HashMap,
+        panic,
+        rc::Rc,
+        sync::mpsc,
+        thread,
+        time::Duration,
+    };
+
+    use nativeshell_core::{
+        util::{Capsule, FutureCompleter},
+        Context, GetMessageChannel, MessageChannel, MethodCall, MethodCallError, MethodCallReply,
+        MethodHandler, MockIsolate, PlatformError, PlatformResult, SendMessageError,
+        SimpleMethodHandler, TryFromError, Value,
+    };
+
+    struct EchoHandler {}
+
+    impl MethodHandler for EchoHandler {
+        fn on_method_call(&self, call: MethodCall, reply: MethodCallReply) {
+            reply.send_ok(call.args);
+        }
+    }
+
+    struct RawHandler {}
+
+    impl MethodHandler for RawHandler {
+        fn on_method_call(&self, call: MethodCall, reply: MethodCallReply) {
+            match call.method.as_str() {
+                "ok" => reply.send_raw_ok(call.args.encode()),
+                _ => reply.send_raw_err(
+                    Value::List(vec!["code".into(), "message".into(), call.args]).encode(),
+                ),
+            }
+        }
+    }
+
+    #[test]
+    fn test_from_result() {
+        let ok: Result<i64, PlatformError> = Ok(10);
+        assert_eq!(Value::from(ok), Value::List(vec!["ok".into(), 10.into()]));
+
+        let ok: Result<Option<String>, PlatformError> = Ok(None);
+        assert_eq!(Value::from(ok), Value::List(vec!["ok".into(), Value::Null]));
+
+        let err: Result<i64, PlatformError> = Err(PlatformError {
+            code: "code".into(),
+            message: Some("message".into()),
+            detail: 5.into(),
+        });
+        assert_eq!(
+            Value::from(err),
+            Value::List(vec![
+                "err".into(),
+                "code".into(),
+                "message".into(),
+                5.into()
+            ])
+        );
+
+        let err: Result<i64, TryFromError> = Err(TryFromError::BadType);
+        assert_eq!(
+            Value::from(err),
+            Value::List(vec![
+                "err".into(),
+                "invalid_arguments".into(),
+                TryFromError::BadType.to_string().into(),
+                Value::Null,
+            ])
+        );
+    }
+
+    #[test]
+    fn test_raw_reply() {
+        Context::run_test(async {
+            let _registration = RawHandler {}.register("raw_channel");
+            let isolate = MockIsolate::new().attach(&Context::get().message_channel());
+
+            let value = Value::List(vec!["abc".into(), Value::F64List(vec![1.0, 2.0])]);
+            let reply = isolate
+                .send_message_async("raw_channel", vec!["ok".into(), value.clone()].into())
+                .await
+                .unwrap();
+            assert_eq!(
+                reply,
+                Value::List(vec!["ok_raw".into(), Value::U8List(value.encode())])
+            );
+
+            let res = isolate
+                .call_method_async("raw_channel", "ok", value.clone())
+                .await;
+            assert_eq!(res.unwrap(), value);
+
+            let res = isolate
+                .call_method_async("raw_channel", "err", value.clone())
+                .await;
+            match res {
+                Err(MethodCallError::PlatformError(err)) => {
+                    assert_eq!(err.code, "code");
+                    assert_eq!(err.message.as_deref(), Some("message"));
+                    assert_eq!(err.detail, value);
+                }
+                _ => panic!("unexpected result {:?}", res),
+            }
+        });
+    }
+
+    struct ArgsHandler {
+        args_ptr: Cell<*const u8>,
+    }
+
+    impl MethodHandler for ArgsHandler {
+        fn on_method_call(&self, call: MethodCall, reply: MethodCallReply) {
+            let args: Vec<u8> = call.into_args().try_into().unwrap();
+            self.args_ptr.set(args.as_ptr());
+            reply.send_ok(args.len() as i64);
+        }
+    }
+
+    #[test]
+    fn test_into_args() {
+        Context::run_test(async {
+            let handler = Rc::new(ArgsHandler {
+                args_ptr: Cell::new(std::ptr::null()),
+            });
+            let _registration = ArgsHandler::register_weak(&handler, "args_channel");
+            let isolate = MockIsolate::new().attach(&Context::get().message_channel());
+
+            let args = vec![7u8; 1024 * 1024];
+            let ptr = args.as_ptr();
+            let res = isolate
+                .call_method_async("args_channel", "len", Value::U8List(args))
+                .await;
+            assert_eq!(res.unwrap(), Value::I64(1024 * 1024));
+            // Same allocation means the arguments were not cloned
+            assert_eq!(handler.args_ptr.get(), ptr);
+        });
+    }
+
+    #[test]
+    fn test_register_weak() {
+        Context::run_test(async {
+            let handler = Rc::new(EchoHandler {});
+            let _registration = EchoHandler::register_weak(&handler, "weak_channel");
+            let isolate = MockIsolate::new().attach(&Context::get().message_channel());
+
+            let res = isolate
+                .call_method_async("weak_channel", "echo", 10.into())
+                .await;
+            assert_eq!(res.unwrap(), Value::I64(10));
+
+            drop(handler);
+
+            let res = isolate
+                .call_method_async("weak_channel", "echo", 10.into())
+                .await;
+            assert!(matches!(res, Err(MethodCallError::NotImplemented)));
+        });
+    }
+
+    #[derive(Debug, PartialEq)]
+    struct QuotaExceeded {
+        used: i64,
+        limit: i64,
+    }
+
+    impl From<QuotaExceeded> for Value {
+        fn from(detail: QuotaExceeded) -> Self {
+            Value::Map(
+                vec![
+                    ("used".into(), detail.used.into()),
+                    ("limit".into(), detail.limit.into()),
+                ]
+                .into(),
+            )
+        }
+    }
+
+    impl TryFrom<Value> for QuotaExceeded {
+        type Error = TryFromError;
+
+        fn try_from(value: Value) -> Result<Self, Self::Error> {
+            let map: HashMap<String, i64> = value.try_into()?;
+            let get = |key: &str| {
+                map.get(key)
+                    .copied()
+                    .ok_or_else(|| TryFromError::OtherError(format!("missing {}", key)))
+            };
+            Ok(QuotaExceeded {
+                used: get("used")?,
+                limit: get("limit")?,
+            })
+        }
+    }
+
+    struct QuotaHandler {}
+
+    impl MethodHandler for QuotaHandler {
+        fn on_method_call(&self, _call: MethodCall, reply: MethodCallReply) {
+            reply.send_err(PlatformError::with_typed_detail(
+                "quota_exceeded",
+                "Quota exceeded",
+                QuotaExceeded {
+                    used: 12,
+                    limit: 10,
+                },
+            ));
+        }
+    }
+
+    #[test]
+    fn test_typed_error_detail() {
+        Context::run_test(async {
+            let _registration = QuotaHandler {}.register("quota_channel");
+            let isolate = MockIsolate::new().attach(&Context::get().message_channel());
+
+            let res = isolate
+                .call_method_async("quota_channel", "upload", Value::Null)
+                .await;
+            match res {
+                Err(MethodCallError::PlatformError(err)) => {
+                    assert_eq!(err.code, "quota_exceeded");
+                    assert_eq!(
+                        err.detail_as::<QuotaExceeded>().unwrap(),
+                        QuotaExceeded {
+                            used: 12,
+                            limit: 10,
+                        }
+                    );
+                    assert!(err.detail_as::<String>().is_err());
+                }
+                _ => panic!("unexpected result {:?}", res),
+            }
+        });
+    }
+
+    struct PanickingHandler {}
+
+    impl MethodHandler for PanickingHandler {
+        fn on_method_call(&self, call: MethodCall, reply: MethodCallReply) {
+            match call.method.as_str() {
+                "panic" => panic!("handler failed"),
+                _ => reply.send_ok(call.args),
+            }
+        }
+    }
+
+    #[test]
+    fn test_handler_panic() {
+        Context::run_test(async {
+            let _registration = PanickingHandler {}.register("panicking_channel");
+            let isolate = MockIsolate::new().attach(&Context::get().message_channel());
+
+            let res = isolate
+                .call_method_async("panicking_channel", "panic", Value::Null)
+                .await;
+            match res {
+                Err(MethodCallError::PlatformError(err)) => {
+                    assert_eq!(err.code, "handler_panic");
+                    assert_eq!(err.message.as_deref(), Some("handler failed"));
+                }
+                _ => panic!("unexpected result {:?}", res),
+            }
+
+            let res = isolate
+                .call_method_async("panicking_channel", "echo", 10.into())
+                .await;
+            assert_eq!(res.unwrap(), Value::I64(10));
+        });
+    }
+
+    struct CounterHandler {
+        total: i64,
+    }
+
+    impl SimpleMethodHandler for CounterHandler {
+        fn handle(&mut self, call: MethodCall) -> PlatformResult {
+            match call.method.as_str() {
+                "add" => {
+                    self.total += i64::try_from(call.args)?;
+                    Ok(self.total.into())
+                }
+                _ => Err(PlatformError {
+                    code: "unknown_method".into(),
+                    message: Some(call.method),
+                    detail: Value::Null,
+                }),
+            }
+        }
+    }
+
+    #[test]
+    fn test_simple_method_handler() {
+        Context::run_test(async {
+            let registration = CounterHandler { total: 0 }.register("counter_channel");
+            let isolate = MockIsolate::new().attach(&Context::get().message_channel());
+
+            let res = isolate
+                .call_method_async("counter_channel", "add", 2.into())
+                .await;
+            assert_eq!(res.unwrap(), Value::I64(2));
+            let res = isolate
+                .call_method_async("counter_channel", "add", 3.into())
+                .await;
+            assert_eq!(res.unwrap(), Value::I64(5));
+            assert_eq!(registration.handler().borrow().total, 5);
+
+            let res = isolate
+                .call_method_async("counter_channel", "reset", Value::Null)
+                .await;
+            match res {
+                Err(MethodCallError::PlatformError(err)) => {
+                    assert_eq!(err.code, "unknown_method");
+                    assert_eq!(err.message.as_deref(), Some("reset"));
+                }
+                _ => panic!("unexpected result {:?}", res),
+            }
+
+            let res = isolate
+                .call_method_async("counter_channel", "add", "x".into())
+                .await;
+            match res {
+                Err(MethodCallError::PlatformError(err)) => {
+                    assert_eq!(err.code, "invalid_arguments");
+                }
+                _ => panic!("unexpected result {:?}", res),
+            }
+        });
+    }
+
+    struct PartialHandler {}
+
+    impl MethodHandler for PartialHandler {
+        fn on_method_call(&self, call: MethodCall, reply: MethodCallReply) {
+            match call.method.as_str() {
+                "fail" => reply.send_err(PlatformError {
+                    code: "failed".into(),
+                    message: None,
+                    detail: Value::Null,
+                }),
+                _ => reply.send_not_implemented(),
+            }
+        }
+    }
+
+    #[test]
+    fn test_not_implemented() {
+        Context::run_test(async {
+            let _registration = PartialHandler {}.register("partial_channel");
+            let isolate = MockIsolate::new().attach(&Context::get().message_channel());
+
+            let res = isolate
+                .call_method_async("partial_channel", "missing", Value::Null)
+                .await;
+            assert!(matches!(res, Err(MethodCallError::NotImplemented)));
+
+            let res = isolate
+                .call_method_async("unregistered_channel", "missing", Value::Null)
+                .await;
+            assert!(matches!(res, Err(MethodCallError::NotImplemented)));
+
+            let res = isolate
+                .call_method_async("partial_channel", "fail", Value::Null)
+                .await;
+            match res {
+                Err(MethodCallError::PlatformError(err)) => assert_eq!(err.code, "failed"),
+                _ => panic!("unexpected result {:?}", res),
+            }
+        });
+    }
+
+    #[test]
+    fn test_ready() {
+        Context::run_test(async {
+            let context = Context::get();
+            let run_loop = context.run_loop();
+            let registration = EchoHandler {}.register("ready_channel");
+            let isolate = MockIsolate::new().attach(&context.message_channel());
+
+            let resolved = Rc::new(Cell::new(false));
+            let resolved_clone = resolved.clone();
+            let ready = registration.ready();
+            let ready = run_loop.spawn(async move {
+                let isolate = ready.await;
+                resolved_clone.set(true);
+                isolate
+            });
+            run_loop.wait(Duration::from_millis(5)).await;
+            assert!(!resolved.get());
+
+            isolate.ping("ready_channel");
+            assert_eq!(ready.await, isolate.isolate_id());
+            // Already contacted, resolves immediately.
+            assert_eq!(registration.ready().await, isolate.isolate_id());
+
+            // Contact is forgotten after unregistering; message counts as contact.
+            drop(registration);
+            let registration = EchoHandler {}.register("ready_channel");
+            let ready = registration.ready();
+            let res = isolate
+                .call_method_async("ready_channel", "echo", Value::Null)
+                .await;
+            assert!(res.is_ok());
+            assert_eq!(ready.await, isolate.isolate_id());
+
+            // Pending future stays armed when unregistered and follows rechannel.
+            drop(registration);
+            let pending = context.message_channel().channel_ready("ready_channel");
+            let registration = EchoHandler {}.register("ready_channel");
+            registration.rechannel("ready_renamed");
+            isolate.ping("ready_renamed");
+            assert_eq!(pending.await, isolate.isolate_id());
+        });
+    }
+
+    struct ChunkedHandler {
+        log: Rc<RefCell<Vec<String>>>,
+    }
+
+    fn process_chunk(
+        log: Rc<RefCell<Vec<String>>>,
+        name: String,
+        chunk: i64,
+        reply: MethodCallReply,
+    ) {
+        log.borrow_mut().push(format!("{}{}", name, chunk));
+        if chunk == 2 {
+            reply.send_ok(chunk);
+        } else {
+            reply.yield_now(move |reply| process_chunk(log, name, chunk + 1, reply));
+        }
+    }
+
+    impl MethodHandler for ChunkedHandler {
+        fn on_method_call(&self, call: MethodCall, reply: MethodCallReply) {
+            process_chunk(self.log.clone(), call.method, 0, reply);
+        }
+    }
+
+    #[test]
+    fn test_yield_now() {
+        Context::run_test(async {
+            let log = Rc::new(RefCell::new(Vec::new()));
+            let _registration = ChunkedHandler { log: log.clone() }.register("chunked_channel");
+            let context = Context::get();
+            let isolate = MockIsolate::new().attach(&context.message_channel());
+
+            let calls: Vec<_> = ["a", "b"]
+                .iter()
+                .map(|name| {
+                    let isolate = isolate.clone();
+                    context.run_loop().spawn(async move {
+                        isolate
+                            .call_method_async("chunked_channel", name, Value::Null)
+                            .await
+                    })
+                })
+                .collect();
+            for call in calls {
+                assert_eq!(call.await.unwrap(), Value::I64(2));
+            }
+            assert_eq!(*log.borrow(), vec!["a0", "b0", "a1", "b1", "a2", "b2"]);
+        });
+    }
+
+    #[test]
+    fn test_call_method_blocking() {
+        Context::run_test(async {
+            let context = Context::get();
+            let isolate = MockIsolate::new();
+            isolate.register_method_handler("worker_channel", |call, reply| {
+                let value: i64 = call.args.try_into().unwrap();
+                reply(Ok((value * 2).into()));
+            });
+            let isolate = isolate.attach(&context.message_channel());
+            let isolate_id = isolate.isolate_id();
+
+            // Would deadlock on run loop thread.
+            let res = panic::catch_unwind(|| {
+                MessageChannel::call_method_blocking(
+                    isolate_id,
+                    "worker_channel",
+                    "double",
+                    1.into(),
+                )
+            });
+            assert!(res.is_err());
+
+            let (future, completer) = FutureCompleter::new();
+            let mut completer = Capsule::new(completer);
+            let sender = context.run_loop_sender();
+            thread::spawn(move || {
+                let value = MessageChannel::call_method_blocking(
+                    isolate_id,
+                    "worker_channel",
+                    "double",
+                    21.into(),
+                )
+                .unwrap();
+                sender.send(move || completer.take().unwrap().complete(value));
+            });
+            assert_eq!(future.await, Value::I64(42));
+        });
+    }
+
+    #[test]
+    fn test_call_method_blocking_shutdown() {
+        let (isolate_sender, isolate_receiver) = mpsc::channel();
+        let context_thread = thread::spawn(move || {
+            let context = Context::new();
+            let isolate = MockIsolate::new();
+            let captured = isolate.capture_messages("worker_channel");
+            let isolate = isolate.attach(&context.message_channel());
+            context.get_attachment(|| isolate.clone());
+            isolate_sender.send(isolate.isolate_id()).unwrap();
+
+            // Shut down the context once the call arrives, without replying.
+            context.run_loop().spawn(async move {
+                while captured.is_empty() {
+                    Context::get()
+                        .run_loop()
+                        .wait(Duration::from_millis(1))
+                        .await;
+                }
+                Context::get().run_loop().stop();
+            });
+            context.run_loop().run();
+        });
+        let isolate_id = isolate_receiver.recv().unwrap();
+        let res =
+            MessageChannel::call_method_blocking(isolate_id, "worker_channel", "double", 21.into());
+        assert!(matches!(
+            res,
+            Err(MethodCallError::SendError(
+                SendMessageError::IsolateShutDown
+            ))
+        ));
+        context_thread.join().unwrap();
+    }
+}
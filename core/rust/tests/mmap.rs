@@ -0,0 +1,62 @@
+#[cfg(feature = "mmap")]
+mod tests {
+    use std::{borrow::Cow, fs, io::Write};
+
+    use nativeshell_core::{DecodeError, MappedValue, Value};
+
+    #[test]
+    fn test_decode_mmap() {
+        let values: Vec<Value> = vec![
+            "first".into(),
+            Value::U8List(vec![1, 2, 3]),
+            Value::F64List(vec![1.5, 2.5]),
+            vec![Value::I64(10), "nested".into()].into(),
+            Value::Null,
+        ];
+        let path = std::env::temp_dir().join(format!("nativeshell_mmap_{}", std::process::id()));
+        let mut file = fs::File::create(&path).unwrap();
+        for value in &values {
+            file.write_all(&value.encode()).unwrap();
+        }
+        drop(file);
+
+        let mapped = Value::decode_mmap(&path).unwrap();
+        let decoded: Vec<_> = mapped.iter().collect::<Result<_, _>>().unwrap();
+        let owned: Vec<Value> = decoded.iter().map(MappedValue::to_value).collect();
+        assert_eq!(owned, values);
+        // Strings and byte lists borrow from the mapping.
+        let range = mapped.as_bytes().as_ptr_range();
+        match (&decoded[0], &decoded[1]) {
+            (MappedValue::String(Cow::Borrowed(s)), MappedValue::U8List(bytes)) => {
+                assert!(range.contains(&s.as_ptr()));
+                assert!(range.contains(&bytes.as_ptr()));
+                assert_eq!(*bytes, &[1, 2, 3]);
+            }
+            other => panic!("unexpected values {:?}", other),
+        }
+        assert_eq!(
+            decoded[2],
+            MappedValue::Value(Value::F64List(vec![1.5, 2.5]))
+        );
+        drop(decoded);
+        drop(mapped);
+
+        // Truncated value fails and stops the iteration.
+        let mut bytes = fs::read(&path).unwrap();
+        let partial = Value::I64List(vec![1, 2]).encode();
+        bytes.extend_from_slice(&partial[..partial.len() - 1]);
+        fs::write(&path, bytes).unwrap();
+        let mapped = Value::decode_mmap(&path).unwrap();
+        let mut iter = mapped.iter();
+        assert_eq!(iter.by_ref().take(5).count(), 5);
+        assert_eq!(iter.next(), Some(Err(DecodeError::Malformed)));
+        assert_eq!(iter.next(), None);
+        assert_eq!(iter.position(), mapped.as_bytes().len());
+        drop(mapped);
+
+        fs::write(&path, []).unwrap();
+        assert_eq!(Value::decode_mmap(&path).unwrap().iter().count(), 0);
+
+        fs::remove_file(&path).unwrap();
+    }
+}
@@ -0,0 +1,35 @@
+#[cfg(feature = "mock")]
+mod tests {
+    use std::time::Duration;
+
+    use nativeshell_core::{select2, util::FutureCompleter, Context, Either};
+
+    #[test]
+    fn test_select2() {
+        Context::run_test(async {
+            let context = Context::get();
+            let run_loop = context.run_loop();
+
+            // Completer wins
+            let (future, completer) = FutureCompleter::new();
+            run_loop
+                .schedule(Duration::from_millis(10), move || completer.complete(10))
+                .detach();
+            let res = select2(run_loop.wait(Duration::from_secs(10)), future).await;
+            assert_eq!(res, Either::Right(10));
+
+            // Sleep wins, completer is never completed
+            let (future, _completer) = FutureCompleter::<i64>::new();
+            let res = select2(run_loop.wait(Duration::from_millis(10)), future).await;
+            assert_eq!(res, Either::Left(()));
+
+            let (future, completer) = FutureCompleter::new();
+            completer.complete("reply");
+            let res = select2! {
+                _ = run_loop.wait(Duration::from_secs(10)) => None,
+                reply = future => Some(reply),
+            };
+            assert_eq!(res, Some("reply"));
+        });
+    }
+}
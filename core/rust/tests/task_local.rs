@@ -0,0 +1,51 @@
+#[cfg(feature = "mock")]
+mod tests {
+    use std::time::Duration;
+
+    use nativeshell_core::{task_local, Context};
+
+    task_local! {
+        static TRACE_ID: String;
+    }
+
+    async fn nested(delay: u64) -> String {
+        Context::get()
+            .run_loop()
+            .wait(Duration::from_millis(delay))
+            .await;
+        TRACE_ID.get()
+    }
+
+    async fn handler(delay: u64) -> (String, String) {
+        let before = TRACE_ID.get();
+        let after = nested(delay).await;
+        (before, after)
+    }
+
+    #[test]
+    fn test_task_local() {
+        Context::run_test(async {
+            assert_eq!(TRACE_ID.try_get(), None);
+
+            let context = Context::get();
+            let run_loop = context.run_loop();
+            let first = run_loop.spawn(TRACE_ID.scope("first".into(), handler(20)));
+            let second = run_loop.spawn(TRACE_ID.scope("second".into(), handler(5)));
+
+            // Interleaved tasks only see their own value.
+            assert_eq!(first.await, ("first".into(), "first".into()));
+            assert_eq!(second.await, ("second".into(), "second".into()));
+
+            // Nested scope shadows the outer value until it completes.
+            let res = TRACE_ID
+                .scope("outer".into(), async {
+                    let inner = TRACE_ID.scope("inner".into(), nested(1)).await;
+                    (inner, TRACE_ID.get())
+                })
+                .await;
+            assert_eq!(res, ("inner".into(), "outer".into()));
+
+            assert_eq!(TRACE_ID.try_get(), None);
+        });
+    }
+}
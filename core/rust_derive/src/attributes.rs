@@ -12,6 +12,7 @@ pub const RENAME: Symbol = Symbol("rename");
 pub const RENAME_ALL: Symbol = Symbol("rename_all");
 pub const SKIP: Symbol = Symbol("skip");
 pub const SKIP_IF_EMPTY: Symbol = Symbol("skip_if_empty");
+pub const FLATTEN: Symbol = Symbol("flatten");
 pub const DEFAULT: Symbol = Symbol("default");
 pub const TAG: Symbol = Symbol("tag");
 pub const CONTENT: Symbol = Symbol("content");
@@ -87,12 +88,25 @@ pub struct StructAttributes {
     pub rename_all: RenameRule,
 }
 
+#[derive(Debug, Default)]
+pub struct MethodsAttributes {
+    pub rename_all: RenameRule,
+}
+
+#[derive(Debug, Default)]
+pub struct MethodAttributes {
+    pub rename: Option<StringWithSpan>,
+    pub skip: bool,
+}
+
 #[derive(Debug, Default)]
 pub struct FieldAttributes {
     pub rename: Option<StringWithSpan>,
     pub skip: bool,
     pub skip_if_empty: bool,
     pub default: bool,
+    // Entries of the field value are stored directly in parent map
+    pub flatten: bool,
 }
 
 fn str_from_lit(lit: &Lit, span: Option<Span>) -> StringWithSpan {
@@ -231,6 +245,61 @@ pub fn parse_field_attributes(attrs: &[Attribute]) -> FieldAttributes {
                     res.skip = true;
                 } else if path == SKIP_IF_EMPTY {
                     res.skip_if_empty = true;
+                } else if path == FLATTEN {
+                    res.flatten = true;
+                } else {
+                    Diagnostic::spanned(path.span(), Level::Error, "unknown attribute".into())
+                        .emit();
+                }
+            }
+            _ => {
+                Diagnostic::spanned(m.span(), Level::Error, "unknown attribute".into()).emit();
+            }
+        }
+    }
+    if res.flatten {
+        if let Some(rename) = &res.rename {
+            Diagnostic::spanned(
+                rename.span,
+                Level::Error,
+                "flattened field can not be renamed".into(),
+            )
+            .emit();
+        }
+    }
+    res
+}
+
+pub fn parse_methods_attributes(args: &[NestedMeta]) -> MethodsAttributes {
+    let mut res = MethodsAttributes::default();
+    for arg in args {
+        match arg {
+            NestedMeta::Meta(Meta::NameValue(nv)) if nv.path == RENAME_ALL => {
+                res.rename_all = rename_rule_from_lit(&nv.lit);
+            }
+            _ => {
+                Diagnostic::spanned(arg.span(), Level::Error, "unknown attribute".into()).abort();
+            }
+        }
+    }
+    res
+}
+
+pub fn parse_method_attributes(attrs: &[Attribute]) -> MethodAttributes {
+    let mut res = MethodAttributes::default();
+    let meta = extract_nativeshell_meta(attrs);
+    for m in &meta {
+        match m {
+            Meta::NameValue(nv) => {
+                if nv.path == RENAME {
+                    res.rename = Some(str_from_lit(&nv.lit, Some(nv.span())))
+                } else {
+                    Diagnostic::spanned(nv.span(), Level::Error, "unknown attribute".into()).emit();
+                }
+            }
+            Meta::Path(path) => {
+                if path == SKIP {
+                    res.skip = true;
                 } else {
                     Diagnostic::spanned(path.span(), Level::Error, "unknown attribute".into())
                         .emit();
@@ -9,7 +9,7 @@ use crate::{
         parse_struct_attributes, EnumAttributes, StringWithSpan, StructAttributes,
     },
     case::RenameRule,
-    rename_field, rename_variant,
+    check_duplicate_key, rename_field, rename_variant,
 };
 
 fn insert_fields(
@@ -17,12 +17,14 @@ fn insert_fields(
     prefix: Option<Ident>,
     fields_named: &FieldsNamed,
     rename_rule: &RenameRule,
+    mut keys: Vec<String>,
 ) -> TokenStream {
     struct Field {
         string: String,
         field: TokenStream,
     }
     let mut fields = Vec::<Field>::new();
+    let mut flattened = Vec::<TokenStream>::new();
 
     for field in &fields_named.named {
         let ident = field.ident.clone().unwrap();
@@ -30,16 +32,24 @@ fn insert_fields(
         if attributes.skip {
             continue;
         }
-        let string = rename_field(
-            &format!("{}", ident),
-            rename_rule,
-            &attributes.rename.map(|a| a.value),
-        );
         let field_access = if let Some(prefix) = &prefix {
             quote! { #prefix.#ident }
         } else {
             quote! { #ident }
         };
+        if attributes.flatten {
+            flattened.push(quote! {
+                ::nativeshell_core::derive_internal::flatten_into(&mut #target, #field_access.into());
+            });
+            continue;
+        }
+        let string = rename_field(
+            &format!("{}", ident),
+            rename_rule,
+            &attributes.rename.map(|a| a.value),
+        );
+        check_duplicate_key(&keys, &string, ident.span());
+        keys.push(string.clone());
         let token_stream = if attributes.skip_if_empty {
             quote! {
                 if (&&::nativeshell_core::derive_internal::Wrap(& #field_access)).is_none() == false {
@@ -66,6 +76,9 @@ fn insert_fields(
         #(
             #fields
         )*
+        #(
+            #flattened
+        )*
     }
 }
 
@@ -119,7 +132,12 @@ impl FromEnum {
                 let create_vec = quote! {
                     let mut #target = ::std::vec::Vec::<(::nativeshell_core::Value, ::nativeshell_core::Value)>::new();
                 };
-                let insert = insert_fields(&target, None, &fields, &attributes.rename_all);
+                // Tag without content is stored next to the fields.
+                let keys = match (&self.attributes.tag, &self.attributes.content) {
+                    (Some(tag), None) => vec![tag.value.clone()],
+                    _ => Vec::new(),
+                };
+                let insert = insert_fields(&target, None, &fields, &attributes.rename_all, keys);
                 let epilogue = match (&self.attributes.tag, &self.attributes.content) {
                     (None, None) => quote! {
                         let __ns_value = ::nativeshell_core::Value::Map(#target.into());
@@ -268,6 +286,7 @@ impl FromStruct {
                     Some(format_ident!("__ns_value")),
                     &fields,
                     &self.attributes.rename_all,
+                    Vec::new(),
                 );
                 quote! {
                     let mut #target = ::std::vec::Vec::<(::nativeshell_core::Value, ::nativeshell_core::Value)>::new();
@@ -1,17 +1,28 @@
 use case::RenameRule;
+use proc_macro2::Span;
 use proc_macro_error::{proc_macro_error, Diagnostic, Level};
-use syn::DeriveInput;
+use syn::{AttributeArgs, DeriveInput, ItemImpl};
 
 use quote::quote;
 
 mod attributes;
 mod case;
 mod from;
+mod methods;
 mod try_into;
 
 use from::*;
+use methods::*;
 use try_into::*;
 
+/// Generates `From<T> for Value` implementation. Structs with named fields
+/// are converted to `Value::Map`.
+///
+/// Fields marked with `#[nativeshell(flatten)]` must convert to `Value::Map`;
+/// their entries are stored directly in the parent map instead of under a
+/// nested key. Fields whose keys conflict are rejected at compile time; flattened
+/// entries conflicting with keys already present in the parent map (regular
+/// fields and earlier flattened fields) panic during conversion.
 #[proc_macro_derive(IntoValue, attributes(nativeshell))]
 #[proc_macro_error]
 pub fn into_value(input: proc_macro::TokenStream) -> proc_macro::TokenStream {
@@ -44,13 +55,26 @@ pub fn into_value(input: proc_macro::TokenStream) -> proc_macro::TokenStream {
     proc_macro::TokenStream::from(tokens)
 }
 
+/// Generates `TryFrom<Value>` implementation, counterpart of `IntoValue`.
+///
+/// Fields marked with `#[nativeshell(flatten)]` are converted from entries not
+/// consumed by other (non-flattened) fields. Flattened structs only take their
+/// own keys, remaining entries are passed to the flattened field of other type
+/// (i.e. map); if there are several such fields, the first one gets all
+/// entries. Flattened keys conflicting with each other or with regular fields
+/// result in an error.
 #[proc_macro_derive(TryFromValue, attributes(nativeshell))]
 #[proc_macro_error]
 pub fn try_from_value(input: proc_macro::TokenStream) -> proc_macro::TokenStream {
     let ast = syn::parse_macro_input!(input as DeriveInput);
     let name = ast.ident;
+    let mut flatten_keys = None;
     let token_stream = match ast.data {
-        syn::Data::Struct(s) => TryIntoStruct::new(name.clone(), ast.attrs).process(s),
+        syn::Data::Struct(s) => {
+            let try_into = TryIntoStruct::new(name.clone(), ast.attrs);
+            flatten_keys = try_into.flatten_keys(&s);
+            try_into.process(s)
+        }
         syn::Data::Enum(e) => TryIntoEnum::new(name.clone(), ast.attrs).process(e),
         syn::Data::Union(_) => {
             Diagnostic::spanned(
@@ -64,6 +88,17 @@ pub fn try_from_value(input: proc_macro::TokenStream) -> proc_macro::TokenStream
 
     let (impl_generics, ty_generics, where_clause) = ast.generics.split_for_impl();
 
+    let flatten_keys = flatten_keys.map(|body| {
+        quote! {
+            #[automatically_derived]
+            impl #impl_generics ::nativeshell_core::derive_internal::FlattenKeys for #name #ty_generics #where_clause {
+                fn flatten_keys() -> ::std::option::Option<::std::vec::Vec<&'static str>> {
+                    #body
+                }
+            }
+        }
+    });
+
     let tokens = quote! {
         #[automatically_derived]
         impl #impl_generics core::convert::TryFrom<::nativeshell_core::Value> for #name #ty_generics #where_clause {
@@ -73,10 +108,32 @@ pub fn try_from_value(input: proc_macro::TokenStream) -> proc_macro::TokenStream
                 #token_stream
             }
         }
+        #flatten_keys
     };
     proc_macro::TokenStream::from(tokens)
 }
 
+/// Generates `MethodHandler` implementation for the type that dispatches
+/// method calls to methods of the annotated `impl` block. Arguments are
+/// converted using `TryFrom<Value>` (multiple arguments are expected to be
+/// sent as a list) and return value is sent as reply; `Result` return value
+/// is sent using `MethodCallReply::send`. Calls to unknown methods are replied
+/// with `not_implemented` error.
+///
+/// Method names can be changed with `#[nativeshell_methods(rename_all = "...")]`
+/// and `#[nativeshell(rename = "...")]` on individual methods. Methods marked
+/// with `#[nativeshell(skip)]` or without `self` receiver are not dispatched.
+#[proc_macro_attribute]
+#[proc_macro_error]
+pub fn nativeshell_methods(
+    attr: proc_macro::TokenStream,
+    item: proc_macro::TokenStream,
+) -> proc_macro::TokenStream {
+    let args = syn::parse_macro_input!(attr as AttributeArgs);
+    let item = syn::parse_macro_input!(item as ItemImpl);
+    proc_macro::TokenStream::from(Methods::new(item, args).process())
+}
+
 pub(crate) fn rename_field(
     original: &str,
     rename_rule: &RenameRule,
@@ -88,6 +145,13 @@ pub(crate) fn rename_field(
     rename_rule.apply_to_field(original)
 }
 
+// Aborts if `key` is already used by another field of the same struct.
+pub(crate) fn check_duplicate_key(keys: &[String], key: &str, span: Span) {
+    if keys.iter().any(|k| k == key) {
+        Diagnostic::spanned(span, Level::Error, format!("duplicate key \"{}\"", key)).abort();
+    }
+}
+
 pub(crate) fn rename_variant(
     original: &str,
     rename_rule: &RenameRule,
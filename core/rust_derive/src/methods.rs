@@ -0,0 +1,163 @@
+use proc_macro2::TokenStream;
+use proc_macro_error::{Diagnostic, Level};
+use quote::quote;
+use syn::{
+    spanned::Spanned, FnArg, ImplItem, ImplItemMethod, ItemImpl, NestedMeta, ReturnType, Type,
+};
+
+use crate::{
+    attributes::{parse_method_attributes, parse_methods_attributes, NATIVESHELL},
+    rename_field,
+};
+
+pub struct Methods {
+    item: ItemImpl,
+    args: Vec<NestedMeta>,
+}
+
+impl Methods {
+    pub fn new(item: ItemImpl, args: Vec<NestedMeta>) -> Self {
+        Self { item, args }
+    }
+
+    pub fn process(mut self) -> TokenStream {
+        let attributes = parse_methods_attributes(&self.args);
+        let mut arms = Vec::new();
+        for item in &mut self.item.items {
+            if let ImplItem::Method(method) = item {
+                let method_attributes = parse_method_attributes(&method.attrs);
+                method.attrs.retain(|a| a.path != NATIVESHELL);
+                if method_attributes.skip || method.sig.receiver().is_none() {
+                    continue;
+                }
+                let name = rename_field(
+                    &method.sig.ident.to_string(),
+                    &attributes.rename_all,
+                    &method_attributes.rename.map(|r| r.value),
+                );
+                let call = Self::process_method(method);
+                arms.push(quote! { #name => { #call } });
+            }
+        }
+
+        let item = &self.item;
+        let self_ty = &item.self_ty;
+        let (impl_generics, _, where_clause) = item.generics.split_for_impl();
+        quote! {
+            #item
+
+            #[automatically_derived]
+            impl #impl_generics ::nativeshell_core::MethodHandler for #self_ty #where_clause {
+                fn on_method_call(
+                    &self,
+                    __ns_call: ::nativeshell_core::MethodCall,
+                    __ns_reply: ::nativeshell_core::MethodCallReply,
+                ) {
+                    match __ns_call.method.as_str() {
+                        #(#arms)*
+                        _ => __ns_reply.send_error(
+                            ::nativeshell_core::NOT_IMPLEMENTED_ERROR_CODE.into(),
+                            Some(format!("method \"{}\" not implemented", __ns_call.method)),
+                            ::nativeshell_core::Value::Null,
+                        ),
+                    }
+                }
+            }
+        }
+    }
+
+    fn process_method(method: &ImplItemMethod) -> TokenStream {
+        if method.sig.asyncness.is_some() {
+            Diagnostic::spanned(
+                method.sig.span(),
+                Level::Error,
+                "async methods are not supported".into(),
+            )
+            .abort();
+        }
+        let ident = &method.sig.ident;
+        let types: Vec<&Type> = method
+            .sig
+            .inputs
+            .iter()
+            .filter_map(|a| match a {
+                FnArg::Typed(arg) => Some(arg.ty.as_ref()),
+                FnArg::Receiver(_) => None,
+            })
+            .collect();
+        let names: Vec<_> = (0..types.len())
+            .map(|i| quote::format_ident!("__ns_arg{}", i))
+            .collect();
+
+        // Single argument is converted from call arguments directly, multiple
+        // arguments are expected to be sent as a list.
+        let convert = |name: &proc_macro2::Ident, ty: &Type, value: TokenStream| {
+            quote! {
+                let #name: #ty = match ::core::convert::TryInto::try_into(#value) {
+                    Ok(value) => value,
+                    Err(err) => {
+                        let err: ::nativeshell_core::TryFromError = err.into();
+                        __ns_reply.send_err(err);
+                        return;
+                    }
+                };
+            }
+        };
+        let conversions = match types.len() {
+            0 => quote! {},
+            1 => convert(&names[0], types[0], quote! { __ns_call.into_args() }),
+            len => {
+                let items: Vec<_> = names
+                    .iter()
+                    .zip(types.iter())
+                    .map(|(name, ty)| convert(name, ty, quote! { __ns_args.next().unwrap() }))
+                    .collect();
+                quote! {
+                    let __ns_args: ::std::vec::Vec<::nativeshell_core::Value> =
+                        match ::core::convert::TryInto::try_into(__ns_call.into_args()) {
+                            Ok(args) => args,
+                            Err(err) => {
+                                __ns_reply.send_err(err);
+                                return;
+                            }
+                        };
+                    if __ns_args.len() != #len {
+                        __ns_reply.send_error(
+                            "invalid_arguments".into(),
+                            Some(format!("expected {} arguments, got {}", #len, __ns_args.len())),
+                            ::nativeshell_core::Value::Null,
+                        );
+                        return;
+                    }
+                    let mut __ns_args = __ns_args.into_iter();
+                    #(#items)*
+                }
+            }
+        };
+        let reply = if Self::returns_result(&method.sig.output) {
+            quote! { __ns_reply.send(__ns_result) }
+        } else {
+            quote! { __ns_reply.send_ok(__ns_result) }
+        };
+        quote! {
+            #conversions
+            let __ns_result = self.#ident(#(#names),*);
+            #reply
+        }
+    }
+
+    fn returns_result(output: &ReturnType) -> bool {
+        match output {
+            ReturnType::Type(_, ty) => match ty.as_ref() {
+                Type::Path(path) => path
+                    .path
+                    .segments
+                    .last()
+                    .map(|s| s.ident == "Result")
+                    .unwrap_or(false),
+                _ => false,
+            },
+            ReturnType::Default => false,
+        }
+    }
+}
@@ -3,7 +3,7 @@ use proc_macro_error::{Diagnostic, Level};
 use quote::quote;
 use syn::{
     spanned::Spanned, Attribute, DataEnum, DataStruct, Fields, FieldsNamed, FieldsUnnamed, Ident,
-    Type,
+    Index, Type,
 };
 
 use crate::{
@@ -12,7 +12,7 @@ use crate::{
         parse_struct_attributes, EnumAttributes, StringWithSpan, StructAttributes,
     },
     case::RenameRule,
-    rename_field, rename_variant,
+    check_duplicate_key, rename_field, rename_variant,
 };
 
 pub struct TryIntoEnum {
@@ -39,13 +39,22 @@ impl TryIntoEnum {
     fn process_tag(&self, data: &DataEnum) -> TokenStream {
         let tag = self.attributes.tag.clone().unwrap().value;
         let (strings, variants) = self.process_variants(data, true);
+        // Content is nested under separate key, otherwise the fields are stored
+        // alongside the tag.
+        let variants: Vec<TokenStream> = match &self.attributes.content {
+            Some(content) => variants
+                .into_iter()
+                .map(|v| with_path_segment(v, &content.value))
+                .collect(),
+            None => variants,
+        };
         let extract_value = match &self.attributes.content {
             Some(content) => {
                 let content = &content.value;
                 quote! {
                     let mut __ns_value = ::nativeshell_core::Value::Null;
                     for __ns_row in __ns_map {
-                        if let ::nativeshell_core::Value::String(__ns_content_value) = __ns_row.0 {
+                        if let ::nativeshell_core::Value::String(__ns_content_value) = &__ns_row.0 {
                             if __ns_content_value == #content {
                                 __ns_value = __ns_row.1;
                                 break;
@@ -59,8 +68,8 @@ impl TryIntoEnum {
             },
         };
         quote! {
-            match __ns_value {
-                ::nativeshell_core::Value::Map(__ns_map) => {
+            match ::nativeshell_core::derive_internal::into_map(__ns_value) {
+                Ok(__ns_map) => {
                     let mut __ns_tag_value = Option::<String>::None;
                     for __ns_row in __ns_map.iter() {
                         if let (::nativeshell_core::Value::String(__ns_tag),
@@ -79,7 +88,7 @@ impl TryIntoEnum {
                         (__ns_other) => return ::core::result::Result::Err(Self::Error::OtherError(format!("unknown enum value {}", __ns_other))),
                     }
                 }
-                __ns_other => {
+                Err(__ns_other) => {
                     return ::core::result::Result::Err(Self::Error::OtherError(format!("can not deserialize {:?} as enum", __ns_other)));
                 }
             }
@@ -89,10 +98,15 @@ impl TryIntoEnum {
     fn process_no_tag(&self, data: &DataEnum) -> TokenStream {
         let unit_enums = self.process_unit_enums(data);
         let (strings, variants) = self.process_variants(data, false);
+        let variants: Vec<TokenStream> = variants
+            .into_iter()
+            .zip(strings.iter())
+            .map(|(v, s)| with_path_segment(v, s))
+            .collect();
         quote! {
             #unit_enums
-            match __ns_value {
-                ::nativeshell_core::Value::Map(__ns_map) => {
+            match ::nativeshell_core::derive_internal::into_map(__ns_value) {
+                Ok(__ns_map) => {
                     let __ns_row = __ns_map.into_iter().next().ok_or(Self::Error::OtherError("unexpected empty map".into()))?;
                     let __ns_key : String = __ns_row.0.try_into().map_err(|e|Self::Error::OtherError("enum type must be a String".into()))?;
                     let __ns_value = __ns_row.1;
@@ -103,7 +117,7 @@ impl TryIntoEnum {
                         (other) => return ::core::result::Result::Err(Self::Error::OtherError(format!("unknown enum value {}", other))),
                     }
                 }
-                other => {
+                Err(other) => {
                     return ::core::result::Result::Err(Self::Error::OtherError(format!("can not deserialize {:?} as enum", other)));
                 }
             }
@@ -159,7 +173,7 @@ impl TryIntoEnum {
         }
         let enum_name = &self.name;
         quote! {
-            if let ::nativeshell_core::Value::String(string) = __ns_value {
+            if let ::nativeshell_core::Value::String(string) = &__ns_value {
                 #(
                     if string == #strings {
                         return ::core::result::Result::Ok(#enum_name::#variants);
@@ -179,6 +193,14 @@ impl TryIntoEnum {
     }
 }
 
+// Prepends `segment` to path of errors returned from `variant` code.
+fn with_path_segment(variant: TokenStream, segment: &str) -> TokenStream {
+    quote! {
+        let __ns_variant = || -> ::core::result::Result<Self, Self::Error> { #variant; };
+        return __ns_variant().map_err(|e| e.prepend_path(#segment));
+    }
+}
+
 fn process_struct(
     span: Span,
     fields: &Fields,
@@ -216,7 +238,8 @@ fn process_struct_unnamed(
         }
     } else {
         let rows: Vec<TokenStream> = unnamed.unnamed.iter()
-            .map(|field| {
+            .enumerate()
+            .map(|(index, field)| {
                 let ty= &field.ty;
                 quote! {
                     {
@@ -224,15 +247,15 @@ fn process_struct_unnamed(
                         (&mut &mut &mut ::nativeshell_core::derive_internal::WrapMut(&mut res)).assign(
                             iter.next().ok_or_else(||Self::Error::OtherError("missing value".into()))?,
                             false,
-                        )?;
+                        ).map_err(|e| e.prepend_path(#index))?;
                         res.unwrap()
                     }
                 }
             })
             .collect();
         quote! {
-            match __ns_value {
-                ::nativeshell_core::Value::List(entries) => {
+            match ::nativeshell_core::derive_internal::into_list(__ns_value) {
+                Ok(entries) => {
                     let mut iter = entries.into_iter();
                     return Ok(#constructor(
                         #(
@@ -261,6 +284,9 @@ fn process_struct_named(
 
     let mut skip_fields = Vec::<Ident>::new();
 
+    let mut flatten_fields = Vec::<Ident>::new();
+    let mut flatten_types = Vec::<Type>::new();
+
     let constructor = if let Some(suffix) = constructor_suffix {
         quote! { Self:: #suffix}
     } else {
@@ -275,11 +301,17 @@ fn process_struct_named(
                 skip_fields.push(ident.clone());
                 continue;
             }
+            if attributes.flatten {
+                flatten_fields.push(ident.clone());
+                flatten_types.push(field.ty.clone());
+                continue;
+            }
             let string = rename_field(
                 &format!("{}", ident),
                 &rename_rule,
                 &attributes.rename.map(|a| a.value),
             );
+            check_duplicate_key(&strings, &string, ident.span());
             err_missing_field.push(format!("required field \"{}\" missing in value.", string));
             strings.push(string);
             fields.push(ident.clone());
@@ -288,24 +320,72 @@ fn process_struct_named(
         }
     }
 
+    // Entries not consumed by regular fields are passed to flattened fields.
+    // Fields with known keys (structs) take their entries first, remaining
+    // entries go to the first flattened field without known keys (i.e. map).
+    let (declare_flattened, collect_flattened, assign_flattened) = if flatten_fields.is_empty() {
+        (quote! {}, quote! {}, quote! {})
+    } else {
+        let flatten_index: Vec<Index> = (0..flatten_fields.len()).map(Index::from).collect();
+        (
+            quote! {
+                let mut __ns_flattened = ::std::vec::Vec::<(::nativeshell_core::Value, ::nativeshell_core::Value)>::new();
+            },
+            quote! {
+                __ns_flattened.push((::nativeshell_core::Value::String(__ns_name), __ns_e.1));
+            },
+            quote! {
+                use ::nativeshell_core::derive_internal::KnownKeys;
+                let __ns_flatten_keys = [
+                    #(
+                        (&::nativeshell_core::derive_internal::Keys::<#flatten_types>(::core::marker::PhantomData)).known_keys(),
+                    )*
+                ];
+                ::nativeshell_core::derive_internal::check_flattened_keys(&[#(#strings),*], &__ns_flatten_keys)?;
+                #(
+                    if let Some(keys) = &__ns_flatten_keys[#flatten_index] {
+                        (&mut &mut &mut ::nativeshell_core::derive_internal::WrapMut(&mut #flatten_fields)).assign(
+                            ::nativeshell_core::derive_internal::take_flattened(&mut __ns_flattened, Some(keys)),
+                            false,
+                        )?;
+                    }
+                )*
+                #(
+                    if __ns_flatten_keys[#flatten_index].is_none() {
+                        (&mut &mut &mut ::nativeshell_core::derive_internal::WrapMut(&mut #flatten_fields)).assign(
+                            ::nativeshell_core::derive_internal::take_flattened(&mut __ns_flattened, None),
+                            false,
+                        )?;
+                    }
+                )*
+            },
+        )
+    };
+
     quote! {
         #(
             let mut #fields = ::std::option::Option::<#types>::None;
         )*;
+        #(
+            let mut #flatten_fields = ::std::option::Option::<#flatten_types>::None;
+        )*;
+        #declare_flattened
 
-        match __ns_value {
-            ::nativeshell_core::Value::Map(entries) => {
+        match ::nativeshell_core::derive_internal::into_map(__ns_value) {
+            Ok(entries) => {
                 for __ns_e in entries {
-                    let __ns_name = match __ns_e.0 {
-                        ::nativeshell_core::Value::String(name) => name,
-                        _ => return Err(Self::Error::OtherError("key value must be a string.".into()))
+                    let __ns_name = match ::nativeshell_core::derive_internal::into_string(__ns_e.0) {
+                        Ok(name) => name,
+                        Err(_) => return Err(Self::Error::OtherError("key value must be a string.".into()))
                     };
                     #(
                         if __ns_name == #strings {
-                            (&mut &mut &mut ::nativeshell_core::derive_internal::WrapMut(&mut #fields)).assign(__ns_e.1, #skip_if_empty)?;
+                            (&mut &mut &mut ::nativeshell_core::derive_internal::WrapMut(&mut #fields)).assign(__ns_e.1, #skip_if_empty)
+                                .map_err(|e| e.prepend_path(#strings))?;
                             continue;
                         }
                     )*;
+                    #collect_flattened
                 }
             }
             _=> {
@@ -313,6 +393,8 @@ fn process_struct_named(
             }
         }
 
+        #assign_flattened
+
         #(
             (&mut &mut &mut::nativeshell_core::derive_internal::WrapMut(&mut #fields)).set_optional_to_none();
         )*;
@@ -321,6 +403,9 @@ fn process_struct_named(
             #(
                 #fields :  #fields.ok_or(Self::Error::OtherError(#err_missing_field.into()))?,
             )*
+            #(
+                #flatten_fields : #flatten_fields.unwrap(),
+            )*
             #(
                 #skip_fields : ::std::default::Default::default(),
             )*
@@ -342,6 +427,47 @@ impl TryIntoStruct {
         }
     }
 
+    /// Body of `FlattenKeys::flatten_keys` for structs with named fields.
+    pub fn flatten_keys(&self, data: &DataStruct) -> Option<TokenStream> {
+        let named = match &data.fields {
+            Fields::Named(named) => named,
+            _ => return None,
+        };
+        let mut strings = Vec::<String>::new();
+        let mut flatten_types = Vec::<Type>::new();
+        for field in &named.named {
+            let attributes = parse_field_attributes(&field.attrs);
+            if let Some(ident) = &field.ident {
+                if attributes.skip {
+                    continue;
+                }
+                if attributes.flatten {
+                    flatten_types.push(field.ty.clone());
+                    continue;
+                }
+                strings.push(rename_field(
+                    &format!("{}", ident),
+                    &self.attributes.rename_all,
+                    &attributes.rename.map(|a| a.value),
+                ));
+            }
+        }
+        let use_known_keys = if flatten_types.is_empty() {
+            quote! {}
+        } else {
+            quote! { use ::nativeshell_core::derive_internal::KnownKeys; }
+        };
+        Some(quote! {
+            #use_known_keys
+            #[allow(unused_mut)]
+            let mut keys = ::std::vec![#(#strings),*];
+            #(
+                keys.extend((&::nativeshell_core::derive_internal::Keys::<#flatten_types>(::core::marker::PhantomData)).known_keys()?);
+            )*
+            Some(keys)
+        })
+    }
+
     pub fn process(self, data: DataStruct) -> TokenStream {
         process_struct(
             self.name.span(),